@@ -10,7 +10,7 @@ use bytes::Bytes;
 use futures::stream::StreamExt;
 use log::*;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     time::{Duration, Instant},
 };
 use structopt::StructOpt;
@@ -57,6 +57,12 @@ struct ClientServerBenchmark {
     /// Number of cross shards messages allowed before blocking the main server loop
     #[structopt(long, default_value = "1")]
     cross_shard_queue_size: usize,
+    /// What to do when the queue of cross shards messages is full: Block, DropOldest, or Reject
+    #[structopt(long, default_value = "Block")]
+    cross_shard_queue_policy: network::CrossShardQueuePolicy,
+    /// Number of connections to each other shard used in parallel to send cross shards messages
+    #[structopt(long, default_value = "1")]
+    cross_shard_connections: usize,
 }
 
 fn main() {
@@ -100,12 +106,15 @@ impl ClientServerBenchmark {
         info!("Preparing accounts.");
         let mut keys = Vec::new();
         for _ in 0..self.committee_size {
-            keys.push(get_key_pair());
+            keys.push(get_authority_key_pair());
         }
-        let committee = Committee {
-            voting_rights: keys.iter().map(|(k, _)| (*k, 1)).collect(),
-            total_votes: self.committee_size,
-        };
+        let committee = keys
+            .iter()
+            .fold(CommitteeBuilder::new(), |builder, (name, _)| {
+                builder.add_authority(*name, 1)
+            })
+            .build()
+            .expect("Invalid committee");
 
         // Pick an authority and create one state per shard.
         let (public_auth0, secret_auth0) = keys.pop().unwrap();
@@ -129,11 +138,19 @@ impl ClientServerBenchmark {
             assert!(states[i].in_shard(&keypair.0));
             let client = AccountOffchainState {
                 balance: Balance::from(Amount::from(100)),
+                initial_balance: Balance::from(Amount::from(100)),
                 next_sequence_number: SequenceNumber::from(0),
                 pending_confirmation: None,
                 confirmed_log: Vec::new(),
+                confirmed_log_start: SequenceNumber::from(0),
+                pruned_amount: Balance::zero(),
+                checkpoint: None,
                 synchronization_log: Vec::new(),
                 received_log: Vec::new(),
+                received_keys: HashSet::new(),
+                received_fees: Balance::zero(),
+                frozen: false,
+                next_freeze_version: SequenceNumber::from(0),
             };
             states[i].accounts.insert(keypair.0, client);
             account_keys.push(keypair);
@@ -150,6 +167,8 @@ impl ClientServerBenchmark {
                 amount: Amount::from(50),
                 sequence_number: SequenceNumber::from(0),
                 user_data: UserData::default(),
+                valid_until: None,
+                fee: Amount::zero(),
             };
             next_recipient = *pubx;
             let order = TransferOrder::new(transfer.clone(), secx);
@@ -181,7 +200,7 @@ impl ClientServerBenchmark {
     }
 
     async fn spawn_server(&self, state: AuthorityState) -> transport::SpawnedServer {
-        let server = network::Server::new(
+        let mut server = network::Server::new(
             self.protocol,
             self.host.clone(),
             self.port,
@@ -189,11 +208,37 @@ impl ClientServerBenchmark {
             self.buffer_size,
             self.cross_shard_queue_size,
         );
+        server.set_cross_shard_queue_policy(self.cross_shard_queue_policy);
+        server.set_cross_shard_connections(self.cross_shard_connections);
         server.spawn().await.unwrap()
     }
 
+    /// Wait until every shard answers pings.
+    async fn wait_for_servers(&self) {
+        let mut client = network::Client::new(
+            self.protocol,
+            self.host.clone(),
+            self.port,
+            self.num_shards,
+            self.buffer_size,
+            Duration::from_micros(self.send_timeout_us),
+            Duration::from_micros(self.recv_timeout_us),
+        );
+        for shard in 0..self.num_shards {
+            loop {
+                match client.health(shard).await {
+                    Ok(health) => {
+                        debug!("Shard {} is up: {:?}", shard, health);
+                        break;
+                    }
+                    Err(_) => time::delay_for(Duration::from_millis(50)).await,
+                }
+            }
+        }
+    }
+
     async fn launch_client(&self, mut orders: Vec<(u32, Bytes)>) {
-        time::delay_for(Duration::from_millis(1000)).await;
+        self.wait_for_servers().await;
 
         let items_number = orders.len() / 2;
         let time_start = Instant::now();
@@ -219,8 +264,14 @@ impl ClientServerBenchmark {
                     .or_insert_with(Vec::new)
                     .push(buf.clone());
             }
-            let responses = mass_client.run(sharded_requests).concat().await;
-            info!("Received {} responses.", responses.len(),);
+            let responses: Vec<_> = mass_client.run(sharded_requests).collect().await;
+            info!(
+                "Received {} responses.",
+                responses
+                    .iter()
+                    .map(|shard| shard.responses.len())
+                    .sum::<usize>()
+            );
         } else {
             // Use actual client core
             let mut client = network::Client::new(
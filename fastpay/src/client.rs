@@ -3,51 +3,43 @@
 
 #![deny(warnings)]
 
-use fastpay::{config::*, network, transport};
+use fastpay::{config::*, network, tls::TlsConfig, transport};
 use fastpay_core::{
-    authority::*, base_types::*, client::*, committee::Committee, messages::*, serialize::*,
+    authority::*,
+    base_types::*,
+    client::*,
+    committee::{Committee, CommitteeBuilder},
+    messages::*,
+    serialize::*,
 };
 
 use bytes::Bytes;
-use futures::stream::StreamExt;
+use futures::{
+    future::{AbortHandle, Abortable},
+    stream::StreamExt,
+};
 use log::*;
 use std::{
     collections::{HashMap, HashSet},
+    convert::TryFrom,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 use structopt::StructOpt;
-use tokio::runtime::Runtime;
+use tokio::{runtime::Runtime, time};
 
-fn make_authority_clients(
-    committee_config: &CommitteeConfig,
-    buffer_size: usize,
-    send_timeout: std::time::Duration,
-    recv_timeout: std::time::Duration,
-) -> HashMap<AuthorityName, network::Client> {
-    let mut authority_clients = HashMap::new();
-    for config in &committee_config.authorities {
-        let config = config.clone();
-        let client = network::Client::new(
-            config.network_protocol,
-            config.host,
-            config.base_port,
-            config.num_shards,
-            buffer_size,
-            send_timeout,
-            recv_timeout,
-        );
-        authority_clients.insert(config.address, client);
-    }
-    authority_clients
-}
+/// How often the throughput of a benchmark phase is reported.
+const THROUGHPUT_REPORT_PERIOD: Duration = Duration::from_secs(1);
 
 fn make_authority_mass_clients(
     committee_config: &CommitteeConfig,
+    tls_config: &TlsConfig,
     buffer_size: usize,
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
     max_in_flight: u64,
-) -> Vec<(u32, network::MassClient)> {
+    observers: Vec<Arc<dyn network::MassClientObserver>>,
+) -> Vec<(AuthorityName, u32, network::MassClient)> {
     let mut authority_clients = Vec::new();
     for config in &committee_config.authorities {
         let client = network::MassClient::new(
@@ -59,7 +51,14 @@ fn make_authority_mass_clients(
             recv_timeout,
             max_in_flight / config.num_shards as u64, // Distribute window to diff shards
         );
-        authority_clients.push((config.num_shards, client));
+        let client = observers.iter().fold(client, |client, observer| {
+            client.with_observer(observer.clone())
+        });
+        let client = match tls_config.client_config(&config.address) {
+            Some(tls_config) => client.with_tls_config(tls_config),
+            None => client,
+        };
+        authority_clients.push((config.address, config.num_shards, client));
     }
     authority_clients
 }
@@ -67,15 +66,19 @@ fn make_authority_mass_clients(
 fn make_client_state(
     accounts: &AccountsConfig,
     committee_config: &CommitteeConfig,
+    tls_config: &TlsConfig,
     address: FastPayAddress,
     buffer_size: usize,
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
-) -> ClientState<network::Client> {
+) -> ClientState<BoxedAuthorityClient> {
     let account = accounts.get(&address).expect("Unknown account");
     let committee = Committee::new(committee_config.voting_rights());
-    let authority_clients =
-        make_authority_clients(committee_config, buffer_size, send_timeout, recv_timeout);
+    verify_certificate_chain(&account.sent_certificates, &committee)
+        .expect("Invalid sent certificates in accounts config");
+    let factory = network::ClientFactory::new(buffer_size, send_timeout, recv_timeout)
+        .with_tls_config(tls_config.clone());
+    let authority_clients = committee_config.make_authority_clients(&factory);
     ClientState::new(
         address,
         account.key.copy(),
@@ -104,6 +107,8 @@ fn make_benchmark_transfer_orders(
             amount: Amount::from(1),
             sequence_number: account.next_sequence_number,
             user_data: UserData::default(),
+            valid_until: None,
+            fee: Amount::zero(),
         };
         debug!("Preparing transfer order: {:?}", transfer);
         account.next_sequence_number = account.next_sequence_number.increment().unwrap();
@@ -129,10 +134,13 @@ fn make_benchmark_certificates_from_orders_and_server_configs(
         let server_config = AuthorityServerConfig::read(file).expect("Fail to read server config");
         keys.push((server_config.authority.address, server_config.key));
     }
-    let committee = Committee {
-        voting_rights: keys.iter().map(|(k, _)| (*k, 1)).collect(),
-        total_votes: keys.len(),
-    };
+    let committee = keys
+        .iter()
+        .fold(CommitteeBuilder::new(), |builder, (name, _)| {
+            builder.add_authority(*name, 1)
+        })
+        .build()
+        .expect("Invalid committee");
     assert!(
         keys.len() >= committee.quorum_threshold(),
         "Not enough server configs were provided with --server-configs"
@@ -160,63 +168,139 @@ fn make_benchmark_certificates_from_votes(
     votes: Vec<SignedTransferOrder>,
 ) -> Vec<(FastPayAddress, Bytes)> {
     let committee = Committee::new(committee_config.voting_rights());
-    let mut aggregators = HashMap::new();
-    let mut certificates = Vec::new();
-    let mut done_senders = HashSet::new();
-    for vote in votes {
-        // We aggregate votes indexed by sender.
-        let address = vote.value.transfer.sender;
-        if done_senders.contains(&address) {
-            continue;
+    let (certificates, errors) = CertifiedTransferOrder::from_votes(votes, &committee);
+    for error in errors {
+        warn!("Ignoring invalid vote: {}", error);
+    }
+    certificates
+        .into_iter()
+        .map(|certificate| {
+            debug!("Found certificate: {:?}", certificate);
+            let buf = serialize_cert(&certificate);
+            (certificate.value.transfer.sender, buf.into())
+        })
+        .collect()
+}
+
+/// Measure how long sampled orders take to be accepted by a quorum of authorities.
+struct FinalityProbe {
+    /// Sampled orders, indexed by their serialization.
+    sampled_orders: HashMap<Bytes, FastPayAddress>,
+    tracker: Mutex<network::FinalityTracker<FastPayAddress>>,
+}
+
+impl network::MassClientObserver for FinalityProbe {
+    fn request_sent(&self, request: &Bytes) {
+        if let Some(sender) = self.sampled_orders.get(request) {
+            self.tracker.lock().unwrap().submit(*sender, Instant::now());
         }
-        debug!(
-            "Processing vote on {}'s transfer by {}",
-            encode_address(&address),
-            encode_address(&vote.authority)
-        );
-        let value = vote.value;
-        let aggregator = aggregators
-            .entry(address)
-            .or_insert_with(|| SignatureAggregator::try_new(value, &committee).unwrap());
-        match aggregator.append(vote.authority, vote.signature) {
-            Ok(Some(certificate)) => {
-                debug!("Found certificate: {:?}", certificate);
-                let buf = serialize_cert(&certificate);
-                certificates.push((address, buf.into()));
-                done_senders.insert(address);
-            }
-            Ok(None) => {
-                debug!("Added one vote");
-            }
-            Err(error) => {
-                error!("Failed to aggregate vote: {}", error);
-            }
+    }
+
+    fn response_received(&self, response: &[u8]) {
+        if let Some(info) = deserialize_response(response) {
+            self.tracker
+                .lock()
+                .unwrap()
+                .respond(&info.sender, Instant::now());
         }
     }
-    certificates
 }
 
-/// Broadcast a bulk of requests to each authority.
+/// What happened to the orders broadcast to one shard of one authority.
+#[derive(Debug)]
+struct ShardOutcome {
+    authority: AuthorityName,
+    shard: ShardId,
+    successes: usize,
+    error_replies: usize,
+    timeouts: u64,
+    send_failures: u64,
+}
+
+impl ShardOutcome {
+    fn new(authority: AuthorityName, responses: &network::ShardResponses) -> Self {
+        let error_replies = responses.error_replies();
+        Self {
+            authority,
+            shard: responses.shard,
+            successes: responses.responses.len() - error_replies,
+            error_replies,
+            timeouts: responses.timeouts,
+            send_failures: responses.send_failures,
+        }
+    }
+
+    fn dropped_orders(&self) -> u64 {
+        self.error_replies as u64 + self.timeouts + self.send_failures
+    }
+}
+
+/// Broadcast a bulk of requests to each authority. One out of `latency_sample_period`
+/// orders is used to measure latency (if not zero). Returns the responses, and what
+/// happened to the orders sent to each shard of each authority.
+#[allow(clippy::too_many_arguments)]
 async fn mass_broadcast_orders(
     phase: &'static str,
     committee_config: &CommitteeConfig,
+    tls_config: &TlsConfig,
     buffer_size: usize,
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
     max_in_flight: u64,
+    latency_sample_period: usize,
     orders: Vec<(FastPayAddress, Bytes)>,
-) -> Vec<Bytes> {
+) -> (Vec<Bytes>, Vec<ShardOutcome>) {
     let time_start = Instant::now();
     info!("Broadcasting {} {} orders", orders.len(), phase);
+    let probe = if latency_sample_period > 0 {
+        let committee = Committee::new(committee_config.voting_rights());
+        Some(Arc::new(FinalityProbe {
+            sampled_orders: orders
+                .iter()
+                .step_by(latency_sample_period)
+                .map(|(address, buf)| (buf.clone(), *address))
+                .collect(),
+            tracker: Mutex::new(network::FinalityTracker::new(committee.quorum_threshold())),
+        }))
+    } else {
+        None
+    };
+    let counters = Arc::new(network::ThroughputCounters::default());
+    let mut observers: Vec<Arc<dyn network::MassClientObserver>> = vec![counters.clone()];
+    if let Some(probe) = &probe {
+        observers.push(probe.clone());
+    }
     let authority_clients = make_authority_mass_clients(
         committee_config,
+        tls_config,
         buffer_size,
         send_timeout,
         recv_timeout,
         max_in_flight,
+        observers,
     );
+    // Report the throughput of the last period while the orders are sent.
+    let (reporter, registration) = AbortHandle::new_pair();
+    tokio::spawn(Abortable::new(
+        async move {
+            let mut timer = time::interval(THROUGHPUT_REPORT_PERIOD);
+            timer.tick().await;
+            loop {
+                timer.tick().await;
+                let (sent, received) = counters.take();
+                info!(
+                    "Sent {} {} orders and received {} responses in the last {} ms",
+                    sent,
+                    phase,
+                    received,
+                    THROUGHPUT_REPORT_PERIOD.as_millis()
+                );
+            }
+        },
+        registration,
+    ));
     let mut streams = Vec::new();
-    for (num_shards, client) in authority_clients {
+    for (name, num_shards, client) in authority_clients {
         // Re-index orders by shard for this particular authority client.
         let mut sharded_requests = HashMap::new();
         for (address, buf) in &orders {
@@ -226,10 +310,35 @@ async fn mass_broadcast_orders(
                 .or_insert_with(Vec::new)
                 .push(buf.clone());
         }
-        streams.push(client.run(sharded_requests));
+        streams.push(
+            client
+                .run(sharded_requests)
+                .map(move |responses| (name, responses)),
+        );
     }
-    let responses = futures::stream::select_all(streams).concat().await;
+    let shard_responses: Vec<_> = futures::stream::select_all(streams).collect().await;
     let time_elapsed = time_start.elapsed();
+    reporter.abort();
+    let mut responses = Vec::new();
+    let mut outcomes = Vec::new();
+    for (authority, shard_responses) in shard_responses {
+        let outcome = ShardOutcome::new(authority, &shard_responses);
+        if outcome.dropped_orders() > 0 {
+            warn!(
+                "Authority {:?} (shard {}) dropped {} {} orders ({} succeeded): {} error replies, {} timeouts, {} send failures",
+                outcome.authority,
+                outcome.shard,
+                outcome.dropped_orders(),
+                phase,
+                outcome.successes,
+                outcome.error_replies,
+                outcome.timeouts,
+                outcome.send_failures
+            );
+        }
+        outcomes.push(outcome);
+        responses.extend(shard_responses.responses);
+    }
     warn!(
         "Received {} responses in {} ms.",
         responses.len(),
@@ -240,7 +349,19 @@ async fn mass_broadcast_orders(
         (orders.len() as u128) * 1_000_000 / time_elapsed.as_micros(),
         phase
     );
-    responses
+    if let Some(probe) = probe {
+        let tracker = probe.tracker.lock().unwrap();
+        let latency = tracker.histogram();
+        warn!(
+            "Quorum latency of {} sampled {} orders: p50 <= {} us, p90 <= {} us, p99 <= {} us",
+            latency.count(),
+            phase,
+            latency.percentile(50),
+            latency.percentile(90),
+            latency.percentile(99)
+        );
+    }
+    (responses, outcomes)
 }
 
 fn mass_update_recipients(
@@ -254,6 +375,22 @@ fn mass_update_recipients(
     }
 }
 
+/// Replace serialized certificates by compact confirmation orders.
+fn make_compact_certificates(
+    certificates: &[(FastPayAddress, Bytes)],
+) -> Vec<(FastPayAddress, Bytes)> {
+    certificates
+        .iter()
+        .filter_map(|(sender, buf)| match deserialize_message(&buf[..]) {
+            Ok(SerializedMessage::Cert(certificate)) => {
+                let order = CompactConfirmationOrder::new(&certificate);
+                Some((*sender, serialize_compact_cert(&order).into()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
 fn deserialize_response(response: &[u8]) -> Option<AccountInfoResponse> {
     match deserialize_message(response) {
         Ok(SerializedMessage::InfoResp(info)) => Some(*info),
@@ -330,6 +467,10 @@ enum ClientCommands {
         address: String,
     },
 
+    /// Obtain the balances of all our accounts that are backed by a quorum of authorities
+    #[structopt(name = "query_balances")]
+    QueryBalances,
+
     /// Send one transfer per account in bulk mode
     #[structopt(name = "benchmark")]
     Benchmark {
@@ -344,6 +485,14 @@ enum ClientCommands {
         /// Use server configuration files to generate certificates (instead of aggregating received votes).
         #[structopt(long)]
         server_configs: Option<Vec<String>>,
+
+        /// Measure the latency of one out of N orders (0 to disable)
+        #[structopt(long, default_value = "100")]
+        latency_sample_period: usize,
+
+        /// Omit transfer orders from confirmation orders when authorities already know them
+        #[structopt(long)]
+        compact_confirmations: bool,
     },
 
     /// Create new user accounts and print the public keys
@@ -353,6 +502,15 @@ enum ClientCommands {
         #[structopt(long, default_value = "0")]
         initial_funding: Balance,
 
+        /// Base64-encoded 32-byte master seed to derive the keys of the accounts from,
+        /// instead of generating random keys
+        #[structopt(long)]
+        seed: Option<String>,
+
+        /// Derivation index of the first account created from the seed
+        #[structopt(long, default_value = "0")]
+        first_index: u32,
+
         /// Number of additional accounts to create
         num: u32,
     },
@@ -372,6 +530,9 @@ fn main() {
         AccountsConfig::read_or_create(accounts_config_path).expect("Unable to read user accounts");
     let committee_config =
         CommitteeConfig::read(committee_config_path).expect("Unable to read committee config file");
+    let tls_config = committee_config
+        .tls_config()
+        .expect("Unable to read TLS certificates");
 
     match options.cmd {
         ClientCommands::Transfer { from, to, amount } => {
@@ -384,6 +545,7 @@ fn main() {
                 let mut client_state = make_client_state(
                     &accounts_config,
                     &committee_config,
+                    &tls_config,
                     sender,
                     buffer_size,
                     send_timeout,
@@ -403,6 +565,7 @@ fn main() {
                 let mut recipient_client_state = make_client_state(
                     &accounts_config,
                     &committee_config,
+                    &tls_config,
                     recipient,
                     buffer_size,
                     send_timeout,
@@ -428,6 +591,7 @@ fn main() {
                 let mut client_state = make_client_state(
                     &accounts_config,
                     &committee_config,
+                    &tls_config,
                     user_address,
                     buffer_size,
                     send_timeout,
@@ -447,10 +611,32 @@ fn main() {
             });
         }
 
+        ClientCommands::QueryBalances => {
+            let mut rt = Runtime::new().unwrap();
+            rt.block_on(async move {
+                let committee = Committee::new(committee_config.voting_rights());
+                let factory = network::ClientFactory::new(buffer_size, send_timeout, recv_timeout)
+                    .with_tls_config(tls_config);
+                let mut authority_clients = committee_config.make_authority_clients(&factory);
+                let accounts = accounts_config
+                    .accounts()
+                    .map(|account| account.address)
+                    .collect();
+                let balances =
+                    get_strong_majority_balances(&mut authority_clients, &committee, accounts)
+                        .await;
+                for (address, balance) in balances {
+                    println!("{}: {:?}", encode_address(&address), balance);
+                }
+            });
+        }
+
         ClientCommands::Benchmark {
             max_in_flight,
             max_orders,
             server_configs,
+            latency_sample_period,
+            compact_confirmations,
         } => {
             let max_orders = max_orders.unwrap_or_else(|| accounts_config.num_accounts());
 
@@ -459,13 +645,15 @@ fn main() {
                 warn!("Starting benchmark phase 1 (transfer orders)");
                 let (orders, serialize_orders) =
                     make_benchmark_transfer_orders(&mut accounts_config, max_orders);
-                let responses = mass_broadcast_orders(
+                let (responses, _) = mass_broadcast_orders(
                     "transfer",
                     &committee_config,
+                    &tls_config,
                     buffer_size,
                     send_timeout,
                     recv_timeout,
                     max_in_flight,
+                    latency_sample_period,
                     serialize_orders,
                 )
                 .await;
@@ -486,16 +674,60 @@ fn main() {
                     warn!("Using committee config");
                     make_benchmark_certificates_from_votes(&committee_config, votes)
                 };
-                let responses = mass_broadcast_orders(
+                let broadcast_certificates = if compact_confirmations {
+                    warn!("Using compact confirmation orders");
+                    make_compact_certificates(&certificates)
+                } else {
+                    certificates.clone()
+                };
+                let (mut responses, outcomes) = mass_broadcast_orders(
                     "confirmation",
                     &committee_config,
+                    &tls_config,
                     buffer_size,
                     send_timeout,
                     recv_timeout,
                     max_in_flight,
-                    certificates.clone(),
+                    latency_sample_period,
+                    broadcast_certificates,
                 )
                 .await;
+                if compact_confirmations {
+                    // Authorities reject the compact confirmation orders of transfers that
+                    // they do not know. Send the full certificates of these transfers.
+                    let mut num_confirmations = HashMap::new();
+                    for info in responses
+                        .iter()
+                        .filter_map(|buf| deserialize_response(&buf[..]))
+                    {
+                        *num_confirmations.entry(info.sender).or_insert(0) += 1;
+                    }
+                    let num_authorities = committee_config.authorities.len();
+                    let missing_certificates: Vec<_> = certificates
+                        .iter()
+                        .filter(|(sender, _)| {
+                            num_confirmations.get(sender).copied().unwrap_or(0) < num_authorities
+                        })
+                        .cloned()
+                        .collect();
+                    if !missing_certificates.is_empty() {
+                        responses.extend(
+                            mass_broadcast_orders(
+                                "full confirmation",
+                                &committee_config,
+                                &tls_config,
+                                buffer_size,
+                                send_timeout,
+                                recv_timeout,
+                                max_in_flight,
+                                latency_sample_period,
+                                missing_certificates,
+                            )
+                            .await
+                            .0,
+                        );
+                    }
+                }
                 let mut confirmed = HashSet::new();
                 let num_valid =
                     responses
@@ -512,6 +744,17 @@ fn main() {
                     num_valid,
                     confirmed.len()
                 );
+                let failing_shards: Vec<_> = outcomes
+                    .iter()
+                    .filter(|outcome| outcome.timeouts + outcome.send_failures > 0)
+                    .map(|outcome| (outcome.authority, outcome.shard))
+                    .collect();
+                if !failing_shards.is_empty() {
+                    warn!(
+                        "Shards that dropped confirmation orders: {:?}",
+                        failing_shards
+                    );
+                }
 
                 warn!("Updating local state of user accounts");
                 // Make sure that the local balances are accurate so that future
@@ -526,11 +769,19 @@ fn main() {
 
         ClientCommands::CreateAccounts {
             initial_funding,
+            seed,
+            first_index,
             num,
         } => {
+            let seed = seed.map(|seed| {
+                let bytes = base64::decode(&seed).expect("Invalid base64 seed");
+                <[u8; 32]>::try_from(&bytes[..]).expect("The seed must be 32 bytes long")
+            });
             let num_accounts: u32 = num;
-            for _ in 0..num_accounts {
-                let account = UserAccount::new(initial_funding);
+            for index in first_index..first_index + num_accounts {
+                let path = [index];
+                let derivation = seed.as_ref().map(|seed| (seed, &path[..]));
+                let account = UserAccount::new(initial_funding, derivation);
                 println!("{}:{}", encode_address(&account.address), initial_funding);
                 accounts_config.insert(account);
             }
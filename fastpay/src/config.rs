@@ -1,20 +1,53 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::transport::NetworkProtocol;
+use crate::{
+    network::AuthorityClientFactory,
+    tls::{self, TlsConfig, TlsIdentity},
+    transport::NetworkProtocol,
+};
 use fastpay_core::{
+    authority::AuthoritySnapshot,
     base_types::*,
-    client::ClientState,
-    messages::{Address, CertifiedTransferOrder},
+    client::{BoxedAuthorityClient, ClientState},
+    messages::{
+        Address, CertifiedGenesisState, CertifiedTransferOrder, GenesisState, SignedGenesisState,
+    },
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     fs::{self, File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Write},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
 };
 
+#[cfg(test)]
+#[path = "unit_tests/config_tests.rs"]
+mod config_tests;
+
+/// Config files with this extension are stored in BCS rather than JSON, which is faster
+/// to parse for large committees and account stores.
+const BINARY_CONFIG_EXTENSION: &str = "bcs";
+
+fn is_binary_config(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map_or(false, |extension| extension == BINARY_CONFIG_EXTENSION)
+}
+
+fn read_binary_config<T: DeserializeOwned>(path: &str) -> Result<T, io::Error> {
+    let data = fs::read(path)?;
+    bcs::from_bytes(&data).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn write_binary_config<T: Serialize>(value: &T, path: &str) -> Result<(), io::Error> {
+    let data =
+        bcs::to_bytes(value).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    fs::write(path, data)
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthorityConfig {
     pub network_protocol: NetworkProtocol,
@@ -22,10 +55,13 @@ pub struct AuthorityConfig {
         serialize_with = "address_as_base64",
         deserialize_with = "address_from_base64"
     )]
-    pub address: FastPayAddress,
+    pub address: AuthorityName,
     pub host: String,
     pub base_port: u32,
     pub num_shards: u32,
+    /// Path to the PEM certificate presented by the authority when using TLS.
+    #[serde(default)]
+    pub tls_certificate: Option<String>,
 }
 
 impl AuthorityConfig {
@@ -35,19 +71,28 @@ impl AuthorityConfig {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AuthorityServerConfig {
     pub authority: AuthorityConfig,
     pub key: KeyPair,
+    /// Path to the PEM (PKCS8) private key of the TLS certificate.
+    #[serde(default)]
+    pub tls_key: Option<String>,
 }
 
 impl AuthorityServerConfig {
     pub fn read(path: &str) -> Result<Self, std::io::Error> {
+        if is_binary_config(path) {
+            return read_binary_config(path);
+        }
         let data = fs::read(path)?;
         Ok(serde_json::from_slice(data.as_slice())?)
     }
 
     pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
+        if is_binary_config(path) {
+            return write_binary_config(self, path);
+        }
         let file = OpenOptions::new().create(true).write(true).open(path)?;
         let mut writer = BufWriter::new(file);
         let data = serde_json::to_string_pretty(self).unwrap();
@@ -55,6 +100,14 @@ impl AuthorityServerConfig {
         writer.write_all(b"\n")?;
         Ok(())
     }
+
+    /// Read the TLS certificate and key of this authority, if any.
+    pub fn tls_identity(&self) -> Result<Option<TlsIdentity>, std::io::Error> {
+        match (&self.authority.tls_certificate, &self.tls_key) {
+            (Some(certificate), Some(key)) => Ok(Some(TlsIdentity::read(certificate, key)?)),
+            _ => Ok(None),
+        }
+    }
 }
 
 pub struct CommitteeConfig {
@@ -63,6 +116,11 @@ pub struct CommitteeConfig {
 
 impl CommitteeConfig {
     pub fn read(path: &str) -> Result<Self, std::io::Error> {
+        if is_binary_config(path) {
+            return Ok(Self {
+                authorities: read_binary_config(path)?,
+            });
+        }
         let file = File::open(path)?;
         let reader = BufReader::new(file);
         let stream = serde_json::Deserializer::from_reader(reader).into_iter();
@@ -72,6 +130,9 @@ impl CommitteeConfig {
     }
 
     pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
+        if is_binary_config(path) {
+            return write_binary_config(&self.authorities, path);
+        }
         let file = OpenOptions::new().create(true).write(true).open(path)?;
         let mut writer = BufWriter::new(file);
         for config in &self.authorities {
@@ -88,9 +149,32 @@ impl CommitteeConfig {
         }
         map
     }
+
+    /// Create a client for each authority.
+    pub fn make_authority_clients(
+        &self,
+        factory: &dyn AuthorityClientFactory,
+    ) -> HashMap<AuthorityName, BoxedAuthorityClient> {
+        self.authorities
+            .iter()
+            .map(|config| (config.address, factory.make(config)))
+            .collect()
+    }
+
+    /// Read the TLS certificates of the authorities that provide one.
+    pub fn tls_config(&self) -> Result<TlsConfig, std::io::Error> {
+        let mut config = TlsConfig::default();
+        for authority in &self.authorities {
+            if let Some(path) = &authority.tls_certificate {
+                let certificate = tls::read_certificates(path)?.remove(0);
+                config.trust_authority(authority.address, certificate);
+            }
+        }
+        Ok(config)
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct UserAccount {
     #[serde(
         serialize_with = "address_as_base64",
@@ -105,8 +189,16 @@ pub struct UserAccount {
 }
 
 impl UserAccount {
-    pub fn new(balance: Balance) -> Self {
-        let (address, key) = get_key_pair();
+    /// Create an account with a random key, or with the key derived from the given master
+    /// seed and derivation path (see `KeyPair::derive_from_seed`).
+    pub fn new(balance: Balance, derivation: Option<(&[u8; 32], &[u32])>) -> Self {
+        let (address, key) = match derivation {
+            Some((master, path)) => {
+                let key = KeyPair::derive_from_seed(master, path);
+                (key.public(), key)
+            }
+            None => get_key_pair(),
+        };
         Self {
             address,
             key,
@@ -118,6 +210,7 @@ impl UserAccount {
     }
 }
 
+#[derive(Debug)]
 pub struct AccountsConfig {
     accounts: BTreeMap<FastPayAddress, UserAccount>,
 }
@@ -135,6 +228,10 @@ impl AccountsConfig {
         self.accounts.len()
     }
 
+    pub fn accounts(&self) -> impl Iterator<Item = &UserAccount> {
+        self.accounts.values()
+    }
+
     pub fn accounts_mut(&mut self) -> impl Iterator<Item = &mut UserAccount> {
         self.accounts.values_mut()
     }
@@ -171,6 +268,19 @@ impl AccountsConfig {
             .write(true)
             .read(true)
             .open(path)?;
+        if is_binary_config(path) {
+            let accounts: Vec<UserAccount> = if file.metadata()?.len() == 0 {
+                Vec::new()
+            } else {
+                read_binary_config(path)?
+            };
+            return Ok(Self {
+                accounts: accounts
+                    .into_iter()
+                    .map(|account| (account.address, account))
+                    .collect(),
+            });
+        }
         let reader = BufReader::new(file);
         let stream = serde_json::Deserializer::from_reader(reader).into_iter();
         Ok(Self {
@@ -182,6 +292,10 @@ impl AccountsConfig {
     }
 
     pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
+        if is_binary_config(path) {
+            let accounts: Vec<_> = self.accounts.values().collect();
+            return write_binary_config(&accounts, path);
+        }
         let file = OpenOptions::new().write(true).open(path)?;
         let mut writer = BufWriter::new(file);
         for account in self.accounts.values() {
@@ -223,3 +337,81 @@ impl InitialStateConfig {
         Ok(())
     }
 }
+
+/// The initial balances of all accounts and the signatures of the authorities that approved
+/// them. Servers load it at startup once a quorum has signed.
+pub struct GenesisConfig {
+    pub certificate: CertifiedGenesisState,
+}
+
+impl GenesisConfig {
+    pub fn new(
+        accounts: Vec<(FastPayAddress, Balance)>,
+        fee_recipient: Option<FastPayAddress>,
+    ) -> Self {
+        Self {
+            certificate: CertifiedGenesisState {
+                value: GenesisState {
+                    accounts,
+                    fee_recipient,
+                },
+                signatures: Vec::new(),
+            },
+        }
+    }
+
+    pub fn read(path: &str) -> Result<Self, std::io::Error> {
+        let certificate = if is_binary_config(path) {
+            read_binary_config(path)?
+        } else {
+            let data = fs::read(path)?;
+            serde_json::from_slice(data.as_slice())?
+        };
+        Ok(Self { certificate })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
+        if is_binary_config(path) {
+            return write_binary_config(&self.certificate, path);
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        let mut writer = BufWriter::new(file);
+        let data = serde_json::to_string_pretty(&self.certificate).unwrap();
+        writer.write_all(data.as_ref())?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Add (or replace) the signature of the given authority.
+    pub fn sign(&mut self, name: AuthorityName, secret: &KeyPair) {
+        let vote = SignedGenesisState::new(self.certificate.value.clone(), name, secret);
+        let signatures = &mut self.certificate.signatures;
+        signatures.retain(|(authority, _)| *authority != name);
+        signatures.push((vote.authority, vote.signature));
+    }
+}
+
+/// Save the state of an authority shard, e.g. to migrate or back up the shard.
+pub fn write_authority_snapshot(
+    snapshot: &AuthoritySnapshot,
+    path: &str,
+) -> Result<(), std::io::Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, snapshot)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+pub fn read_authority_snapshot(path: &str) -> Result<AuthoritySnapshot, std::io::Error> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
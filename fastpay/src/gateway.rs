@@ -0,0 +1,163 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use fastpay_core::{client::AuthorityClient, error::FastPayError};
+use hyper::{
+    body::HttpBody,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{convert::Infallible, net::SocketAddr};
+
+#[cfg(test)]
+#[path = "unit_tests/gateway_tests.rs"]
+mod gateway_tests;
+
+/// Default maximum size of the body of a request (bytes).
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Accept JSON requests over HTTP and forward them to an authority, for clients that
+/// cannot use the binary protocol (e.g. web browsers):
+/// * `POST /transfer` with a `TransferOrder`,
+/// * `POST /confirm` with a `ConfirmationOrder`,
+/// * `POST /account` with an `AccountInfoRequest`.
+///
+/// Successful requests are answered with an `AccountInfoResponse`, failed ones with a
+/// `FastPayError` and the status code 400. Bodies larger than `max_body_size` are
+/// rejected with the status code 413.
+pub struct HttpGateway<A> {
+    client: A,
+    max_body_size: usize,
+}
+
+impl<A> HttpGateway<A>
+where
+    A: AuthorityClient + Clone + Send + Sync + 'static,
+{
+    pub fn new(client: A) -> Self {
+        Self {
+            client,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Listen on the given address (port 0 picks a free port) and serve requests in a
+    /// new task. Return the local address of the gateway.
+    pub fn spawn(
+        self,
+        address: &SocketAddr,
+    ) -> Result<
+        (
+            SocketAddr,
+            tokio::task::JoinHandle<Result<(), hyper::Error>>,
+        ),
+        hyper::Error,
+    > {
+        let client = self.client;
+        let max_body_size = self.max_body_size;
+        let service = make_service_fn(move |_| {
+            let client = client.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let client = client.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            Self::handle_request(client, request, max_body_size).await,
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::try_bind(address)?.serve(service);
+        let local_address = server.local_addr();
+        info!("Listening to HTTP requests on {}", local_address);
+        Ok((local_address, tokio::spawn(server)))
+    }
+
+    async fn handle_request(
+        mut client: A,
+        request: Request<Body>,
+        max_body_size: usize,
+    ) -> Response<Body> {
+        if request.method() != Method::POST {
+            return Self::empty_response(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let path = request.uri().path().to_string();
+        let body = match Self::read_body(request.into_body(), max_body_size).await {
+            Ok(Some(body)) => body,
+            Ok(None) => return Self::empty_response(StatusCode::PAYLOAD_TOO_LARGE),
+            Err(error) => {
+                warn!("Failed to read HTTP request: {}", error);
+                return Self::empty_response(StatusCode::BAD_REQUEST);
+            }
+        };
+        let reply = match path.as_str() {
+            "/transfer" => match Self::decode(&body) {
+                Ok(order) => client.handle_transfer_order(order).await,
+                Err(error) => Err(error),
+            },
+            "/confirm" => match Self::decode(&body) {
+                Ok(order) => client.handle_confirmation_order(order).await,
+                Err(error) => Err(error),
+            },
+            "/account" => match Self::decode(&body) {
+                Ok(request) => client.handle_account_info_request(request).await,
+                Err(error) => Err(error),
+            },
+            _ => return Self::empty_response(StatusCode::NOT_FOUND),
+        };
+        match reply {
+            Ok(info) => Self::json_response(StatusCode::OK, &info),
+            Err(error) => {
+                debug!("HTTP query failed: {}", error);
+                Self::json_response(StatusCode::BAD_REQUEST, &error)
+            }
+        }
+    }
+
+    /// Read the body of a request, or return `None` as soon as it exceeds the given size.
+    async fn read_body(
+        mut body: Body,
+        max_body_size: usize,
+    ) -> Result<Option<Vec<u8>>, hyper::Error> {
+        if body.size_hint().lower() > max_body_size as u64 {
+            return Ok(None);
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > max_body_size {
+                return Ok(None);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(Some(bytes))
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, FastPayError> {
+        serde_json::from_slice(body).map_err(|_| FastPayError::InvalidDecoding)
+    }
+
+    fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+        let body = serde_json::to_vec(value).expect("Responses should be serializable");
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("application/json"),
+        );
+        response
+    }
+
+    fn empty_response(status: StatusCode) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    }
+}
@@ -4,5 +4,8 @@
 #![deny(warnings)]
 
 pub mod config;
+#[cfg(feature = "http-gateway")]
+pub mod gateway;
 pub mod network;
+pub mod tls;
 pub mod transport;
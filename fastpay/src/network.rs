@@ -1,14 +1,35 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::transport::*;
-use fastpay_core::{authority::*, base_types::*, client::*, error::*, messages::*, serialize::*};
+use crate::{config::AuthorityConfig, tls::TlsConfig, transport::*};
+use fastpay_core::{
+    authority::*, base_types::*, client::*, committee::Committee, error::*, messages::*,
+    serialize::*,
+};
 
 use bytes::Bytes;
-use futures::{channel::mpsc, future::FutureExt, sink::SinkExt, stream::StreamExt};
+use clap::arg_enum;
+use futures::{
+    future::{AbortHandle, Abortable, Aborted},
+    stream::StreamExt,
+};
 use log::*;
-use std::io;
-use tokio::time;
+use rustls::ClientConfig;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, time};
+
+#[cfg(test)]
+#[path = "unit_tests/network_tests.rs"]
+mod network_tests;
 
 pub struct Server {
     network_protocol: NetworkProtocol,
@@ -17,9 +38,18 @@ pub struct Server {
     state: AuthorityState,
     buffer_size: usize,
     cross_shard_queue_size: usize,
+    cross_shard_queue_policy: CrossShardQueuePolicy,
+    cross_shard_connections: usize,
+    cross_shard_retry_period: Duration,
+    verification_threads: usize,
+    peer_rate_limit: Option<RateLimit>,
+    tls_config: TlsConfig,
     // Stats
     packets_processed: u64,
     user_errors: u64,
+    /// Delays of the cross-shard updates received since the last periodic report.
+    cross_shard_latency: LatencyHistogram,
+    started: Instant,
 }
 
 impl Server {
@@ -38,11 +68,56 @@ impl Server {
             state,
             buffer_size,
             cross_shard_queue_size,
+            cross_shard_queue_policy: CrossShardQueuePolicy::Block,
+            cross_shard_connections: 1,
+            cross_shard_retry_period: CROSS_SHARD_RETRY_PERIOD,
+            verification_threads: num_cpus::get(),
+            peer_rate_limit: None,
+            tls_config: TlsConfig::default(),
             packets_processed: 0,
             user_errors: 0,
+            cross_shard_latency: LatencyHistogram::default(),
+            started: Instant::now(),
         }
     }
 
+    /// Choose what happens to cross-shard messages when the queue is full.
+    pub fn set_cross_shard_queue_policy(&mut self, policy: CrossShardQueuePolicy) {
+        self.cross_shard_queue_policy = policy;
+    }
+
+    /// Choose how many connections to each other shard are used in parallel to send
+    /// cross-shard messages.
+    pub fn set_cross_shard_connections(&mut self, connections: usize) {
+        self.cross_shard_connections = connections;
+    }
+
+    /// Choose how long to wait before sending again unacknowledged cross-shard updates.
+    /// The delay doubles after each attempt, up to `MAX_CROSS_SHARD_RETRY_BACKOFF` times
+    /// this period, until all updates are acknowledged.
+    pub fn set_cross_shard_retry_period(&mut self, period: Duration) {
+        self.cross_shard_retry_period = period;
+    }
+
+    /// Choose how many TCP or TLS messages may have their signatures verified at the same
+    /// time, before being handled one at a time. Zero verifies signatures while handling
+    /// messages instead.
+    pub fn set_verification_threads(&mut self, threads: usize) {
+        self.verification_threads = threads;
+    }
+
+    /// Limit the rate of the transfer orders and certificates of each IP address, before
+    /// their signatures are verified. Disabled by default.
+    pub fn set_peer_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.peer_rate_limit = limit;
+    }
+
+    /// Set the TLS identity of this authority, as well as the certificates expected when
+    /// connecting to other authorities.
+    pub fn set_tls_config(&mut self, config: TlsConfig) {
+        self.tls_config = config;
+    }
+
     pub fn packets_processed(&self) -> u64 {
         self.packets_processed
     }
@@ -51,20 +126,41 @@ impl Server {
         self.user_errors
     }
 
+    /// Report the progress of this shard without touching account states.
+    pub fn health(&self) -> HealthResponse {
+        HealthResponse {
+            shard_id: self.state.shard_id,
+            number_of_accounts: self.state.accounts.len() as u64,
+            last_transaction_index: self.state.last_transaction_index,
+            uptime_us: self.started.elapsed().as_micros() as u64,
+            cross_shard_updates: self.cross_shard_latency.count(),
+            cross_shard_delay_p50_us: self.cross_shard_latency.percentile(50),
+            cross_shard_delay_p99_us: self.cross_shard_latency.percentile(99),
+        }
+    }
+
     async fn forward_cross_shard_queries(
         network_protocol: NetworkProtocol,
         base_address: String,
         base_port: u32,
         this_shard: ShardId,
-        mut receiver: mpsc::Receiver<(Vec<u8>, ShardId)>,
+        connections: usize,
+        tls_config: Option<Arc<ClientConfig>>,
+        queue: Arc<CrossShardQueue>,
     ) {
         let mut pool = network_protocol
-            .make_outgoing_connection_pool()
+            .make_parallel_outgoing_connection_pool(connections, tls_config)
             .await
             .expect("Initialization should not fail");
 
         let mut queries_sent = 0u64;
-        while let Some((buf, shard)) = receiver.next().await {
+        while let Some(command) = queue.pop().await {
+            let (buf, shard) = match command {
+                CrossShardCommand::Update { certificate, shard } => {
+                    (serialize_cross_shard(&certificate, now_us()), shard)
+                }
+                CrossShardCommand::Ack { buf, shard } => (buf, shard),
+            };
             // Send cross-shard query.
             let remote_address = format!("{}:{}", base_address, base_port + shard);
             let status = pool.send_data_to(&buf, &remote_address).await;
@@ -99,95 +195,499 @@ impl Server {
             self.base_port + self.state.shard_id
         );
 
-        let (cross_shard_sender, cross_shard_receiver) = mpsc::channel(self.cross_shard_queue_size);
+        let cross_shard_queue = Arc::new(CrossShardQueue::new(
+            self.cross_shard_queue_size,
+            self.cross_shard_queue_policy,
+        ));
         tokio::spawn(Self::forward_cross_shard_queries(
             self.network_protocol,
             self.base_address.clone(),
             self.base_port,
             self.state.shard_id,
-            cross_shard_receiver,
+            self.cross_shard_connections,
+            // Shards connect to the other shards of the same authority.
+            self.tls_config.client_config(&self.state.name),
+            cross_shard_queue.clone(),
         ));
 
         let buffer_size = self.buffer_size;
         let protocol = self.network_protocol;
+        let tls_config = self.tls_config.server_config();
+        let cross_shard_retry_delay = self.cross_shard_retry_period;
         let state = RunningServerState {
             server: self,
-            cross_shard_sender,
+            cross_shard_queue,
+            cross_shard_retry_delay,
+            next_cross_shard_retry: None,
         };
         // Launch server for the appropriate protocol.
-        protocol.spawn_server(&address, state, buffer_size).await
+        protocol
+            .spawn_server(&address, state, buffer_size, tls_config)
+            .await
+    }
+}
+
+/// Distribution of delays in microseconds, using power-of-two buckets.
+#[derive(Default, Clone, Debug)]
+pub struct LatencyHistogram {
+    /// Bucket `i` counts the delays `d` such that `2^(i-1) <= d < 2^i`.
+    buckets: [u64; 32],
+    count: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, delay_us: u64) {
+        let bucket = (64 - delay_us.leading_zeros()) as usize;
+        self.buckets[std::cmp::min(bucket, 31)] += 1;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Upper bound of the given percentile of the recorded delays (0 if empty).
+    pub fn percentile(&self, percent: u64) -> u64 {
+        let target = (self.count * percent + 99) / 100;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= std::cmp::max(target, 1) {
+                return (1u64 << i) - 1;
+            }
+        }
+        0
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Measures the delay between the first submission of sampled requests and the moment
+/// a threshold of responses (e.g. a quorum of votes) was received for each of them.
+/// Responses are matched by id, so they may arrive in any order.
+pub struct FinalityTracker<K> {
+    threshold: usize,
+    /// Submission time and number of responses received so far.
+    requests: HashMap<K, (Instant, usize)>,
+    histogram: LatencyHistogram,
+}
+
+impl<K: Eq + Hash> FinalityTracker<K> {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            requests: HashMap::new(),
+            histogram: LatencyHistogram::default(),
+        }
+    }
+
+    /// Record the submission of a request. Only the first submission of an id counts.
+    pub fn submit(&mut self, id: K, time: Instant) {
+        self.requests.entry(id).or_insert((time, 0));
+    }
+
+    /// Record a response. Return true if the request just reached the threshold.
+    /// Responses to unknown ids are ignored.
+    pub fn respond(&mut self, id: &K, time: Instant) -> bool {
+        match self.requests.get_mut(id) {
+            Some((submitted, responses)) => {
+                *responses += 1;
+                if *responses != self.threshold {
+                    return false;
+                }
+                let delay = time.saturating_duration_since(*submitted);
+                self.histogram.record(delay.as_micros() as u64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Delays of the requests that reached the threshold.
+    pub fn histogram(&self) -> &LatencyHistogram {
+        &self.histogram
+    }
+}
+
+/// Lock-free counters of the requests sent and the responses received by mass clients,
+/// used to report the throughput of a benchmark while it runs.
+#[derive(Default)]
+pub struct ThroughputCounters {
+    requests_sent: AtomicU64,
+    responses_received: AtomicU64,
+}
+
+impl ThroughputCounters {
+    /// Return the number of requests sent and responses received since the last call.
+    pub fn take(&self) -> (u64, u64) {
+        (
+            self.requests_sent.swap(0, Ordering::Relaxed),
+            self.responses_received.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+impl MassClientObserver for ThroughputCounters {
+    fn request_sent(&self, _request: &Bytes) {
+        self.requests_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn response_received(&self, _response: &[u8]) {
+        self.responses_received.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn now_us() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Default delay before unacknowledged cross-shard updates are sent again.
+const CROSS_SHARD_RETRY_PERIOD: Duration = Duration::from_secs(1);
+/// Largest delay between two retries, as a multiple of the retry period.
+pub const MAX_CROSS_SHARD_RETRY_BACKOFF: u32 = 64;
+
+/// Instructions for the task forwarding messages to other shards.
+enum CrossShardCommand {
+    /// Send a cross-shard update and retry until it is acknowledged.
+    Update {
+        certificate: CertifiedTransferOrder,
+        shard: ShardId,
+    },
+    /// Acknowledge a cross-shard update received from another shard.
+    Ack { buf: Vec<u8>, shard: ShardId },
+}
+
+// What to do with cross-shard messages when the queue of the forwarding task is full.
+arg_enum! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum CrossShardQueuePolicy {
+        // Wait for room in the queue, stalling the handling of other messages.
+        Block,
+        // Drop the oldest queued message. Dropped updates are sent again by later retries.
+        DropOldest,
+        // Refuse incoming messages that would produce cross-shard messages.
+        Reject,
+    }
+}
+
+/// Bounded queue of commands for the task forwarding messages to other shards.
+struct CrossShardQueue {
+    capacity: usize,
+    policy: CrossShardQueuePolicy,
+    inner: Mutex<CrossShardQueueInner>,
+    /// Notified when a command is pushed or the queue is closed.
+    pushed: Notify,
+    /// Notified when a command is popped.
+    popped: Notify,
+}
+
+#[derive(Default)]
+struct CrossShardQueueInner {
+    commands: VecDeque<CrossShardCommand>,
+    closed: bool,
+}
+
+impl CrossShardQueue {
+    fn new(capacity: usize, policy: CrossShardQueuePolicy) -> Self {
+        Self {
+            capacity: std::cmp::max(capacity, 1),
+            policy,
+            inner: Mutex::new(CrossShardQueueInner::default()),
+            pushed: Notify::new(),
+            popped: Notify::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.inner.lock().unwrap().commands.len() >= self.capacity
+    }
+
+    /// Add a command, following the policy of the queue if it is full. Commands may be
+    /// dropped safely because unacknowledged updates are always sent again.
+    async fn push(&self, command: CrossShardCommand) {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.commands.len() < self.capacity {
+                    inner.commands.push_back(command);
+                    self.pushed.notify();
+                    return;
+                }
+                match self.policy {
+                    CrossShardQueuePolicy::Block => (),
+                    CrossShardQueuePolicy::DropOldest => {
+                        warn!("Cross-shard queue is full: dropping the oldest message");
+                        inner.commands.pop_front();
+                        inner.commands.push_back(command);
+                        return;
+                    }
+                    CrossShardQueuePolicy::Reject => {
+                        warn!("Cross-shard queue is full: dropping a message");
+                        return;
+                    }
+                }
+            }
+            self.popped.notified().await;
+        }
+    }
+
+    /// Remove the oldest command. Return `None` once the queue is closed.
+    async fn pop(&self) -> Option<CrossShardCommand> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(command) = inner.commands.pop_front() {
+                    self.popped.notify();
+                    return Some(command);
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+            self.pushed.notified().await;
+        }
+    }
+
+    fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.pushed.notify();
     }
 }
 
 struct RunningServerState {
     server: Server,
-    cross_shard_sender: mpsc::Sender<(Vec<u8>, ShardId)>,
+    cross_shard_queue: Arc<CrossShardQueue>,
+    /// Delay before the next retry of the pending cross-shard updates of the state.
+    cross_shard_retry_delay: Duration,
+    /// When to send the pending cross-shard updates again, if any.
+    next_cross_shard_retry: Option<Instant>,
 }
 
-impl MessageHandler for RunningServerState {
-    fn handle_message<'a>(
-        &'a mut self,
-        buffer: &'a [u8],
-    ) -> futures::future::BoxFuture<'a, Option<Vec<u8>>> {
+impl Drop for RunningServerState {
+    fn drop(&mut self) {
+        self.cross_shard_queue.close();
+    }
+}
+
+impl RunningServerState {
+    /// Under the `Reject` policy, refuse orders that may produce cross-shard updates while
+    /// the queue is full.
+    fn check_cross_shard_queue(&self) -> Result<(), FastPayError> {
+        if self.server.cross_shard_queue_policy == CrossShardQueuePolicy::Reject
+            && self.cross_shard_queue.is_full()
+        {
+            return Err(FastPayError::CrossShardQueueFull);
+        }
+        Ok(())
+    }
+
+    async fn handle_confirmation_order(
+        &mut self,
+        confirmation_order: ConfirmationOrder,
+        verified: bool,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        self.check_cross_shard_queue()?;
+        let state = &mut self.server.state;
+        let (info, cross_shard_updates) = if verified {
+            state.handle_verified_confirmation_order(confirmation_order)?
+        } else {
+            state.handle_confirmation_order(confirmation_order)?
+        };
+        self.schedule_cross_shard_updates(cross_shard_updates).await;
+        Ok(info)
+    }
+
+    /// Send messages to other shards.
+    async fn schedule_cross_shard_updates(&mut self, cross_shard_updates: Vec<CrossShardUpdate>) {
+        for cross_shard_update in cross_shard_updates {
+            let shard = cross_shard_update.shard_id;
+            debug!(
+                "Scheduling cross shard query: {} -> {}",
+                self.server.state.shard_id, shard
+            );
+            self.cross_shard_queue
+                .push(CrossShardCommand::Update {
+                    certificate: cross_shard_update.transfer_certificate,
+                    shard,
+                })
+                .await;
+        }
+    }
+
+    /// Send again the cross-shard updates that were not acknowledged yet, doubling the
+    /// delay after each attempt until all of them are.
+    async fn retry_cross_shard_updates(&mut self) {
+        let period = self.server.cross_shard_retry_period;
+        if self.server.state.pending_cross_shard_updates.is_empty() {
+            self.cross_shard_retry_delay = period;
+            self.next_cross_shard_retry = None;
+            return;
+        }
+        let now = Instant::now();
+        match self.next_cross_shard_retry {
+            Some(deadline) if deadline <= now => (),
+            Some(_) => return,
+            None => {
+                // Leave time to the first attempt.
+                self.next_cross_shard_retry = Some(now + self.cross_shard_retry_delay);
+                return;
+            }
+        }
+        let updates: Vec<_> = self
+            .server
+            .state
+            .pending_cross_shard_updates
+            .values()
+            .cloned()
+            .collect();
+        debug!(
+            "Sending {} unacknowledged cross-shard updates again",
+            updates.len()
+        );
+        self.schedule_cross_shard_updates(updates).await;
+        self.cross_shard_retry_delay = std::cmp::min(
+            self.cross_shard_retry_delay * 2,
+            period * MAX_CROSS_SHARD_RETRY_BACKOFF,
+        );
+        self.next_cross_shard_retry = Some(now + self.cross_shard_retry_delay);
+    }
+
+    /// Handle a decoded message. If `verified` is true, the signatures of transfer orders
+    /// and certificates were already checked by a `SignatureVerifier`.
+    fn handle_deserialized_message(
+        &mut self,
+        result: Result<SerializedMessage, failure::Error>,
+        verified: bool,
+    ) -> futures::future::BoxFuture<'_, Option<Vec<u8>>> {
         Box::pin(async move {
-            let result = deserialize_message(buffer);
             let reply = match result {
                 Err(_) => Err(FastPayError::InvalidDecoding),
                 Ok(result) => {
                     match result {
-                        SerializedMessage::Order(message) => self
-                            .server
-                            .state
-                            .handle_transfer_order(*message)
-                            .map(|info| Some(serialize_info_response(&info))),
+                        SerializedMessage::Order(message) => {
+                            let state = &mut self.server.state;
+                            if verified {
+                                state.handle_verified_transfer_order(*message)
+                            } else {
+                                state.handle_transfer_order(*message)
+                            }
+                            .map(|info| Some(serialize_info_response(&info)))
+                        }
                         SerializedMessage::Cert(message) => {
                             let confirmation_order = ConfirmationOrder {
-                                transfer_certificate: message.as_ref().clone(),
+                                transfer_certificate: *message,
                             };
-                            match self
-                                .server
-                                .state
-                                .handle_confirmation_order(confirmation_order)
-                            {
-                                Ok((info, send_shard)) => {
-                                    // Send a message to other shard
-                                    if let Some(cross_shard_update) = send_shard {
-                                        let shard = cross_shard_update.shard_id;
-                                        let tmp_out = serialize_cross_shard(&message);
-                                        debug!(
-                                            "Scheduling cross shard query: {} -> {}",
-                                            self.server.state.shard_id, shard
-                                        );
-                                        self.cross_shard_sender
-                                            .send((tmp_out, shard))
-                                            .await
-                                            .expect("internal channel should not fail");
-                                    };
-
-                                    // Response
+                            self.handle_confirmation_order(confirmation_order, verified)
+                                .await
+                                .map(|info| Some(serialize_info_response(&info)))
+                        }
+                        SerializedMessage::CompactCert(message) => {
+                            match self.check_cross_shard_queue().and_then(|()| {
+                                self.server
+                                    .state
+                                    .handle_compact_confirmation_order(*message)
+                            }) {
+                                Ok((info, cross_shard_updates)) => {
+                                    self.schedule_cross_shard_updates(cross_shard_updates).await;
                                     Ok(Some(serialize_info_response(&info)))
                                 }
                                 Err(error) => Err(error),
                             }
                         }
+                        SerializedMessage::Confirmations(batch) => {
+                            // Orders are applied in sequence until the first error.
+                            let mut reply = Err(FastPayError::EmptyConfirmationBatch);
+                            for order in batch.orders {
+                                reply = self.handle_confirmation_order(order, false).await;
+                                if reply.is_err() {
+                                    break;
+                                }
+                            }
+                            reply.map(|info| Some(serialize_info_response(&info)))
+                        }
                         SerializedMessage::InfoReq(message) => self
                             .server
                             .state
                             .handle_account_info_request(*message)
                             .map(|info| Some(serialize_info_response(&info))),
+                        SerializedMessage::BatchInfoReq(message) => self
+                            .server
+                            .state
+                            .handle_batch_account_info_request(*message)
+                            .map(|response| Some(serialize_batch_info_response(&response))),
+                        SerializedMessage::SnapshotReq(message) => self
+                            .server
+                            .state
+                            .handle_account_snapshot_request(*message)
+                            .map(|vote| Some(serialize_snapshot_vote(&vote))),
+                        SerializedMessage::CommitteeReq(message) => self
+                            .server
+                            .state
+                            .handle_committee_request(*message)
+                            .map(|vote| Some(serialize_committee_vote(&vote))),
+                        SerializedMessage::EquivocationProofReq(message) => self
+                            .server
+                            .state
+                            .handle_equivocation_proof_request(*message)
+                            .map(|vote| Some(serialize_equivocation_proof_vote(&vote))),
+                        SerializedMessage::Freeze(message) => self
+                            .server
+                            .state
+                            .handle_freeze_order(*message)
+                            .map(|info| Some(serialize_info_response(&info))),
+                        SerializedMessage::CrossShard(_)
+                            if self.check_cross_shard_queue().is_err() =>
+                        {
+                            // The sender will retry until the update is acknowledged.
+                            warn!("Cross-shard queue is full: ignoring cross-shard query");
+                            Ok(None) // Nothing to reply
+                        }
                         SerializedMessage::CrossShard(message) => {
+                            let delay = now_us().saturating_sub(message.timestamp_us);
+                            debug!(
+                                "Received cross shard query: {} -> {} after {} us",
+                                self.server.state.which_shard(
+                                    &message.transfer_certificate.value.transfer.sender
+                                ),
+                                self.server.state.shard_id,
+                                delay
+                            );
+                            self.server.cross_shard_latency.record(delay);
                             match self
                                 .server
                                 .state
-                                .handle_cross_shard_recipient_commit(*message)
+                                .handle_cross_shard_recipient_commit(message.transfer_certificate)
                             {
-                                Ok(_) => Ok(None), // Nothing to reply
+                                Ok(ack) => {
+                                    // Acknowledge the update to the shard of the sender.
+                                    self.cross_shard_queue
+                                        .push(CrossShardCommand::Ack {
+                                            buf: serialize_cross_shard_ack(&ack),
+                                            shard: ack.shard_id,
+                                        })
+                                        .await;
+                                    Ok(None) // Nothing to reply
+                                }
                                 Err(error) => {
                                     error!("Failed to handle cross-shard query: {}", error);
                                     Ok(None) // Nothing to reply
                                 }
                             }
                         }
+                        SerializedMessage::CrossShardAck(message) => {
+                            if let Err(error) = self.server.state.handle_cross_shard_ack(*message) {
+                                error!("Failed to handle cross-shard ack: {}", error);
+                            }
+                            Ok(None) // Nothing to reply
+                        }
+                        SerializedMessage::Ping => {
+                            Ok(Some(serialize_health_response(&self.server.health())))
+                        }
                         _ => Err(FastPayError::UnexpectedMessage),
                     }
                 }
@@ -202,6 +702,16 @@ impl MessageHandler for RunningServerState {
                     self.server.state.shard_id,
                     self.server.packets_processed
                 );
+                let latency = &mut self.server.cross_shard_latency;
+                if latency.count() > 0 {
+                    info!(
+                        "Cross-shard queries received: {}, delay p50 <= {} us, p99 <= {} us",
+                        latency.count(),
+                        latency.percentile(50),
+                        latency.percentile(99)
+                    );
+                    latency.reset();
+                }
             }
 
             match reply {
@@ -216,6 +726,138 @@ impl MessageHandler for RunningServerState {
     }
 }
 
+impl MessageHandler for RunningServerState {
+    type Verified = SerializedMessage;
+
+    fn handle_message<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+    ) -> futures::future::BoxFuture<'a, Option<Vec<u8>>> {
+        self.handle_deserialized_message(deserialize_message(buffer), false)
+    }
+
+    fn verification_pool(&self) -> Option<VerificationPool<SerializedMessage>> {
+        if self.server.verification_threads == 0 {
+            return None;
+        }
+        let verifier = SignatureVerifier {
+            committee: self.server.state.committee.clone(),
+        };
+        Some(VerificationPool::new(
+            Arc::new(verifier),
+            self.server.verification_threads,
+        ))
+    }
+
+    fn rate_limiter(&self) -> Option<PeerRateLimiter> {
+        let limit = self.server.peer_rate_limit?;
+        Some(PeerRateLimiter::new(
+            limit,
+            is_order_or_certificate,
+            self.server.state.clock.clone(),
+        ))
+    }
+
+    fn handle_verified_message(
+        &mut self,
+        message: SerializedMessage,
+    ) -> futures::future::BoxFuture<'_, Option<Vec<u8>>> {
+        self.handle_deserialized_message(Ok(message), true)
+    }
+
+    fn timer_period(&self) -> Option<Duration> {
+        Some(self.server.cross_shard_retry_period)
+    }
+
+    fn handle_timer(&mut self) -> futures::future::BoxFuture<'_, ()> {
+        Box::pin(self.retry_cross_shard_updates())
+    }
+}
+
+/// Check the signatures of transfer orders and certificates, independently of the state
+/// of accounts. Other messages are left to the server.
+struct SignatureVerifier {
+    committee: Committee,
+}
+
+impl MessageVerifier for SignatureVerifier {
+    type Message = SerializedMessage;
+
+    fn verify(&self, buffer: &[u8]) -> Result<SerializedMessage, Option<Vec<u8>>> {
+        let message = deserialize_message(buffer)
+            .map_err(|_| Some(serialize_error(&FastPayError::InvalidDecoding)))?;
+        let result = match &message {
+            SerializedMessage::Order(order) => order.check_signature(),
+            SerializedMessage::Cert(certificate) => certificate.check(&self.committee),
+            _ => Err(FastPayError::UnexpectedMessage),
+        };
+        match result {
+            Ok(()) => Ok(message),
+            Err(error) => {
+                warn!("User query failed verification: {}", error);
+                Err(Some(serialize_error(&error)))
+            }
+        }
+    }
+
+    fn needs_verification(&self, buffer: &[u8]) -> bool {
+        is_order_or_certificate(buffer)
+    }
+}
+
+/// Create the clients used to reach authorities, e.g. to choose the transport at runtime.
+pub trait AuthorityClientFactory {
+    fn make(&self, config: &AuthorityConfig) -> BoxedAuthorityClient;
+}
+
+/// Reach authorities over the network protocol of their configuration.
+#[derive(Clone)]
+pub struct ClientFactory {
+    buffer_size: usize,
+    send_timeout: std::time::Duration,
+    recv_timeout: std::time::Duration,
+    tls_config: TlsConfig,
+}
+
+impl ClientFactory {
+    pub fn new(
+        buffer_size: usize,
+        send_timeout: std::time::Duration,
+        recv_timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            buffer_size,
+            send_timeout,
+            recv_timeout,
+            tls_config: TlsConfig::default(),
+        }
+    }
+
+    /// Use the given certificates to authenticate authorities over TLS.
+    pub fn with_tls_config(mut self, config: TlsConfig) -> Self {
+        self.tls_config = config;
+        self
+    }
+}
+
+impl AuthorityClientFactory for ClientFactory {
+    fn make(&self, config: &AuthorityConfig) -> BoxedAuthorityClient {
+        let client = Client::new(
+            config.network_protocol,
+            config.host.clone(),
+            config.base_port,
+            config.num_shards,
+            self.buffer_size,
+            self.send_timeout,
+            self.recv_timeout,
+        );
+        match self.tls_config.client_config(&config.address) {
+            Some(tls_config) => Box::new(client.with_tls_config(tls_config)),
+            None => Box::new(client),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
     network_protocol: NetworkProtocol,
@@ -225,6 +867,7 @@ pub struct Client {
     buffer_size: usize,
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
+    tls_config: Option<Arc<ClientConfig>>,
 }
 
 impl Client {
@@ -245,9 +888,16 @@ impl Client {
             buffer_size,
             send_timeout,
             recv_timeout,
+            tls_config: None,
         }
     }
 
+    /// Only accept the TLS certificate expected by the given configuration.
+    pub fn with_tls_config(mut self, config: Arc<ClientConfig>) -> Self {
+        self.tls_config = Some(config);
+        self
+    }
+
     async fn send_recv_bytes_internal(
         &mut self,
         shard: ShardId,
@@ -256,7 +906,7 @@ impl Client {
         let address = format!("{}:{}", self.base_address, self.base_port + shard);
         let mut stream = self
             .network_protocol
-            .connect(address, self.buffer_size)
+            .connect(address, self.buffer_size, self.tls_config.clone())
             .await?;
         // Send message
         time::timeout(self.send_timeout, stream.write_data(&buf)).await??;
@@ -264,11 +914,11 @@ impl Client {
         time::timeout(self.recv_timeout, stream.read_data()).await?
     }
 
-    pub async fn send_recv_bytes(
+    async fn send_recv_message(
         &mut self,
         shard: ShardId,
         buf: Vec<u8>,
-    ) -> Result<AccountInfoResponse, FastPayError> {
+    ) -> Result<SerializedMessage, FastPayError> {
         match self.send_recv_bytes_internal(shard, buf).await {
             Err(error) => Err(FastPayError::ClientIoError {
                 error: format!("{}", error),
@@ -276,14 +926,32 @@ impl Client {
             Ok(response) => {
                 // Parse reply
                 match deserialize_message(&response[..]) {
-                    Ok(SerializedMessage::InfoResp(resp)) => Ok(*resp),
                     Ok(SerializedMessage::Error(error)) => Err(*error),
+                    Ok(message) => Ok(message),
                     Err(_) => Err(FastPayError::InvalidDecoding),
-                    _ => Err(FastPayError::UnexpectedMessage),
                 }
             }
         }
     }
+
+    pub async fn send_recv_bytes(
+        &mut self,
+        shard: ShardId,
+        buf: Vec<u8>,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        match self.send_recv_message(shard, buf).await? {
+            SerializedMessage::InfoResp(resp) => Ok(*resp),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
+
+    /// Check that the given shard is up and report its progress.
+    pub async fn health(&mut self, shard: ShardId) -> Result<HealthResponse, FastPayError> {
+        match self.send_recv_message(shard, serialize_ping()).await? {
+            SerializedMessage::Pong(response) => Ok(*response),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
 }
 
 impl AuthorityClient for Client {
@@ -314,6 +982,71 @@ impl AuthorityClient for Client {
         })
     }
 
+    /// Confirm several transfers with one request per shard. Falls back to sending
+    /// orders one by one if the authority does not understand batches.
+    fn handle_confirmation_orders(
+        &mut self,
+        orders: Vec<ConfirmationOrder>,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Box::pin(async move {
+            // Split the orders into consecutive runs handled by the same shard.
+            let mut runs: Vec<(ShardId, Vec<ConfirmationOrder>)> = Vec::new();
+            for order in orders {
+                let shard = AuthorityState::get_shard(
+                    self.num_shards,
+                    &order.transfer_certificate.value.transfer.sender,
+                );
+                match runs.last_mut() {
+                    Some((last_shard, run))
+                        if *last_shard == shard
+                            && run.len() < MAX_CONFIRMATION_ORDERS_PER_BATCH =>
+                    {
+                        run.push(order)
+                    }
+                    _ => runs.push((shard, vec![order])),
+                }
+            }
+            let mut info = None;
+            for (shard, run) in runs {
+                let result = self
+                    .send_recv_bytes(shard, serialize_confirmation_orders(&run))
+                    .await;
+                info = Some(match result {
+                    Err(FastPayError::InvalidDecoding) | Err(FastPayError::UnexpectedMessage) => {
+                        handle_confirmation_orders_one_by_one(self, run).await?
+                    }
+                    result => result?,
+                });
+            }
+            info.ok_or(FastPayError::EmptyConfirmationBatch)
+        })
+    }
+
+    /// Confirm a transfer without the transfer order. Falls back to the full confirmation
+    /// order if the authority does not know the transfer order or compact orders.
+    fn handle_compact_confirmation_order(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Box::pin(async move {
+            let shard =
+                AuthorityState::get_shard(self.num_shards, &certificate.value.transfer.sender);
+            let order = CompactConfirmationOrder::new(&certificate);
+            match self
+                .send_recv_bytes(shard, serialize_compact_cert(&order))
+                .await
+            {
+                Err(FastPayError::UnknownTransferOrder)
+                | Err(FastPayError::InvalidDecoding)
+                | Err(FastPayError::UnexpectedMessage) => {
+                    self.send_recv_bytes(shard, serialize_cert(&certificate))
+                        .await
+                }
+                result => result,
+            }
+        })
+    }
+
     /// Handle information requests for this account.
     fn handle_account_info_request(
         &mut self,
@@ -325,6 +1058,121 @@ impl AuthorityClient for Client {
                 .await
         })
     }
+
+    /// Send one batch to each shard involved, and answer in the order of the request.
+    fn handle_batch_account_info_request(
+        &mut self,
+        request: BatchAccountInfoRequest,
+    ) -> AsyncResult<BatchAccountInfoResponse, FastPayError> {
+        Box::pin(async move {
+            let mut senders_by_shard = BTreeMap::new();
+            for sender in &request.senders {
+                let shard = AuthorityState::get_shard(self.num_shards, sender);
+                senders_by_shard
+                    .entry(shard)
+                    .or_insert_with(Vec::new)
+                    .push(*sender);
+            }
+            let mut answers = HashMap::new();
+            for (shard, senders) in senders_by_shard {
+                for senders in senders.chunks(MAX_ACCOUNTS_PER_INFO_BATCH) {
+                    let batch = BatchAccountInfoRequest {
+                        senders: senders.to_vec(),
+                    };
+                    match self
+                        .send_recv_message(shard, serialize_batch_info_request(&batch))
+                        .await?
+                    {
+                        SerializedMessage::BatchInfoResp(response) => {
+                            answers.extend(response.responses)
+                        }
+                        _ => return Err(FastPayError::UnexpectedMessage),
+                    }
+                }
+            }
+            let responses = request
+                .senders
+                .into_iter()
+                .map(|sender| {
+                    let answer = answers
+                        .get(&sender)
+                        .cloned()
+                        .unwrap_or(Err(FastPayError::UnexpectedMessage));
+                    (sender, answer)
+                })
+                .collect();
+            Ok(BatchAccountInfoResponse { responses })
+        })
+    }
+
+    /// Obtain a signed snapshot of this account.
+    fn handle_account_snapshot_request(
+        &mut self,
+        request: AccountSnapshotRequest,
+    ) -> AsyncResult<SignedAccountSnapshot, FastPayError> {
+        Box::pin(async move {
+            let shard = AuthorityState::get_shard(self.num_shards, &request.account);
+            match self
+                .send_recv_message(shard, serialize_snapshot_request(&request))
+                .await?
+            {
+                SerializedMessage::SnapshotVote(vote) => Ok(*vote),
+                _ => Err(FastPayError::UnexpectedMessage),
+            }
+        })
+    }
+
+    /// Obtain the committee of the authority, as signed by its first shard.
+    fn handle_committee_request(
+        &mut self,
+        request: CommitteeRequest,
+    ) -> AsyncResult<SignedCommittee, FastPayError> {
+        Box::pin(async move {
+            match self
+                .send_recv_message(0, serialize_committee_request(&request))
+                .await?
+            {
+                SerializedMessage::CommitteeVote(vote) => Ok(*vote),
+                _ => Err(FastPayError::UnexpectedMessage),
+            }
+        })
+    }
+
+    /// Obtain the evidence of equivocation of an account.
+    fn handle_equivocation_proof_request(
+        &mut self,
+        request: EquivocationProofRequest,
+    ) -> AsyncResult<SignedEquivocationProof, FastPayError> {
+        Box::pin(async move {
+            let shard = AuthorityState::get_shard(self.num_shards, &request.sender);
+            match self
+                .send_recv_message(shard, serialize_equivocation_proof_request(&request))
+                .await?
+            {
+                SerializedMessage::EquivocationProofVote(vote) => Ok(*vote),
+                _ => Err(FastPayError::UnexpectedMessage),
+            }
+        })
+    }
+
+    /// Freeze or unfreeze the outgoing transfers of an account.
+    fn handle_freeze_order(
+        &mut self,
+        order: FreezeOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Box::pin(async move {
+            let shard = AuthorityState::get_shard(self.num_shards, &order.freeze.account);
+            self.send_recv_bytes(shard, serialize_freeze_order(&order))
+                .await
+        })
+    }
+}
+
+/// Callbacks invoked by a `MassClient` for every request sent and response received.
+pub trait MassClientObserver: Send + Sync {
+    fn request_sent(&self, request: &Bytes);
+
+    fn response_received(&self, response: &[u8]);
 }
 
 #[derive(Clone)]
@@ -336,6 +1184,8 @@ pub struct MassClient {
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
     max_in_flight: u64,
+    observers: Vec<Arc<dyn MassClientObserver>>,
+    tls_config: Option<Arc<ClientConfig>>,
 }
 
 impl MassClient {
@@ -356,18 +1206,36 @@ impl MassClient {
             send_timeout,
             recv_timeout,
             max_in_flight,
+            observers: Vec::new(),
+            tls_config: None,
         }
     }
 
-    async fn run_shard(&self, shard: u32, requests: Vec<Bytes>) -> Result<Vec<Bytes>, io::Error> {
+    /// Only accept the TLS certificate expected by the given configuration.
+    pub fn with_tls_config(mut self, config: Arc<ClientConfig>) -> Self {
+        self.tls_config = Some(config);
+        self
+    }
+
+    /// Add an observer of the requests and responses of this client.
+    pub fn with_observer(mut self, observer: Arc<dyn MassClientObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    async fn run_shard(
+        &self,
+        shard: u32,
+        requests: Vec<Bytes>,
+    ) -> Result<ShardResponses, io::Error> {
         let address = format!("{}:{}", self.base_address, self.base_port + shard);
         let mut stream = self
             .network_protocol
-            .connect(address, self.buffer_size)
+            .connect(address, self.buffer_size, self.tls_config.clone())
             .await?;
         let mut requests = requests.iter();
         let mut in_flight: u64 = 0;
-        let mut responses = Vec::new();
+        let mut responses = ShardResponses::new(shard);
 
         loop {
             while in_flight < self.max_in_flight {
@@ -382,9 +1250,21 @@ impl MassClient {
                     Some(request) => request,
                 };
                 let status = time::timeout(self.send_timeout, stream.write_data(request)).await;
-                if let Err(error) = status {
-                    error!("Failed to send request: {}", error);
-                    continue;
+                match status {
+                    Ok(Ok(())) => (),
+                    Ok(Err(error)) => {
+                        error!("Failed to send request: {}", error);
+                        responses.send_failures += 1;
+                        continue;
+                    }
+                    Err(error) => {
+                        error!("Failed to send request: {}", error);
+                        responses.send_failures += 1;
+                        continue;
+                    }
+                }
+                for observer in &self.observers {
+                    observer.request_sent(request);
                 }
                 in_flight += 1;
             }
@@ -393,12 +1273,17 @@ impl MassClient {
             }
             match time::timeout(self.recv_timeout, stream.read_data()).await {
                 Ok(Ok(buffer)) => {
-                    in_flight -= 1;
-                    responses.push(Bytes::from(buffer));
+                    // Responses may still arrive after a timeout.
+                    in_flight = in_flight.saturating_sub(1);
+                    for observer in &self.observers {
+                        observer.response_received(&buffer);
+                    }
+                    responses.responses.push(Bytes::from(buffer));
                 }
                 Ok(Err(error)) => {
                     if error.kind() == io::ErrorKind::UnexpectedEof {
                         info!("Socket closed by server");
+                        responses.send_failures += in_flight + requests.len() as u64;
                         return Ok(responses);
                     }
                     error!("Received error response: {}", error);
@@ -408,44 +1293,127 @@ impl MassClient {
                         "Timeout while receiving response: {} (in flight: {})",
                         error, in_flight
                     );
+                    // Give up on the requests in flight.
+                    responses.timeouts += in_flight;
+                    in_flight = 0;
                 }
             }
         }
     }
 
     /// Spin off one task for each shard based on this authority client.
-    pub fn run<I>(&self, sharded_requests: I) -> impl futures::stream::Stream<Item = Vec<Bytes>>
+    pub fn run<I>(&self, sharded_requests: I) -> MassClientHandle
     where
         I: IntoIterator<Item = (ShardId, Vec<Bytes>)>,
     {
-        let handles = futures::stream::FuturesUnordered::new();
+        let tasks = futures::stream::FuturesUnordered::new();
+        let mut abort_handles = Vec::new();
         for (shard, requests) in sharded_requests {
             let client = self.clone();
-            handles.push(
-                tokio::spawn(async move {
-                    info!(
-                        "Sending {} requests to {}:{} (shard {})",
-                        client.network_protocol,
-                        client.base_address,
-                        client.base_port + shard,
-                        shard
-                    );
-                    let responses = client
-                        .run_shard(shard, requests)
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            abort_handles.push(abort_handle);
+            tasks.push(tokio::spawn(async move {
+                info!(
+                    "Sending {} requests to {}:{} (shard {})",
+                    client.network_protocol,
+                    client.base_address,
+                    client.base_port + shard,
+                    shard
+                );
+                let num_requests = requests.len() as u64;
+                // Aborting drops the connection of this shard.
+                let responses =
+                    match Abortable::new(client.run_shard(shard, requests), abort_registration)
                         .await
-                        .unwrap_or_else(|_| Vec::new());
-                    info!(
-                        "Done sending {} requests to {}:{} (shard {})",
-                        client.network_protocol,
-                        client.base_address,
-                        client.base_port + shard,
-                        shard
-                    );
-                    responses
-                })
-                .then(|x| async { x.unwrap_or_else(|_| Vec::new()) }),
-            );
+                    {
+                        Ok(Ok(responses)) => responses,
+                        Ok(Err(error)) => {
+                            error!("Failed to connect to shard {}: {}", shard, error);
+                            let mut responses = ShardResponses::new(shard);
+                            responses.send_failures = num_requests;
+                            responses
+                        }
+                        Err(Aborted) => {
+                            info!("Aborted sending requests to shard {}", shard);
+                            ShardResponses::new(shard)
+                        }
+                    };
+                info!(
+                    "Done sending {} requests to {}:{} (shard {})",
+                    client.network_protocol,
+                    client.base_address,
+                    client.base_port + shard,
+                    shard
+                );
+                responses
+            }));
+        }
+        MassClientHandle {
+            tasks,
+            abort_handles,
         }
-        handles
+    }
+}
+
+/// The responses received from one shard by a `MassClient`, and the number of requests
+/// that were lost.
+#[derive(Debug, Default)]
+pub struct ShardResponses {
+    pub shard: ShardId,
+    pub responses: Vec<Bytes>,
+    /// Requests that could not be sent, or whose connection was closed before a response.
+    pub send_failures: u64,
+    /// Requests given up after waiting for a response for the receive timeout.
+    pub timeouts: u64,
+}
+
+impl ShardResponses {
+    pub fn new(shard: ShardId) -> Self {
+        Self {
+            shard,
+            ..Self::default()
+        }
+    }
+
+    /// Number of responses reporting an error instead of a result.
+    pub fn error_replies(&self) -> usize {
+        self.responses
+            .iter()
+            .filter(|buffer| {
+                matches!(
+                    deserialize_message(&buffer[..]),
+                    Ok(SerializedMessage::Error(_))
+                )
+            })
+            .count()
+    }
+}
+
+/// The running tasks of a `MassClient`. Yields the responses of each shard.
+pub struct MassClientHandle {
+    tasks: futures::stream::FuturesUnordered<tokio::task::JoinHandle<ShardResponses>>,
+    abort_handles: Vec<AbortHandle>,
+}
+
+impl MassClientHandle {
+    /// Stop sending requests, close all connections and wait for the tasks to finish.
+    pub async fn shutdown(self) {
+        for handle in &self.abort_handles {
+            handle.abort();
+        }
+        self.tasks.for_each(|_| async {}).await;
+    }
+}
+
+impl futures::stream::Stream for MassClientHandle {
+    type Item = ShardResponses;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.tasks
+            .poll_next_unpin(cx)
+            .map(|x| x.map(|result| result.unwrap_or_default()))
     }
 }
@@ -3,7 +3,7 @@
 
 #![deny(warnings)]
 
-use fastpay::{config::*, network, transport};
+use fastpay::{config::*, network, tls, transport};
 use fastpay_core::{authority::*, base_types::*, committee::Committee};
 
 use futures::future::join_all;
@@ -16,18 +16,20 @@ fn make_shard_server(
     local_ip_addr: &str,
     server_config_path: &str,
     committee_config_path: &str,
-    initial_accounts_config_path: &str,
+    genesis_config_path: &str,
     buffer_size: usize,
     cross_shard_queue_size: usize,
+    cross_shard_queue_policy: network::CrossShardQueuePolicy,
+    cross_shard_connections: usize,
+    sign_receipts: bool,
     shard: u32,
 ) -> network::Server {
     let server_config =
         AuthorityServerConfig::read(server_config_path).expect("Fail to read server config");
     let committee_config =
         CommitteeConfig::read(committee_config_path).expect("Fail to read committee config");
-    let initial_accounts_config = InitialStateConfig::read(initial_accounts_config_path)
-        .expect("Fail to read initial account config");
-
+    let genesis_config =
+        GenesisConfig::read(genesis_config_path).expect("Fail to read genesis config");
     let committee = Committee::new(committee_config.voting_rights());
     let num_shards = server_config.authority.num_shards;
 
@@ -38,40 +40,45 @@ fn make_shard_server(
         shard,
         num_shards,
     );
+    state
+        .load_genesis(&genesis_config.certificate)
+        .expect("Invalid genesis config");
+    state.sign_receipts = sign_receipts;
 
-    // Load initial states
-    for (address, balance) in &initial_accounts_config.accounts {
-        if AuthorityState::get_shard(num_shards, address) != shard {
-            continue;
-        }
-        let client = AccountOffchainState {
-            balance: *balance,
-            next_sequence_number: SequenceNumber::from(0),
-            pending_confirmation: None,
-            confirmed_log: Vec::new(),
-            synchronization_log: Vec::new(),
-            received_log: Vec::new(),
-        };
-        state.accounts.insert(*address, client);
-    }
-
-    network::Server::new(
+    let mut server = network::Server::new(
         server_config.authority.network_protocol,
         local_ip_addr.to_string(),
         server_config.authority.base_port,
         state,
         buffer_size,
         cross_shard_queue_size,
-    )
+    );
+    server.set_cross_shard_queue_policy(cross_shard_queue_policy);
+    server.set_cross_shard_connections(cross_shard_connections);
+    if let Some(identity) = server_config
+        .tls_identity()
+        .expect("Fail to read TLS certificate or key")
+    {
+        let mut tls_config = tls::TlsConfig::default();
+        tls_config
+            .set_local_identity(server_config.authority.address, identity)
+            .expect("Invalid TLS certificate or key");
+        server.set_tls_config(tls_config);
+    }
+    server
 }
 
+#[allow(clippy::too_many_arguments)]
 fn make_servers(
     local_ip_addr: &str,
     server_config_path: &str,
     committee_config_path: &str,
-    initial_accounts_config_path: &str,
+    genesis_config_path: &str,
     buffer_size: usize,
     cross_shard_queue_size: usize,
+    cross_shard_queue_policy: network::CrossShardQueuePolicy,
+    cross_shard_connections: usize,
+    sign_receipts: bool,
 ) -> Vec<network::Server> {
     let server_config =
         AuthorityServerConfig::read(server_config_path).expect("Fail to read server config");
@@ -83,9 +90,12 @@ fn make_servers(
             local_ip_addr,
             server_config_path,
             committee_config_path,
-            initial_accounts_config_path,
+            genesis_config_path,
             buffer_size,
             cross_shard_queue_size,
+            cross_shard_queue_policy,
+            cross_shard_connections,
+            sign_receipts,
             shard,
         ))
     }
@@ -120,26 +130,46 @@ enum ServerCommands {
         #[structopt(long, default_value = "1000")]
         cross_shard_queue_size: usize,
 
+        /// What to do when the queue of cross shards messages is full: Block, DropOldest, or Reject
+        #[structopt(long, default_value = "Block")]
+        cross_shard_queue_policy: network::CrossShardQueuePolicy,
+
+        /// Number of connections to each other shard used in parallel to send cross shards messages
+        #[structopt(long, default_value = "1")]
+        cross_shard_connections: usize,
+
         /// Path to the file containing the public description of all authorities in this FastPay committee
         #[structopt(long)]
         committee: String,
 
-        /// Path to the file describing the initial user accounts
+        /// Path to the genesis state signed by a quorum of the committee (see sign_genesis)
         #[structopt(long)]
-        initial_accounts: String,
+        genesis: String,
 
         /// Runs a specific shard (from 0 to shards-1)
         #[structopt(long)]
         shard: Option<u32>,
+
+        /// Sign a receipt in response to each confirmation order
+        #[structopt(long)]
+        sign_receipts: bool,
     },
 
     /// Generate a new server configuration and output its public description
     #[structopt(name = "generate")]
     Generate {
-        /// Chooses a network protocol between Udp and Tcp
+        /// Chooses a network protocol between Udp, Tcp, and Tls
         #[structopt(long, default_value = "Udp")]
         protocol: transport::NetworkProtocol,
 
+        /// Path to the PEM certificate presented to clients (Tls only)
+        #[structopt(long)]
+        tls_certificate: Option<String>,
+
+        /// Path to the PEM (PKCS8) private key of the certificate (Tls only)
+        #[structopt(long)]
+        tls_key: Option<String>,
+
         /// Sets the public name of the host
         #[structopt(long)]
         host: String,
@@ -152,6 +182,22 @@ enum ServerCommands {
         #[structopt(long)]
         shards: u32,
     },
+
+    /// Add the signature of this authority to the genesis state, creating it if needed
+    #[structopt(name = "sign_genesis")]
+    SignGenesis {
+        /// Path to the file describing the initial user accounts
+        #[structopt(long)]
+        initial_accounts: String,
+
+        /// Path to the genesis state shared by all authorities
+        #[structopt(long)]
+        genesis: String,
+
+        /// Account credited with transfer fees (fees are burnt otherwise)
+        #[structopt(long)]
+        fee_recipient: Option<String>,
+    },
 }
 
 fn main() {
@@ -164,9 +210,12 @@ fn main() {
         ServerCommands::Run {
             buffer_size,
             cross_shard_queue_size,
+            cross_shard_queue_policy,
+            cross_shard_connections,
             committee,
-            initial_accounts,
+            genesis,
             shard,
+            sign_receipts,
         } => {
             // Run the server
             let servers = match shard {
@@ -176,9 +225,12 @@ fn main() {
                         "0.0.0.0", // Allow local IP address to be different from the public one.
                         server_config_path,
                         &committee,
-                        &initial_accounts,
+                        &genesis,
                         buffer_size,
                         cross_shard_queue_size,
+                        cross_shard_queue_policy,
+                        cross_shard_connections,
+                        sign_receipts,
                         shard,
                     );
                     vec![server]
@@ -189,9 +241,12 @@ fn main() {
                         "0.0.0.0", // Allow local IP address to be different from the public one.
                         server_config_path,
                         &committee,
-                        &initial_accounts,
+                        &genesis,
                         buffer_size,
                         cross_shard_queue_size,
+                        cross_shard_queue_policy,
+                        cross_shard_connections,
+                        sign_receipts,
                     )
                 }
             };
@@ -217,24 +272,70 @@ fn main() {
 
         ServerCommands::Generate {
             protocol,
+            tls_certificate,
+            tls_key,
             host,
             port,
             shards,
         } => {
-            let (address, key) = get_key_pair();
+            let (address, key) = get_authority_key_pair();
             let authority = AuthorityConfig {
                 network_protocol: protocol,
                 address,
                 host,
                 base_port: port,
                 num_shards: shards,
+                tls_certificate,
+            };
+            let server = AuthorityServerConfig {
+                authority,
+                key,
+                tls_key,
             };
-            let server = AuthorityServerConfig { authority, key };
             server
                 .write(server_config_path)
                 .expect("Unable to write server config file");
             info!("Wrote server config file");
             server.authority.print();
         }
+
+        ServerCommands::SignGenesis {
+            initial_accounts,
+            genesis,
+            fee_recipient,
+        } => {
+            let fee_recipient = fee_recipient.map(|address| {
+                decode_address(&address).expect("Failed to decode fee recipient's address")
+            });
+            let server_config = AuthorityServerConfig::read(server_config_path)
+                .expect("Fail to read server config");
+            let initial_accounts_config = InitialStateConfig::read(&initial_accounts)
+                .expect("Fail to read initial account config");
+            let mut genesis_config = match GenesisConfig::read(&genesis) {
+                Ok(config) => {
+                    assert_eq!(
+                        config.certificate.value.accounts, initial_accounts_config.accounts,
+                        "Genesis state does not match the initial accounts"
+                    );
+                    assert_eq!(
+                        config.certificate.value.fee_recipient, fee_recipient,
+                        "Genesis state does not match the fee recipient"
+                    );
+                    config
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    GenesisConfig::new(initial_accounts_config.accounts, fee_recipient)
+                }
+                Err(error) => panic!("Fail to read genesis config: {}", error),
+            };
+            genesis_config.sign(server_config.authority.address, &server_config.key);
+            genesis_config
+                .write(&genesis)
+                .expect("Unable to write genesis config file");
+            info!(
+                "Signed genesis state ({} signatures)",
+                genesis_config.certificate.signatures.len()
+            );
+        }
     }
 }
@@ -0,0 +1,143 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use fastpay_core::base_types::AuthorityName;
+use log::*;
+use rustls::{
+    internal::pemfile, Certificate, ClientConfig, NoClientAuth, PrivateKey, RootCertStore,
+    ServerCertVerified, ServerCertVerifier, ServerConfig, TLSError,
+};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader},
+    sync::Arc,
+};
+
+/// The server name used during TLS handshakes. Authorities are identified by their
+/// certificate rather than by a DNS name, so this value is never checked.
+const TLS_SERVER_NAME: &str = "fastpay";
+
+/// The certificate chain and private key presented by a FastPay authority.
+pub struct TlsIdentity {
+    certificates: Vec<Certificate>,
+    key: PrivateKey,
+}
+
+impl TlsIdentity {
+    pub fn new(certificates: Vec<Certificate>, key: PrivateKey) -> Self {
+        Self { certificates, key }
+    }
+
+    /// Read a PEM-encoded certificate chain and PKCS8 private key.
+    pub fn read(certificate_path: &str, key_path: &str) -> Result<Self, io::Error> {
+        let certificates = read_certificates(certificate_path)?;
+        let file = File::open(key_path)?;
+        let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(file))
+            .map_err(|()| invalid_data("Invalid PKCS8 private key"))?;
+        match keys.pop() {
+            Some(key) => Ok(Self::new(certificates, key)),
+            None => Err(invalid_data("Missing PKCS8 private key")),
+        }
+    }
+}
+
+/// Read a PEM-encoded certificate chain.
+pub fn read_certificates(path: &str) -> Result<Vec<Certificate>, io::Error> {
+    let file = File::open(path)?;
+    let certificates = pemfile::certs(&mut BufReader::new(file))
+        .map_err(|()| invalid_data("Invalid PEM certificate"))?;
+    if certificates.is_empty() {
+        return Err(invalid_data("Missing PEM certificate"));
+    }
+    Ok(certificates)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// TLS settings of the servers and clients of a process.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// Configuration used by the local servers, if any.
+    server_config: Option<Arc<ServerConfig>>,
+    /// Certificate expected from each known authority.
+    trusted_authorities: BTreeMap<AuthorityName, Certificate>,
+}
+
+impl TlsConfig {
+    /// Set the identity presented by the servers of the given authority. The servers
+    /// also trust this identity when they connect to each other.
+    pub fn set_local_identity(
+        &mut self,
+        name: AuthorityName,
+        identity: TlsIdentity,
+    ) -> Result<(), io::Error> {
+        let certificate = identity.certificates[0].clone();
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(identity.certificates, identity.key)
+            .map_err(|error| invalid_data(&error.to_string()))?;
+        self.server_config = Some(Arc::new(config));
+        self.trust_authority(name, certificate);
+        Ok(())
+    }
+
+    /// Accept the given (end-entity) certificate as the identity of the given authority.
+    pub fn trust_authority(&mut self, name: AuthorityName, certificate: Certificate) {
+        self.trusted_authorities.insert(name, certificate);
+    }
+
+    /// The configuration of the local servers, if an identity was set.
+    pub fn server_config(&self) -> Option<Arc<ServerConfig>> {
+        self.server_config.clone()
+    }
+
+    /// A configuration for connecting to the given authority, if its certificate is known.
+    /// Connections only succeed if the server presents this certificate.
+    pub fn client_config(&self, name: &AuthorityName) -> Option<Arc<ClientConfig>> {
+        let verifier = AuthorityCertVerifier {
+            name: *name,
+            certificate: self.trusted_authorities.get(name)?.clone(),
+        };
+        let mut config = ClientConfig::new();
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        Some(Arc::new(config))
+    }
+}
+
+pub(crate) fn server_name() -> webpki::DNSNameRef<'static> {
+    webpki::DNSNameRef::try_from_ascii_str(TLS_SERVER_NAME).expect("valid DNS name")
+}
+
+/// Only accept servers presenting the certificate of the authority being dialed.
+/// The TLS handshake then proves that the server owns the corresponding private key.
+struct AuthorityCertVerifier {
+    name: AuthorityName,
+    certificate: Certificate,
+}
+
+impl ServerCertVerifier for AuthorityCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let certificate = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        if *certificate != self.certificate {
+            return Err(TLSError::General(format!(
+                "Certificate does not belong to authority {:?}",
+                self.name
+            )));
+        }
+        debug!("Verified TLS certificate of authority {:?}", self.name);
+        Ok(ServerCertVerified::assertion())
+    }
+}
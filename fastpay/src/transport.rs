@@ -1,15 +1,34 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::tls;
 use clap::arg_enum;
-use futures::future;
+use fastpay_core::{
+    authority::{RateLimit, TokenBucket},
+    clock::Clock,
+};
+use futures::{
+    future::{self, AbortHandle, Abortable},
+    StreamExt,
+};
 use log::*;
+use rustls::{ClientConfig, ServerConfig};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, convert::TryInto, io, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    hash::Hash,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::{
     net::{TcpListener, TcpStream, UdpSocket},
     prelude::*,
+    sync::{mpsc, Semaphore},
 };
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
 
 #[cfg(test)]
 #[path = "unit_tests/transport_tests.rs"]
@@ -18,12 +37,39 @@ mod transport_tests;
 /// Suggested buffer size
 pub const DEFAULT_MAX_DATAGRAM_SIZE: &str = "65507";
 
+/// The largest payload of a UDP datagram.
+const MAX_UDP_DATAGRAM_SIZE: usize = 65507;
+/// Size of the header of each UDP fragment: message id, fragment index, and fragment count.
+const FRAGMENT_HEADER_SIZE: usize = 8;
+/// Maximal number of fragments in a UDP message.
+const MAX_FRAGMENTS: usize = 1024;
+/// How long to wait for the missing fragments of a UDP message.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bounds on the incomplete UDP messages kept in memory. A peer may always send one
+/// message of the largest size.
+const FRAGMENT_LIMITS: FragmentLimits = FragmentLimits {
+    messages_per_peer: 16,
+    bytes_per_peer: MAX_FRAGMENTS * MAX_UDP_DATAGRAM_SIZE,
+    messages: 1024,
+    bytes: 4 * MAX_FRAGMENTS * MAX_UDP_DATAGRAM_SIZE,
+};
+/// Number of messages waiting for each connection of a `ParallelDataStreamPool`.
+const PARALLEL_POOL_QUEUE_SIZE: usize = 1000;
+/// Maximal number of peers tracked by a `PeerRateLimiter`.
+const MAX_RATE_LIMITED_PEERS: usize = 10_000;
+/// Maximal number of UDP messages being verified at the same time.
+const MAX_PENDING_VERIFICATIONS: usize = 1000;
+/// Maximal number of messages of a TCP connection being verified ahead of the one being
+/// handled.
+const MAX_PIPELINED_MESSAGES: usize = 16;
+
 // Supported transport protocols.
 arg_enum! {
     #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
     pub enum NetworkProtocol {
         Udp,
         Tcp,
+        Tls,
     }
 }
 
@@ -47,8 +93,225 @@ pub trait DataStreamPool: Send {
 
 /// The handler required to create a service.
 pub trait MessageHandler {
+    /// Messages decoded and checked by the verification pool, if any.
+    type Verified: Send + 'static;
+
     fn handle_message<'a>(&'a mut self, buffer: &'a [u8])
         -> future::BoxFuture<'a, Option<Vec<u8>>>;
+
+    /// Optional checks that servers run on incoming messages before taking exclusive
+    /// access to the handler, concurrently for all peers.
+    fn verification_pool(&self) -> Option<VerificationPool<Self::Verified>> {
+        None
+    }
+
+    /// Optional limit on the rate of messages of each peer, checked by servers before
+    /// verifying or handling messages.
+    fn rate_limiter(&self) -> Option<PeerRateLimiter> {
+        None
+    }
+
+    /// Handle a message accepted by the verification pool.
+    fn handle_verified_message(
+        &mut self,
+        _message: Self::Verified,
+    ) -> future::BoxFuture<'_, Option<Vec<u8>>> {
+        Box::pin(future::ready(None))
+    }
+
+    /// How often servers call `handle_timer` between messages, if ever.
+    fn timer_period(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Periodic work of the handler, e.g. sending again unacknowledged messages.
+    fn handle_timer(&mut self) -> future::BoxFuture<'_, ()> {
+        Box::pin(future::ready(()))
+    }
+}
+
+/// Stop the task calling `handle_timer` when the server stops.
+struct TimerGuard(AbortHandle);
+
+impl Drop for TimerGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Call `handle_timer` periodically on a shared handler, until the guard is dropped.
+fn spawn_timer<S>(guarded_state: &Arc<futures::lock::Mutex<S>>, period: Duration) -> TimerGuard
+where
+    S: MessageHandler + Send + 'static,
+{
+    let guarded_state = guarded_state.clone();
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    tokio::spawn(Abortable::new(
+        async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                guarded_state.lock().await.handle_timer().await;
+            }
+        },
+        abort_registration,
+    ));
+    TimerGuard(abort_handle)
+}
+
+/// Wait for the next tick of an optional timer, or forever.
+async fn next_tick(timer: &mut Option<tokio::time::Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Stateless checks of incoming messages, typically signature verification.
+pub trait MessageVerifier: Send + Sync {
+    type Message;
+
+    /// Decode the message and run the checks skipped by `handle_verified_message`.
+    /// Rejected messages are never handled: the error is the reply to send back, if any.
+    fn verify(&self, buffer: &[u8]) -> Result<Self::Message, Option<Vec<u8>>>;
+
+    /// Cheap test run before `verify`, e.g. on the type of the message. Messages that
+    /// fail it are given to `handle_message` without using a blocking thread.
+    fn needs_verification(&self, _buffer: &[u8]) -> bool {
+        true
+    }
+}
+
+/// The outcome of a `VerificationPool` for a message.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Verification<M> {
+    /// The message does not need verification and goes to `handle_message`.
+    Skipped(Vec<u8>),
+    /// The message goes to `handle_verified_message`.
+    Accepted(M),
+    /// The message is dropped, with an optional reply.
+    Rejected(Option<Vec<u8>>),
+}
+
+/// Run a `MessageVerifier` on blocking threads, with a bounded number of messages
+/// being verified at the same time.
+pub struct VerificationPool<M> {
+    verifier: Arc<dyn MessageVerifier<Message = M>>,
+    permits: Arc<Semaphore>,
+}
+
+impl<M> Clone for VerificationPool<M> {
+    fn clone(&self) -> Self {
+        Self {
+            verifier: self.verifier.clone(),
+            permits: self.permits.clone(),
+        }
+    }
+}
+
+impl<M> VerificationPool<M>
+where
+    M: Send + 'static,
+{
+    pub fn new(verifier: Arc<dyn MessageVerifier<Message = M>>, threads: usize) -> Self {
+        Self {
+            verifier,
+            permits: Arc::new(Semaphore::new(std::cmp::max(threads, 1))),
+        }
+    }
+
+    async fn verify(self, buffer: Vec<u8>) -> Verification<M> {
+        if !self.verifier.needs_verification(&buffer) {
+            return Verification::Skipped(buffer);
+        }
+        let _permit = self.permits.acquire().await;
+        let verifier = self.verifier.clone();
+        let result = tokio::task::spawn_blocking(move || verifier.verify(&buffer))
+            .await
+            .expect("Message verifiers should not panic");
+        match result {
+            Ok(message) => Verification::Accepted(message),
+            Err(reply) => Verification::Rejected(reply),
+        }
+    }
+}
+
+/// Handle a message once verified, if needed.
+async fn handle_verification<S>(
+    state: &mut S,
+    verification: Verification<S::Verified>,
+) -> Option<Vec<u8>>
+where
+    S: MessageHandler,
+{
+    match verification {
+        Verification::Skipped(buffer) => state.handle_message(&buffer[..]).await,
+        Verification::Accepted(message) => state.handle_verified_message(message).await,
+        Verification::Rejected(reply) => reply,
+    }
+}
+
+/// Limit the rate of the messages received from each IP address (token bucket), before
+/// they are deserialized or verified. Only the messages selected by `is_limited` count,
+/// e.g. the ones that need a signature verification.
+#[derive(Clone)]
+pub struct PeerRateLimiter {
+    limit: RateLimit,
+    is_limited: fn(&[u8]) -> bool,
+    clock: Arc<dyn Clock>,
+    buckets: Arc<Mutex<PeerBuckets>>,
+}
+
+struct PeerBuckets {
+    buckets: HashMap<IpAddr, TokenBucket>,
+    /// Earliest time to look for unused buckets again once the limit of peers is reached.
+    next_cleanup: Instant,
+}
+
+impl PeerRateLimiter {
+    pub fn new(limit: RateLimit, is_limited: fn(&[u8]) -> bool, clock: Arc<dyn Clock>) -> Self {
+        let next_cleanup = clock.now();
+        Self {
+            limit,
+            is_limited,
+            clock,
+            buckets: Arc::new(Mutex::new(PeerBuckets {
+                buckets: HashMap::new(),
+                next_cleanup,
+            })),
+        }
+    }
+
+    /// Whether a message of the given peer may be processed. New peers are rejected while
+    /// too many peers have used their tokens recently.
+    pub fn allow(&self, peer: IpAddr, buffer: &[u8]) -> bool {
+        if !(self.is_limited)(buffer) {
+            return true;
+        }
+        let limit = self.limit;
+        let now = self.clock.now();
+        let mut peers = self.buckets.lock().unwrap();
+        if !peers.buckets.contains_key(&peer) && peers.buckets.len() >= MAX_RATE_LIMITED_PEERS {
+            if now < peers.next_cleanup {
+                return false;
+            }
+            // Full buckets are equivalent to missing ones.
+            peers
+                .buckets
+                .retain(|_, bucket| !bucket.is_full(limit, now));
+            peers.next_cleanup = now + limit.period;
+            if peers.buckets.len() >= MAX_RATE_LIMITED_PEERS {
+                return false;
+            }
+        }
+        peers
+            .buckets
+            .entry(peer)
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .try_take(limit, now)
+    }
 }
 
 /// The result of spawning a server is oneshot channel to kill it and a handle to track completion.
@@ -72,36 +335,64 @@ impl SpawnedServer {
 }
 
 impl NetworkProtocol {
-    /// Create a DataStream for this protocol.
+    /// Create a DataStream for this protocol. TLS connections require the configuration
+    /// of the authority being dialed (see `TlsConfig::client_config`).
     pub async fn connect(
         self,
         address: String,
         max_data_size: usize,
+        tls_config: Option<Arc<ClientConfig>>,
     ) -> Result<Box<dyn DataStream>, std::io::Error> {
         let stream: Box<dyn DataStream> = match self {
             NetworkProtocol::Udp => Box::new(UdpDataStream::connect(address, max_data_size).await?),
             NetworkProtocol::Tcp => Box::new(TcpDataStream::connect(address, max_data_size).await?),
+            NetworkProtocol::Tls => {
+                let connector = tls_connector(tls_config)?;
+                Box::new(TlsDataStream::connect(address, max_data_size, connector).await?)
+            }
         };
         Ok(stream)
     }
 
-    /// Create a DataStreamPool for this protocol.
+    /// Create a DataStreamPool for this protocol. With TLS, all destinations must present
+    /// the certificate of the same authority.
     pub async fn make_outgoing_connection_pool(
         self,
+        tls_config: Option<Arc<ClientConfig>>,
     ) -> Result<Box<dyn DataStreamPool>, std::io::Error> {
         let pool: Box<dyn DataStreamPool> = match self {
             Self::Udp => Box::new(UdpDataStreamPool::new().await?),
             Self::Tcp => Box::new(TcpDataStreamPool::new().await?),
+            Self::Tls => Box::new(TlsDataStreamPool::new(tls_connector(tls_config)?).await?),
         };
         Ok(pool)
     }
 
-    /// Run a server for this protocol and the given message handler.
+    /// Create a pool that opens `size` connections to each destination and sends over
+    /// them in parallel.
+    pub async fn make_parallel_outgoing_connection_pool(
+        self,
+        size: usize,
+        tls_config: Option<Arc<ClientConfig>>,
+    ) -> Result<ParallelDataStreamPool, std::io::Error> {
+        let mut pools = Vec::new();
+        for _ in 0..std::cmp::max(size, 1) {
+            pools.push(
+                self.make_outgoing_connection_pool(tls_config.clone())
+                    .await?,
+            );
+        }
+        Ok(ParallelDataStreamPool::new(pools))
+    }
+
+    /// Run a server for this protocol and the given message handler. TLS servers require
+    /// a configuration with a local identity (see `TlsConfig::server_config`).
     pub async fn spawn_server<S>(
         self,
         address: &str,
         state: S,
         buffer_size: usize,
+        tls_config: Option<Arc<ServerConfig>>,
     ) -> Result<SpawnedServer, std::io::Error>
     where
         S: MessageHandler + Send + 'static,
@@ -116,26 +407,327 @@ impl NetworkProtocol {
                 let listener = TcpListener::bind(address).await?;
                 tokio::spawn(Self::run_tcp_server(listener, state, receiver, buffer_size))
             }
+            Self::Tls => {
+                let tls_config = tls_config.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "No TLS identity was configured")
+                })?;
+                let acceptor = TlsAcceptor::from(tls_config);
+                let listener = TcpListener::bind(address).await?;
+                tokio::spawn(Self::run_tls_server(
+                    listener,
+                    acceptor,
+                    state,
+                    receiver,
+                    buffer_size,
+                ))
+            }
         };
         Ok(SpawnedServer { complete, handle })
     }
 }
 
+/// Distribute outgoing messages round-robin over several pools, each driven by its own
+/// task, so that a burst of messages is not serialized through a single connection.
+/// Sending only waits for a slot in the queue of the chosen pool: delivery errors are
+/// logged by the corresponding task.
+pub struct ParallelDataStreamPool {
+    senders: Vec<mpsc::Sender<(Vec<u8>, String)>>,
+    next: usize,
+}
+
+impl ParallelDataStreamPool {
+    pub fn new(pools: Vec<Box<dyn DataStreamPool>>) -> Self {
+        let senders = pools
+            .into_iter()
+            .map(|mut pool| {
+                let (sender, mut receiver) =
+                    mpsc::channel::<(Vec<u8>, String)>(PARALLEL_POOL_QUEUE_SIZE);
+                tokio::spawn(async move {
+                    while let Some((buffer, address)) = receiver.recv().await {
+                        if let Err(error) = pool.send_data_to(&buffer, &address).await {
+                            error!("Failed to send data to {}: {}", address, error);
+                        }
+                    }
+                });
+                sender
+            })
+            .collect();
+        Self { senders, next: 0 }
+    }
+
+    /// Number of parallel connections to each destination.
+    pub fn size(&self) -> usize {
+        self.senders.len()
+    }
+}
+
+impl DataStreamPool for ParallelDataStreamPool {
+    fn send_data_to<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+        address: &'a str,
+    ) -> future::BoxFuture<'a, Result<(), io::Error>> {
+        Box::pin(async move {
+            let index = self.next;
+            self.next = (self.next + 1) % self.senders.len();
+            self.senders[index]
+                .send((buffer.to_vec(), address.to_string()))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Connection task stopped"))
+        })
+    }
+}
+
+/// Split a message into UDP datagrams of at most `datagram_size` bytes.
+fn make_fragments(
+    message_id: u32,
+    buffer: &[u8],
+    datagram_size: usize,
+) -> Result<Vec<Vec<u8>>, io::Error> {
+    let chunk_size = std::cmp::min(datagram_size, MAX_UDP_DATAGRAM_SIZE)
+        .checked_sub(FRAGMENT_HEADER_SIZE)
+        .filter(|size| *size > 0)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Datagram size is too small"))?;
+    // Empty messages still need one datagram.
+    let count = std::cmp::max(1, (buffer.len() + chunk_size - 1) / chunk_size);
+    if count > MAX_FRAGMENTS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Message is too large for UDP",
+        ));
+    }
+    let fragments = (0..count)
+        .map(|index| {
+            let chunk =
+                &buffer[index * chunk_size..std::cmp::min(buffer.len(), (index + 1) * chunk_size)];
+            let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            fragment.extend_from_slice(&message_id.to_le_bytes());
+            fragment.extend_from_slice(&(index as u16).to_le_bytes());
+            fragment.extend_from_slice(&(count as u16).to_le_bytes());
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect();
+    Ok(fragments)
+}
+
+/// The fragments received so far for a UDP message.
+struct FragmentGroup {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    bytes: usize,
+    started: Instant,
+    /// Position of the message in the order of arrival.
+    sequence: u64,
+}
+
+/// The incomplete messages of one peer.
+#[derive(Default)]
+struct PeerFragments {
+    /// Message ids, oldest first.
+    messages: BTreeMap<u64, u32>,
+    bytes: usize,
+}
+
+/// Maximal number of incomplete messages and of bytes received for them.
+#[derive(Clone, Copy, Debug)]
+struct FragmentLimits {
+    messages_per_peer: usize,
+    bytes_per_peer: usize,
+    messages: usize,
+    bytes: usize,
+}
+
+/// Reassemble the UDP messages received from one or several peers.
+/// When a limit is reached, the oldest incomplete messages are dropped first.
+struct FragmentAssembler<K> {
+    groups: HashMap<(K, u32), FragmentGroup>,
+    /// Incomplete messages, oldest first.
+    order: BTreeMap<u64, (K, u32)>,
+    peers: HashMap<K, PeerFragments>,
+    next_sequence: u64,
+    bytes: usize,
+    timeout: Duration,
+    limits: FragmentLimits,
+}
+
+impl<K> FragmentAssembler<K>
+where
+    K: Copy + Eq + Hash,
+{
+    fn new(timeout: Duration, limits: FragmentLimits) -> Self {
+        Self {
+            groups: HashMap::new(),
+            order: BTreeMap::new(),
+            peers: HashMap::new(),
+            next_sequence: 0,
+            bytes: 0,
+            timeout,
+            limits,
+        }
+    }
+
+    /// Process a datagram. Returns the complete message once all its fragments were received.
+    fn insert(&mut self, peer: K, datagram: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        if datagram.len() < FRAGMENT_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing UDP fragment header",
+            ));
+        }
+        let message_id = u32::from_le_bytes(datagram[0..4].try_into().unwrap());
+        let index = u16::from_le_bytes(datagram[4..6].try_into().unwrap()) as usize;
+        let count = u16::from_le_bytes(datagram[6..8].try_into().unwrap()) as usize;
+        if index >= count || count > MAX_FRAGMENTS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid UDP fragment header",
+            ));
+        }
+        let payload = &datagram[FRAGMENT_HEADER_SIZE..];
+        if count == 1 {
+            return Ok(Some(payload.to_vec()));
+        }
+        self.remove_expired_groups();
+        let key = (peer, message_id);
+        match self.groups.get(&key) {
+            Some(group) => {
+                if group.fragments.len() != count {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Inconsistent UDP fragment count",
+                    ));
+                }
+                if group.fragments[index].is_some() {
+                    return Ok(None);
+                }
+            }
+            None => self.add_group(key, count),
+        }
+        self.make_room(key, payload.len())?;
+        let group = self.groups.get_mut(&key).unwrap();
+        group.fragments[index] = Some(payload.to_vec());
+        group.missing -= 1;
+        group.bytes += payload.len();
+        self.peers.get_mut(&peer).unwrap().bytes += payload.len();
+        self.bytes += payload.len();
+        if group.missing > 0 {
+            return Ok(None);
+        }
+        let group = self.remove_group(key).unwrap();
+        Ok(Some(
+            group.fragments.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    /// Forget about incomplete messages that are too old.
+    fn remove_expired_groups(&mut self) {
+        while let Some((_, key)) = self.order.iter().next() {
+            let key = *key;
+            if self.groups[&key].started.elapsed() < self.timeout {
+                break;
+            }
+            self.remove_group(key);
+        }
+    }
+
+    /// Start a new message, dropping older ones of the same peer or of any peer if needed.
+    fn add_group(&mut self, key: (K, u32), count: usize) {
+        let peer = self.peers.entry(key.0).or_default();
+        if peer.messages.len() >= self.limits.messages_per_peer {
+            let oldest = *peer.messages.values().next().unwrap();
+            self.remove_group((key.0, oldest));
+        }
+        if self.groups.len() >= self.limits.messages {
+            let oldest = *self.order.values().next().unwrap();
+            self.remove_group(oldest);
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.groups.insert(
+            key,
+            FragmentGroup {
+                fragments: vec![None; count],
+                missing: count,
+                bytes: 0,
+                started: Instant::now(),
+                sequence,
+            },
+        );
+        self.order.insert(sequence, key);
+        self.peers
+            .entry(key.0)
+            .or_default()
+            .messages
+            .insert(sequence, key.1);
+    }
+
+    /// Drop the oldest messages other than `key` until `size` more bytes fit within the
+    /// limits. Fails (and drops `key`) if the message alone is too large.
+    fn make_room(&mut self, key: (K, u32), size: usize) -> Result<(), io::Error> {
+        loop {
+            let peer = &self.peers[&key.0];
+            let oldest = if peer.bytes + size > self.limits.bytes_per_peer {
+                peer.messages
+                    .values()
+                    .map(|message_id| (key.0, *message_id))
+                    .find(|other| *other != key)
+            } else if self.bytes + size > self.limits.bytes {
+                self.order.values().copied().find(|other| *other != key)
+            } else {
+                return Ok(());
+            };
+            match oldest {
+                Some(oldest) => {
+                    self.remove_group(oldest);
+                }
+                None => {
+                    self.remove_group(key);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "UDP message exceeds the memory limits",
+                    ));
+                }
+            }
+        }
+    }
+
+    fn remove_group(&mut self, key: (K, u32)) -> Option<FragmentGroup> {
+        let group = self.groups.remove(&key)?;
+        self.order.remove(&group.sequence);
+        let peer = self.peers.get_mut(&key.0).unwrap();
+        peer.messages.remove(&group.sequence);
+        peer.bytes -= group.bytes;
+        if peer.messages.is_empty() {
+            self.peers.remove(&key.0);
+        }
+        self.bytes -= group.bytes;
+        Some(group)
+    }
+}
+
 /// An implementation of DataStream based on UDP.
+/// Messages are split into several datagrams when needed.
 struct UdpDataStream {
     socket: UdpSocket,
     address: String,
     buffer: Vec<u8>,
+    max_data_size: usize,
+    next_message_id: u32,
+    assembler: FragmentAssembler<()>,
 }
 
 impl UdpDataStream {
     async fn connect(address: String, max_data_size: usize) -> Result<Self, std::io::Error> {
         let socket = UdpSocket::bind(&"0.0.0.0:0").await?;
-        let buffer = vec![0u8; max_data_size];
+        let buffer = vec![0u8; MAX_UDP_DATAGRAM_SIZE];
         Ok(Self {
             socket,
             address,
             buffer,
+            max_data_size,
+            next_message_id: 0,
+            assembler: FragmentAssembler::new(FRAGMENT_TIMEOUT, FRAGMENT_LIMITS),
         })
     }
 }
@@ -146,15 +738,25 @@ impl DataStream for UdpDataStream {
         buffer: &'a [u8],
     ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
         Box::pin(async move {
-            self.socket.send_to(buffer, &*self.address).await?;
+            let message_id = self.next_message_id;
+            self.next_message_id = self.next_message_id.wrapping_add(1);
+            for fragment in make_fragments(message_id, buffer, self.max_data_size)? {
+                self.socket.send_to(&fragment, &*self.address).await?;
+            }
             Ok(())
         })
     }
 
     fn read_data(&mut self) -> future::BoxFuture<Result<Vec<u8>, std::io::Error>> {
         Box::pin(async move {
-            let size = self.socket.recv(&mut self.buffer).await?;
-            Ok(self.buffer[..size].into())
+            loop {
+                let size = self.socket.recv(&mut self.buffer).await?;
+                match self.assembler.insert((), &self.buffer[..size]) {
+                    Ok(Some(message)) => return Ok(message),
+                    Ok(None) => (),
+                    Err(error) => warn!("Ignoring UDP datagram: {}", error),
+                }
+            }
         })
     }
 }
@@ -162,12 +764,16 @@ impl DataStream for UdpDataStream {
 /// An implementation of DataStreamPool based on UDP.
 struct UdpDataStreamPool {
     socket: UdpSocket,
+    next_message_id: u32,
 }
 
 impl UdpDataStreamPool {
     async fn new() -> Result<Self, std::io::Error> {
         let socket = UdpSocket::bind(&"0.0.0.0:0").await?;
-        Ok(Self { socket })
+        Ok(Self {
+            socket,
+            next_message_id: 0,
+        })
     }
 }
 
@@ -178,7 +784,11 @@ impl DataStreamPool for UdpDataStreamPool {
         address: &'a str,
     ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
         Box::pin(async move {
-            self.socket.send_to(buffer, address).await?;
+            let message_id = self.next_message_id;
+            self.next_message_id = self.next_message_id.wrapping_add(1);
+            for fragment in make_fragments(message_id, buffer, MAX_UDP_DATAGRAM_SIZE)? {
+                self.socket.send_to(&fragment, address).await?;
+            }
             Ok(())
         })
     }
@@ -195,18 +805,71 @@ impl NetworkProtocol {
     where
         S: MessageHandler + Send + 'static,
     {
-        let mut buffer = vec![0; buffer_size];
+        let mut buffer = vec![0; MAX_UDP_DATAGRAM_SIZE];
+        let verification_pool = state.verification_pool();
+        let rate_limiter = state.rate_limiter();
+        let mut assembler = FragmentAssembler::<SocketAddr>::new(FRAGMENT_TIMEOUT, FRAGMENT_LIMITS);
+        let mut verifications = futures::stream::FuturesUnordered::new();
+        let mut next_message_id: u32 = 0;
+        let mut timer = state.timer_period().map(tokio::time::interval);
         loop {
-            let (size, peer) =
-                match future::select(exit_future, Box::pin(socket.recv_from(&mut buffer))).await {
-                    future::Either::Left(_) => break,
-                    future::Either::Right((value, new_exit_future)) => {
-                        exit_future = new_exit_future;
-                        value?
+            // Stop reading the socket while too many messages are being verified.
+            let event = tokio::select! {
+                _ = &mut exit_future => break,
+                value = socket.recv_from(&mut buffer),
+                    if verifications.len() < MAX_PENDING_VERIFICATIONS =>
+                {
+                    future::Either::Left(value?)
+                }
+                Some(value) = verifications.next() => future::Either::Right(value),
+                _ = next_tick(&mut timer) => {
+                    state.handle_timer().await;
+                    continue;
+                }
+            };
+            let (peer, verification) = match event {
+                future::Either::Left((size, peer)) => {
+                    let message = match assembler.insert(peer, &buffer[..size]) {
+                        Ok(Some(message)) => message,
+                        Ok(None) => continue,
+                        Err(error) => {
+                            warn!("Ignoring UDP datagram from {}: {}", peer, error);
+                            continue;
+                        }
+                    };
+                    if let Some(limiter) = &rate_limiter {
+                        if !limiter.allow(peer.ip(), &message) {
+                            debug!("Ignoring rate-limited message from {}", peer);
+                            continue;
+                        }
+                    }
+                    match &verification_pool {
+                        Some(pool) => {
+                            let pool = pool.clone();
+                            verifications.push(async move { (peer, pool.verify(message).await) });
+                            continue;
+                        }
+                        None => (peer, Verification::Skipped(message)),
+                    }
+                }
+                future::Either::Right(value) => value,
+            };
+            if let Some(reply) = handle_verification(&mut state, verification).await {
+                let message_id = next_message_id;
+                next_message_id = next_message_id.wrapping_add(1);
+                let status = match make_fragments(message_id, &reply[..], buffer_size) {
+                    Ok(fragments) => {
+                        let mut status = Ok(0);
+                        for fragment in fragments {
+                            status = socket.send_to(&fragment, &peer).await;
+                            if status.is_err() {
+                                break;
+                            }
+                        }
+                        status
                     }
+                    Err(error) => Err(error),
                 };
-            if let Some(reply) = state.handle_message(&buffer[..size]).await {
-                let status = socket.send_to(&reply[..], &peer).await;
                 if let Err(error) = status {
                     error!("Failed to send query response: {}", error);
                 }
@@ -245,7 +908,9 @@ impl TcpDataStream {
                     .expect("length must not exceed u32::MAX"),
             ))
             .await?;
-        stream.write_all(buffer).await
+        stream.write_all(buffer).await?;
+        // Encrypted streams buffer data until flushed.
+        stream.flush().await
     }
 
     async fn tcp_read_data<S>(stream: &mut S, max_size: usize) -> Result<Vec<u8>, std::io::Error>
@@ -331,9 +996,13 @@ impl NetworkProtocol {
     where
         S: MessageHandler + Send + 'static,
     {
+        let verification_pool = state.verification_pool();
+        let rate_limiter = state.rate_limiter();
+        let timer_period = state.timer_period();
         let guarded_state = Arc::new(futures::lock::Mutex::new(state));
+        let _timer = timer_period.map(|period| spawn_timer(&guarded_state, period));
         loop {
-            let (mut socket, _) =
+            let (socket, peer) =
                 match future::select(exit_future, Box::pin(listener.accept())).await {
                     future::Either::Left(_) => break,
                     future::Either::Right((value, new_exit_future)) => {
@@ -344,29 +1013,230 @@ impl NetworkProtocol {
             socket.set_send_buffer_size(buffer_size)?;
             socket.set_recv_buffer_size(buffer_size)?;
             let guarded_state = guarded_state.clone();
-            tokio::spawn(async move {
-                loop {
-                    let buffer = match TcpDataStream::tcp_read_data(&mut socket, buffer_size).await
-                    {
-                        Ok(buffer) => buffer,
-                        Err(err) => {
-                            // We expect an EOF error at the end.
-                            if err.kind() != io::ErrorKind::UnexpectedEof {
-                                error!("Error while reading TCP stream: {}", err);
-                            }
-                            break;
-                        }
-                    };
+            tokio::spawn(Self::handle_tcp_connection(
+                socket,
+                peer.ip(),
+                guarded_state,
+                verification_pool.clone(),
+                rate_limiter.clone(),
+                buffer_size,
+            ));
+        }
+        Ok(())
+    }
 
-                    if let Some(reply) =
-                        guarded_state.lock().await.handle_message(&buffer[..]).await
-                    {
-                        let status = TcpDataStream::tcp_write_data(&mut socket, &reply[..]).await;
-                        if let Err(error) = status {
-                            error!("Failed to send query response: {}", error);
+    /// Serve the requests received on a single (TCP or TLS) connection.
+    /// Messages are verified in parallel with other connections and with the next messages
+    /// of the same connection, but handled one at a time, in order.
+    async fn handle_tcp_connection<T, S>(
+        socket: T,
+        peer: IpAddr,
+        guarded_state: Arc<futures::lock::Mutex<S>>,
+        verification_pool: Option<VerificationPool<S::Verified>>,
+        rate_limiter: Option<PeerRateLimiter>,
+        buffer_size: usize,
+    ) where
+        T: AsyncRead + AsyncWrite + Unpin,
+        S: MessageHandler,
+    {
+        let (mut reader, mut writer) = tokio::io::split(socket);
+        let (mut sender, mut receiver) = mpsc::channel(MAX_PIPELINED_MESSAGES);
+        let read_messages = async move {
+            loop {
+                let buffer = match TcpDataStream::tcp_read_data(&mut reader, buffer_size).await {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        // We expect an EOF error at the end.
+                        if err.kind() != io::ErrorKind::UnexpectedEof {
+                            error!("Error while reading TCP stream: {}", err);
                         }
-                    };
+                        break;
+                    }
+                };
+                if let Some(limiter) = &rate_limiter {
+                    if !limiter.allow(peer, &buffer) {
+                        debug!("Ignoring rate-limited message from {}", peer);
+                        continue;
+                    }
+                }
+                let verification = match &verification_pool {
+                    Some(pool) => future::Either::Left(tokio::spawn(pool.clone().verify(buffer))),
+                    None => future::Either::Right(future::ok::<_, tokio::task::JoinError>(
+                        Verification::Skipped(buffer),
+                    )),
+                };
+                if sender.send(verification).await.is_err() {
+                    break;
                 }
+            }
+        };
+        let handle_messages = async move {
+            while let Some(verification) = receiver.recv().await {
+                let verification = verification
+                    .await
+                    .expect("Message verifiers should not panic");
+                let mut state = guarded_state.lock().await;
+                let reply = handle_verification(&mut *state, verification).await;
+                drop(state);
+                if let Some(reply) = reply {
+                    let status = TcpDataStream::tcp_write_data(&mut writer, &reply[..]).await;
+                    if let Err(error) = status {
+                        error!("Failed to send query response: {}", error);
+                    }
+                };
+            }
+        };
+        future::join(read_messages, handle_messages).await;
+    }
+}
+
+fn tls_connector(tls_config: Option<Arc<ClientConfig>>) -> Result<TlsConnector, io::Error> {
+    match tls_config {
+        Some(config) => Ok(TlsConnector::from(config)),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No TLS certificate is known for the remote authority",
+        )),
+    }
+}
+
+/// An implementation of DataStream based on TLS over TCP.
+struct TlsDataStream {
+    stream: client::TlsStream<TcpStream>,
+    max_data_size: usize,
+}
+
+impl TlsDataStream {
+    async fn connect(
+        address: String,
+        max_data_size: usize,
+        connector: TlsConnector,
+    ) -> Result<Self, std::io::Error> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_send_buffer_size(max_data_size)?;
+        stream.set_recv_buffer_size(max_data_size)?;
+        let stream = connector.connect(tls::server_name(), stream).await?;
+        Ok(Self {
+            stream,
+            max_data_size,
+        })
+    }
+}
+
+impl DataStream for TlsDataStream {
+    fn write_data<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+    ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
+        Box::pin(TcpDataStream::tcp_write_data(&mut self.stream, buffer))
+    }
+
+    fn read_data(&mut self) -> future::BoxFuture<Result<Vec<u8>, std::io::Error>> {
+        Box::pin(TcpDataStream::tcp_read_data(
+            &mut self.stream,
+            self.max_data_size,
+        ))
+    }
+}
+
+/// An implementation of DataStreamPool based on TLS over TCP.
+struct TlsDataStreamPool {
+    connector: TlsConnector,
+    streams: HashMap<String, client::TlsStream<TcpStream>>,
+}
+
+impl TlsDataStreamPool {
+    async fn new(connector: TlsConnector) -> Result<Self, std::io::Error> {
+        let streams = HashMap::new();
+        Ok(Self { connector, streams })
+    }
+
+    async fn get_stream(
+        &mut self,
+        address: &str,
+    ) -> Result<&mut client::TlsStream<TcpStream>, io::Error> {
+        if !self.streams.contains_key(address) {
+            let stream = match TcpStream::connect(address).await {
+                Ok(stream) => self.connector.connect(tls::server_name(), stream).await,
+                Err(error) => Err(error),
+            };
+            match stream {
+                Ok(s) => {
+                    self.streams.insert(address.to_string(), s);
+                }
+                Err(error) => {
+                    error!("Failed to open connection to {}: {}", address, error);
+                    return Err(error);
+                }
+            };
+        };
+        Ok(self.streams.get_mut(address).unwrap())
+    }
+}
+
+impl DataStreamPool for TlsDataStreamPool {
+    fn send_data_to<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+        address: &'a str,
+    ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
+        Box::pin(async move {
+            let stream = self.get_stream(address).await?;
+            TcpDataStream::tcp_write_data(stream, buffer).await
+        })
+    }
+}
+
+// Server implementation for TLS.
+impl NetworkProtocol {
+    async fn run_tls_server<S>(
+        mut listener: TcpListener,
+        acceptor: TlsAcceptor,
+        state: S,
+        mut exit_future: futures::channel::oneshot::Receiver<()>,
+        buffer_size: usize,
+    ) -> Result<(), std::io::Error>
+    where
+        S: MessageHandler + Send + 'static,
+    {
+        let verification_pool = state.verification_pool();
+        let rate_limiter = state.rate_limiter();
+        let timer_period = state.timer_period();
+        let guarded_state = Arc::new(futures::lock::Mutex::new(state));
+        let _timer = timer_period.map(|period| spawn_timer(&guarded_state, period));
+        loop {
+            let (socket, peer) =
+                match future::select(exit_future, Box::pin(listener.accept())).await {
+                    future::Either::Left(_) => break,
+                    future::Either::Right((value, new_exit_future)) => {
+                        exit_future = new_exit_future;
+                        value?
+                    }
+                };
+            socket.set_send_buffer_size(buffer_size)?;
+            socket.set_recv_buffer_size(buffer_size)?;
+            let acceptor = acceptor.clone();
+            let guarded_state = guarded_state.clone();
+            let verification_pool = verification_pool.clone();
+            let rate_limiter = rate_limiter.clone();
+            tokio::spawn(async move {
+                // Run the handshake outside of the main loop.
+                let socket: server::TlsStream<TcpStream> = match acceptor.accept(socket).await {
+                    Ok(socket) => socket,
+                    Err(error) => {
+                        error!("TLS handshake failed: {}", error);
+                        return;
+                    }
+                };
+                Self::handle_tcp_connection(
+                    socket,
+                    peer.ip(),
+                    guarded_state,
+                    verification_pool,
+                    rate_limiter,
+                    buffer_size,
+                )
+                .await
             });
         }
         Ok(())
@@ -0,0 +1,103 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use fastpay_core::{authority::AuthorityState, committee::Committee};
+
+fn make_committee_config() -> CommitteeConfig {
+    let authorities = (0..4)
+        .map(|i| AuthorityConfig {
+            network_protocol: NetworkProtocol::Tcp,
+            address: get_authority_key_pair().0,
+            host: "127.0.0.1".to_string(),
+            base_port: 9000 + 100 * i,
+            num_shards: 2,
+            tls_certificate: if i == 0 {
+                Some("authority.pem".to_string())
+            } else {
+                None
+            },
+        })
+        .collect();
+    CommitteeConfig { authorities }
+}
+
+#[test]
+fn test_committee_config_formats() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = make_committee_config();
+    let expected = serde_json::to_string(&config.authorities).unwrap();
+    for name in &["committee.json", "committee.bcs"] {
+        let path = dir.path().join(name);
+        let path = path.to_str().unwrap();
+        config.write(path).unwrap();
+        let read = CommitteeConfig::read(path).unwrap();
+        assert_eq!(serde_json::to_string(&read.authorities).unwrap(), expected);
+    }
+    // Only the `.bcs` file is binary.
+    let json = fs::read(dir.path().join("committee.json")).unwrap();
+    let binary = fs::read(dir.path().join("committee.bcs")).unwrap();
+    assert!(
+        serde_json::from_slice::<AuthorityConfig>(json.split(|b| *b == b'\n').next().unwrap())
+            .is_ok()
+    );
+    assert!(binary.len() < json.len());
+}
+
+#[test]
+fn test_binary_accounts_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("accounts.bcs");
+    let path = path.to_str().unwrap();
+    let mut config = AccountsConfig::read_or_create(path).unwrap();
+    assert_eq!(config.num_accounts(), 0);
+    for i in 0..3 {
+        config.insert(UserAccount::new(Balance::from(i), None));
+    }
+    config.write(path).unwrap();
+
+    let read = AccountsConfig::read_or_create(path).unwrap();
+    assert_eq!(read.num_accounts(), 3);
+    for (address, account) in &config.accounts {
+        let copy = read.get(address).unwrap();
+        assert_eq!(copy.key.public(), account.key.public());
+        assert_eq!(copy.balance, account.balance);
+    }
+}
+
+#[test]
+fn test_genesis_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let keys: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+    let (address, _) = get_key_pair();
+    let mut config = GenesisConfig::new(vec![(address, Balance::from(7))], None);
+    for name in &["genesis.json", "genesis.bcs"] {
+        let path = dir.path().join(name);
+        let path = path.to_str().unwrap();
+        config.write(path).unwrap();
+        for (name, secret) in keys.iter().take(3) {
+            // Authorities sign the file one after the other.
+            let mut config = GenesisConfig::read(path).unwrap();
+            config.sign(*name, secret);
+            config.sign(*name, secret);
+            config.write(path).unwrap();
+        }
+        let read = GenesisConfig::read(path).unwrap();
+        assert_eq!(read.certificate.signatures.len(), 3);
+
+        let (name, secret) = &keys[3];
+        let mut state = AuthorityState::new(committee.clone(), *name, secret.copy());
+        state.load_genesis(&read.certificate).unwrap();
+        assert_eq!(
+            state.accounts.get(&address).unwrap().balance,
+            Balance::from(7)
+        );
+    }
+    // Two signatures are not a quorum.
+    config.sign(keys[0].0, &keys[0].1);
+    config.sign(keys[1].0, &keys[1].1);
+    let (name, secret) = &keys[3];
+    let mut state = AuthorityState::new(committee, *name, secret.copy());
+    assert!(state.load_genesis(&config.certificate).is_err());
+}
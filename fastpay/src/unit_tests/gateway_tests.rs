@@ -0,0 +1,99 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use fastpay_core::{base_types::*, messages::*, testing::*};
+use tokio::runtime::Runtime;
+
+async fn post(address: &SocketAddr, path: &str, body: Vec<u8>) -> (StatusCode, Vec<u8>) {
+    let request = Request::post(format!("http://{}{}", address, path))
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let response = hyper::Client::new().request(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, body.to_vec())
+}
+
+#[test]
+fn test_http_gateway() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let (mut authority_clients, _) = init_local_authorities(1, 1);
+        fund_account(&mut authority_clients, sender, vec![5]);
+        let client = authority_clients.into_iter().next().unwrap().1;
+        let (address, _) = HttpGateway::new(client)
+            .spawn(&"127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let transfer = Transfer {
+            sender,
+            recipient: Address::FastPay(get_key_pair().0),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            valid_until: None,
+            fee: Amount::zero(),
+        };
+        let order = TransferOrder::new(transfer, &sender_key);
+        let (status, body) = post(&address, "/transfer", serde_json::to_vec(&order).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let info: AccountInfoResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info.sender, sender);
+        assert_eq!(info.balance, Balance::from(5));
+        assert_eq!(info.pending_confirmation.unwrap().value, order);
+
+        let request = AccountInfoRequest {
+            sender: get_key_pair().0,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+        };
+        let (status, body) =
+            post(&address, "/account", serde_json::to_vec(&request).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: FastPayError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error, FastPayError::UnknownSenderAccount);
+
+        let (status, body) = post(&address, "/confirm", b"{}".to_vec()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: FastPayError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error, FastPayError::InvalidDecoding);
+
+        let (status, _) = post(&address, "/unknown", Vec::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    });
+}
+
+#[test]
+fn test_http_gateway_max_body_size() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (authority_clients, _) = init_local_authorities(1, 1);
+        let client = authority_clients.into_iter().next().unwrap().1;
+        let mut gateway = HttpGateway::new(client);
+        gateway.set_max_body_size(100);
+        let (address, _) = gateway.spawn(&"127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let (status, _) = post(&address, "/confirm", vec![b' '; 100]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = post(&address, "/confirm", vec![b' '; 101]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Bodies of unknown length are cut off while reading.
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..4 {
+                if sender.send_data(vec![b' '; 40].into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let request = Request::post(format!("http://{}/confirm", address))
+            .body(body)
+            .unwrap();
+        let response = hyper::Client::new().request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    });
+}
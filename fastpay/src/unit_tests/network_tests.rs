@@ -0,0 +1,766 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::config::CommitteeConfig;
+use fastpay_core::{client::Client as _, committee::Committee, testing::*};
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+use tokio::{io::AsyncReadExt, net::TcpListener, runtime::Runtime, time::timeout};
+
+#[test]
+fn test_mass_client_shutdown() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = MassClient::new(
+            NetworkProtocol::Tcp,
+            "127.0.0.1".to_string(),
+            port.into(),
+            1000,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+            1,
+        );
+        let requests = vec![Bytes::from(vec![1, 2, 3]), Bytes::from(vec![4, 5])];
+        let handle = client.run(vec![(0, requests)]);
+
+        // Receive the first request but never answer it.
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buffer = [0u8; 64];
+        assert!(socket.read(&mut buffer).await.unwrap() > 0);
+
+        // Shutting down does not wait for the receive timeout.
+        timeout(Duration::from_secs(5), handle.shutdown())
+            .await
+            .unwrap();
+
+        // The connection was closed by the client.
+        let closed = timeout(Duration::from_secs(5), async {
+            while socket.read(&mut buffer).await.unwrap() > 0 {}
+        })
+        .await;
+        assert!(closed.is_ok());
+    });
+}
+
+/// Answer every message with the given reply, if any.
+struct FixedReply(Option<Vec<u8>>);
+
+impl MessageHandler for FixedReply {
+    type Verified = ();
+
+    fn handle_message<'a>(
+        &'a mut self,
+        _buffer: &'a [u8],
+    ) -> futures::future::BoxFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { self.0.clone() })
+    }
+}
+
+#[test]
+fn test_mass_client_shard_breakdown() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        // Find two consecutive free ports for shards 0 and 1.
+        let (base_port, first_server) = loop {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = u32::from(listener.local_addr().unwrap().port());
+            drop(listener);
+            let address = format!("127.0.0.1:{}", port + 1);
+            if let Ok(server) = NetworkProtocol::Tcp
+                .spawn_server(&address, FixedReply(None), 1000, None)
+                .await
+            {
+                break (port, server);
+            }
+        };
+        let error = serialize_error(&FastPayError::UnknownSenderAccount);
+        let second_server = NetworkProtocol::Tcp
+            .spawn_server(
+                &format!("127.0.0.1:{}", base_port),
+                FixedReply(Some(error)),
+                1000,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let client = MassClient::new(
+            NetworkProtocol::Tcp,
+            "127.0.0.1".to_string(),
+            base_port,
+            1000,
+            Duration::from_secs(5),
+            Duration::from_millis(200),
+            10,
+        );
+        let requests = vec![Bytes::from(vec![1, 2, 3]); 3];
+        let mut results: Vec<ShardResponses> = client
+            .run(vec![(0, requests.clone()), (1, requests)])
+            .collect()
+            .await;
+        results.sort_by_key(|responses| responses.shard);
+
+        // Shard 0 answers with errors, shard 1 never answers.
+        assert_eq!(results[0].shard, 0);
+        assert_eq!(results[0].responses.len(), 3);
+        assert_eq!(results[0].error_replies(), 3);
+        assert_eq!(results[0].timeouts, 0);
+        assert_eq!(results[1].shard, 1);
+        assert!(results[1].responses.is_empty());
+        assert_eq!(results[1].timeouts, 3);
+        assert_eq!(results[1].send_failures, 0);
+
+        first_server.kill().await.unwrap();
+        second_server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_throughput_counters() {
+    let counters = ThroughputCounters::default();
+    assert_eq!(counters.take(), (0, 0));
+    for _ in 0..3 {
+        counters.request_sent(&Bytes::from(vec![1]));
+    }
+    counters.response_received(&[2]);
+    assert_eq!(counters.take(), (3, 1));
+    // Counting starts again after each snapshot.
+    assert_eq!(counters.take(), (0, 0));
+    counters.response_received(&[2]);
+    assert_eq!(counters.take(), (0, 1));
+}
+
+#[test]
+fn test_latency_histogram() {
+    let mut histogram = LatencyHistogram::default();
+    assert_eq!(histogram.percentile(50), 0);
+    for delay in 1..=100 {
+        histogram.record(delay);
+    }
+    histogram.record(5000);
+    assert_eq!(histogram.count(), 101);
+    assert_eq!(histogram.percentile(50), 63);
+    assert_eq!(histogram.percentile(99), 127);
+    assert_eq!(histogram.percentile(100), 8191);
+    histogram.reset();
+    assert_eq!(histogram.count(), 0);
+}
+
+/// Spawn a TCP server for a single authority where `sender` owns `balance`.
+async fn spawn_authority(
+    sender: FastPayAddress,
+    balance: Balance,
+) -> (Committee, KeyPair, u32, SpawnedServer) {
+    spawn_authority_with_accounts(vec![(sender, balance)]).await
+}
+
+/// Spawn a TCP server for a single authority with the given accounts.
+async fn spawn_authority_with_accounts(
+    accounts: Vec<(FastPayAddress, Balance)>,
+) -> (Committee, KeyPair, u32, SpawnedServer) {
+    let (authority, authority_key) = get_authority_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    let committee = Committee::new(voting_rights);
+    let mut state = AuthorityState::new(committee.clone(), authority, authority_key.copy());
+    for (sender, balance) in accounts {
+        state.accounts.insert(
+            sender,
+            AccountOffchainState {
+                balance,
+                initial_balance: balance,
+                next_sequence_number: SequenceNumber::from(0),
+                pending_confirmation: None,
+                confirmed_log: Vec::new(),
+                confirmed_log_start: SequenceNumber::from(0),
+                pruned_amount: Balance::zero(),
+                checkpoint: None,
+                synchronization_log: Vec::new(),
+                received_log: Vec::new(),
+                received_keys: HashSet::new(),
+                received_fees: Balance::zero(),
+                frozen: false,
+                next_freeze_version: SequenceNumber::from(0),
+            },
+        );
+    }
+
+    // Find a free port for the server.
+    let port = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        u32::from(listener.local_addr().unwrap().port())
+    };
+    let server = Server::new(
+        NetworkProtocol::Tcp,
+        "127.0.0.1".to_string(),
+        port,
+        state,
+        65000,
+        100,
+    )
+    .spawn()
+    .await
+    .unwrap();
+    (committee, authority_key, port, server)
+}
+
+fn make_client(port: u32) -> Client {
+    Client::new(
+        NetworkProtocol::Tcp,
+        "127.0.0.1".to_string(),
+        port,
+        1,
+        65000,
+        Duration::from_secs(5),
+        Duration::from_secs(5),
+    )
+}
+
+fn make_certificate(
+    order: TransferOrder,
+    committee: &Committee,
+    authority_key: &KeyPair,
+) -> CertifiedTransferOrder {
+    let authority = *committee.voting_rights.keys().next().unwrap();
+    let vote = SignedTransferOrder::new(order.clone(), authority, authority_key);
+    SignatureAggregator::try_new(order, committee)
+        .unwrap()
+        .append(vote.authority, vote.signature)
+        .unwrap()
+        .unwrap()
+}
+
+fn make_transfer_order(
+    sender: FastPayAddress,
+    sender_key: &KeyPair,
+    recipient: FastPayAddress,
+    sequence_number: u64,
+) -> TransferOrder {
+    let transfer = Transfer {
+        sender,
+        recipient: Address::FastPay(recipient),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::from(sequence_number),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    TransferOrder::new(transfer, sender_key)
+}
+
+fn authority_config(address: AuthorityName, port: u32) -> AuthorityConfig {
+    AuthorityConfig {
+        network_protocol: NetworkProtocol::Tcp,
+        address,
+        host: "127.0.0.1".to_string(),
+        base_port: port,
+        num_shards: 1,
+        tls_certificate: None,
+    }
+}
+
+/// Serve clients from in-memory authorities.
+struct LocalClientFactory(HashMap<AuthorityName, LocalAuthorityClient>);
+
+impl AuthorityClientFactory for LocalClientFactory {
+    fn make(&self, config: &AuthorityConfig) -> BoxedAuthorityClient {
+        Box::new(self.0[&config.address].clone())
+    }
+}
+
+/// Transfer one unit out of an account holding two, using clients made by `factory`,
+/// and return the remaining amount.
+async fn transfer_with_factory(
+    factory: &dyn AuthorityClientFactory,
+    committee_config: &CommitteeConfig,
+    committee: Committee,
+    sender: FastPayAddress,
+    sender_key: KeyPair,
+) -> Amount {
+    let authority_clients = committee_config.make_authority_clients(factory);
+    let mut client = ClientState::new(
+        sender,
+        sender_key,
+        committee,
+        authority_clients,
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        Balance::from(2),
+    );
+    let (recipient, _) = get_key_pair();
+    client
+        .transfer_to_fastpay(Amount::from(1), recipient, UserData::default())
+        .await
+        .unwrap();
+    client.get_spendable_amount().await.unwrap()
+}
+
+#[test]
+fn test_authority_client_factories() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        // In-memory authorities.
+        let (sender, sender_key) = get_key_pair();
+        let (mut authority_clients, committee) = init_local_authorities(4, 1);
+        fund_account(&mut authority_clients, sender, vec![2, 2, 2, 2]);
+        let committee_config = CommitteeConfig {
+            authorities: authority_clients
+                .keys()
+                .map(|name| authority_config(*name, 0))
+                .collect(),
+        };
+        let factory = LocalClientFactory(authority_clients);
+        let amount =
+            transfer_with_factory(&factory, &committee_config, committee, sender, sender_key).await;
+        assert_eq!(amount, Amount::from(1));
+
+        // Network authority.
+        let (sender, sender_key) = get_key_pair();
+        let (committee, _, port, server) = spawn_authority(sender, Balance::from(2)).await;
+        let name = *committee.voting_rights.keys().next().unwrap();
+        let committee_config = CommitteeConfig {
+            authorities: vec![authority_config(name, port)],
+        };
+        let factory = ClientFactory::new(65000, Duration::from_secs(5), Duration::from_secs(5));
+        let amount =
+            transfer_with_factory(&factory, &committee_config, committee, sender, sender_key).await;
+        assert_eq!(amount, Amount::from(1));
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_ping() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, _) = get_key_pair();
+        let (_, _, port, server) = spawn_authority(sender, Balance::from(100)).await;
+
+        let mut client = make_client(port);
+        let health = client.health(0).await.unwrap();
+        assert_eq!(health.shard_id, 0);
+        assert_eq!(health.number_of_accounts, 1);
+        assert_eq!(health.last_transaction_index, VersionNumber::new());
+        assert_eq!(health.cross_shard_updates, 0);
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_cross_shard_latency_in_health() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let (recipient, _) = get_key_pair();
+        let (committee, authority_key, port, server) =
+            spawn_authority(sender, Balance::from(100)).await;
+        let order = make_transfer_order(sender, &sender_key, recipient, 0);
+        let certificate = make_certificate(order, &committee, &authority_key);
+
+        // A cross-shard update sent 5ms ago.
+        let mut stream = NetworkProtocol::Tcp
+            .connect(format!("127.0.0.1:{}", port), 65000, None)
+            .await
+            .unwrap();
+        stream
+            .write_data(&serialize_cross_shard(&certificate, now_us() - 5000))
+            .await
+            .unwrap();
+
+        let mut client = make_client(port);
+        let health = timeout(Duration::from_secs(5), async {
+            loop {
+                let health = client.health(0).await.unwrap();
+                if health.cross_shard_updates > 0 {
+                    return health;
+                }
+                time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(health.cross_shard_updates, 1);
+        assert!(health.cross_shard_delay_p50_us >= 5000);
+        assert_eq!(
+            health.cross_shard_delay_p99_us,
+            health.cross_shard_delay_p50_us
+        );
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_handle_batch_account_info_request() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, _) = get_key_pair();
+        let (unknown, _) = get_key_pair();
+        let (_, _, port, server) = spawn_authority(sender, Balance::from(100)).await;
+
+        let mut client = make_client(port);
+        let request = BatchAccountInfoRequest {
+            senders: vec![unknown, sender],
+        };
+        let response = client
+            .handle_batch_account_info_request(request)
+            .await
+            .unwrap();
+        assert_eq!(response.responses.len(), 2);
+        assert_eq!(response.responses[0].0, unknown);
+        assert_eq!(
+            response.responses[0].1,
+            Err(FastPayError::UnknownSenderAccount)
+        );
+        assert_eq!(response.responses[1].0, sender);
+        let info = response.responses[1].1.as_ref().unwrap();
+        assert_eq!(info.balance, Balance::from(100));
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_handle_confirmation_orders_in_one_batch() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let (recipient, _) = get_key_pair();
+        let (committee, authority_key, port, server) =
+            spawn_authority(sender, Balance::from(100)).await;
+
+        let orders = (0..100)
+            .map(|i| {
+                let order = make_transfer_order(sender, &sender_key, recipient, i);
+                ConfirmationOrder::new(make_certificate(order, &committee, &authority_key))
+            })
+            .collect();
+
+        let mut client = make_client(port);
+        let info = client.handle_confirmation_orders(orders).await.unwrap();
+        assert_eq!(info.next_sequence_number, SequenceNumber::from(100));
+        assert_eq!(info.balance, Balance::zero());
+
+        assert_eq!(
+            client.handle_confirmation_orders(Vec::new()).await,
+            Err(FastPayError::EmptyConfirmationBatch)
+        );
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_concurrent_orders_are_applied_in_sequence() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let senders: Vec<_> = (0..20).map(|_| get_key_pair()).collect();
+        let (recipient, _) = get_key_pair();
+        let accounts = senders
+            .iter()
+            .map(|(sender, _)| (*sender, Balance::from(10)))
+            .collect();
+        let (committee, authority_key, port, server) =
+            spawn_authority_with_accounts(accounts).await;
+
+        // Every sender uses its own connections, so that signatures are verified in parallel.
+        let transfers = senders.iter().map(|(sender, sender_key)| {
+            let committee = &committee;
+            let authority_key = &authority_key;
+            async move {
+                let mut client = make_client(port);
+                let (_, forger_key) = get_key_pair();
+                for i in 0..5 {
+                    let order = make_transfer_order(*sender, sender_key, recipient, i);
+                    let mut forged_order = order.clone();
+                    forged_order.signature = Signature::new(&order.transfer, &forger_key);
+                    assert!(client.handle_transfer_order(forged_order).await.is_err());
+                    client.handle_transfer_order(order.clone()).await.unwrap();
+                    let certificate = make_certificate(order, committee, authority_key);
+                    client
+                        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        futures::future::join_all(transfers).await;
+
+        let mut client = make_client(port);
+        let account_info = |address| AccountInfoRequest {
+            sender: address,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+        };
+        for (sender, _) in &senders {
+            let info = client
+                .handle_account_info_request(account_info(*sender))
+                .await
+                .unwrap();
+            assert_eq!(info.next_sequence_number, SequenceNumber::from(5));
+            assert_eq!(info.balance, Balance::from(5));
+            assert!(info.pending_confirmation.is_none());
+        }
+        let info = client
+            .handle_account_info_request(account_info(recipient))
+            .await
+            .unwrap();
+        assert_eq!(info.balance, Balance::from(100));
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_handle_compact_confirmation_order() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let (recipient, _) = get_key_pair();
+        let (committee, authority_key, port, server) =
+            spawn_authority(sender, Balance::from(2)).await;
+        let mut client = make_client(port);
+
+        // The authority voted for the first transfer: the compact order is enough.
+        let order = make_transfer_order(sender, &sender_key, recipient, 0);
+        client.handle_transfer_order(order.clone()).await.unwrap();
+        let certificate = make_certificate(order, &committee, &authority_key);
+        let info = client
+            .handle_compact_confirmation_order(certificate)
+            .await
+            .unwrap();
+        assert_eq!(info.next_sequence_number, SequenceNumber::from(1));
+
+        // The authority never saw the second transfer: the client falls back to the
+        // full confirmation order.
+        let order = make_transfer_order(sender, &sender_key, recipient, 1);
+        let certificate = make_certificate(order, &committee, &authority_key);
+        let compact_order = CompactConfirmationOrder::new(&certificate);
+        assert_eq!(
+            client
+                .send_recv_bytes(0, serialize_compact_cert(&compact_order))
+                .await,
+            Err(FastPayError::UnknownTransferOrder)
+        );
+        let info = client
+            .handle_compact_confirmation_order(certificate)
+            .await
+            .unwrap();
+        assert_eq!(info.next_sequence_number, SequenceNumber::from(2));
+        assert_eq!(info.balance, Balance::zero());
+        server.kill().await.unwrap();
+    });
+}
+
+fn popped_sequence_number(command: Option<CrossShardCommand>) -> SequenceNumber {
+    match command {
+        Some(CrossShardCommand::Update { certificate, .. }) => {
+            certificate.value.transfer.sequence_number
+        }
+        _ => panic!("expected a cross-shard update"),
+    }
+}
+
+#[test]
+fn test_cross_shard_queue_policies() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let (recipient, _) = get_key_pair();
+        let (authority, authority_key) = get_authority_key_pair();
+        let mut voting_rights = BTreeMap::new();
+        voting_rights.insert(authority, 1);
+        let committee = Committee::new(voting_rights);
+        let update = |i| CrossShardCommand::Update {
+            certificate: make_certificate(
+                make_transfer_order(sender, &sender_key, recipient, i),
+                &committee,
+                &authority_key,
+            ),
+            shard: 1,
+        };
+
+        // Blocking waits for room in the queue.
+        let queue = CrossShardQueue::new(2, CrossShardQueuePolicy::Block);
+        queue.push(update(0)).await;
+        queue.push(update(1)).await;
+        assert!(queue.is_full());
+        assert!(timeout(Duration::from_millis(100), queue.push(update(2)))
+            .await
+            .is_err());
+        let (_, popped) = futures::join!(queue.push(update(2)), queue.pop());
+        assert_eq!(popped_sequence_number(popped), SequenceNumber::from(0));
+        assert_eq!(
+            popped_sequence_number(queue.pop().await),
+            SequenceNumber::from(1)
+        );
+        assert_eq!(
+            popped_sequence_number(queue.pop().await),
+            SequenceNumber::from(2)
+        );
+
+        // Dropping the oldest update leaves it to later retries.
+        let queue = CrossShardQueue::new(2, CrossShardQueuePolicy::DropOldest);
+        for i in 0..3 {
+            queue.push(update(i)).await;
+        }
+        assert_eq!(
+            popped_sequence_number(queue.pop().await),
+            SequenceNumber::from(1)
+        );
+        assert_eq!(
+            popped_sequence_number(queue.pop().await),
+            SequenceNumber::from(2)
+        );
+        assert!(timeout(Duration::from_millis(10), queue.pop())
+            .await
+            .is_err());
+
+        // Rejecting leaves the new update to later retries.
+        let queue = CrossShardQueue::new(2, CrossShardQueuePolicy::Reject);
+        for i in 0..3 {
+            queue.push(update(i)).await;
+        }
+        assert_eq!(
+            popped_sequence_number(queue.pop().await),
+            SequenceNumber::from(0)
+        );
+        assert_eq!(
+            popped_sequence_number(queue.pop().await),
+            SequenceNumber::from(1)
+        );
+        assert!(timeout(Duration::from_millis(10), queue.pop())
+            .await
+            .is_err());
+
+        // Closing the queue stops the forwarding task.
+        queue.close();
+        assert!(queue.pop().await.is_none());
+    });
+}
+
+#[test]
+fn test_finality_tracker() {
+    let start = std::time::Instant::now();
+    let mut tracker = FinalityTracker::new(2);
+    for id in 0..100u64 {
+        tracker.submit(id, start);
+    }
+    // Re-submissions do not reset the submission time.
+    tracker.submit(0, start + Duration::from_secs(1));
+
+    // Responses arrive out of order. Request `id` reaches a quorum after `id + 1` ms.
+    for id in (0..100u64).rev() {
+        assert!(!tracker.respond(&id, start));
+    }
+    for id in (0..100u64).rev() {
+        let time = start + Duration::from_millis(id + 1);
+        assert!(tracker.respond(&id, time));
+    }
+    // Late and unknown responses are ignored.
+    assert!(!tracker.respond(&0, start + Duration::from_secs(10)));
+    assert!(!tracker.respond(&1000, start));
+
+    let histogram = tracker.histogram();
+    assert_eq!(histogram.count(), 100);
+    assert_eq!(histogram.percentile(50), 65535);
+    assert_eq!(histogram.percentile(90), 131_071);
+    assert_eq!(histogram.percentile(99), 131_071);
+}
+
+#[test]
+fn test_cross_shard_updates_are_retried_until_acknowledged() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (authority, authority_key) = get_authority_key_pair();
+        let mut voting_rights = BTreeMap::new();
+        voting_rights.insert(authority, 1);
+        let committee = Committee::new(voting_rights);
+        let (sender, sender_key) = get_key_pair();
+        let sender_shard = AuthorityState::get_shard(2, &sender);
+        let recipient = loop {
+            let (recipient, _) = get_key_pair();
+            if AuthorityState::get_shard(2, &recipient) != sender_shard {
+                break recipient;
+            }
+        };
+        let make_server = |shard, port| {
+            let mut state = AuthorityState::new_shard(
+                committee.clone(),
+                authority,
+                authority_key.copy(),
+                shard,
+                2,
+            );
+            if shard == sender_shard {
+                let mut account = AccountOffchainState::new();
+                account.balance = Balance::from(2);
+                state.accounts.insert(sender, account);
+            }
+            let mut server = Server::new(
+                NetworkProtocol::Tcp,
+                "127.0.0.1".to_string(),
+                port,
+                state,
+                65000,
+                100,
+            );
+            server.set_cross_shard_retry_period(Duration::from_millis(20));
+            server
+        };
+        // Find two consecutive free ports for shards 0 and 1.
+        let port = loop {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = u32::from(listener.local_addr().unwrap().port());
+            if TcpListener::bind(format!("127.0.0.1:{}", port + 1))
+                .await
+                .is_ok()
+            {
+                break port;
+            }
+        };
+
+        // Only the shard of the sender is running at first.
+        let sender_server = make_server(sender_shard, port).spawn().await.unwrap();
+        let mut client = Client::new(
+            NetworkProtocol::Tcp,
+            "127.0.0.1".to_string(),
+            port,
+            2,
+            65000,
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+        );
+        let order = make_transfer_order(sender, &sender_key, recipient, 0);
+        let certificate = make_certificate(order, &committee, &authority_key);
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+        time::delay_for(Duration::from_millis(300)).await;
+
+        // The update reaches the shard of the recipient once it is up.
+        let recipient_server = make_server(1 - sender_shard, port).spawn().await.unwrap();
+        let request = AccountInfoRequest {
+            sender: recipient,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+        };
+        let credited = timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(info) = client.handle_account_info_request(request.clone()).await {
+                    if info.balance == Balance::from(1) {
+                        break;
+                    }
+                }
+                time::delay_for(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+        assert!(credited.is_ok());
+        sender_server.kill().await.unwrap();
+        recipient_server.kill().await.unwrap();
+    });
+}
@@ -26,6 +26,8 @@ impl TestService {
 }
 
 impl MessageHandler for TestService {
+    type Verified = ();
+
     fn handle_message<'a>(
         &'a mut self,
         buffer: &'a [u8],
@@ -35,24 +37,37 @@ impl MessageHandler for TestService {
     }
 }
 
-async fn test_server(protocol: NetworkProtocol) -> Result<(usize, usize), std::io::Error> {
+async fn test_server(
+    protocol: NetworkProtocol,
+    server_tls_config: Option<Arc<ServerConfig>>,
+    client_tls_config: Option<Arc<ClientConfig>>,
+) -> Result<(usize, usize), std::io::Error> {
     let address = get_new_local_address().await.unwrap();
 
     let counter = Arc::new(AtomicUsize::new(0));
     let mut received = 0;
 
     let server = protocol
-        .spawn_server(&address, TestService::new(counter.clone()), 100)
+        .spawn_server(
+            &address,
+            TestService::new(counter.clone()),
+            100,
+            server_tls_config,
+        )
         .await?;
 
-    let mut client = protocol.connect(address.clone(), 1000).await?;
+    let mut client = protocol
+        .connect(address.clone(), 1000, client_tls_config.clone())
+        .await?;
     client.write_data(b"abcdef").await?;
     received += client.read_data().await?.len();
     client.write_data(b"abcd").await?;
     received += client.read_data().await?.len();
 
     // Use a second connection (here pooled).
-    let mut pool = protocol.make_outgoing_connection_pool().await?;
+    let mut pool = protocol
+        .make_outgoing_connection_pool(client_tls_config)
+        .await?;
     pool.send_data_to(b"abc", &address).await?;
 
     // Try to read data on the first connection (should fail).
@@ -78,7 +93,9 @@ async fn test_server(protocol: NetworkProtocol) -> Result<(usize, usize), std::i
 #[test]
 fn udp_server() {
     let mut rt = Runtime::new().unwrap();
-    let (processed, received) = rt.block_on(test_server(NetworkProtocol::Udp)).unwrap();
+    let (processed, received) = rt
+        .block_on(test_server(NetworkProtocol::Udp, None, None))
+        .unwrap();
     assert_eq!(processed, 13);
     assert_eq!(received, 10);
 }
@@ -86,8 +103,473 @@ fn udp_server() {
 #[test]
 fn tcp_server() {
     let mut rt = Runtime::new().unwrap();
-    let (processed, received) = rt.block_on(test_server(NetworkProtocol::Tcp)).unwrap();
+    let (processed, received) = rt
+        .block_on(test_server(NetworkProtocol::Tcp, None, None))
+        .unwrap();
     // Active TCP connections are allowed to finish before the server is gracefully killed.
     assert_eq!(processed, 17);
     assert_eq!(received, 14);
 }
+
+fn make_tls_identity() -> (tls::TlsIdentity, rustls::Certificate) {
+    let certificate = rcgen::generate_simple_self_signed(vec!["fastpay".to_string()]).unwrap();
+    let der = certificate.serialize_der().unwrap();
+    let identity = tls::TlsIdentity::new(
+        vec![rustls::Certificate(der.clone())],
+        rustls::PrivateKey(certificate.serialize_private_key_der()),
+    );
+    (identity, rustls::Certificate(der))
+}
+
+#[test]
+fn tls_server() {
+    let (name, _) = fastpay_core::base_types::get_authority_key_pair();
+    let (other_name, _) = fastpay_core::base_types::get_authority_key_pair();
+    let (unknown_name, _) = fastpay_core::base_types::get_authority_key_pair();
+    let (identity, _) = make_tls_identity();
+    let (_, other_certificate) = make_tls_identity();
+    let mut tls_config = tls::TlsConfig::default();
+    tls_config.set_local_identity(name, identity).unwrap();
+    tls_config.trust_authority(other_name, other_certificate);
+
+    let mut rt = Runtime::new().unwrap();
+    // Servers need a TLS identity.
+    assert!(rt
+        .block_on(test_server(
+            NetworkProtocol::Tls,
+            None,
+            tls_config.client_config(&name)
+        ))
+        .is_err());
+    // Clients need the certificate of the authority that they dial.
+    assert!(tls_config.client_config(&unknown_name).is_none());
+    assert!(rt
+        .block_on(test_server(
+            NetworkProtocol::Tls,
+            tls_config.server_config(),
+            None
+        ))
+        .is_err());
+    // Clients reject the certificates of other (trusted) authorities.
+    assert!(rt
+        .block_on(test_server(
+            NetworkProtocol::Tls,
+            tls_config.server_config(),
+            tls_config.client_config(&other_name)
+        ))
+        .is_err());
+
+    let (processed, received) = rt
+        .block_on(test_server(
+            NetworkProtocol::Tls,
+            tls_config.server_config(),
+            tls_config.client_config(&name),
+        ))
+        .unwrap();
+    // Same as TCP.
+    assert_eq!(processed, 17);
+    assert_eq!(received, 14);
+}
+
+async fn test_large_message(protocol: NetworkProtocol) -> Result<Vec<u8>, std::io::Error> {
+    let address = get_new_local_address().await.unwrap();
+    let counter = Arc::new(AtomicUsize::new(0));
+    let server = protocol
+        .spawn_server(&address, TestService::new(counter), 100, None)
+        .await?;
+
+    // Much larger than the datagrams of both the client and the server.
+    let mut client = protocol.connect(address, 1000, None).await?;
+    let message: Vec<u8> = (0..20_000).map(|i| i as u8).collect();
+    client.write_data(&message).await?;
+    let reply = timeout(Duration::from_secs(5), client.read_data()).await??;
+    server.kill().await?;
+    Ok(reply)
+}
+
+#[test]
+fn udp_large_message() {
+    let mut rt = Runtime::new().unwrap();
+    let reply = rt
+        .block_on(test_large_message(NetworkProtocol::Udp))
+        .unwrap();
+    assert_eq!(reply, (0..20_000).map(|i| i as u8).collect::<Vec<u8>>());
+}
+
+#[test]
+fn test_fragment_assembler() {
+    let message: Vec<u8> = (0..100).collect();
+    let fragments = make_fragments(7, &message, FRAGMENT_HEADER_SIZE + 30).unwrap();
+    assert_eq!(fragments.len(), 4);
+
+    // Fragments may arrive out of order and more than once.
+    let mut assembler = FragmentAssembler::new(FRAGMENT_TIMEOUT, FRAGMENT_LIMITS);
+    assert_eq!(assembler.insert(1, &fragments[3]).unwrap(), None);
+    assert_eq!(assembler.insert(1, &fragments[1]).unwrap(), None);
+    assert_eq!(assembler.insert(1, &fragments[1]).unwrap(), None);
+    assert_eq!(assembler.insert(2, &fragments[0]).unwrap(), None);
+    assert_eq!(assembler.insert(1, &fragments[0]).unwrap(), None);
+    assert_eq!(assembler.insert(1, &fragments[2]).unwrap(), Some(message));
+    assert!(assembler.insert(1, &[0, 1, 2]).is_err());
+
+    // Incomplete messages are eventually discarded.
+    let mut assembler = FragmentAssembler::new(Duration::from_millis(10), FRAGMENT_LIMITS);
+    assert_eq!(assembler.insert(1, &fragments[0]).unwrap(), None);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(assembler.insert(2, &fragments[0]).unwrap(), None);
+    assert_eq!(assembler.groups.len(), 1);
+}
+
+#[test]
+fn test_fragment_assembler_limits() {
+    let limits = FragmentLimits {
+        messages_per_peer: 2,
+        bytes_per_peer: 100,
+        messages: 3,
+        bytes: 150,
+    };
+    let make = |message_id, size: u8| {
+        let message: Vec<u8> = (0..size).collect();
+        make_fragments(message_id, &message, FRAGMENT_HEADER_SIZE + 30).unwrap()
+    };
+
+    // Starting a third message drops the oldest message of the same peer.
+    let mut assembler = FragmentAssembler::new(FRAGMENT_TIMEOUT, limits);
+    for message_id in 0..3 {
+        assert_eq!(assembler.insert(1, &make(message_id, 40)[0]).unwrap(), None);
+    }
+    assert!(!assembler.groups.contains_key(&(1, 0)));
+    assert_eq!(assembler.groups.len(), 2);
+    // Other peers are not affected until the total number of messages is reached.
+    assert_eq!(assembler.insert(2, &make(0, 40)[0]).unwrap(), None);
+    assert_eq!(assembler.groups.len(), 3);
+    assert_eq!(assembler.insert(3, &make(0, 40)[0]).unwrap(), None);
+    assert_eq!(assembler.groups.len(), 3);
+    assert!(!assembler.groups.contains_key(&(1, 1)));
+    assert_eq!(assembler.bytes, 90);
+    assert_eq!(assembler.peers.len(), 3);
+
+    // The bytes received from one peer are bounded.
+    let mut assembler = FragmentAssembler::new(FRAGMENT_TIMEOUT, limits);
+    let first = make(0, 100);
+    for fragment in &first[..3] {
+        assert_eq!(assembler.insert(1, fragment).unwrap(), None);
+    }
+    let second = make(1, 40);
+    assert_eq!(assembler.insert(1, &second[0]).unwrap(), None);
+    assert!(!assembler.groups.contains_key(&(1, 0)));
+    assert_eq!(
+        assembler.insert(1, &second[1]).unwrap(),
+        Some((0..40).collect())
+    );
+    assert_eq!(assembler.bytes, 0);
+    assert!(assembler.peers.is_empty());
+    // Messages larger than the limit are dropped.
+    for fragment in &make(2, 120)[..3] {
+        assert_eq!(assembler.insert(1, fragment).unwrap(), None);
+    }
+    assert!(assembler.insert(1, &make(2, 120)[3]).is_err());
+    assert!(assembler.groups.is_empty());
+
+    // The bytes received from all peers are bounded.
+    let mut assembler = FragmentAssembler::new(FRAGMENT_TIMEOUT, limits);
+    for peer in 0..2 {
+        for fragment in &make(0, 100)[..3] {
+            assert_eq!(assembler.insert(peer, fragment).unwrap(), None);
+        }
+    }
+    assert!(!assembler.groups.contains_key(&(0, 0)));
+    assert_eq!(assembler.bytes, 90);
+    assert_eq!(
+        assembler.insert(1, &make(0, 100)[3]).unwrap(),
+        Some((0..100).collect())
+    );
+}
+
+#[test]
+fn udp_client_ignores_invalid_datagrams() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut client = NetworkProtocol::Udp
+            .connect(address, 1000, None)
+            .await
+            .unwrap();
+        client.write_data(b"abc").await.unwrap();
+        let mut buffer = vec![0; 1000];
+        let (_, peer) = server.recv_from(&mut buffer).await.unwrap();
+
+        server.send_to(&[0, 1, 2], &peer).await.unwrap();
+        for fragment in make_fragments(0, b"abcdef", FRAGMENT_HEADER_SIZE + 3).unwrap() {
+            server.send_to(&fragment, &peer).await.unwrap();
+        }
+        let reply = timeout(Duration::from_secs(5), client.read_data())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply, b"abcdef".to_vec());
+    });
+}
+
+/// A pool that takes some time to deliver each message.
+struct SlowSink {
+    delivered: Arc<AtomicUsize>,
+}
+
+impl DataStreamPool for SlowSink {
+    fn send_data_to<'a>(
+        &'a mut self,
+        _buffer: &'a [u8],
+        _address: &'a str,
+    ) -> future::BoxFuture<'a, Result<(), io::Error>> {
+        Box::pin(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            self.delivered.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })
+    }
+}
+
+async fn time_parallel_sends(size: usize, messages: usize) -> Duration {
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let pools = (0..size)
+        .map(|_| {
+            Box::new(SlowSink {
+                delivered: delivered.clone(),
+            }) as Box<dyn DataStreamPool>
+        })
+        .collect();
+    let mut pool = ParallelDataStreamPool::new(pools);
+    assert_eq!(pool.size(), size);
+    let start = Instant::now();
+    for _ in 0..messages {
+        pool.send_data_to(b"abc", "shard").await.unwrap();
+    }
+    while delivered.load(Ordering::Relaxed) < messages {
+        tokio::time::delay_for(Duration::from_millis(1)).await;
+    }
+    start.elapsed()
+}
+
+#[test]
+fn test_parallel_pool_increases_send_rate() {
+    let mut rt = Runtime::new().unwrap();
+    let sequential = rt.block_on(time_parallel_sends(1, 40));
+    let parallel = rt.block_on(time_parallel_sends(4, 40));
+    assert!(sequential >= Duration::from_millis(400));
+    assert!(
+        parallel * 2 < sequential,
+        "{:?} vs {:?}",
+        parallel,
+        sequential
+    );
+}
+
+/// Accept messages starting with 0, counting the messages actually verified.
+struct CountingVerifier(AtomicUsize);
+
+impl MessageVerifier for CountingVerifier {
+    type Message = Vec<u8>;
+
+    fn verify(&self, buffer: &[u8]) -> Result<Vec<u8>, Option<Vec<u8>>> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        if buffer[0] == 0 {
+            Ok(buffer.to_vec())
+        } else {
+            Err(Some(b"rejected".to_vec()))
+        }
+    }
+
+    fn needs_verification(&self, buffer: &[u8]) -> bool {
+        buffer[0] != 2
+    }
+}
+
+#[test]
+fn test_verification_pool_skips_messages() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let verifier = Arc::new(CountingVerifier(AtomicUsize::new(0)));
+        let pool = VerificationPool::new(verifier.clone(), 2);
+        assert_eq!(
+            pool.clone().verify(vec![0]).await,
+            Verification::Accepted(vec![0])
+        );
+        assert_eq!(
+            pool.clone().verify(vec![1]).await,
+            Verification::Rejected(Some(b"rejected".to_vec()))
+        );
+        assert_eq!(verifier.0.load(Ordering::Relaxed), 2);
+        // Messages that do not need verification are handed back untouched.
+        assert_eq!(pool.verify(vec![2]).await, Verification::Skipped(vec![2]));
+        assert_eq!(verifier.0.load(Ordering::Relaxed), 2);
+    });
+}
+
+/// Accept messages starting with 0 after a delay.
+struct SlowVerifier;
+
+impl MessageVerifier for SlowVerifier {
+    type Message = Vec<u8>;
+
+    fn verify(&self, buffer: &[u8]) -> Result<Vec<u8>, Option<Vec<u8>>> {
+        std::thread::sleep(Duration::from_millis(200));
+        if buffer[0] == 0 {
+            Ok(buffer.to_vec())
+        } else {
+            Err(Some(b"rejected".to_vec()))
+        }
+    }
+
+    fn needs_verification(&self, buffer: &[u8]) -> bool {
+        buffer[0] != 2
+    }
+}
+
+/// Tell verified messages apart from the other ones in replies.
+struct VerifyingService;
+
+impl MessageHandler for VerifyingService {
+    type Verified = Vec<u8>;
+
+    fn handle_message<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+    ) -> future::BoxFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Some([b"handled:", buffer].concat()) })
+    }
+
+    fn verification_pool(&self) -> Option<VerificationPool<Vec<u8>>> {
+        Some(VerificationPool::new(Arc::new(SlowVerifier), 4))
+    }
+
+    fn handle_verified_message(
+        &mut self,
+        message: Vec<u8>,
+    ) -> future::BoxFuture<'_, Option<Vec<u8>>> {
+        Box::pin(async move { Some([b"verified:", &message[..]].concat()) })
+    }
+}
+
+async fn test_server_verification(protocol: NetworkProtocol) {
+    let address = get_new_local_address().await.unwrap();
+    let server = protocol
+        .spawn_server(&address, VerifyingService, 1000, None)
+        .await
+        .unwrap();
+    let mut client = protocol.connect(address.clone(), 1000, None).await.unwrap();
+    let start = Instant::now();
+    // Messages of a connection are verified concurrently but answered in order.
+    let messages = vec![vec![0, 1], vec![1], vec![2], vec![0, 2]];
+    for message in &messages {
+        client.write_data(message).await.unwrap();
+    }
+    let mut replies = Vec::new();
+    for _ in &messages {
+        replies.push(client.read_data().await.unwrap());
+    }
+    assert!(start.elapsed() < Duration::from_millis(600));
+    server.kill().await.unwrap();
+    let mut expected = vec![
+        b"verified:\x00\x01".to_vec(),
+        b"rejected".to_vec(),
+        b"handled:\x02".to_vec(),
+        b"verified:\x00\x02".to_vec(),
+    ];
+    if let NetworkProtocol::Udp = protocol {
+        // UDP replies are sent as soon as possible.
+        replies.sort();
+        expected.sort();
+    }
+    assert_eq!(replies, expected);
+}
+
+#[test]
+fn test_servers_use_verification_pool() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(test_server_verification(NetworkProtocol::Udp));
+    rt.block_on(test_server_verification(NetworkProtocol::Tcp));
+}
+
+#[test]
+fn test_peer_rate_limiter() {
+    let clock = fastpay_core::clock::MockClock::new();
+    let limit = RateLimit {
+        burst: 2,
+        period: Duration::from_secs(1),
+    };
+    let limiter = PeerRateLimiter::new(limit, |buffer| buffer[0] == 0, Arc::new(clock.clone()));
+    let peer: IpAddr = "10.0.0.1".parse().unwrap();
+    let other_peer: IpAddr = "10.0.0.2".parse().unwrap();
+    assert!(limiter.allow(peer, &[0]));
+    assert!(limiter.allow(peer, &[0]));
+    assert!(!limiter.allow(peer, &[0]));
+    // Other messages and other peers are not limited.
+    assert!(limiter.allow(peer, &[1]));
+    assert!(limiter.allow(other_peer, &[0]));
+    clock.advance(Duration::from_secs(1));
+    assert!(limiter.allow(peer, &[0]));
+    assert!(!limiter.allow(peer, &[0]));
+}
+
+/// Reply to every message, but only accept two messages per peer.
+struct LimitedService;
+
+impl MessageHandler for LimitedService {
+    type Verified = ();
+
+    fn handle_message<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+    ) -> future::BoxFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Some(Vec::from(buffer)) })
+    }
+
+    fn rate_limiter(&self) -> Option<PeerRateLimiter> {
+        let limit = RateLimit {
+            burst: 2,
+            period: Duration::from_secs(3600),
+        };
+        Some(PeerRateLimiter::new(
+            limit,
+            |_| true,
+            Arc::new(fastpay_core::clock::SystemClock),
+        ))
+    }
+}
+
+async fn count_rate_limited_replies(protocol: NetworkProtocol) -> usize {
+    let address = get_new_local_address().await.unwrap();
+    let server = protocol
+        .spawn_server(&address, LimitedService, 1000, None)
+        .await
+        .unwrap();
+    let mut replies = 0;
+    // The limit applies to the peer, not to the connection.
+    for _ in 0..2 {
+        let mut client = protocol.connect(address.clone(), 1000, None).await.unwrap();
+        for _ in 0..2 {
+            client.write_data(b"abc").await.unwrap();
+            if let Ok(reply) = timeout(Duration::from_millis(200), client.read_data()).await {
+                assert_eq!(reply.unwrap(), b"abc".to_vec());
+                replies += 1;
+            }
+        }
+    }
+    server.kill().await.unwrap();
+    replies
+}
+
+#[test]
+fn test_servers_apply_rate_limiter() {
+    let mut rt = Runtime::new().unwrap();
+    assert_eq!(
+        rt.block_on(count_rate_limited_replies(NetworkProtocol::Udp)),
+        2
+    );
+    assert_eq!(
+        rt.block_on(count_rate_limited_replies(NetworkProtocol::Tcp)),
+        2
+    );
+}
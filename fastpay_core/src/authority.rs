@@ -1,27 +1,154 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{base_types::*, committee::Committee, error::FastPayError, messages::*};
-use std::{collections::BTreeMap, convert::TryInto};
+use crate::{
+    base_types::*,
+    certificate_cache::CertificateCache,
+    clock::{Clock, SystemClock},
+    committee::Committee,
+    error::FastPayError,
+    messages::*,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    convert::TryInto,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
 
 #[cfg(test)]
 #[path = "unit_tests/authority_tests.rs"]
 mod authority_tests;
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct AccountOffchainState {
     /// Balance of the FastPay account.
     pub balance: Balance,
+    /// Balance of the account when it was created, e.g. by the initial configuration.
+    pub initial_balance: Balance,
     /// Sequence number tracking spending actions.
     pub next_sequence_number: SequenceNumber,
     /// Whether we have signed a transfer for this sequence number already.
     pub pending_confirmation: Option<SignedTransferOrder>,
-    /// All confirmed certificates for this sender.
+    /// Confirmed certificates for this sender, starting at `confirmed_log_start`.
     pub confirmed_log: Vec<CertifiedTransferOrder>,
+    /// Sequence number of the first certificate in `confirmed_log`. Earlier certificates
+    /// were pruned.
+    pub confirmed_log_start: SequenceNumber,
+    /// Total amount spent by the pruned certificates.
+    pub pruned_amount: Balance,
+    /// The certified snapshot that allowed pruning the confirmed log, if any.
+    pub checkpoint: Option<CertifiedAccountSnapshot>,
     /// All executed Primary synchronization orders for this recipient.
     pub synchronization_log: Vec<PrimarySynchronizationOrder>,
     /// All confirmed certificates as a receiver.
     pub received_log: Vec<CertifiedTransferOrder>,
+    /// Sender and sequence number of all the certificates credited to this account,
+    /// including the ones that only credited fees.
+    pub received_keys: HashSet<(FastPayAddress, SequenceNumber)>,
+    /// Total fees credited to this account as fee recipient. The corresponding
+    /// certificates are not kept in `received_log`.
+    pub received_fees: Balance,
+    /// Whether the owner has frozen outgoing transfers.
+    pub frozen: bool,
+    /// Lowest version accepted for the next freeze order.
+    pub next_freeze_version: SequenceNumber,
+}
+
+/// Limit the rate of transfer orders accepted for each account (token bucket).
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Maximal number of orders accepted at once.
+    pub burst: u32,
+    /// Time needed to accept one more order.
+    pub period: Duration,
+}
+
+/// State transitions of an authority (shard), emitted once they are committed.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum AuthorityEvent {
+    /// A transfer was confirmed and debited from its sender.
+    TransferConfirmed {
+        sender: FastPayAddress,
+        sequence_number: SequenceNumber,
+    },
+    /// An account was created by an incoming transfer.
+    AccountCreated { address: FastPayAddress },
+    /// An account was credited by a confirmed transfer.
+    AccountCredited {
+        address: FastPayAddress,
+        amount: Amount,
+    },
+}
+
+/// The persistent state of an authority shard, e.g. to migrate or back up the shard.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct AuthoritySnapshot {
+    pub shard_id: ShardId,
+    pub number_of_shards: u32,
+    pub last_transaction_index: VersionNumber,
+    pub accounts: Vec<(FastPayAddress, AccountOffchainState)>,
+    /// Cross-shard updates not yet acknowledged, to be sent again by the new shard.
+    pub pending_cross_shard_updates: Vec<CrossShardUpdate>,
+}
+
+/// The amounts credited to an account by a transfer.
+#[derive(Clone, Copy, Debug, Default)]
+struct Credit {
+    /// Total amount received, including the fee.
+    total: Amount,
+    /// Fee received as the fee recipient.
+    fee: Amount,
+}
+
+/// Default bound on the number of early certificates buffered for each account.
+pub const DEFAULT_MAX_BUFFERED_CONFIRMATIONS: usize = 16;
+
+/// Remaining capacity under a `RateLimit`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: u32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            last_refill: now,
+        }
+    }
+
+    /// Add the tokens earned since the last refill, then take one if possible.
+    /// Rate limits are ignored under the `benchmark` feature.
+    pub fn try_take(&mut self, limit: RateLimit, now: Instant) -> bool {
+        if cfg!(feature = "benchmark") {
+            return true;
+        }
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let earned = elapsed.as_nanos() / std::cmp::max(limit.period.as_nanos(), 1);
+        if self.tokens as u128 + earned >= limit.burst as u128 {
+            self.tokens = limit.burst;
+            self.last_refill = now;
+        } else {
+            self.tokens += earned as u32;
+            self.last_refill += limit.period * earned as u32;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    /// Whether the bucket would be full at the given time, i.e. equivalent to a new one.
+    pub fn is_full(&self, limit: RateLimit, now: Instant) -> bool {
+        let missing = limit.burst.saturating_sub(self.tokens);
+        now.saturating_duration_since(self.last_refill) >= limit.period * missing
+    }
 }
 
 pub struct AuthorityState {
@@ -39,6 +166,37 @@ pub struct AuthorityState {
     pub shard_id: ShardId,
     /// The number of shards. 1 if single shard.
     pub number_of_shards: u32,
+    /// Cross-shard updates sent by this shard and not yet acknowledged, indexed by
+    /// sender, sequence number, and destination shard.
+    pub pending_cross_shard_updates:
+        BTreeMap<(FastPayAddress, SequenceNumber, ShardId), CrossShardUpdate>,
+    /// Account credited with the fees of transfers. Fees are burnt if unset.
+    /// Set by the genesis state, so that all authorities use the same value.
+    pub fee_recipient: Option<FastPayAddress>,
+    /// Smallest amount accepted in new transfers, e.g. to prevent dust transfers.
+    pub min_transfer_amount: Amount,
+    /// Optional limit on the rate of new transfer orders per account. Servers may also limit
+    /// the rate of messages per network peer before they are verified.
+    pub rate_limit: Option<RateLimit>,
+    /// Remaining capacity of each known account under the rate limit.
+    token_buckets: BTreeMap<FastPayAddress, TokenBucket>,
+    /// Number of recent confirmed certificates never pruned. Pruning is disabled if unset.
+    pub pruning_depth: Option<usize>,
+    /// First evidence of equivocation observed for each sender.
+    pub equivocation_proofs: BTreeMap<FastPayAddress, SignedEquivocationProof>,
+    /// Optional channel notified of state transitions, e.g. by an embedding application.
+    pub event_sender: Option<broadcast::Sender<AuthorityEvent>>,
+    /// Valid certificates received before their predecessors, in arrival order.
+    buffered_confirmations: BTreeMap<FastPayAddress, VecDeque<CertifiedTransferOrder>>,
+    /// Maximal number of early certificates buffered for each account. The oldest one is
+    /// dropped on overflow.
+    pub max_buffered_confirmations: usize,
+    /// Optional cache of the certificates already checked. Disabled if unset.
+    pub certificate_cache: Option<CertificateCache>,
+    /// Whether to answer confirmation orders with a signed receipt (see `Receipt`).
+    pub sign_receipts: bool,
+    /// Source of time for rate limiting and for the expiry of transfer orders.
+    pub clock: Arc<dyn Clock>,
 }
 
 /// Interface provided by each (shard of an) authority.
@@ -55,7 +213,15 @@ pub trait Authority {
     fn handle_confirmation_order(
         &mut self,
         order: ConfirmationOrder,
-    ) -> Result<(AccountInfoResponse, Option<CrossShardUpdate>), FastPayError>;
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError>;
+
+    /// Confirm a transfer whose transfer order is already known to this authority.
+    /// Fails with `UnknownTransferOrder` otherwise, in which case the client should send the
+    /// full confirmation order instead.
+    fn handle_compact_confirmation_order(
+        &mut self,
+        order: CompactConfirmationOrder,
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError>;
 
     /// Force synchronization to finalize transfers from Primary to FastPay.
     fn handle_primary_synchronization_order(
@@ -69,12 +235,46 @@ pub trait Authority {
         request: AccountInfoRequest,
     ) -> Result<AccountInfoResponse, FastPayError>;
 
+    /// Handle information requests for several accounts, without their certificates.
+    fn handle_batch_account_info_request(
+        &self,
+        request: BatchAccountInfoRequest,
+    ) -> Result<BatchAccountInfoResponse, FastPayError>;
+
+    /// Sign the current balance and sequence number of an account.
+    fn handle_account_snapshot_request(
+        &self,
+        request: AccountSnapshotRequest,
+    ) -> Result<SignedAccountSnapshot, FastPayError>;
+
+    /// Freeze or unfreeze the outgoing transfers of an account.
+    fn handle_freeze_order(
+        &mut self,
+        order: FreezeOrder,
+    ) -> Result<AccountInfoResponse, FastPayError>;
+
+    /// Sign the committee of this authority.
+    fn handle_committee_request(
+        &self,
+        request: CommitteeRequest,
+    ) -> Result<SignedCommittee, FastPayError>;
+
+    /// Obtain the first evidence of equivocation observed for an account, if any.
+    fn handle_equivocation_proof_request(
+        &self,
+        request: EquivocationProofRequest,
+    ) -> Result<SignedEquivocationProof, FastPayError>;
+
     /// Handle cross updates from another shard of the same authority.
-    /// This relies on deliver-once semantics of a trusted channel between shards.
+    /// Updates may be delivered several times: only the first one credits the recipient.
+    /// The returned acknowledgement must be sent back to the shard of the sender.
     fn handle_cross_shard_recipient_commit(
         &mut self,
         certificate: CertifiedTransferOrder,
-    ) -> Result<(), FastPayError>;
+    ) -> Result<CrossShardAck, FastPayError>;
+
+    /// Handle the acknowledgement of a cross-shard update sent by this shard.
+    fn handle_cross_shard_ack(&mut self, ack: CrossShardAck) -> Result<(), FastPayError>;
 }
 
 impl Authority for AuthorityState {
@@ -83,148 +283,72 @@ impl Authority for AuthorityState {
         &mut self,
         order: TransferOrder,
     ) -> Result<AccountInfoResponse, FastPayError> {
-        // Check the sender's signature and retrieve the transfer data.
-        fp_ensure!(
-            self.in_shard(&order.transfer.sender),
-            FastPayError::WrongShard
-        );
-        order.check_signature()?;
-        let transfer = &order.transfer;
-        let sender = transfer.sender;
-        fp_ensure!(
-            transfer.sequence_number <= SequenceNumber::max(),
-            FastPayError::InvalidSequenceNumber
-        );
-        fp_ensure!(
-            transfer.amount > Amount::zero(),
-            FastPayError::IncorrectTransferAmount
-        );
-        match self.accounts.get_mut(&sender) {
-            None => fp_bail!(FastPayError::UnknownSenderAccount),
-            Some(account) => {
-                if let Some(pending_confirmation) = &account.pending_confirmation {
-                    fp_ensure!(
-                        &pending_confirmation.value.transfer == transfer,
-                        FastPayError::PreviousTransferMustBeConfirmedFirst {
-                            pending_confirmation: pending_confirmation.value.clone()
-                        }
-                    );
-                    // This exact transfer order was already signed. Return the previous value.
-                    return Ok(account.make_account_info(sender));
-                }
-                fp_ensure!(
-                    account.next_sequence_number == transfer.sequence_number,
-                    FastPayError::UnexpectedSequenceNumber
-                );
-                fp_ensure!(
-                    account.balance >= transfer.amount.into(),
-                    FastPayError::InsufficientFunding {
-                        current_balance: account.balance
-                    }
-                );
-                let signed_order = SignedTransferOrder::new(order, self.name, &self.secret);
-                account.pending_confirmation = Some(signed_order);
-                Ok(account.make_account_info(sender))
-            }
-        }
+        self.process_transfer_order(order, false)
     }
 
-    /// Confirm a transfer.
+    /// Confirm a transfer. Certificates received before their predecessors are buffered
+    /// and applied as soon as the gap is filled.
     fn handle_confirmation_order(
         &mut self,
         confirmation_order: ConfirmationOrder,
-    ) -> Result<(AccountInfoResponse, Option<CrossShardUpdate>), FastPayError> {
-        let certificate = confirmation_order.transfer_certificate;
-        // Check the certificate and retrieve the transfer data.
-        fp_ensure!(
-            self.in_shard(&certificate.value.transfer.sender),
-            FastPayError::WrongShard
-        );
-        certificate.check(&self.committee)?;
-        let transfer = certificate.value.transfer.clone();
-
-        // First we copy all relevant data from sender.
-        let mut sender_account = self
-            .accounts
-            .entry(transfer.sender)
-            .or_insert_with(AccountOffchainState::new);
-        let mut sender_sequence_number = sender_account.next_sequence_number;
-        let mut sender_balance = sender_account.balance;
-
-        // Check and update the copied state
-        if sender_sequence_number < transfer.sequence_number {
-            fp_bail!(FastPayError::MissingEalierConfirmations {
-                current_sequence_number: sender_sequence_number
-            });
-        }
-        if sender_sequence_number > transfer.sequence_number {
-            // Transfer was already confirmed.
-            return Ok((sender_account.make_account_info(transfer.sender), None));
-        }
-        sender_balance = sender_balance.try_sub(transfer.amount.into())?;
-        sender_sequence_number = sender_sequence_number.increment()?;
-
-        // Commit sender state back to the database (Must never fail!)
-        sender_account.balance = sender_balance;
-        sender_account.next_sequence_number = sender_sequence_number;
-        sender_account.pending_confirmation = None;
-        sender_account.confirmed_log.push(certificate.clone());
-        let info = sender_account.make_account_info(transfer.sender);
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError> {
+        self.process_confirmation_order(confirmation_order, false)
+    }
 
-        // Update FastPay recipient state locally or issue a cross-shard update (Must never fail!)
-        let recipient = match transfer.recipient {
-            Address::FastPay(recipient) => recipient,
-            Address::Primary(_) => {
-                // Nothing else to do for Primary recipients.
-                return Ok((info, None));
-            }
-        };
-        // If the recipient is in the same shard, read and update the account.
-        if self.in_shard(&recipient) {
-            let recipient_account = self
-                .accounts
-                .entry(recipient)
-                .or_insert_with(AccountOffchainState::new);
-            recipient_account.balance = recipient_account
-                .balance
-                .try_add(transfer.amount.into())
-                .unwrap_or_else(|_| Balance::max());
-            recipient_account.received_log.push(certificate);
-            // Done updating recipient.
-            return Ok((info, None));
-        }
-        // Otherwise, we need to send a cross-shard update.
-        let cross_shard = Some(CrossShardUpdate {
-            shard_id: self.which_shard(&recipient),
-            transfer_certificate: certificate,
-        });
-        Ok((info, cross_shard))
+    /// Confirm a transfer using a known transfer order.
+    fn handle_compact_confirmation_order(
+        &mut self,
+        order: CompactConfirmationOrder,
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError> {
+        self.check_shard(&order.sender)?;
+        let account = self.account_state(&order.sender)?;
+        // Look for the transfer order that we voted for or already confirmed.
+        let known_order = account
+            .pending_confirmation
+            .iter()
+            .map(|vote| &vote.value)
+            .chain(
+                account
+                    .confirmed_certificate(order.sequence_number)
+                    .map(|certificate| &certificate.value),
+            )
+            .find(|known_order| order.matches(known_order))
+            .cloned()
+            .ok_or(FastPayError::UnknownTransferOrder)?;
+        self.handle_confirmation_order(order.expand(known_order)?)
     }
 
-    // NOTE: Need to rely on deliver-once semantics from comms channel
     fn handle_cross_shard_recipient_commit(
         &mut self,
         certificate: CertifiedTransferOrder,
-    ) -> Result<(), FastPayError> {
+    ) -> Result<CrossShardAck, FastPayError> {
         // TODO: check certificate again?
         let transfer = &certificate.value.transfer;
-
-        let recipient = match transfer.recipient {
-            Address::FastPay(recipient) => recipient,
-            Address::Primary(_) => {
-                fp_bail!(FastPayError::InvalidCrossShardUpdate);
-            }
+        let credits: Vec<_> = self
+            .transfer_credits(transfer)?
+            .into_iter()
+            .filter(|(account, _)| self.in_shard(account))
+            .collect();
+        fp_ensure!(!credits.is_empty(), FastPayError::InvalidCrossShardUpdate);
+        let ack = CrossShardAck {
+            shard_id: self.which_shard(&transfer.sender),
+            sender: transfer.sender,
+            sequence_number: transfer.sequence_number,
+            updated_shard_id: self.shard_id,
         };
-        fp_ensure!(self.in_shard(&recipient), FastPayError::WrongShard);
-        let recipient_account = self
-            .accounts
-            .entry(recipient)
-            .or_insert_with(AccountOffchainState::new);
-        recipient_account.balance = recipient_account
-            .balance
-            .try_add(transfer.amount.into())
-            .unwrap_or_else(|_| Balance::max());
-        recipient_account.received_log.push(certificate);
+        for (account, credit) in credits {
+            self.credit_account(account, credit, &certificate);
+        }
+        Ok(ack)
+    }
+
+    fn handle_cross_shard_ack(&mut self, ack: CrossShardAck) -> Result<(), FastPayError> {
+        self.check_shard(&ack.sender)?;
+        self.pending_cross_shard_updates.remove(&(
+            ack.sender,
+            ack.sequence_number,
+            ack.updated_shard_id,
+        ));
         Ok(())
     }
 
@@ -235,7 +359,7 @@ impl Authority for AuthorityState {
     ) -> Result<AccountInfoResponse, FastPayError> {
         // Update recipient state; note that the blockchain client is trusted.
         let recipient = order.recipient;
-        fp_ensure!(self.in_shard(&recipient), FastPayError::WrongShard);
+        self.check_shard(&recipient)?;
 
         let recipient_account = self
             .accounts
@@ -261,32 +385,141 @@ impl Authority for AuthorityState {
         &self,
         request: AccountInfoRequest,
     ) -> Result<AccountInfoResponse, FastPayError> {
-        fp_ensure!(self.in_shard(&request.sender), FastPayError::WrongShard);
+        self.check_shard(&request.sender)?;
         let account = self.account_state(&request.sender)?;
         let mut response = account.make_account_info(request.sender);
         if let Some(seq) = request.request_sequence_number {
-            if let Some(cert) = account.confirmed_log.get(usize::from(seq)) {
+            if let Some(cert) = account.confirmed_certificate(seq) {
                 response.requested_certificate = Some(cert.clone());
             } else {
-                fp_bail!(FastPayError::CertificateNotfound)
+                match &account.checkpoint {
+                    Some(checkpoint) if seq < account.confirmed_log_start => {
+                        fp_bail!(FastPayError::CertificatePruned {
+                            snapshot: checkpoint.value.clone()
+                        })
+                    }
+                    _ => fp_bail!(FastPayError::CertificateNotfound),
+                }
             }
         }
         if let Some(idx) = request.request_received_transfers_excluding_first_nth {
-            response.requested_received_transfers = account.received_log[idx..].to_vec();
+            // Clients must fetch long logs in several requests.
+            response.requested_received_transfers = account.received_log[idx..]
+                .iter()
+                .take(MAX_RECEIVED_CERTIFICATES_PER_RESPONSE)
+                .cloned()
+                .collect();
+            // The log keeps the order of arrival so that indices remain valid for paging,
+            // but each page is returned in a deterministic order.
+            response
+                .requested_received_transfers
+                .sort_by_key(CertifiedTransferOrder::key);
         }
         Ok(response)
     }
+
+    fn handle_batch_account_info_request(
+        &self,
+        request: BatchAccountInfoRequest,
+    ) -> Result<BatchAccountInfoResponse, FastPayError> {
+        let responses = request
+            .senders
+            .into_iter()
+            .map(|sender| {
+                let request = AccountInfoRequest {
+                    sender,
+                    request_sequence_number: None,
+                    request_received_transfers_excluding_first_nth: None,
+                };
+                (sender, self.handle_account_info_request(request))
+            })
+            .collect();
+        Ok(BatchAccountInfoResponse { responses })
+    }
+
+    fn handle_account_snapshot_request(
+        &self,
+        request: AccountSnapshotRequest,
+    ) -> Result<SignedAccountSnapshot, FastPayError> {
+        self.check_shard(&request.account)?;
+        let account = self.account_state(&request.account)?;
+        let snapshot = AccountSnapshot {
+            account: request.account,
+            balance: account.balance,
+            next_sequence_number: account.next_sequence_number,
+        };
+        Ok(SignedAccountSnapshot::new(
+            snapshot,
+            self.name,
+            &self.secret,
+        ))
+    }
+
+    fn handle_committee_request(
+        &self,
+        _request: CommitteeRequest,
+    ) -> Result<SignedCommittee, FastPayError> {
+        let value = CommitteeDescription::new(&self.committee);
+        Ok(SignedCommittee::new(value, self.name, &self.secret))
+    }
+
+    fn handle_equivocation_proof_request(
+        &self,
+        request: EquivocationProofRequest,
+    ) -> Result<SignedEquivocationProof, FastPayError> {
+        self.check_shard(&request.sender)?;
+        self.equivocation_proofs
+            .get(&request.sender)
+            .cloned()
+            .ok_or(FastPayError::UnknownEquivocationProof)
+    }
+
+    fn handle_freeze_order(
+        &mut self,
+        order: FreezeOrder,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        let freeze = &order.freeze;
+        self.check_shard(&freeze.account)?;
+        order.check_signature()?;
+        match self.accounts.get_mut(&freeze.account) {
+            None => fp_bail!(FastPayError::UnknownSenderAccount),
+            Some(account) => {
+                if freeze.version < account.next_freeze_version {
+                    // Repeating the last order is harmless.
+                    fp_ensure!(
+                        freeze.version.increment()? == account.next_freeze_version
+                            && freeze.frozen == account.frozen,
+                        FastPayError::StaleFreezeOrder {
+                            next_freeze_version: account.next_freeze_version
+                        }
+                    );
+                } else {
+                    account.frozen = freeze.frozen;
+                    account.next_freeze_version = freeze.version.increment()?;
+                }
+                Ok(account.make_account_info(freeze.account))
+            }
+        }
+    }
 }
 
 impl Default for AccountOffchainState {
     fn default() -> Self {
         Self {
             balance: Balance::zero(),
+            initial_balance: Balance::zero(),
             next_sequence_number: SequenceNumber::new(),
             pending_confirmation: None,
             confirmed_log: Vec::new(),
+            confirmed_log_start: SequenceNumber::new(),
+            pruned_amount: Balance::zero(),
+            checkpoint: None,
             synchronization_log: Vec::new(),
             received_log: Vec::new(),
+            received_keys: HashSet::new(),
+            received_fees: Balance::zero(),
+            frozen: false,
+            next_freeze_version: SequenceNumber::new(),
         }
     }
 }
@@ -296,6 +529,16 @@ impl AccountOffchainState {
         Self::default()
     }
 
+    /// The confirmed certificate with the given sequence number, unless it was pruned.
+    fn confirmed_certificate(
+        &self,
+        sequence_number: SequenceNumber,
+    ) -> Option<&CertifiedTransferOrder> {
+        let index =
+            usize::from(sequence_number).checked_sub(usize::from(self.confirmed_log_start))?;
+        self.confirmed_log.get(index)
+    }
+
     fn make_account_info(&self, sender: FastPayAddress) -> AccountInfoResponse {
         AccountInfoResponse {
             sender,
@@ -304,18 +547,123 @@ impl AccountOffchainState {
             pending_confirmation: self.pending_confirmation.clone(),
             requested_certificate: None,
             requested_received_transfers: Vec::new(),
+            receipt: None,
+            frozen: self.frozen,
+            next_freeze_version: self.next_freeze_version,
+        }
+    }
+
+    /// Check that the balance and the next sequence number of the account `address` agree
+    /// with its logs, and that all logged certificates are valid.
+    pub fn verify_invariants(
+        &self,
+        address: FastPayAddress,
+        committee: &Committee,
+    ) -> Result<(), FastPayError> {
+        let start = usize::from(self.confirmed_log_start);
+        for (index, certificate) in self.confirmed_log.iter().enumerate() {
+            certificate.check(committee)?;
+            let transfer = &certificate.value.transfer;
+            fp_ensure!(
+                transfer.sender == address
+                    && usize::from(transfer.sequence_number) == start + index,
+                FastPayError::InvalidAccountState {
+                    address,
+                    reason: format!("Unexpected confirmed certificate at index {}", index),
+                }
+            );
+        }
+        for certificate in &self.received_log {
+            certificate.check(committee)?;
+            fp_ensure!(
+                self.received_keys.contains(&certificate.key()),
+                FastPayError::InvalidAccountState {
+                    address,
+                    reason: "Received certificate is missing from the received keys".to_string(),
+                }
+            );
+        }
+        fp_ensure!(
+            usize::from(self.next_sequence_number) == start + self.confirmed_log.len(),
+            FastPayError::InvalidAccountState {
+                address,
+                reason: format!(
+                    "Next sequence number {:?} does not match {} confirmed certificates",
+                    self.next_sequence_number,
+                    start + self.confirmed_log.len()
+                ),
+            }
+        );
+        let expected_balance = self.expected_balance(address)?;
+        fp_ensure!(
+            self.balance == expected_balance,
+            FastPayError::InvalidAccountState {
+                address,
+                reason: format!(
+                    "Balance {:?} does not match the logs {:?}",
+                    self.balance, expected_balance
+                ),
+            }
+        );
+        Ok(())
+    }
+
+    /// Recompute the balance and the next sequence number of the account `address` from
+    /// its logs. A pending transfer that no longer matches the next sequence number is dropped.
+    pub fn repair(&mut self, address: FastPayAddress) -> Result<(), FastPayError> {
+        let balance = self.expected_balance(address)?;
+        let next_sequence_number = self
+            .confirmed_log_start
+            .checked_add(self.confirmed_log.len() as u64)?;
+        if let Some(order) = &self.pending_confirmation {
+            if order.value.transfer.sequence_number != next_sequence_number {
+                self.pending_confirmation = None;
+            }
+        }
+        self.balance = balance;
+        self.next_sequence_number = next_sequence_number;
+        Ok(())
+    }
+
+    /// The balance resulting from the initial balance and the logged operations.
+    fn expected_balance(&self, address: FastPayAddress) -> Result<Balance, FastPayError> {
+        let mut balance = self.initial_balance.try_add(self.received_fees)?;
+        for order in &self.synchronization_log {
+            balance = balance.try_add(order.amount.into())?;
         }
+        for certificate in &self.received_log {
+            let transfer = &certificate.value.transfer;
+            if transfer.recipient == Address::FastPay(address) && !transfer.is_self_transfer() {
+                balance = balance.try_add(transfer.amount.into())?;
+            }
+        }
+        balance = balance.try_sub(self.pruned_amount)?;
+        for certificate in &self.confirmed_log {
+            balance = balance.try_sub(certificate.value.transfer.debited_amount()?.into())?;
+        }
+        Ok(balance)
     }
 
     #[cfg(test)]
     pub fn new_with_balance(balance: Balance, received_log: Vec<CertifiedTransferOrder>) -> Self {
         Self {
             balance,
+            initial_balance: balance,
             next_sequence_number: SequenceNumber::new(),
             pending_confirmation: None,
             confirmed_log: Vec::new(),
+            confirmed_log_start: SequenceNumber::new(),
+            pruned_amount: Balance::zero(),
+            checkpoint: None,
             synchronization_log: Vec::new(),
+            received_keys: received_log
+                .iter()
+                .map(CertifiedTransferOrder::key)
+                .collect(),
             received_log,
+            received_fees: Balance::zero(),
+            frozen: false,
+            next_freeze_version: SequenceNumber::new(),
         }
     }
 }
@@ -330,6 +678,19 @@ impl AuthorityState {
             last_transaction_index: VersionNumber::new(),
             shard_id: 0,
             number_of_shards: 1,
+            pending_cross_shard_updates: BTreeMap::new(),
+            fee_recipient: None,
+            min_transfer_amount: Amount::from(1),
+            rate_limit: None,
+            token_buckets: BTreeMap::new(),
+            pruning_depth: None,
+            equivocation_proofs: BTreeMap::new(),
+            event_sender: None,
+            buffered_confirmations: BTreeMap::new(),
+            max_buffered_confirmations: DEFAULT_MAX_BUFFERED_CONFIRMATIONS,
+            certificate_cache: None,
+            sign_receipts: false,
+            clock: Arc::new(SystemClock),
         }
     }
 
@@ -348,13 +709,503 @@ impl AuthorityState {
             last_transaction_index: VersionNumber::new(),
             shard_id,
             number_of_shards,
+            pending_cross_shard_updates: BTreeMap::new(),
+            fee_recipient: None,
+            min_transfer_amount: Amount::from(1),
+            rate_limit: None,
+            token_buckets: BTreeMap::new(),
+            pruning_depth: None,
+            equivocation_proofs: BTreeMap::new(),
+            event_sender: None,
+            buffered_confirmations: BTreeMap::new(),
+            max_buffered_confirmations: DEFAULT_MAX_BUFFERED_CONFIRMATIONS,
+            certificate_cache: None,
+            sign_receipts: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Drop the confirmed certificates of an account that are summarized by the given
+    /// certified snapshot, always keeping the `pruning_depth` most recent ones.
+    /// Returns the number of certificates removed.
+    pub fn prune_confirmed_log(
+        &mut self,
+        checkpoint: CertifiedAccountSnapshot,
+    ) -> Result<usize, FastPayError> {
+        let address = checkpoint.value.account;
+        self.check_shard(&address)?;
+        let depth = match self.pruning_depth {
+            Some(depth) => depth,
+            None => return Ok(0),
+        };
+        checkpoint.check(&self.committee)?;
+        let account = self
+            .accounts
+            .get_mut(&address)
+            .ok_or(FastPayError::UnknownSenderAccount)?;
+        let start = usize::from(account.confirmed_log_start);
+        let end = std::cmp::min(
+            usize::from(checkpoint.value.next_sequence_number),
+            usize::from(account.next_sequence_number).saturating_sub(depth),
+        );
+        if end <= start {
+            return Ok(0);
+        }
+        let count = end - start;
+        let mut pruned_amount = account.pruned_amount;
+        for certificate in &account.confirmed_log[..count] {
+            pruned_amount =
+                pruned_amount.try_add(certificate.value.transfer.debited_amount()?.into())?;
+        }
+        // Must never fail!
+        account.confirmed_log.drain(..count);
+        account.confirmed_log_start = SequenceNumber::from(end as u64);
+        account.pruned_amount = pruned_amount;
+        account.checkpoint = Some(checkpoint);
+        Ok(count)
+    }
+
+    /// Create the accounts of this shard listed in a certified genesis state and set the
+    /// fee recipient. Only accepted while the shard has no accounts yet.
+    pub fn load_genesis(&mut self, genesis: &CertifiedGenesisState) -> Result<(), FastPayError> {
+        fp_ensure!(self.accounts.is_empty(), FastPayError::GenesisAlreadyLoaded);
+        genesis.check(&self.committee)?;
+        self.fee_recipient = genesis.value.fee_recipient;
+        for (address, balance) in &genesis.value.accounts {
+            if self.in_shard(address) {
+                let account = self
+                    .accounts
+                    .entry(*address)
+                    .or_insert_with(AccountOffchainState::new);
+                account.balance = *balance;
+                account.initial_balance = *balance;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the accounts of this shard and the progress of the primary synchronization.
+    pub fn export_snapshot(&self) -> AuthoritySnapshot {
+        AuthoritySnapshot {
+            shard_id: self.shard_id,
+            number_of_shards: self.number_of_shards,
+            last_transaction_index: self.last_transaction_index,
+            accounts: self
+                .accounts
+                .iter()
+                .map(|(address, account)| (*address, account.clone()))
+                .collect(),
+            pending_cross_shard_updates: self
+                .pending_cross_shard_updates
+                .values()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Replace the accounts of this shard with the ones of a snapshot. Fails without
+    /// changes if the snapshot was taken by another shard or contains an account that
+    /// belongs to another shard. The state derived from the previous accounts is reset.
+    pub fn import_snapshot(&mut self, snapshot: AuthoritySnapshot) -> Result<(), FastPayError> {
+        fp_ensure!(
+            snapshot.shard_id == self.shard_id
+                && snapshot.number_of_shards == self.number_of_shards,
+            FastPayError::WrongSnapshotShard {
+                shard_id: snapshot.shard_id,
+                number_of_shards: snapshot.number_of_shards,
+            }
+        );
+        for (address, _) in &snapshot.accounts {
+            self.check_shard(address)?;
         }
+        for update in &snapshot.pending_cross_shard_updates {
+            self.check_shard(&update.transfer_certificate.value.transfer.sender)?;
+        }
+        self.accounts = snapshot.accounts.into_iter().collect();
+        self.last_transaction_index = snapshot.last_transaction_index;
+        self.pending_cross_shard_updates = snapshot
+            .pending_cross_shard_updates
+            .into_iter()
+            .map(|update| {
+                let (sender, sequence_number) = update.transfer_certificate.key();
+                ((sender, sequence_number, update.shard_id), update)
+            })
+            .collect();
+        self.buffered_confirmations.clear();
+        self.token_buckets.clear();
+        self.equivocation_proofs.clear();
+        if let Some(cache) = &mut self.certificate_cache {
+            cache.clear();
+        }
+        Ok(())
     }
 
     pub fn in_shard(&self, address: &FastPayAddress) -> bool {
         self.which_shard(address) == self.shard_id
     }
 
+    /// The FastPay accounts credited by a transfer: the recipient and the fee recipient.
+    fn transfer_credits(
+        &self,
+        transfer: &Transfer,
+    ) -> Result<BTreeMap<FastPayAddress, Credit>, FastPayError> {
+        let mut credits = BTreeMap::new();
+        // The amount of a self-transfer is never debited (see `Transfer::debited_amount`).
+        if let Address::FastPay(recipient) = transfer.recipient {
+            if !transfer.is_self_transfer() {
+                let credit = Credit {
+                    total: transfer.amount,
+                    fee: Amount::zero(),
+                };
+                credits.insert(recipient, credit);
+            }
+        }
+        if let Some(fee_recipient) = self.fee_recipient {
+            if transfer.fee > Amount::zero() {
+                let credit = credits.entry(fee_recipient).or_insert_with(Credit::default);
+                credit.total = credit.total.try_add(transfer.fee)?;
+                credit.fee = transfer.fee;
+            }
+        }
+        Ok(credits)
+    }
+
+    /// Credit an account in this shard, unless the certificate was already received.
+    /// Certificates that only credit a fee are not logged.
+    fn credit_account(
+        &mut self,
+        address: FastPayAddress,
+        credit: Credit,
+        certificate: &CertifiedTransferOrder,
+    ) {
+        let created = !self.accounts.contains_key(&address);
+        let account = self
+            .accounts
+            .entry(address)
+            .or_insert_with(AccountOffchainState::new);
+        if !account.received_keys.insert(certificate.key()) {
+            // This update was already executed.
+            return;
+        }
+        let amount = credit.total;
+        account.balance = account
+            .balance
+            .try_add(amount.into())
+            .unwrap_or_else(|_| Balance::max());
+        if amount > credit.fee {
+            account.received_log.push(certificate.clone());
+        }
+        account.received_fees = account
+            .received_fees
+            .try_add(credit.fee.into())
+            .unwrap_or_else(|_| Balance::max());
+        if created {
+            self.emit(AuthorityEvent::AccountCreated { address });
+        }
+        self.emit(AuthorityEvent::AccountCredited { address, amount });
+    }
+
+    /// Same as `handle_transfer_order` for an order whose signature was already checked,
+    /// e.g. on another thread with `TransferOrder::check_signature`.
+    pub fn handle_verified_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        self.process_transfer_order(order, true)
+    }
+
+    /// Same as `handle_confirmation_order` for a certificate that was already checked
+    /// against the committee of this authority.
+    pub fn handle_verified_confirmation_order(
+        &mut self,
+        confirmation_order: ConfirmationOrder,
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError> {
+        self.process_confirmation_order(confirmation_order, true)
+    }
+
+    fn process_transfer_order(
+        &mut self,
+        order: TransferOrder,
+        signature_checked: bool,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        // Check the sender's signature and retrieve the transfer data.
+        self.check_shard(&order.transfer.sender)?;
+        if !signature_checked {
+            order.check_signature()?;
+        }
+        let transfer = &order.transfer;
+        let sender = transfer.sender;
+        fp_ensure!(
+            transfer.sequence_number <= SequenceNumber::max(),
+            FastPayError::InvalidSequenceNumber
+        );
+        fp_ensure!(
+            transfer.amount > Amount::zero(),
+            FastPayError::IncorrectTransferAmount
+        );
+        fp_ensure!(
+            transfer.amount >= self.min_transfer_amount,
+            FastPayError::AmountBelowMinimum {
+                minimum: self.min_transfer_amount
+            }
+        );
+        match self.accounts.get_mut(&sender) {
+            None => fp_bail!(FastPayError::UnknownSenderAccount),
+            Some(account) => {
+                if let Some(pending_confirmation) = &account.pending_confirmation {
+                    let pending_order = &pending_confirmation.value;
+                    if &pending_order.transfer != transfer
+                        && pending_order.transfer.sequence_number == transfer.sequence_number
+                    {
+                        // Both orders are signed by the sender: keep the evidence.
+                        let proof = EquivocationProof {
+                            first: pending_order.clone(),
+                            second: order.clone(),
+                        };
+                        let (name, secret) = (self.name, &self.secret);
+                        self.equivocation_proofs
+                            .entry(sender)
+                            .or_insert_with(|| SignedEquivocationProof::new(proof, name, secret));
+                    }
+                    fp_ensure!(
+                        &pending_order.transfer == transfer,
+                        FastPayError::PreviousTransferMustBeConfirmedFirst {
+                            pending_confirmation: pending_order.clone()
+                        }
+                    );
+                    // This exact transfer order was already signed. Return the previous value.
+                    return Ok(account.make_account_info(sender));
+                }
+                // Only charge new orders signed by a known account, so that forged orders
+                // cannot exhaust the limit of another account, buckets never outnumber
+                // accounts, and retries of a signed order are free.
+                if let Some(limit) = self.rate_limit {
+                    let now = self.clock.now();
+                    let bucket = self
+                        .token_buckets
+                        .entry(sender)
+                        .or_insert_with(|| TokenBucket::new(limit, now));
+                    fp_ensure!(
+                        bucket.try_take(limit, now),
+                        FastPayError::RateLimited { address: sender }
+                    );
+                }
+                fp_ensure!(!account.frozen, FastPayError::AccountFrozen);
+                if let Some(valid_until) = transfer.valid_until {
+                    fp_ensure!(
+                        self.clock.transaction_index(self.last_transaction_index) <= valid_until,
+                        FastPayError::ExpiredTransferOrder { valid_until }
+                    );
+                }
+                fp_ensure!(
+                    account.next_sequence_number == transfer.sequence_number,
+                    FastPayError::UnexpectedSequenceNumber {
+                        sender,
+                        expected_sequence_number: account.next_sequence_number,
+                        received_sequence_number: transfer.sequence_number,
+                    }
+                );
+                fp_ensure!(
+                    account.balance >= transfer.total_amount()?.into(),
+                    FastPayError::InsufficientFunding {
+                        current_balance: account.balance
+                    }
+                );
+                let signed_order = SignedTransferOrder::new(order, self.name, &self.secret);
+                account.pending_confirmation = Some(signed_order);
+                Ok(account.make_account_info(sender))
+            }
+        }
+    }
+
+    fn process_confirmation_order(
+        &mut self,
+        confirmation_order: ConfirmationOrder,
+        signatures_checked: bool,
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError> {
+        let certificate = confirmation_order.transfer_certificate;
+        let sender = certificate.value.transfer.sender;
+        let (mut info, mut updates) =
+            match self.apply_confirmation(certificate.clone(), signatures_checked) {
+                Err(error @ FastPayError::MissingEalierConfirmations { .. }) => {
+                    // The certificate was checked before the sequence number.
+                    self.buffer_confirmation(certificate);
+                    return Err(error);
+                }
+                result => result?,
+            };
+        while let Some(next) = self.take_buffered_confirmation(&sender) {
+            // Buffered certificates were already checked.
+            match self.apply_confirmation(next.clone(), true) {
+                Ok((next_info, next_updates)) => {
+                    info = next_info;
+                    updates.extend(next_updates);
+                }
+                Err(_) => {
+                    // E.g. the balance would underflow. Keep the certificate for a later
+                    // attempt: the updates of the certificates applied so far must still be
+                    // returned.
+                    self.buffer_confirmation(next);
+                    break;
+                }
+            }
+        }
+        if self.sign_receipts {
+            info.receipt = Some(SignedReceipt::new(
+                Receipt::new(&certificate.value.transfer),
+                self.name,
+                &self.secret,
+            ));
+        }
+        Ok((info, updates))
+    }
+
+    /// Check a certificate (unless this was already done) and execute it if it is the
+    /// next one for its sender.
+    fn apply_confirmation(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+        signatures_checked: bool,
+    ) -> Result<(AccountInfoResponse, Vec<CrossShardUpdate>), FastPayError> {
+        // Check the certificate and retrieve the transfer data.
+        self.check_shard(&certificate.value.transfer.sender)?;
+        if !signatures_checked {
+            self.check_certificate(&certificate)?;
+        }
+        let transfer = certificate.value.transfer.clone();
+        let credits = self.transfer_credits(&transfer)?;
+
+        // First we copy all relevant data from sender.
+        let mut sender_account = self
+            .accounts
+            .entry(transfer.sender)
+            .or_insert_with(AccountOffchainState::new);
+        let mut sender_sequence_number = sender_account.next_sequence_number;
+        let mut sender_balance = sender_account.balance;
+
+        // Check and update the copied state
+        if sender_sequence_number < transfer.sequence_number {
+            fp_bail!(FastPayError::MissingEalierConfirmations {
+                current_sequence_number: sender_sequence_number
+            });
+        }
+        if sender_sequence_number > transfer.sequence_number {
+            // Transfer was already confirmed.
+            return Ok((
+                sender_account.make_account_info(transfer.sender),
+                Vec::new(),
+            ));
+        }
+        sender_balance = sender_balance.try_sub(transfer.debited_amount()?.into())?;
+        sender_sequence_number = sender_sequence_number.increment()?;
+
+        // Commit sender state back to the database (Must never fail!)
+        sender_account.balance = sender_balance;
+        sender_account.next_sequence_number = sender_sequence_number;
+        sender_account.pending_confirmation = None;
+        sender_account.confirmed_log.push(certificate.clone());
+        let info = sender_account.make_account_info(transfer.sender);
+        self.emit(AuthorityEvent::TransferConfirmed {
+            sender: transfer.sender,
+            sequence_number: transfer.sequence_number,
+        });
+
+        // Update the FastPay recipient and the fee recipient locally or issue cross-shard
+        // updates (Must never fail!)
+        let mut shards = BTreeSet::new();
+        for (account, credit) in credits {
+            if self.in_shard(&account) {
+                self.credit_account(account, credit, &certificate);
+            } else {
+                shards.insert(self.which_shard(&account));
+            }
+        }
+        // Keep cross-shard updates until they are acknowledged.
+        let mut updates = Vec::new();
+        for shard_id in shards {
+            let cross_shard = CrossShardUpdate {
+                shard_id,
+                transfer_certificate: certificate.clone(),
+            };
+            self.pending_cross_shard_updates.insert(
+                (transfer.sender, transfer.sequence_number, shard_id),
+                cross_shard.clone(),
+            );
+            updates.push(cross_shard);
+        }
+        Ok((info, updates))
+    }
+
+    /// Keep an early certificate until its predecessors are confirmed. The certificate
+    /// must have been checked already.
+    fn buffer_confirmation(&mut self, certificate: CertifiedTransferOrder) {
+        if self.max_buffered_confirmations == 0 {
+            return;
+        }
+        let queue = self
+            .buffered_confirmations
+            .entry(certificate.value.transfer.sender)
+            .or_insert_with(VecDeque::new);
+        let sequence_number = certificate.value.transfer.sequence_number;
+        if queue
+            .iter()
+            .any(|buffered| buffered.value.transfer.sequence_number == sequence_number)
+        {
+            return;
+        }
+        if queue.len() >= self.max_buffered_confirmations {
+            queue.pop_front();
+        }
+        queue.push_back(certificate);
+    }
+
+    /// Remove and return the buffered certificate that the given account expects next, if
+    /// any. Certificates that are now obsolete are dropped.
+    fn take_buffered_confirmation(
+        &mut self,
+        sender: &FastPayAddress,
+    ) -> Option<CertifiedTransferOrder> {
+        let next_sequence_number = self.accounts.get(sender)?.next_sequence_number;
+        let queue = self.buffered_confirmations.get_mut(sender)?;
+        queue.retain(|buffered| buffered.value.transfer.sequence_number >= next_sequence_number);
+        let position = queue
+            .iter()
+            .position(|buffered| buffered.value.transfer.sequence_number == next_sequence_number);
+        let certificate = position.and_then(|position| queue.remove(position));
+        if queue.is_empty() {
+            self.buffered_confirmations.remove(sender);
+        }
+        certificate
+    }
+
+    fn check_certificate(
+        &mut self,
+        certificate: &CertifiedTransferOrder,
+    ) -> Result<(), FastPayError> {
+        match &mut self.certificate_cache {
+            Some(cache) => cache.check(certificate, &self.committee),
+            None => certificate.check(&self.committee),
+        }
+    }
+
+    fn emit(&self, event: AuthorityEvent) {
+        if let Some(sender) = &self.event_sender {
+            // Sending only fails when nobody is listening.
+            let _ = sender.send(event);
+        }
+    }
+
+    fn check_shard(&self, address: &FastPayAddress) -> Result<(), FastPayError> {
+        fp_ensure!(
+            self.in_shard(address),
+            FastPayError::WrongShard {
+                address: *address,
+                shard_id: self.which_shard(address),
+            }
+        );
+        Ok(())
+    }
+
     pub fn get_shard(num_shards: u32, address: &FastPayAddress) -> u32 {
         const LAST_INTEGER_INDEX: usize = std::mem::size_of::<FastPayAddress>() - 4;
         u32::from_le_bytes(address.0[LAST_INTEGER_INDEX..].try_into().expect("4 bytes"))
@@ -6,7 +6,12 @@ use ed25519_dalek::{Signer, Verifier};
 
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
-use std::convert::{TryFrom, TryInto};
+use sha2::{Digest, Sha512};
+use std::{
+    convert::{TryFrom, TryInto},
+    hash::Hash,
+};
+use subtle::ConstantTimeEq;
 
 use crate::error::FastPayError;
 
@@ -29,6 +34,7 @@ pub struct SequenceNumber(u64);
 
 pub type ShardId = u32;
 pub type VersionNumber = SequenceNumber;
+pub type Epoch = SequenceNumber;
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Default, Debug, Serialize, Deserialize)]
 pub struct UserData(pub Option<[u8; 32]>);
@@ -36,12 +42,28 @@ pub struct UserData(pub Option<[u8; 32]>);
 // TODO: Make sure secrets are not copyable and movable to control where they are in memory
 pub struct KeyPair(dalek::Keypair);
 
-#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Serialize, Deserialize)]
+#[derive(Eq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize)]
 pub struct PublicKeyBytes(pub [u8; dalek::PUBLIC_KEY_LENGTH]);
 
 pub type PrimaryAddress = PublicKeyBytes;
 pub type FastPayAddress = PublicKeyBytes;
-pub type AuthorityName = PublicKeyBytes;
+
+/// The public key of an authority. Unlike account addresses, authority names are not
+/// interchangeable with `PublicKeyBytes`, which keeps the two roles apart:
+///
+/// ```compile_fail
+/// use fastpay_core::base_types::*;
+///
+/// fn credit(_recipient: FastPayAddress) {}
+///
+/// let name = AuthorityName::from(get_key_pair().0);
+/// credit(name);
+/// ```
+///
+/// Authority names are serialized exactly like the underlying public key.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AuthorityName(pub PublicKeyBytes);
 
 pub fn get_key_pair() -> (FastPayAddress, KeyPair) {
     let mut csprng = OsRng;
@@ -49,20 +71,27 @@ pub fn get_key_pair() -> (FastPayAddress, KeyPair) {
     (PublicKeyBytes(keypair.public.to_bytes()), KeyPair(keypair))
 }
 
-pub fn address_as_base64<S>(key: &PublicKeyBytes, serializer: S) -> Result<S::Ok, S::Error>
+pub fn get_authority_key_pair() -> (AuthorityName, KeyPair) {
+    let (address, key_pair) = get_key_pair();
+    (AuthorityName(address), key_pair)
+}
+
+pub fn address_as_base64<K, S>(key: &K, serializer: S) -> Result<S::Ok, S::Error>
 where
+    K: AsRef<PublicKeyBytes>,
     S: serde::ser::Serializer,
 {
-    serializer.serialize_str(&encode_address(key))
+    serializer.serialize_str(&encode_address(key.as_ref()))
 }
 
-pub fn address_from_base64<'de, D>(deserializer: D) -> Result<PublicKeyBytes, D::Error>
+pub fn address_from_base64<'de, K, D>(deserializer: D) -> Result<K, D::Error>
 where
+    K: From<PublicKeyBytes>,
     D: serde::de::Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
     let value = decode_address(&s).map_err(|err| serde::de::Error::custom(err.to_string()))?;
-    Ok(value)
+    Ok(value.into())
 }
 
 pub fn encode_address(key: &PublicKeyBytes) -> String {
@@ -82,9 +111,13 @@ pub fn dbg_addr(name: u8) -> FastPayAddress {
     PublicKeyBytes(addr)
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Eq, Copy, Clone, Serialize, Deserialize)]
 pub struct Signature(dalek::Signature);
 
+/// Number of signatures above which batch verification is split across threads.
+#[cfg(feature = "parallel")]
+const PARALLEL_VERIFICATION_THRESHOLD: usize = 32;
+
 impl KeyPair {
     /// Avoid implementing `clone` on secret keys to prevent mistakes.
     pub fn copy(&self) -> KeyPair {
@@ -93,6 +126,28 @@ impl KeyPair {
             public: dalek::PublicKey::from_bytes(self.0.public.as_bytes()).unwrap(),
         })
     }
+
+    /// Deterministically derive a key pair from a master seed and a derivation path, so
+    /// that a single seed is enough to recover many accounts.
+    /// This scheme is specific to FastPay: it is NOT compatible with BIP32 (or SLIP-0010),
+    /// but derived keys will not change across versions.
+    pub fn derive_from_seed(master: &[u8; 32], path: &[u32]) -> KeyPair {
+        let mut hasher = Sha512::new();
+        hasher.update(b"FastPay key derivation");
+        hasher.update(master);
+        for index in path {
+            hasher.update(&index.to_le_bytes());
+        }
+        let digest = hasher.finalize();
+        let secret = dalek::SecretKey::from_bytes(&digest[..dalek::SECRET_KEY_LENGTH])
+            .expect("digest is long enough");
+        let public = dalek::PublicKey::from(&secret);
+        KeyPair(dalek::Keypair { secret, public })
+    }
+
+    pub fn public(&self) -> PublicKeyBytes {
+        PublicKeyBytes(self.0.public.to_bytes())
+    }
 }
 
 impl Serialize for KeyPair {
@@ -117,6 +172,38 @@ impl<'de> Deserialize<'de> for KeyPair {
     }
 }
 
+impl ConstantTimeEq for PublicKeyBytes {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+/// Authority names are routinely compared against values received from the network:
+/// do not leak where two keys differ.
+impl PartialEq for PublicKeyBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Hash for PublicKeyBytes {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl ConstantTimeEq for Signature {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.0.to_bytes().ct_eq(&other.0.to_bytes())
+    }
+}
+
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
 impl std::fmt::Debug for Signature {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         let s = base64::encode(&self.0);
@@ -125,6 +212,13 @@ impl std::fmt::Debug for Signature {
     }
 }
 
+/// Secret keys are never printed, so that structures containing them can be logged safely.
+impl std::fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "KeyPair(<redacted>)")
+    }
+}
+
 impl std::fmt::Debug for PublicKeyBytes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         let s = base64::encode(&self.0);
@@ -133,6 +227,36 @@ impl std::fmt::Debug for PublicKeyBytes {
     }
 }
 
+impl AsRef<PublicKeyBytes> for PublicKeyBytes {
+    fn as_ref(&self) -> &PublicKeyBytes {
+        self
+    }
+}
+
+impl std::fmt::Debug for AuthorityName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+impl From<PublicKeyBytes> for AuthorityName {
+    fn from(key: PublicKeyBytes) -> Self {
+        AuthorityName(key)
+    }
+}
+
+impl From<AuthorityName> for PublicKeyBytes {
+    fn from(name: AuthorityName) -> Self {
+        name.0
+    }
+}
+
+impl AsRef<PublicKeyBytes> for AuthorityName {
+    fn as_ref(&self) -> &PublicKeyBytes {
+        &self.0
+    }
+}
+
 impl Amount {
     pub fn zero() -> Self {
         Amount(0)
@@ -216,7 +340,7 @@ impl TryFrom<Balance> for Amount {
 }
 
 impl SequenceNumber {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         SequenceNumber(0)
     }
 
@@ -224,21 +348,37 @@ impl SequenceNumber {
         SequenceNumber(0x7fff_ffff_ffff_ffff)
     }
 
-    pub fn increment(self) -> Result<SequenceNumber, FastPayError> {
-        let val = self.0.checked_add(1);
-        match val {
-            None => Err(FastPayError::SequenceOverflow),
-            Some(val) => Ok(Self(val)),
+    /// Add `value`, failing with `SequenceOverflow` above `SequenceNumber::max()`.
+    pub fn checked_add(self, value: u64) -> Result<SequenceNumber, FastPayError> {
+        match self.0.checked_add(value) {
+            Some(val) if val <= Self::max().0 => Ok(Self(val)),
+            _ => Err(FastPayError::SequenceOverflow),
         }
     }
 
-    pub fn decrement(self) -> Result<SequenceNumber, FastPayError> {
-        let val = self.0.checked_sub(1);
-        match val {
+    /// Subtract `value`, failing with `SequenceUnderflow` below zero.
+    pub fn checked_sub(self, value: u64) -> Result<SequenceNumber, FastPayError> {
+        match self.0.checked_sub(value) {
             None => Err(FastPayError::SequenceUnderflow),
             Some(val) => Ok(Self(val)),
         }
     }
+
+    /// Add `value` in place. The sequence number is unchanged in case of error.
+    pub fn checked_add_assign(&mut self, value: u64) -> Result<(), FastPayError> {
+        *self = self.checked_add(value)?;
+        Ok(())
+    }
+
+    /// Same as `checked_add(1)`.
+    pub fn increment(self) -> Result<SequenceNumber, FastPayError> {
+        self.checked_add(1)
+    }
+
+    /// Same as `checked_sub(1)`.
+    pub fn decrement(self) -> Result<SequenceNumber, FastPayError> {
+        self.checked_sub(1)
+    }
 }
 
 impl From<SequenceNumber> for u64 {
@@ -294,6 +434,38 @@ where
     }
 }
 
+/// A signature of a value by someone else than the authorities, e.g. the sender of a
+/// transfer order, to be verified in the same batch as the votes of a certificate.
+pub struct ExtraSignature {
+    message: Vec<u8>,
+    signer: PublicKeyBytes,
+    signature: Signature,
+}
+
+impl ExtraSignature {
+    pub fn new<T, A>(value: &T, signer: A, signature: Signature) -> Self
+    where
+        T: Signable<Vec<u8>>,
+        A: AsRef<PublicKeyBytes>,
+    {
+        let mut message = Vec::new();
+        value.write(&mut message);
+        Self {
+            message,
+            signer: *signer.as_ref(),
+            signature,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), FastPayError> {
+        dalek::PublicKey::from_bytes(&self.signer.0)
+            .and_then(|public_key| public_key.verify(&self.message, &self.signature.0))
+            .map_err(|error| FastPayError::InvalidSignature {
+                error: format!("{}", error),
+            })
+    }
+}
+
 impl Signature {
     pub fn new<T>(value: &T, secret: &KeyPair) -> Self
     where
@@ -319,20 +491,26 @@ impl Signature {
         public_key.verify(&message, &self.0)
     }
 
-    pub fn check<T>(&self, value: &T, author: FastPayAddress) -> Result<(), FastPayError>
+    pub fn check<T, A>(&self, value: &T, author: A) -> Result<(), FastPayError>
     where
         T: Signable<Vec<u8>>,
+        A: AsRef<PublicKeyBytes>,
     {
-        self.check_internal(value, author)
+        self.check_internal(value, *author.as_ref())
             .map_err(|error| FastPayError::InvalidSignature {
                 error: format!("{}", error),
             })
     }
 
-    fn verify_batch_internal<'a, T, I>(value: &'a T, votes: I) -> Result<(), dalek::SignatureError>
+    fn verify_batch_internal<'a, T, A, I>(
+        value: &'a T,
+        votes: I,
+        extra_signatures: &[ExtraSignature],
+    ) -> Result<(), dalek::SignatureError>
     where
         T: Signable<Vec<u8>>,
-        I: IntoIterator<Item = &'a (FastPayAddress, Signature)>,
+        A: AsRef<PublicKeyBytes> + 'a,
+        I: IntoIterator<Item = &'a (A, Signature)>,
     {
         let mut msg = Vec::new();
         value.write(&mut msg);
@@ -342,17 +520,73 @@ impl Signature {
         for (addr, sig) in votes.into_iter() {
             messages.push(&msg);
             signatures.push(sig.0);
-            public_keys.push(dalek::PublicKey::from_bytes(&addr.0)?);
+            public_keys.push(dalek::PublicKey::from_bytes(&addr.as_ref().0)?);
+        }
+        for extra in extra_signatures {
+            messages.push(&extra.message);
+            signatures.push(extra.signature.0);
+            public_keys.push(dalek::PublicKey::from_bytes(&extra.signer.0)?);
         }
-        dalek::verify_batch(&messages[..], &signatures[..], &public_keys[..])
+        Signature::verify_batch_slices(&messages[..], &signatures[..], &public_keys[..])
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn verify_batch_slices(
+        messages: &[&[u8]],
+        signatures: &[dalek::Signature],
+        public_keys: &[dalek::PublicKey],
+    ) -> Result<(), dalek::SignatureError> {
+        dalek::verify_batch(messages, signatures, public_keys)
+    }
+
+    /// Split large batches into one sub-batch per thread. The whole batch is valid iff
+    /// every sub-batch is.
+    #[cfg(feature = "parallel")]
+    fn verify_batch_slices(
+        messages: &[&[u8]],
+        signatures: &[dalek::Signature],
+        public_keys: &[dalek::PublicKey],
+    ) -> Result<(), dalek::SignatureError> {
+        use rayon::prelude::*;
+
+        let threads = rayon::current_num_threads();
+        if signatures.len() <= PARALLEL_VERIFICATION_THRESHOLD || threads <= 1 {
+            return dalek::verify_batch(messages, signatures, public_keys);
+        }
+        let chunk_size = std::cmp::max(
+            (signatures.len() + threads - 1) / threads,
+            PARALLEL_VERIFICATION_THRESHOLD / 2,
+        );
+        messages
+            .par_chunks(chunk_size)
+            .zip(signatures.par_chunks(chunk_size))
+            .zip(public_keys.par_chunks(chunk_size))
+            .try_for_each(|((messages, signatures), public_keys)| {
+                dalek::verify_batch(messages, signatures, public_keys)
+            })
+    }
+
+    pub fn verify_batch<'a, T, A, I>(value: &'a T, votes: I) -> Result<(), FastPayError>
+    where
+        T: Signable<Vec<u8>>,
+        A: AsRef<PublicKeyBytes> + 'a,
+        I: IntoIterator<Item = &'a (A, Signature)>,
+    {
+        Signature::verify_batch_with_extra_signatures(value, votes, &[])
     }
 
-    pub fn verify_batch<'a, T, I>(value: &'a T, votes: I) -> Result<(), FastPayError>
+    /// Same as `verify_batch` with additional signatures of other values.
+    pub fn verify_batch_with_extra_signatures<'a, T, A, I>(
+        value: &'a T,
+        votes: I,
+        extra_signatures: &[ExtraSignature],
+    ) -> Result<(), FastPayError>
     where
         T: Signable<Vec<u8>>,
-        I: IntoIterator<Item = &'a (FastPayAddress, Signature)>,
+        A: AsRef<PublicKeyBytes> + 'a,
+        I: IntoIterator<Item = &'a (A, Signature)>,
     {
-        Signature::verify_batch_internal(value, votes).map_err(|error| {
+        Signature::verify_batch_internal(value, votes, extra_signatures).map_err(|error| {
             FastPayError::InvalidSignature {
                 error: format!("{}", error),
             }
@@ -0,0 +1,99 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{committee::Committee, error::FastPayError, messages::CertifiedTransferOrder};
+use sha2::{Digest, Sha512};
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(test)]
+#[path = "unit_tests/certificate_cache_tests.rs"]
+mod certificate_cache_tests;
+
+/// Remember the certificates that were successfully checked, so that receiving the same
+/// certificate again (e.g. a retried or compact confirmation) does not verify its
+/// signatures again. Certificates are identified by a digest of their serialized bytes,
+/// including all signatures. The cache is emptied whenever the committee changes.
+pub struct CertificateCache {
+    capacity: usize,
+    /// The committee that checked the cached certificates.
+    committee: Option<Committee>,
+    /// Time of last use of each cached certificate.
+    entries: HashMap<[u8; 32], u64>,
+    /// Cached certificates by time of last use.
+    recency: BTreeMap<u64, [u8; 32]>,
+    clock: u64,
+    hits: u64,
+}
+
+impl CertificateCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: std::cmp::max(capacity, 1),
+            committee: None,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+        }
+    }
+
+    /// Same as `CertifiedTransferOrder::check`, unless the exact same certificate was
+    /// already checked successfully by the same committee.
+    pub fn check(
+        &mut self,
+        certificate: &CertifiedTransferOrder,
+        committee: &Committee,
+    ) -> Result<(), FastPayError> {
+        if self.committee.as_ref() != Some(committee) {
+            self.clear();
+            self.committee = Some(committee.clone());
+        }
+        let digest = Self::digest(certificate);
+        self.clock += 1;
+        if let Some(last_use) = self.entries.insert(digest, self.clock) {
+            self.recency.remove(&last_use);
+            self.recency.insert(self.clock, digest);
+            self.hits += 1;
+            return Ok(());
+        }
+        // Only remember valid certificates.
+        if let Err(error) = certificate.check(committee) {
+            self.entries.remove(&digest);
+            return Err(error);
+        }
+        self.recency.insert(self.clock, digest);
+        if self.entries.len() > self.capacity {
+            let oldest_use = *self.recency.keys().next().expect("cache is not empty");
+            let oldest = self.recency.remove(&oldest_use).expect("key exists");
+            self.entries.remove(&oldest);
+        }
+        Ok(())
+    }
+
+    /// Number of checks answered by the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.committee = None;
+    }
+
+    fn digest(certificate: &CertifiedTransferOrder) -> [u8; 32] {
+        let bytes = bcs::to_bytes(certificate).expect("certificates should be serializable");
+        let hash = Sha512::digest(&bytes);
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&hash[..32]);
+        digest
+    }
+}
@@ -6,8 +6,9 @@ use failure::{bail, ensure};
 use futures::{future, StreamExt};
 use rand::seq::SliceRandom;
 use std::{
-    collections::{btree_map, BTreeMap, BTreeSet, HashMap},
+    collections::{btree_map, hash_map, BTreeMap, BTreeSet, HashMap},
     convert::TryFrom,
+    time::Duration,
 };
 
 #[cfg(test)]
@@ -29,11 +30,266 @@ pub trait AuthorityClient {
         order: ConfirmationOrder,
     ) -> AsyncResult<AccountInfoResponse, FastPayError>;
 
+    /// Confirm several transfers in the given order and return the account information
+    /// after the last one. By default, orders are sent one by one.
+    fn handle_confirmation_orders(
+        &mut self,
+        orders: Vec<ConfirmationOrder>,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError>
+    where
+        Self: Send,
+    {
+        Box::pin(handle_confirmation_orders_one_by_one(self, orders))
+    }
+
+    /// Confirm a transfer without sending the transfer order again, if the authority
+    /// already knows it. By default, the full confirmation order is sent.
+    fn handle_compact_confirmation_order(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError>
+    where
+        Self: Send,
+    {
+        Box::pin(async move {
+            self.handle_confirmation_order(ConfirmationOrder::new(certificate))
+                .await
+        })
+    }
+
     /// Handle information requests for this account.
     fn handle_account_info_request(
         &mut self,
         request: AccountInfoRequest,
     ) -> AsyncResult<AccountInfoResponse, FastPayError>;
+
+    /// Handle information requests for several accounts. By default, accounts are queried
+    /// one by one.
+    fn handle_batch_account_info_request(
+        &mut self,
+        request: BatchAccountInfoRequest,
+    ) -> AsyncResult<BatchAccountInfoResponse, FastPayError>
+    where
+        Self: Send,
+    {
+        Box::pin(async move {
+            let mut responses = Vec::new();
+            for sender in request.senders {
+                let request = AccountInfoRequest {
+                    sender,
+                    request_sequence_number: None,
+                    request_received_transfers_excluding_first_nth: None,
+                };
+                responses.push((sender, self.handle_account_info_request(request).await));
+            }
+            Ok(BatchAccountInfoResponse { responses })
+        })
+    }
+
+    /// Obtain a signed snapshot of this account.
+    fn handle_account_snapshot_request(
+        &mut self,
+        request: AccountSnapshotRequest,
+    ) -> AsyncResult<SignedAccountSnapshot, FastPayError>;
+
+    /// Freeze or unfreeze the outgoing transfers of an account.
+    fn handle_freeze_order(
+        &mut self,
+        order: FreezeOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError>;
+
+    /// Obtain the committee of the authority, signed by the authority.
+    fn handle_committee_request(
+        &mut self,
+        request: CommitteeRequest,
+    ) -> AsyncResult<SignedCommittee, FastPayError>;
+
+    /// Obtain the evidence of equivocation of an account, signed by the authority.
+    fn handle_equivocation_proof_request(
+        &mut self,
+        request: EquivocationProofRequest,
+    ) -> AsyncResult<SignedEquivocationProof, FastPayError>;
+}
+
+/// An authority client that can be cloned behind a trait object.
+pub trait BoxableAuthorityClient: AuthorityClient + Send + Sync {
+    fn clone_box(&self) -> BoxedAuthorityClient;
+}
+
+/// A type-erased authority client, e.g. to choose the transport at runtime.
+pub type BoxedAuthorityClient = Box<dyn BoxableAuthorityClient>;
+
+impl<A> BoxableAuthorityClient for A
+where
+    A: AuthorityClient + Send + Sync + Clone + 'static,
+{
+    fn clone_box(&self) -> BoxedAuthorityClient {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for BoxedAuthorityClient {
+    fn clone(&self) -> Self {
+        // Dispatch to the inner client rather than the blanket implementation for `Box`.
+        (**self).clone_box()
+    }
+}
+
+impl AuthorityClient for BoxedAuthorityClient {
+    fn handle_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        (**self).handle_transfer_order(order)
+    }
+
+    fn handle_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        (**self).handle_confirmation_order(order)
+    }
+
+    fn handle_confirmation_orders(
+        &mut self,
+        orders: Vec<ConfirmationOrder>,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        (**self).handle_confirmation_orders(orders)
+    }
+
+    fn handle_compact_confirmation_order(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        (**self).handle_compact_confirmation_order(certificate)
+    }
+
+    fn handle_account_info_request(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        (**self).handle_account_info_request(request)
+    }
+
+    fn handle_batch_account_info_request(
+        &mut self,
+        request: BatchAccountInfoRequest,
+    ) -> AsyncResult<BatchAccountInfoResponse, FastPayError> {
+        (**self).handle_batch_account_info_request(request)
+    }
+
+    fn handle_account_snapshot_request(
+        &mut self,
+        request: AccountSnapshotRequest,
+    ) -> AsyncResult<SignedAccountSnapshot, FastPayError> {
+        (**self).handle_account_snapshot_request(request)
+    }
+
+    fn handle_freeze_order(
+        &mut self,
+        order: FreezeOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        (**self).handle_freeze_order(order)
+    }
+
+    fn handle_committee_request(
+        &mut self,
+        request: CommitteeRequest,
+    ) -> AsyncResult<SignedCommittee, FastPayError> {
+        (**self).handle_committee_request(request)
+    }
+
+    fn handle_equivocation_proof_request(
+        &mut self,
+        request: EquivocationProofRequest,
+    ) -> AsyncResult<SignedEquivocationProof, FastPayError> {
+        (**self).handle_equivocation_proof_request(request)
+    }
+}
+
+/// Send confirmation orders one at a time, e.g. to authorities that do not support batches.
+pub async fn handle_confirmation_orders_one_by_one<A>(
+    client: &mut A,
+    orders: Vec<ConfirmationOrder>,
+) -> Result<AccountInfoResponse, FastPayError>
+where
+    A: AuthorityClient + ?Sized,
+{
+    let mut info = None;
+    for order in orders {
+        info = Some(client.handle_confirmation_order(order).await?);
+    }
+    info.ok_or(FastPayError::EmptyConfirmationBatch)
+}
+
+/// Check offline that the certificates sent by an account (e.g. as stored by a wallet)
+/// are valid for the given committee, come from the same sender, and have consecutive
+/// sequence numbers starting at 0.
+pub fn verify_certificate_chain(
+    certificates: &[CertifiedTransferOrder],
+    committee: &Committee,
+) -> Result<(), FastPayError> {
+    let sender = match certificates.first() {
+        Some(certificate) => certificate.value.transfer.sender,
+        None => return Ok(()),
+    };
+    for (index, certificate) in certificates.iter().enumerate() {
+        let transfer = &certificate.value.transfer;
+        fp_ensure!(
+            transfer.sender == sender && usize::from(transfer.sequence_number) == index,
+            FastPayError::InvalidAccountState {
+                address: sender,
+                reason: format!("Unexpected sent certificate at index {}", index),
+            }
+        );
+        certificate.check(committee)?;
+    }
+    Ok(())
+}
+
+/// Find the highest balance backed by a quorum of authorities for each of the given
+/// accounts (e.g. of a wallet), with one batched request per authority.
+pub async fn get_strong_majority_balances<A>(
+    authority_clients: &mut HashMap<AuthorityName, A>,
+    committee: &Committee,
+    accounts: BTreeSet<FastPayAddress>,
+) -> BTreeMap<FastPayAddress, Balance>
+where
+    A: AuthorityClient + Send,
+{
+    let request = BatchAccountInfoRequest {
+        senders: accounts.iter().cloned().collect(),
+    };
+    let responses: futures::stream::FuturesUnordered<_> = authority_clients
+        .iter_mut()
+        .map(|(name, client)| {
+            let fut = client.handle_batch_account_info_request(request.clone());
+            async move { (*name, fut.await) }
+        })
+        .collect();
+    // Only count one balance per authority and account.
+    let mut votes: BTreeMap<_, BTreeMap<_, _>> = accounts
+        .into_iter()
+        .map(|account| (account, BTreeMap::new()))
+        .collect();
+    for (name, response) in responses.collect::<Vec<_>>().await {
+        let response = match response {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+        for (account, info) in response.responses {
+            if let (Some(balances), Ok(info)) = (votes.get_mut(&account), info) {
+                balances.insert(name, info.balance);
+            }
+        }
+    }
+    votes
+        .into_iter()
+        .map(|(account, balances)| {
+            let balance = committee.get_strong_majority_lower_bound(balances.into_iter().collect());
+            (account, balance)
+        })
+        .collect()
 }
 
 pub struct ClientState<AuthorityClient> {
@@ -50,6 +306,14 @@ pub struct ClientState<AuthorityClient> {
     next_sequence_number: SequenceNumber,
     /// Pending transfer.
     pending_transfer: Option<TransferOrder>,
+    /// Expiration of the new transfers that we create, if any.
+    valid_until: Option<VersionNumber>,
+    /// Fee paid for each of the new transfers that we create.
+    fee: Amount,
+    /// Smallest transfer amount accepted by the authorities.
+    min_transfer_amount: Amount,
+    /// Version of the next freeze order that we create.
+    next_freeze_version: SequenceNumber,
 
     // The remaining fields are used to minimize networking, and may not always be persisted locally.
     /// Transfer certificates that we have created ("sent").
@@ -58,11 +322,35 @@ pub struct ClientState<AuthorityClient> {
     /// Known received certificates, indexed by sender and sequence number.
     /// TODO: API to search and download yet unknown `received_certificates`.
     received_certificates: BTreeMap<(FastPayAddress, SequenceNumber), CertifiedTransferOrder>,
+    /// Number of received certificates already downloaded from each authority.
+    received_trackers: HashMap<AuthorityName, usize>,
     /// The known spendable balance (including a possible initial funding, excluding unknown sent
     /// or received certificates).
     balance: Balance,
 }
 
+/// A client that only observes a FastPay account. It holds no key pair, hence it cannot
+/// initiate or confirm transfers on behalf of the account.
+pub struct ReadOnlyClientState<AuthorityClient> {
+    /// The FastPay address to observe.
+    address: FastPayAddress,
+    /// Our FastPay committee.
+    committee: Committee,
+    /// How to talk to this committee.
+    authority_clients: HashMap<AuthorityName, AuthorityClient>,
+}
+
+/// The expected outcome of a transfer, as computed by `Client::simulate_transfer`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct SimulationReport {
+    /// Sequence number that the transfer would use.
+    pub sequence_number: SequenceNumber,
+    /// Spendable balance once the transfer is executed.
+    pub new_balance: Balance,
+    /// Expected sequence number for the next transfer.
+    pub new_next_sequence_number: SequenceNumber,
+}
+
 // Operations are considered successful when they successfully reach a quorum of authorities.
 pub trait Client {
     /// Send money to a FastPay account.
@@ -87,6 +375,10 @@ pub trait Client {
         certificate: CertifiedTransferOrder,
     ) -> AsyncResult<(), failure::Error>;
 
+    /// Download and receive all the transfers sent to us that we did not know about yet.
+    /// Returns the number of newly received certificates. Pending transfers are not affected.
+    fn receive_all(&mut self) -> AsyncResult<usize, failure::Error>;
+
     /// Send money to a FastPay account.
     /// Do not check balance. (This may block the client)
     /// Do not confirm the transaction.
@@ -101,6 +393,58 @@ pub trait Client {
     /// TODO: Currently, this value only reflects received transfers that were
     /// locally processed by `receive_from_fastpay`.
     fn get_spendable_amount(&mut self) -> AsyncResult<Amount, failure::Error>;
+
+    /// Find how much voting weight currently backs our pending transfer, and whether
+    /// it is enough to form a certificate.
+    fn pending_transfer_votes(&mut self) -> AsyncResult<(usize, bool), failure::Error>;
+
+    /// Check that a transfer would succeed without creating or sending a transfer order.
+    /// Only synchronizes the spendable balance like `get_spendable_amount`.
+    fn simulate_transfer(
+        &mut self,
+        amount: Amount,
+        recipient: Address,
+    ) -> AsyncResult<SimulationReport, failure::Error>;
+
+    /// Stop (or resume) the signing of our outgoing transfers by a quorum of authorities.
+    /// Incoming transfers are still accepted while the account is frozen.
+    fn set_frozen(&mut self, frozen: bool) -> AsyncResult<(), failure::Error>;
+
+    /// Catch up with the highest sequence number known to a quorum of authorities,
+    /// downloading the missing sent certificates in parallel. Returns the new next
+    /// sequence number.
+    /// Authorities may prune the certificates covered by a checkpoint of the account (see
+    /// `AuthorityState::prune_confirmed_log`). These cannot be downloaded anymore: the
+    /// client must keep them, otherwise this fails with `CertificatePruned`.
+    fn fast_forward(&mut self) -> AsyncResult<SequenceNumber, failure::Error>;
+}
+
+// Operations that do not require the key of the account.
+pub trait ReadOnlyClient {
+    /// Find the highest balance that is backed by a quorum of authorities.
+    fn get_strong_majority_balance(&mut self) -> AsyncResult<Balance, failure::Error>;
+
+    /// Download the certificates sent by the account, up to the highest sequence number
+    /// that is known to a quorum of authorities.
+    /// Fails with `CertificatePruned` if authorities have pruned some of them: see
+    /// `Client::fast_forward`.
+    fn download_sent_certificates(
+        &mut self,
+    ) -> AsyncResult<Vec<CertifiedTransferOrder>, failure::Error>;
+
+    /// Download the valid certificates received by the account and known to at least one
+    /// authority.
+    fn download_received_certificates(
+        &mut self,
+    ) -> AsyncResult<Vec<CertifiedTransferOrder>, failure::Error>;
+
+    /// Obtain a snapshot of the account signed by a quorum of authorities.
+    fn certify_account_snapshot(&mut self)
+        -> AsyncResult<CertifiedAccountSnapshot, failure::Error>;
+
+    /// Ask the known authorities for their committee, until a quorum of our current
+    /// committee vouches for the same one.
+    fn discover_committee(&mut self) -> AsyncResult<CertifiedCommittee, failure::Error>;
 }
 
 impl<A> ClientState<A> {
@@ -122,11 +466,16 @@ impl<A> ClientState<A> {
             authority_clients,
             next_sequence_number,
             pending_transfer: None,
+            valid_until: None,
+            fee: Amount::zero(),
+            min_transfer_amount: Amount::from(1),
+            next_freeze_version: SequenceNumber::new(),
             sent_certificates,
             received_certificates: received_certificates
                 .into_iter()
                 .map(|cert| (cert.key(), cert))
                 .collect(),
+            received_trackers: HashMap::new(),
             balance,
         }
     }
@@ -143,6 +492,37 @@ impl<A> ClientState<A> {
         self.balance
     }
 
+    /// Make new transfers expire after the given transaction index of the Primary blockchain.
+    pub fn set_valid_until(&mut self, valid_until: Option<VersionNumber>) {
+        self.valid_until = valid_until;
+    }
+
+    /// Pay the given fee for each new transfer, in addition to the transferred amount.
+    pub fn set_fee(&mut self, fee: Amount) {
+        self.fee = fee;
+    }
+
+    /// Use the minimal transfer amount configured by the authorities.
+    pub fn set_min_transfer_amount(&mut self, min_transfer_amount: Amount) {
+        self.min_transfer_amount = min_transfer_amount;
+    }
+
+    pub fn min_transfer_amount(&self) -> Amount {
+        self.min_transfer_amount
+    }
+
+    /// Check that the authorities accept transfers of the given amount, e.g. to validate
+    /// user inputs before making a transfer.
+    pub fn check_transfer_amount(&self, amount: Amount) -> Result<(), FastPayError> {
+        fp_ensure!(
+            amount >= self.min_transfer_amount,
+            FastPayError::AmountBelowMinimum {
+                minimum: self.min_transfer_amount
+            }
+        );
+        Ok(())
+    }
+
     pub fn pending_transfer(&self) -> &Option<TransferOrder> {
         &self.pending_transfer
     }
@@ -156,6 +536,35 @@ impl<A> ClientState<A> {
     }
 }
 
+impl<A: Clone> ClientState<A> {
+    /// A client observing the same account without access to its key.
+    pub fn read_only(&self) -> ReadOnlyClientState<A> {
+        ReadOnlyClientState::new(
+            self.address,
+            self.committee.clone(),
+            self.authority_clients.clone(),
+        )
+    }
+}
+
+impl<A> ReadOnlyClientState<A> {
+    pub fn new(
+        address: FastPayAddress,
+        committee: Committee,
+        authority_clients: HashMap<AuthorityName, A>,
+    ) -> Self {
+        Self {
+            address,
+            committee,
+            authority_clients,
+        }
+    }
+
+    pub fn address(&self) -> FastPayAddress {
+        self.address
+    }
+}
+
 #[derive(Clone)]
 struct CertificateRequester<A> {
     committee: Committee,
@@ -180,7 +589,8 @@ where
     type Key = SequenceNumber;
     type Value = Result<CertifiedTransferOrder, FastPayError>;
 
-    /// Try to find a certificate for the given sender and sequence number.
+    /// Try to find a certificate for the given sender and sequence number. Fails with
+    /// `CertificatePruned` if no authority has the certificate and some of them pruned it.
     fn query(
         &mut self,
         sequence_number: SequenceNumber,
@@ -191,16 +601,16 @@ where
                 request_sequence_number: Some(sequence_number),
                 request_received_transfers_excluding_first_nth: None,
             };
+            let mut error = FastPayError::ErrorWhileRequestingCertificate;
             // Sequentially try each authority in random order.
             self.authority_clients.shuffle(&mut rand::thread_rng());
             for client in self.authority_clients.iter_mut() {
                 let result = client.handle_account_info_request(request.clone()).await;
-                if let Ok(AccountInfoResponse {
-                    requested_certificate: Some(certificate),
-                    ..
-                }) = &result
-                {
-                    if certificate.check(&self.committee).is_ok() {
+                match &result {
+                    Ok(AccountInfoResponse {
+                        requested_certificate: Some(certificate),
+                        ..
+                    }) if certificate.check(&self.committee).is_ok() => {
                         let transfer = &certificate.value.transfer;
                         if transfer.sender == self.sender
                             && transfer.sequence_number == sequence_number
@@ -208,9 +618,13 @@ where
                             return Ok(certificate.clone());
                         }
                     }
+                    Err(pruned @ FastPayError::CertificatePruned { .. }) => {
+                        error = pruned.clone();
+                    }
+                    _ => (),
                 }
             }
-            Err(FastPayError::ErrorWhileRequestingCertificate)
+            Err(error)
         })
     }
 }
@@ -243,7 +657,6 @@ where
 
     /// Find the highest sequence number that is known to a quorum of authorities.
     /// NOTE: This is only reliable in the synchronous model, with a sufficient timeout value.
-    #[cfg(test)]
     async fn get_strong_majority_sequence_number(
         &mut self,
         sender: FastPayAddress,
@@ -271,6 +684,30 @@ where
         )
     }
 
+    /// Find the highest freeze version that is known to at least one honest authority.
+    async fn get_validity_freeze_version(&mut self) -> SequenceNumber {
+        let request = AccountInfoRequest {
+            sender: self.address,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+        };
+        let versions: futures::stream::FuturesUnordered<_> = self
+            .authority_clients
+            .iter_mut()
+            .map(|(name, client)| {
+                let fut = client.handle_account_info_request(request.clone());
+                async move {
+                    match fut.await {
+                        Ok(info) => Some((*name, info.next_freeze_version)),
+                        _ => None,
+                    }
+                }
+            })
+            .collect();
+        self.committee
+            .get_validity_lower_bound(versions.filter_map(|x| async move { x }).collect().await)
+    }
+
     /// Find the highest balance that is backed by a quorum of authorities.
     /// NOTE: This is only reliable in the synchronous model, with a sufficient timeout value.
     #[cfg(test)]
@@ -298,6 +735,67 @@ where
         )
     }
 
+    /// Collect the votes of all authorities for our pending transfer.
+    async fn collect_pending_transfer_votes(&mut self) -> Result<(usize, bool), failure::Error> {
+        let order = match &self.pending_transfer {
+            Some(order) => order.clone(),
+            None => bail!("No pending transfer"),
+        };
+        let request = AccountInfoRequest {
+            sender: self.address,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+        };
+        let committee = &self.committee;
+        let votes: futures::stream::FuturesUnordered<_> = self
+            .authority_clients
+            .iter_mut()
+            .map(|(name, client)| {
+                let fut = client.handle_account_info_request(request.clone());
+                let order = &order;
+                async move {
+                    let vote = fut.await.ok()?.pending_confirmation?;
+                    if vote.authority != *name || vote.value != *order {
+                        return None;
+                    }
+                    vote.check(committee).ok()
+                }
+            })
+            .collect();
+        let weight: usize = votes
+            .filter_map(|x| async move { x })
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .sum();
+        Ok((weight, weight >= committee.quorum_threshold()))
+    }
+
+    /// Whether a quorum of authorities refuses to sign our pending transfer because it
+    /// expired. Authorities never sign an expired transfer, so it cannot be certified.
+    async fn is_pending_transfer_expired(&mut self) -> bool {
+        let order = match &self.pending_transfer {
+            Some(order) if order.transfer.valid_until.is_some() => order.clone(),
+            _ => return false,
+        };
+        let committee = &self.committee;
+        let weights: futures::stream::FuturesUnordered<_> = self
+            .authority_clients
+            .iter_mut()
+            .map(|(name, client)| {
+                let fut = client.handle_transfer_order(order.clone());
+                async move {
+                    match fut.await {
+                        Err(FastPayError::ExpiredTransferOrder { .. }) => committee.weight(name),
+                        _ => 0,
+                    }
+                }
+            })
+            .collect();
+        let weight: usize = weights.collect::<Vec<_>>().await.into_iter().sum();
+        weight >= committee.quorum_threshold()
+    }
+
     /// Execute a sequence of actions in parallel for a quorum of authorities.
     async fn communicate_with_quorum<'a, V, F>(
         &'a mut self,
@@ -405,9 +903,14 @@ where
                     }
                     // Send all missing confirmation orders.
                     missing_certificates.reverse();
-                    for certificate in missing_certificates {
+                    if !missing_certificates.is_empty() {
                         client
-                            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+                            .handle_confirmation_orders(
+                                missing_certificates
+                                    .into_iter()
+                                    .map(ConfirmationOrder::new)
+                                    .collect(),
+                            )
                             .await?;
                     }
                     // Send the transfer order (if any) and return a vote.
@@ -485,6 +988,86 @@ where
         Ok(sent_certificates)
     }
 
+    /// Same as `download_sent_certificates` but up to the given sequence number, and
+    /// querying all the missing certificates at the same time.
+    async fn download_sent_certificates_in_parallel(
+        &self,
+        next_sequence_number: SequenceNumber,
+    ) -> Result<Vec<CertifiedTransferOrder>, FastPayError> {
+        let requester = CertificateRequester::new(
+            self.committee.clone(),
+            self.authority_clients.values().cloned().collect(),
+            self.address,
+        );
+        let known_sequence_numbers: BTreeSet<_> = self
+            .sent_certificates
+            .iter()
+            .map(|cert| cert.value.transfer.sequence_number)
+            .collect();
+        let mut queries = Vec::new();
+        let mut number = SequenceNumber::from(0);
+        while number < next_sequence_number {
+            if !known_sequence_numbers.contains(&number) {
+                let mut requester = requester.clone();
+                queries.push(async move { requester.query(number).await });
+            }
+            number = number.increment().unwrap_or_else(|_| SequenceNumber::max());
+        }
+        let mut sent_certificates = self.sent_certificates.clone();
+        for result in futures::future::join_all(queries).await {
+            sent_certificates.push(result?);
+        }
+        sent_certificates.sort_by_key(|cert| cert.value.transfer.sequence_number);
+        Ok(sent_certificates)
+    }
+
+    /// Process a certificate sent to us and update the local balance.
+    /// Returns whether the certificate was new.
+    async fn receive_certificate(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> Result<bool, failure::Error> {
+        certificate.check(&self.committee)?;
+        let transfer = &certificate.value.transfer;
+        ensure!(
+            transfer.recipient == Address::FastPay(self.address),
+            "Transfer should be received by us."
+        );
+        ensure!(
+            !transfer.is_self_transfer(),
+            "Transfers to ourselves are accounted for when sending them."
+        );
+        self.communicate_transfers(
+            transfer.sender,
+            vec![certificate.clone()],
+            CommunicateAction::SynchronizeNextSequenceNumber(
+                certificate.value.transfer.sequence_number.increment()?,
+            ),
+        )
+        .await?;
+        // Everything worked: update the local balance.
+        self.credit_received_certificate(certificate)
+    }
+
+    /// Credit the amount of a received certificate, unless it was already received.
+    /// Returns whether the balance was updated.
+    fn credit_received_certificate(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> Result<bool, failure::Error> {
+        let transfer = &certificate.value.transfer;
+        ensure!(
+            transfer.recipient == Address::FastPay(self.address),
+            "Transfer should be received by us."
+        );
+        if let btree_map::Entry::Vacant(entry) = self.received_certificates.entry(transfer.key()) {
+            self.balance = self.balance.try_add(transfer.amount.into())?;
+            entry.insert(certificate);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     /// Send money to a FastPay or Primary recipient.
     async fn transfer(
         &mut self,
@@ -492,13 +1075,15 @@ where
         recipient: Address,
         user_data: UserData,
     ) -> Result<CertifiedTransferOrder, failure::Error> {
+        self.check_transfer_amount(amount)?;
         // Trying to overspend may block the account. To prevent this, we compare with
         // the balance as we know it.
         let safe_amount = self.get_spendable_amount().await?;
         ensure!(
-            amount <= safe_amount,
-            "Requested amount ({:?}) is not backed by sufficient funds ({:?})",
+            amount.try_add(self.fee)? <= safe_amount,
+            "Requested amount ({:?}) and fee ({:?}) are not backed by sufficient funds ({:?})",
             amount,
+            self.fee,
             safe_amount
         );
         let transfer = Transfer {
@@ -507,6 +1092,8 @@ where
             amount,
             sequence_number: self.next_sequence_number,
             user_data,
+            valid_until: self.valid_until,
+            fee: self.fee,
         };
         let order = TransferOrder::new(transfer, &self.secret);
         let certificate = self
@@ -525,7 +1112,7 @@ where
         let mut new_balance = self.balance;
         let mut new_next_sequence_number = self.next_sequence_number;
         for new_cert in &sent_certificates {
-            new_balance = new_balance.try_sub(new_cert.value.transfer.amount.into())?;
+            new_balance = new_balance.try_sub(new_cert.value.transfer.debited_amount()?.into())?;
             if new_cert.value.transfer.sequence_number >= new_next_sequence_number {
                 new_next_sequence_number = new_cert
                     .value
@@ -536,7 +1123,7 @@ where
             }
         }
         for old_cert in &self.sent_certificates {
-            new_balance = new_balance.try_add(old_cert.value.transfer.amount.into())?;
+            new_balance = new_balance.try_add(old_cert.value.transfer.debited_amount()?.into())?;
         }
         // Atomic update
         self.sent_certificates = sent_certificates;
@@ -551,6 +1138,9 @@ where
     }
 
     /// Execute (or retry) a transfer order. Update local balance.
+    /// If this future is dropped before completion, `pending_transfer` is left set and the
+    /// next call to `get_spendable_amount` (e.g. by a new transfer) resumes the transfer.
+    /// All other updates of the local state happen without awaiting in between.
     async fn execute_transfer(
         &mut self,
         order: TransferOrder,
@@ -565,13 +1155,23 @@ where
             "Unexpected sequence number"
         );
         self.pending_transfer = Some(order.clone());
-        let new_sent_certificates = self
+        let new_sent_certificates = match self
             .communicate_transfers(
                 self.address,
                 self.sent_certificates.clone(),
                 CommunicateAction::SendOrder(order.clone()),
             )
-            .await?;
+            .await
+        {
+            Ok(certificates) => certificates,
+            Err(error) => {
+                if self.is_pending_transfer_expired().await {
+                    // No certificate can be formed anymore: free the sequence number.
+                    self.pending_transfer = None;
+                }
+                return Err(error);
+            }
+        };
         assert_eq!(new_sent_certificates.last().unwrap().value, order);
         // Clear `pending_transfer` and update `sent_certificates`,
         // `balance`, and `next_sequence_number`. (Note that if we were using persistent
@@ -634,37 +1234,157 @@ where
         })
     }
 
-    fn receive_from_fastpay(
+    fn pending_transfer_votes(&mut self) -> AsyncResult<(usize, bool), failure::Error> {
+        Box::pin(self.collect_pending_transfer_votes())
+    }
+
+    fn simulate_transfer(
         &mut self,
-        certificate: CertifiedTransferOrder,
-    ) -> AsyncResult<(), failure::Error> {
+        amount: Amount,
+        recipient: Address,
+    ) -> AsyncResult<SimulationReport, failure::Error> {
         Box::pin(async move {
-            certificate.check(&self.committee)?;
-            let transfer = &certificate.value.transfer;
             ensure!(
-                transfer.recipient == Address::FastPay(self.address),
-                "Transfer should be received by us."
+                amount > Amount::zero(),
+                "Cannot transfer a zero amount to {:?}",
+                recipient
             );
-            self.communicate_transfers(
-                transfer.sender,
-                vec![certificate.clone()],
-                CommunicateAction::SynchronizeNextSequenceNumber(
-                    certificate.value.transfer.sequence_number.increment()?,
-                ),
-            )
+            self.check_transfer_amount(amount)?;
+            let safe_amount = self.get_spendable_amount().await?;
+            let transfer = Transfer {
+                sender: self.address,
+                recipient,
+                amount,
+                sequence_number: self.next_sequence_number,
+                user_data: UserData::default(),
+                valid_until: self.valid_until,
+                fee: self.fee,
+            };
+            ensure!(
+                transfer.total_amount()? <= safe_amount,
+                "Requested amount ({:?}) and fee ({:?}) are not backed by sufficient funds ({:?})",
+                amount,
+                self.fee,
+                safe_amount
+            );
+            Ok(SimulationReport {
+                sequence_number: transfer.sequence_number,
+                new_balance: self.balance.try_sub(transfer.debited_amount()?.into())?,
+                new_next_sequence_number: transfer.sequence_number.increment()?,
+            })
+        })
+    }
+
+    fn set_frozen(&mut self, frozen: bool) -> AsyncResult<(), failure::Error> {
+        Box::pin(async move {
+            // Our local version may be stale, e.g. after a restart.
+            self.next_freeze_version = std::cmp::max(
+                self.get_validity_freeze_version().await,
+                self.next_freeze_version,
+            );
+            let freeze = Freeze {
+                account: self.address,
+                frozen,
+                version: self.next_freeze_version,
+            };
+            let order = FreezeOrder::new(freeze, &self.secret);
+            self.communicate_with_quorum(|_, client| {
+                let order = order.clone();
+                Box::pin(async move { client.handle_freeze_order(order).await })
+            })
             .await?;
-            // Everything worked: update the local balance.
-            let transfer = &certificate.value.transfer;
-            if let btree_map::Entry::Vacant(entry) =
-                self.received_certificates.entry(transfer.key())
-            {
-                self.balance = self.balance.try_add(transfer.amount.into())?;
-                entry.insert(certificate);
+            self.next_freeze_version = self.next_freeze_version.increment()?;
+            Ok(())
+        })
+    }
+
+    fn fast_forward(&mut self) -> AsyncResult<SequenceNumber, failure::Error> {
+        Box::pin(async move {
+            if let Some(order) = self.pending_transfer.clone() {
+                // Finish executing the previous transfer.
+                self.execute_transfer(order, /* with_confirmation */ false)
+                    .await?;
+            }
+            let next_sequence_number = std::cmp::max(
+                self.get_strong_majority_sequence_number(self.address).await,
+                self.next_sequence_number,
+            );
+            if self.sent_certificates.len() < next_sequence_number.into() {
+                let new_sent_certificates = self
+                    .download_sent_certificates_in_parallel(next_sequence_number)
+                    .await?;
+                self.update_sent_certificates(new_sent_certificates)?;
             }
+            Ok(self.next_sequence_number)
+        })
+    }
+
+    fn receive_from_fastpay(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> AsyncResult<(), failure::Error> {
+        Box::pin(async move {
+            self.receive_certificate(certificate).await?;
             Ok(())
         })
     }
 
+    fn receive_all(&mut self) -> AsyncResult<usize, failure::Error> {
+        Box::pin(async move {
+            let mut count = 0;
+            loop {
+                let address = self.address;
+                let trackers = &self.received_trackers;
+                let requests: futures::stream::FuturesUnordered<_> = self
+                    .authority_clients
+                    .iter_mut()
+                    .map(|(name, client)| {
+                        let request = AccountInfoRequest {
+                            sender: address,
+                            request_sequence_number: None,
+                            request_received_transfers_excluding_first_nth: Some(
+                                trackers.get(name).cloned().unwrap_or(0),
+                            ),
+                        };
+                        let fut = client.handle_account_info_request(request);
+                        async move { (*name, fut.await) }
+                    })
+                    .collect();
+                let responses: Vec<_> = requests.collect().await;
+                let mut batches = Vec::new();
+                for (name, response) in responses {
+                    if let Ok(info) = response {
+                        let transfers = info.requested_received_transfers;
+                        if !transfers.is_empty() {
+                            batches.push((name, transfers));
+                        }
+                    }
+                }
+                if batches.is_empty() {
+                    // All trackers are up to date.
+                    return Ok(count);
+                }
+                for (name, certificates) in batches {
+                    let size = certificates.len();
+                    for certificate in certificates {
+                        if certificate.value.transfer.recipient != Address::FastPay(self.address)
+                            || self.received_certificates.contains_key(&certificate.key())
+                            || certificate.check(&self.committee).is_err()
+                        {
+                            continue;
+                        }
+                        if self.receive_certificate(certificate).await? {
+                            count += 1;
+                        }
+                    }
+                    // Only skip the certificates of this authority once all of them were
+                    // received, so that failures are retried by the next call.
+                    *self.received_trackers.entry(name).or_insert(0) += size;
+                }
+            }
+        })
+    }
+
     fn transfer_to_fastpay_unsafe_unconfirmed(
         &mut self,
         amount: Amount,
@@ -678,6 +1398,8 @@ where
                 amount,
                 sequence_number: self.next_sequence_number,
                 user_data,
+                valid_until: self.valid_until,
+                fee: self.fee,
             };
             let order = TransferOrder::new(transfer, &self.secret);
             let new_certificate = self
@@ -687,3 +1409,224 @@ where
         })
     }
 }
+
+impl<A> ReadOnlyClientState<A>
+where
+    A: AuthorityClient + Send + Sync + 'static + Clone,
+{
+    /// Query the account on every authority and collect the successful answers.
+    async fn query_all(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> Vec<(AuthorityName, AccountInfoResponse)> {
+        let responses: futures::stream::FuturesUnordered<_> = self
+            .authority_clients
+            .iter_mut()
+            .map(|(name, client)| {
+                let fut = client.handle_account_info_request(request.clone());
+                async move {
+                    match fut.await {
+                        Ok(info) => Some((*name, info)),
+                        _ => None,
+                    }
+                }
+            })
+            .collect();
+        responses.filter_map(|x| async move { x }).collect().await
+    }
+
+    /// Poll the balance backed by a quorum of authorities every `poll_interval`. The
+    /// stream yields the first balance obtained, then each new value whenever it
+    /// changes. Polling stops as soon as the stream is dropped.
+    pub fn watch_balance(
+        &mut self,
+        poll_interval: Duration,
+    ) -> impl futures::Stream<Item = Balance> + '_ {
+        let timer = tokio::time::interval(poll_interval);
+        futures::stream::unfold(
+            (self, timer, None),
+            |(client, mut timer, last_balance)| async move {
+                loop {
+                    timer.tick().await;
+                    if let Ok(balance) = client.get_strong_majority_balance().await {
+                        if last_balance != Some(balance) {
+                            return Some((balance, (client, timer, Some(balance))));
+                        }
+                    }
+                }
+            },
+        )
+    }
+}
+
+impl<A> ReadOnlyClient for ReadOnlyClientState<A>
+where
+    A: AuthorityClient + Send + Sync + 'static + Clone,
+{
+    fn get_strong_majority_balance(&mut self) -> AsyncResult<Balance, failure::Error> {
+        Box::pin(async move {
+            let request = AccountInfoRequest {
+                sender: self.address,
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: None,
+            };
+            let balances = self
+                .query_all(request)
+                .await
+                .into_iter()
+                .map(|(name, info)| (name, info.balance))
+                .collect();
+            Ok(self.committee.get_strong_majority_lower_bound(balances))
+        })
+    }
+
+    fn download_sent_certificates(
+        &mut self,
+    ) -> AsyncResult<Vec<CertifiedTransferOrder>, failure::Error> {
+        Box::pin(async move {
+            let request = AccountInfoRequest {
+                sender: self.address,
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: None,
+            };
+            let numbers = self
+                .query_all(request)
+                .await
+                .into_iter()
+                .map(|(name, info)| (name, info.next_sequence_number))
+                .collect();
+            let next_sequence_number = self.committee.get_strong_majority_lower_bound(numbers);
+            let mut requester = CertificateRequester::new(
+                self.committee.clone(),
+                self.authority_clients.values().cloned().collect(),
+                self.address,
+            );
+            let mut sent_certificates = Vec::new();
+            let mut number = SequenceNumber::from(0);
+            while number < next_sequence_number {
+                sent_certificates.push(requester.query(number).await?);
+                number = number.increment()?;
+            }
+            Ok(sent_certificates)
+        })
+    }
+
+    fn download_received_certificates(
+        &mut self,
+    ) -> AsyncResult<Vec<CertifiedTransferOrder>, failure::Error> {
+        Box::pin(async move {
+            let request = AccountInfoRequest {
+                sender: self.address,
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: Some(0),
+            };
+            let mut received_certificates = BTreeMap::new();
+            for (_, info) in self.query_all(request).await {
+                for certificate in info.requested_received_transfers {
+                    if certificate.value.transfer.recipient == Address::FastPay(self.address)
+                        && certificate.check(&self.committee).is_ok()
+                    {
+                        received_certificates.insert(certificate.key(), certificate);
+                    }
+                }
+            }
+            Ok(received_certificates.into_values().collect())
+        })
+    }
+
+    fn certify_account_snapshot(
+        &mut self,
+    ) -> AsyncResult<CertifiedAccountSnapshot, failure::Error> {
+        Box::pin(async move {
+            let request = AccountSnapshotRequest {
+                account: self.address,
+            };
+            let committee = &self.committee;
+            let mut votes: futures::stream::FuturesUnordered<_> = self
+                .authority_clients
+                .values_mut()
+                .map(|client| client.handle_account_snapshot_request(request.clone()))
+                .collect();
+            // Authorities may disagree while transfers are in flight: aggregate each
+            // version of the account separately.
+            let mut aggregators = HashMap::new();
+            while let Some(vote) = votes.next().await {
+                let SignedAccountSnapshot {
+                    value,
+                    authority,
+                    signature,
+                } = match vote {
+                    Ok(vote) => vote,
+                    Err(_) => continue,
+                };
+                let aggregator = match aggregators.entry(value.clone()) {
+                    hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                    hash_map::Entry::Vacant(entry) => {
+                        entry.insert(SignatureAggregator::try_new(value, committee)?)
+                    }
+                };
+                if let Ok(Some(certificate)) = aggregator.append(authority, signature) {
+                    return Ok(certificate);
+                }
+            }
+            bail!("Failed to obtain a quorum of signatures for the same account snapshot");
+        })
+    }
+
+    fn discover_committee(&mut self) -> AsyncResult<CertifiedCommittee, failure::Error> {
+        Box::pin(async move {
+            let committee = &self.committee;
+            let mut votes: futures::stream::FuturesUnordered<_> = self
+                .authority_clients
+                .values_mut()
+                .map(|client| client.handle_committee_request(CommitteeRequest))
+                .collect();
+            // Authorities may report different committees, e.g. during reconfiguration:
+            // aggregate each of them separately.
+            let mut partial_certificates: HashMap<
+                CommitteeDescription,
+                (usize, CertifiedCommittee),
+            > = HashMap::new();
+            while let Some(vote) = votes.next().await {
+                let vote = match vote {
+                    Ok(vote) => vote,
+                    Err(_) => continue,
+                };
+                let weight = match vote.check(committee) {
+                    Ok(weight) => weight,
+                    Err(_) => continue,
+                };
+                if vote.value.epoch != CURRENT_EPOCH {
+                    // Stale or future committees cannot be used yet.
+                    continue;
+                }
+                let (total_weight, certificate) = partial_certificates
+                    .entry(vote.value.clone())
+                    .or_insert_with(|| {
+                        (
+                            0,
+                            CertifiedCommittee {
+                                value: vote.value.clone(),
+                                signatures: Vec::new(),
+                            },
+                        )
+                    });
+                if certificate
+                    .signatures
+                    .iter()
+                    .any(|(name, _)| *name == vote.authority)
+                {
+                    continue;
+                }
+                *total_weight += weight;
+                certificate
+                    .signatures
+                    .push((vote.authority, vote.signature));
+                if *total_weight >= committee.quorum_threshold() {
+                    return Ok(certificate.clone());
+                }
+            }
+            bail!("Failed to obtain a quorum of signatures for the same committee");
+        })
+    }
+}
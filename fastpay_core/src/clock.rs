@@ -0,0 +1,75 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::base_types::VersionNumber;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The source of time used by the time-dependent checks of an authority.
+pub trait Clock: Send + Sync {
+    /// Physical time, e.g. for rate limiting.
+    fn now(&self) -> Instant;
+
+    /// Logical time against which transfer orders expire, given the last transaction
+    /// index of the Primary blockchain seen by the authority.
+    fn transaction_index(&self, last_transaction_index: VersionNumber) -> VersionNumber {
+        last_transaction_index
+    }
+}
+
+/// Read the time of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves forward when told so, e.g. in tests. Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+    /// Number of transactions added to the index seen by the authority.
+    transaction_offset: Arc<Mutex<u64>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+            transaction_offset: Arc::new(Mutex::new(0)),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// Move the logical time forward by the given number of transactions.
+    pub fn advance_transaction_index(&self, count: u64) {
+        *self.transaction_offset.lock().unwrap() += count;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn transaction_index(&self, last_transaction_index: VersionNumber) -> VersionNumber {
+        let offset = *self.transaction_offset.lock().unwrap();
+        last_transaction_index
+            .checked_add(offset)
+            .unwrap_or_else(|_| VersionNumber::max())
+    }
+}
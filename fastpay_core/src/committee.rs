@@ -1,8 +1,12 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::base_types::*;
-use std::collections::BTreeMap;
+use super::{base_types::*, error::FastPayError};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(test)]
+#[path = "unit_tests/committee_tests.rs"]
+mod committee_tests;
 
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub struct Committee {
@@ -10,6 +14,45 @@ pub struct Committee {
     pub total_votes: usize,
 }
 
+/// Build a committee while checking its voting rights.
+#[derive(Default)]
+pub struct CommitteeBuilder {
+    authorities: Vec<(AuthorityName, usize)>,
+}
+
+impl CommitteeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_authority(mut self, name: AuthorityName, weight: usize) -> Self {
+        self.authorities.push((name, weight));
+        self
+    }
+
+    /// Check that the committee is not empty, that every authority appears once with a
+    /// positive weight, and that the total weight does not overflow.
+    pub fn build(self) -> Result<Committee, FastPayError> {
+        fp_ensure!(!self.authorities.is_empty(), FastPayError::EmptyCommittee);
+        let mut voting_rights = BTreeMap::new();
+        let mut total_votes: usize = 0;
+        for (authority, weight) in self.authorities {
+            fp_ensure!(weight > 0, FastPayError::InvalidVotingRights { authority });
+            fp_ensure!(
+                voting_rights.insert(authority, weight).is_none(),
+                FastPayError::DuplicateAuthority { authority }
+            );
+            total_votes = total_votes
+                .checked_add(weight)
+                .ok_or(FastPayError::VotingRightsOverflow)?;
+        }
+        Ok(Committee {
+            voting_rights,
+            total_votes,
+        })
+    }
+}
+
 impl Committee {
     pub fn new(voting_rights: BTreeMap<AuthorityName, usize>) -> Self {
         let total_votes = voting_rights.iter().fold(0, |sum, (_, votes)| sum + *votes);
@@ -19,6 +62,51 @@ impl Committee {
         }
     }
 
+    /// Allocate `total_voting_units` votes in proportion to the given stakes, using the
+    /// largest remainder method: each authority first receives the integer part of its
+    /// share, then the remaining units go to the largest fractional parts. Equal
+    /// fractional parts are served in the order of authority names. Authorities that
+    /// receive no vote are left out of the committee.
+    pub fn from_stakes(
+        stakes: BTreeMap<AuthorityName, u128>,
+        total_voting_units: usize,
+    ) -> Result<Self, FastPayError> {
+        let total_stake = stakes.values().try_fold(0u128, |sum, stake| {
+            sum.checked_add(*stake)
+                .ok_or(FastPayError::VotingRightsOverflow)
+        })?;
+        fp_ensure!(
+            total_stake > 0 && total_voting_units > 0,
+            FastPayError::EmptyCommittee
+        );
+        let mut allocation = Vec::new();
+        let mut allocated = 0;
+        for (name, stake) in stakes {
+            let product = stake
+                .checked_mul(total_voting_units as u128)
+                .ok_or(FastPayError::VotingRightsOverflow)?;
+            let votes = (product / total_stake) as usize;
+            allocated += votes;
+            allocation.push((name, votes, product % total_stake));
+        }
+        // Stable sort: ties keep the order of names.
+        let mut by_remainder: Vec<_> = (0..allocation.len()).collect();
+        by_remainder.sort_by(|i, j| allocation[*j].2.cmp(&allocation[*i].2));
+        for index in by_remainder
+            .into_iter()
+            .take(total_voting_units - allocated)
+        {
+            allocation[index].1 += 1;
+        }
+        allocation
+            .into_iter()
+            .filter(|(_, votes, _)| *votes > 0)
+            .fold(CommitteeBuilder::new(), |builder, (name, votes, _)| {
+                builder.add_authority(name, votes)
+            })
+            .build()
+    }
+
     pub fn weight(&self, author: &AuthorityName) -> usize {
         *self.voting_rights.get(author).unwrap_or(&0)
     }
@@ -35,8 +123,48 @@ impl Committee {
         (self.total_votes + 2) / 3
     }
 
+    /// Compute the total voting rights of a set of authorities. Unknown authorities count for zero.
+    fn set_weight<'a, I>(&self, authorities: I) -> usize
+    where
+        I: IntoIterator<Item = &'a AuthorityName>,
+    {
+        authorities
+            .into_iter()
+            .fold(0, |sum, name| sum + self.weight(name))
+    }
+
+    /// Whether the given set of authorities holds a quorum of the voting rights.
+    pub fn is_quorum(&self, set: &BTreeSet<AuthorityName>) -> bool {
+        self.set_weight(set) >= self.quorum_threshold()
+    }
+
+    /// Whether the intersection of two sets of authorities is guaranteed to contain at least
+    /// one honest authority, i.e. its voting rights reach the validity threshold.
+    pub fn quorums_intersect(
+        &self,
+        set_a: &BTreeSet<AuthorityName>,
+        set_b: &BTreeSet<AuthorityName>,
+    ) -> bool {
+        self.set_weight(set_a.intersection(set_b)) >= self.validity_threshold()
+    }
+
     /// Find the highest value than is supported by a quorum of authorities.
-    pub fn get_strong_majority_lower_bound<V>(&self, mut values: Vec<(AuthorityName, V)>) -> V
+    pub fn get_strong_majority_lower_bound<V>(&self, values: Vec<(AuthorityName, V)>) -> V
+    where
+        V: Default + std::cmp::Ord,
+    {
+        self.get_lower_bound(values, self.quorum_threshold())
+    }
+
+    /// Find the highest value than is supported by at least one honest authority.
+    pub fn get_validity_lower_bound<V>(&self, values: Vec<(AuthorityName, V)>) -> V
+    where
+        V: Default + std::cmp::Ord,
+    {
+        self.get_lower_bound(values, self.validity_threshold())
+    }
+
+    fn get_lower_bound<V>(&self, mut values: Vec<(AuthorityName, V)>, threshold: usize) -> V
     where
         V: Default + std::cmp::Ord,
     {
@@ -45,7 +173,7 @@ impl Committee {
         let mut score = 0;
         for (name, value) in values {
             score += self.weight(&name);
-            if score >= self.quorum_threshold() {
+            if score >= threshold {
                 return value;
             }
         }
@@ -12,6 +12,7 @@ use std::collections::BTreeMap;
 mod downloader_tests;
 
 /// An asynchronous downloader that ensures that the value for each key is requested at most once.
+/// Concurrent queries for a key that is being downloaded wait for the same result.
 pub struct Downloader<R, K, V> {
     /// User-provided logics to fetch data.
     requester: R,
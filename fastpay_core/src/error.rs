@@ -36,9 +36,14 @@ pub enum FastPayError {
     #[fail(display = "Transfers must have positive amount")]
     IncorrectTransferAmount,
     #[fail(
-        display = "The given sequence number must match the next expected sequence number of the account"
+        display = "The given sequence number {:?} must match the next expected sequence number {:?} of account {:?}",
+        received_sequence_number, expected_sequence_number, sender
     )]
-    UnexpectedSequenceNumber,
+    UnexpectedSequenceNumber {
+        sender: FastPayAddress,
+        expected_sequence_number: SequenceNumber,
+        received_sequence_number: SequenceNumber,
+    },
     #[fail(
         display = "The transferred amount must be not exceed the current account balance: {:?}",
         current_balance
@@ -86,8 +91,14 @@ pub enum FastPayError {
     BalanceOverflow,
     #[fail(display = "Account balance underflow.")]
     BalanceUnderflow,
-    #[fail(display = "Wrong shard used.")]
-    WrongShard,
+    #[fail(
+        display = "Wrong shard used: account {:?} belongs to shard {}.",
+        address, shard_id
+    )]
+    WrongShard {
+        address: FastPayAddress,
+        shard_id: ShardId,
+    },
     #[fail(display = "Invalid cross shard update.")]
     InvalidCrossShardUpdate,
     #[fail(display = "Cannot deserialize.")]
@@ -96,4 +107,67 @@ pub enum FastPayError {
     UnexpectedMessage,
     #[fail(display = "Network error while querying service: {:?}.", error)]
     ClientIoError { error: String },
+    #[fail(
+        display = "Too many transfer orders for account {:?}. Please retry later.",
+        address
+    )]
+    RateLimited { address: FastPayAddress },
+    #[fail(
+        display = "Transfer order expired at transaction index {:?}",
+        valid_until
+    )]
+    ExpiredTransferOrder { valid_until: VersionNumber },
+    // Committee configuration
+    #[fail(display = "A committee must contain at least one authority.")]
+    EmptyCommittee,
+    #[fail(
+        display = "Authority {:?} must have positive voting rights.",
+        authority
+    )]
+    InvalidVotingRights { authority: AuthorityName },
+    #[fail(display = "Authority {:?} appears twice in the committee.", authority)]
+    DuplicateAuthority { authority: AuthorityName },
+    #[fail(display = "Total voting rights overflow.")]
+    VotingRightsOverflow,
+    // Account storage
+    #[fail(display = "Invalid state for account {:?}: {}", address, reason)]
+    InvalidAccountState {
+        address: FastPayAddress,
+        reason: String,
+    },
+    #[fail(display = "A batch of confirmation orders cannot be empty.")]
+    EmptyConfirmationBatch,
+    // Account freezing
+    #[fail(display = "The account is frozen: outgoing transfers are not accepted.")]
+    AccountFrozen,
+    #[fail(
+        display = "Freeze orders must use a version at least {:?}.",
+        next_freeze_version
+    )]
+    StaleFreezeOrder { next_freeze_version: SequenceNumber },
+    #[fail(display = "The transfer order of a compact confirmation order is unknown.")]
+    UnknownTransferOrder,
+    #[fail(display = "The transfer orders of an equivocation proof do not conflict.")]
+    InvalidEquivocationProof,
+    #[fail(
+        display = "Certificate was pruned after the account snapshot {:?}",
+        snapshot
+    )]
+    CertificatePruned { snapshot: AccountSnapshot },
+    #[fail(display = "The authority is busy forwarding cross-shard updates. Retry later.")]
+    CrossShardQueueFull,
+    #[fail(display = "Transfers must have an amount of at least {:?}.", minimum)]
+    AmountBelowMinimum { minimum: Amount },
+    #[fail(display = "The genesis state can only be loaded before any account is created.")]
+    GenesisAlreadyLoaded,
+    #[fail(display = "No evidence of equivocation is known for this account.")]
+    UnknownEquivocationProof,
+    #[fail(
+        display = "The snapshot was taken by shard {} out of {}.",
+        shard_id, number_of_shards
+    )]
+    WrongSnapshotShard {
+        shard_id: ShardId,
+        number_of_shards: u32,
+    },
 }
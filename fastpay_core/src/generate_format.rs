@@ -16,6 +16,9 @@ fn get_registry() -> Result<Registry> {
     tracer.trace_type::<messages::Address>(&samples)?;
     tracer.trace_type::<error::FastPayError>(&samples)?;
     tracer.trace_type::<serialize::SerializedMessage>(&samples)?;
+    tracer.trace_type::<std::result::Result<messages::AccountInfoResponse, error::FastPayError>>(
+        &samples,
+    )?;
     tracer.registry()
 }
 
@@ -8,9 +8,13 @@ pub mod error;
 
 pub mod authority;
 pub mod base_types;
+pub mod certificate_cache;
 pub mod client;
+pub mod clock;
 pub mod committee;
 pub mod downloader;
 pub mod fastpay_smart_contract;
 pub mod messages;
 pub mod serialize;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
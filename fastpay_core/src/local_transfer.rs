@@ -0,0 +1,42 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Perform a transfer between two accounts using a committee of 4 in-memory authorities.
+
+use fastpay_core::{
+    base_types::*,
+    client::{Client, ClientState},
+    testing::*,
+};
+use tokio::runtime::Runtime;
+
+fn main() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, /* num_shards */ 2);
+    let mut recipient = make_client(authority_clients.clone(), committee.clone());
+    let (address, secret) = get_key_pair();
+    fund_account(&mut authority_clients, address, vec![10; 4]);
+    let mut sender = ClientState::new(
+        address,
+        secret,
+        committee,
+        authority_clients,
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        Balance::from(10),
+    );
+
+    rt.block_on(async {
+        let certificate = sender
+            .transfer_to_fastpay(Amount::from(3), recipient.address(), UserData::default())
+            .await
+            .unwrap();
+        recipient.receive_from_fastpay(certificate).await.unwrap();
+        println!(
+            "Sender can spend {:?}, recipient can spend {:?}",
+            sender.get_spendable_amount().await.unwrap(),
+            recipient.get_spendable_amount().await.unwrap()
+        );
+    });
+}
@@ -1,18 +1,35 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{base_types::*, committee::Committee, error::*};
+use super::{
+    base_types::*,
+    committee::{Committee, CommitteeBuilder},
+    error::*,
+};
 
 #[cfg(test)]
 #[path = "unit_tests/messages_tests.rs"]
 mod messages_tests;
 
-use serde::{Deserialize, Serialize};
+use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::HashSet,
+    collections::{hash_map, HashMap, HashSet},
+    fmt,
     hash::{Hash, Hasher},
+    marker::PhantomData,
 };
 
+/// Maximal number of signatures accepted in a certificate.
+pub const MAX_SIGNATURES_PER_CERTIFICATE: usize = 1000;
+/// Maximal number of received certificates returned by an account information request.
+pub const MAX_RECEIVED_CERTIFICATES_PER_RESPONSE: usize = 1000;
+/// Maximal number of confirmation orders sent in one batch.
+pub const MAX_CONFIRMATION_ORDERS_PER_BATCH: usize = 1000;
+/// Maximal number of accounts queried in one batch.
+pub const MAX_ACCOUNTS_PER_INFO_BATCH: usize = 1000;
+/// The epoch of committees, constant until committees can be reconfigured.
+pub const CURRENT_EPOCH: Epoch = Epoch::new();
+
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct FundingTransaction {
     pub recipient: FastPayAddress,
@@ -40,6 +57,11 @@ pub struct Transfer {
     pub amount: Amount,
     pub sequence_number: SequenceNumber,
     pub user_data: UserData,
+    /// Last transaction index of the Primary blockchain (as seen by an authority) at which
+    /// the transfer may still be signed. Certificates are always executed.
+    pub valid_until: Option<VersionNumber>,
+    /// Paid by the sender in addition to `amount`.
+    pub fee: Amount,
 }
 
 #[derive(Eq, Clone, Debug, Serialize, Deserialize)]
@@ -48,29 +70,213 @@ pub struct TransferOrder {
     pub signature: Signature,
 }
 
-#[derive(Eq, Clone, Debug, Serialize, Deserialize)]
-pub struct SignedTransferOrder {
-    pub value: TransferOrder,
+/// A value signed by an authority, e.g. a vote.
+/// Two signed values are equal if they have the same value and authority.
+#[derive(Clone, Debug)]
+pub struct Signed<T> {
+    pub value: T,
     pub authority: AuthorityName,
     pub signature: Signature,
 }
 
-#[derive(Eq, Clone, Debug, Serialize, Deserialize)]
-pub struct CertifiedTransferOrder {
-    pub value: TransferOrder,
+/// A value signed by a quorum of authorities.
+/// Two certificates are equal if they have the same value and signers, in the same order.
+#[derive(Clone, Debug)]
+pub struct Certified<T> {
+    pub value: T,
     pub signatures: Vec<(AuthorityName, Signature)>,
 }
 
+/// A value that authorities sign with `Signed` and `Certified`.
+pub trait Certifiable {
+    /// The part of the value covered by the signatures of the authorities.
+    type Content: BcsSignable;
+
+    /// Names of `Signed<Self>` and `Certified<Self>` in the serialization format.
+    const SIGNED_NAME: &'static str;
+    const CERTIFIED_NAME: &'static str;
+
+    fn content(&self) -> &Self::Content;
+
+    /// Check the parts of the value that do not depend on the authorities, other than
+    /// `extra_signatures`.
+    fn check_value(&self) -> Result<(), FastPayError> {
+        Ok(())
+    }
+
+    /// Signatures contained in the value, e.g. the signature of the sender of a transfer
+    /// order. Certificates verify them in the same batch as the votes.
+    fn extra_signatures(&self) -> Vec<ExtraSignature> {
+        Vec::new()
+    }
+
+    /// Check the value on its own, including its extra signatures.
+    fn check_value_and_signatures(&self) -> Result<(), FastPayError> {
+        self.check_value()?;
+        self.extra_signatures()
+            .iter()
+            .try_for_each(ExtraSignature::check)
+    }
+}
+
+pub type SignedTransferOrder = Signed<TransferOrder>;
+pub type CertifiedTransferOrder = Certified<TransferOrder>;
+
+/// Confirmation orders to be executed in order by an authority.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ConfirmationOrderBatch {
+    #[serde(deserialize_with = "deserialize_confirmation_orders")]
+    pub orders: Vec<ConfirmationOrder>,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct RedeemTransaction {
     pub transfer_certificate: CertifiedTransferOrder,
 }
 
+/// A cross-shard update, as sent to the shard of the recipient.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CrossShardRequest {
+    pub transfer_certificate: CertifiedTransferOrder,
+    /// Time of sending, in microseconds since the Unix epoch. Only used for monitoring.
+    pub timestamp_us: u64,
+}
+
+/// Acknowledgement of a cross-shard update, sent back to the shard of the sender.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CrossShardAck {
+    pub shard_id: ShardId,
+    pub sender: FastPayAddress,
+    pub sequence_number: SequenceNumber,
+    /// The shard that executed the update.
+    pub updated_shard_id: ShardId,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct ConfirmationOrder {
     pub transfer_certificate: CertifiedTransferOrder,
 }
 
+/// A confirmation order without the transfer order, for authorities that already hold it.
+/// The transfer order is identified by its sender, sequence number, and signature.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CompactConfirmationOrder {
+    pub sender: FastPayAddress,
+    pub sequence_number: SequenceNumber,
+    pub order_signature: Signature,
+    #[serde(deserialize_with = "deserialize_signatures")]
+    pub signatures: Vec<(AuthorityName, Signature)>,
+}
+
+/// The state of an account as seen by an authority.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub account: FastPayAddress,
+    pub balance: Balance,
+    pub next_sequence_number: SequenceNumber,
+}
+
+/// Request of the owner of an account to stop (or resume) the signing of its outgoing
+/// transfers. Incoming transfers are not affected. Authorities ignore versions lower
+/// than the last one applied, so that older orders cannot be replayed.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct Freeze {
+    pub account: FastPayAddress,
+    pub frozen: bool,
+    pub version: SequenceNumber,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct FreezeOrder {
+    pub freeze: Freeze,
+    pub signature: Signature,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct AccountSnapshotRequest {
+    pub account: FastPayAddress,
+}
+
+pub type SignedAccountSnapshot = Signed<AccountSnapshot>;
+/// An account snapshot signed by a quorum of authorities.
+pub type CertifiedAccountSnapshot = Certified<AccountSnapshot>;
+
+/// The voting rights of a committee, ordered by authority name.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct CommitteeDescription {
+    pub epoch: Epoch,
+    #[serde(deserialize_with = "deserialize_voting_rights")]
+    pub voting_rights: Vec<(AuthorityName, usize)>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CommitteeRequest;
+
+/// The committee of an authority, signed by this authority.
+pub type SignedCommittee = Signed<CommitteeDescription>;
+
+/// Liveness and progress of an authority shard, as reported to a `Ping` message.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub shard_id: ShardId,
+    pub number_of_accounts: u64,
+    pub last_transaction_index: VersionNumber,
+    pub uptime_us: u64,
+    /// Number of cross-shard updates received since the last periodic report of the shard.
+    pub cross_shard_updates: u64,
+    /// Upper bounds of the median and 99th percentile of their delivery delays.
+    pub cross_shard_delay_p50_us: u64,
+    pub cross_shard_delay_p99_us: u64,
+}
+
+/// Two different transfer orders signed by the same sender for the same sequence number.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct EquivocationProof {
+    pub first: TransferOrder,
+    pub second: TransferOrder,
+}
+
+/// An equivocation proof observed and signed by an authority, e.g. to be gossiped.
+pub type SignedEquivocationProof = Signed<EquivocationProof>;
+
+/// Ask an authority for the evidence of equivocation that it keeps for an account.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct EquivocationProofRequest {
+    pub sender: FastPayAddress,
+}
+
+/// Proof of payment: the confirmed transfer of a sender, as seen by the authorities.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    pub sender: FastPayAddress,
+    pub sequence_number: SequenceNumber,
+    pub recipient: Address,
+    pub amount: Amount,
+}
+
+/// A receipt signed by an authority that confirmed the transfer.
+pub type SignedReceipt = Signed<Receipt>;
+/// A receipt signed by a quorum of authorities.
+pub type CertifiedReceipt = Certified<Receipt>;
+
+/// A committee description signed by a quorum of a trusted committee.
+pub type CertifiedCommittee = Certified<CommitteeDescription>;
+
+/// The initial balances of FastPay accounts and the parameters that all authorities must
+/// agree on.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct GenesisState {
+    pub accounts: Vec<(FastPayAddress, Balance)>,
+    /// Account credited with the fees of transfers. Fees are burnt if unset.
+    pub fee_recipient: Option<FastPayAddress>,
+}
+
+/// A genesis state signed by an authority.
+pub type SignedGenesisState = Signed<GenesisState>;
+/// A genesis state signed by a quorum of the committee, so that authorities and clients
+/// agree on the initial balances.
+pub type CertifiedGenesisState = Certified<GenesisState>;
+
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct AccountInfoRequest {
     pub sender: FastPayAddress,
@@ -85,7 +291,29 @@ pub struct AccountInfoResponse {
     pub next_sequence_number: SequenceNumber,
     pub pending_confirmation: Option<SignedTransferOrder>,
     pub requested_certificate: Option<CertifiedTransferOrder>,
+    #[serde(deserialize_with = "deserialize_received_transfers")]
     pub requested_received_transfers: Vec<CertifiedTransferOrder>,
+    /// Receipt of the transfer, in response to a confirmation order.
+    pub receipt: Option<SignedReceipt>,
+    /// Whether outgoing transfers are frozen, and the version expected by the next
+    /// `Freeze` order.
+    pub frozen: bool,
+    pub next_freeze_version: SequenceNumber,
+}
+
+/// Query the balance and sequence number of several accounts at once.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct BatchAccountInfoRequest {
+    #[serde(deserialize_with = "deserialize_batch_senders")]
+    pub senders: Vec<FastPayAddress>,
+}
+
+/// The answer for each account of a `BatchAccountInfoRequest`, in the same order.
+/// Accounts of other shards are reported with `WrongShard`.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct BatchAccountInfoResponse {
+    #[serde(deserialize_with = "deserialize_batch_responses")]
+    pub responses: Vec<(FastPayAddress, Result<AccountInfoResponse, FastPayError>)>,
 }
 
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
@@ -106,20 +334,22 @@ impl PartialEq for TransferOrder {
     }
 }
 
-impl Hash for SignedTransferOrder {
+impl<T: Hash> Hash for Signed<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.value.hash(state);
         self.authority.hash(state);
     }
 }
 
-impl PartialEq for SignedTransferOrder {
+impl<T: PartialEq> PartialEq for Signed<T> {
     fn eq(&self, other: &Self) -> bool {
         self.value == other.value && self.authority == other.authority
     }
 }
 
-impl Hash for CertifiedTransferOrder {
+impl<T: Eq> Eq for Signed<T> {}
+
+impl<T: Hash> Hash for Certified<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.value.hash(state);
         self.signatures.len().hash(state);
@@ -129,7 +359,7 @@ impl Hash for CertifiedTransferOrder {
     }
 }
 
-impl PartialEq for CertifiedTransferOrder {
+impl<T: PartialEq> PartialEq for Certified<T> {
     fn eq(&self, other: &Self) -> bool {
         self.value == other.value
             && self.signatures.len() == other.signatures.len()
@@ -141,10 +371,32 @@ impl PartialEq for CertifiedTransferOrder {
     }
 }
 
+impl<T: Eq> Eq for Certified<T> {}
+
 impl Transfer {
     pub fn key(&self) -> (FastPayAddress, SequenceNumber) {
         (self.sender, self.sequence_number)
     }
+
+    /// The amount that the balance of the sender must cover, including the fee.
+    pub fn total_amount(&self) -> Result<Amount, FastPayError> {
+        self.amount.try_add(self.fee)
+    }
+
+    pub fn is_self_transfer(&self) -> bool {
+        self.recipient == Address::FastPay(self.sender)
+    }
+
+    /// The amount actually removed from the balance of the sender. A transfer to oneself
+    /// must be backed by the sender's balance like any other transfer, but only costs
+    /// its fee: the amount is neither debited nor credited.
+    pub fn debited_amount(&self) -> Result<Amount, FastPayError> {
+        if self.is_self_transfer() {
+            Ok(self.fee)
+        } else {
+            self.total_amount()
+        }
+    }
 }
 
 impl TransferOrder {
@@ -161,10 +413,99 @@ impl TransferOrder {
     }
 }
 
-impl SignedTransferOrder {
+impl FreezeOrder {
+    pub fn new(freeze: Freeze, secret: &KeyPair) -> Self {
+        let signature = Signature::new(&freeze, secret);
+        Self { freeze, signature }
+    }
+
+    pub fn check_signature(&self) -> Result<(), FastPayError> {
+        self.signature.check(&self.freeze, self.freeze.account)
+    }
+}
+
+impl Certifiable for TransferOrder {
+    type Content = Transfer;
+    const SIGNED_NAME: &'static str = "SignedTransferOrder";
+    const CERTIFIED_NAME: &'static str = "CertifiedTransferOrder";
+
+    fn content(&self) -> &Transfer {
+        &self.transfer
+    }
+
+    fn extra_signatures(&self) -> Vec<ExtraSignature> {
+        vec![ExtraSignature::new(
+            &self.transfer,
+            self.transfer.sender,
+            self.signature,
+        )]
+    }
+}
+
+impl Certifiable for AccountSnapshot {
+    type Content = Self;
+    const SIGNED_NAME: &'static str = "SignedAccountSnapshot";
+    const CERTIFIED_NAME: &'static str = "CertifiedAccountSnapshot";
+
+    fn content(&self) -> &Self {
+        self
+    }
+}
+
+impl Certifiable for CommitteeDescription {
+    type Content = Self;
+    const SIGNED_NAME: &'static str = "SignedCommittee";
+    const CERTIFIED_NAME: &'static str = "CertifiedCommittee";
+
+    fn content(&self) -> &Self {
+        self
+    }
+}
+
+impl Certifiable for EquivocationProof {
+    type Content = Self;
+    const SIGNED_NAME: &'static str = "SignedEquivocationProof";
+    const CERTIFIED_NAME: &'static str = "CertifiedEquivocationProof";
+
+    fn content(&self) -> &Self {
+        self
+    }
+
+    fn check_value(&self) -> Result<(), FastPayError> {
+        self.check_conflict()
+    }
+
+    fn extra_signatures(&self) -> Vec<ExtraSignature> {
+        let mut signatures = self.first.extra_signatures();
+        signatures.extend(self.second.extra_signatures());
+        signatures
+    }
+}
+
+impl Certifiable for Receipt {
+    type Content = Self;
+    const SIGNED_NAME: &'static str = "SignedReceipt";
+    const CERTIFIED_NAME: &'static str = "CertifiedReceipt";
+
+    fn content(&self) -> &Self {
+        self
+    }
+}
+
+impl Certifiable for GenesisState {
+    type Content = Self;
+    const SIGNED_NAME: &'static str = "SignedGenesisState";
+    const CERTIFIED_NAME: &'static str = "CertifiedGenesisState";
+
+    fn content(&self) -> &Self {
+        self
+    }
+}
+
+impl<T: Certifiable> Signed<T> {
     /// Use signing key to create a signed object.
-    pub fn new(value: TransferOrder, authority: AuthorityName, secret: &KeyPair) -> Self {
-        let signature = Signature::new(&value.transfer, secret);
+    pub fn new(value: T, authority: AuthorityName, secret: &KeyPair) -> Self {
+        let signature = Signature::new(value.content(), secret);
         Self {
             value,
             authority,
@@ -174,35 +515,51 @@ impl SignedTransferOrder {
 
     /// Verify the signature and return the non-zero voting right of the authority.
     pub fn check(&self, committee: &Committee) -> Result<usize, FastPayError> {
-        self.value.check_signature()?;
+        self.value.check_value_and_signatures()?;
         let weight = committee.weight(&self.authority);
         fp_ensure!(weight > 0, FastPayError::UnknownSigner);
-        self.signature.check(&self.value.transfer, self.authority)?;
+        self.signature.check(self.value.content(), self.authority)?;
         Ok(weight)
     }
 }
 
-pub struct SignatureAggregator<'a> {
+impl<T: Certifiable> Certified<T> {
+    /// Verify the certificate.
+    pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
+        check_quorum(committee, &self.signatures)?;
+        self.value.check_value()?;
+        Signature::verify_batch_with_extra_signatures(
+            self.value.content(),
+            &self.signatures,
+            &self.value.extra_signatures(),
+        )
+    }
+}
+
+pub struct SignatureAggregator<'a, T> {
     committee: &'a Committee,
     weight: usize,
     used_authorities: HashSet<AuthorityName>,
-    partial: CertifiedTransferOrder,
+    partial: Certified<T>,
 }
 
-impl<'a> SignatureAggregator<'a> {
+impl<'a, T> SignatureAggregator<'a, T>
+where
+    T: Certifiable + Clone,
+{
     /// Start aggregating signatures for the given value into a certificate.
-    pub fn try_new(value: TransferOrder, committee: &'a Committee) -> Result<Self, FastPayError> {
-        value.check_signature()?;
+    pub fn try_new(value: T, committee: &'a Committee) -> Result<Self, FastPayError> {
+        value.check_value_and_signatures()?;
         Ok(Self::new_unsafe(value, committee))
     }
 
-    /// Same as try_new but we don't check the order.
-    pub fn new_unsafe(value: TransferOrder, committee: &'a Committee) -> Self {
+    /// Same as try_new but we don't check the value.
+    pub fn new_unsafe(value: T, committee: &'a Committee) -> Self {
         Self {
             committee,
             weight: 0,
             used_authorities: HashSet::new(),
-            partial: CertifiedTransferOrder {
+            partial: Certified {
                 value,
                 signatures: Vec::new(),
             },
@@ -211,30 +568,41 @@ impl<'a> SignatureAggregator<'a> {
 
     /// Try to append a signature to a (partial) certificate. Returns Some(certificate) if a quorum was reached.
     /// The resulting final certificate is guaranteed to be valid in the sense of `check` below.
+    /// Appending the exact same signature twice is a no-op (e.g. when votes are retried).
     /// Returns an error if the signed value cannot be aggregated.
     pub fn append(
         &mut self,
         authority: AuthorityName,
         signature: Signature,
-    ) -> Result<Option<CertifiedTransferOrder>, FastPayError> {
-        signature.check(&self.partial.value.transfer, authority)?;
-        // Check that each authority only appears once.
-        fp_ensure!(
-            !self.used_authorities.contains(&authority),
-            FastPayError::CertificateAuthorityReuse
-        );
-        self.used_authorities.insert(authority);
+    ) -> Result<Option<Certified<T>>, FastPayError> {
+        // Check that each authority only appears once, unless it is an exact duplicate.
+        if self.used_authorities.contains(&authority) {
+            fp_ensure!(
+                self.partial
+                    .signatures
+                    .iter()
+                    .any(|(name, sig)| *name == authority && *sig == signature),
+                FastPayError::CertificateAuthorityReuse
+            );
+            return Ok(self.certificate_if_complete());
+        }
+        signature.check(self.partial.value.content(), authority)?;
         // Update weight.
         let voting_rights = self.committee.weight(&authority);
         fp_ensure!(voting_rights > 0, FastPayError::UnknownSigner);
+        self.used_authorities.insert(authority);
         self.weight += voting_rights;
         // Update certificate.
         self.partial.signatures.push((authority, signature));
 
+        Ok(self.certificate_if_complete())
+    }
+
+    fn certificate_if_complete(&self) -> Option<Certified<T>> {
         if self.weight >= self.committee.quorum_threshold() {
-            Ok(Some(self.partial.clone()))
+            Some(self.partial.clone())
         } else {
-            Ok(None)
+            None
         }
     }
 }
@@ -245,33 +613,123 @@ impl CertifiedTransferOrder {
         transfer.key()
     }
 
-    /// Verify the certificate.
-    pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
-        // Check the quorum.
-        let mut weight = 0;
-        let mut used_authorities = HashSet::new();
-        for (authority, _) in self.signatures.iter() {
-            // Check that each authority only appears once.
-            fp_ensure!(
-                !used_authorities.contains(authority),
-                FastPayError::CertificateAuthorityReuse
-            );
-            used_authorities.insert(*authority);
-            // Update weight.
-            let voting_rights = committee.weight(authority);
-            fp_ensure!(voting_rights > 0, FastPayError::UnknownSigner);
-            weight += voting_rights;
+    /// Aggregate votes on possibly different transfers into certificates, in the order in
+    /// which they reach a quorum. Votes that do not match their transfer order (or signer)
+    /// are skipped: their errors are returned along with the certificates.
+    pub fn from_votes(
+        votes: Vec<SignedTransferOrder>,
+        committee: &Committee,
+    ) -> (Vec<CertifiedTransferOrder>, Vec<FastPayError>) {
+        let mut aggregators = HashMap::new();
+        let mut certified = HashSet::new();
+        let mut certificates = Vec::new();
+        let mut errors = Vec::new();
+        for vote in votes {
+            let transfer = vote.value.transfer.clone();
+            let aggregator = match aggregators.entry(transfer.clone()) {
+                hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                hash_map::Entry::Vacant(entry) => {
+                    match SignatureAggregator::try_new(vote.value, committee) {
+                        Ok(aggregator) => entry.insert(aggregator),
+                        Err(error) => {
+                            errors.push(error);
+                            continue;
+                        }
+                    }
+                }
+            };
+            match aggregator.append(vote.authority, vote.signature) {
+                Ok(Some(certificate)) => {
+                    if certified.insert(transfer) {
+                        certificates.push(certificate);
+                    }
+                }
+                Ok(None) => (),
+                Err(error) => errors.push(error),
+            }
         }
+        (certificates, errors)
+    }
+}
+
+/// Check that the signers are distinct and form a quorum.
+fn check_quorum(
+    committee: &Committee,
+    signatures: &[(AuthorityName, Signature)],
+) -> Result<(), FastPayError> {
+    let mut weight = 0;
+    let mut used_authorities = HashSet::new();
+    for (authority, _) in signatures.iter() {
+        // Check that each authority only appears once.
         fp_ensure!(
-            weight >= committee.quorum_threshold(),
-            FastPayError::CertificateRequiresQuorum
+            !used_authorities.contains(authority),
+            FastPayError::CertificateAuthorityReuse
         );
-        // All what is left is checking signatures!
-        let inner_sig = (self.value.transfer.sender, self.value.signature);
-        Signature::verify_batch(
-            &self.value.transfer,
-            std::iter::once(&inner_sig).chain(&self.signatures),
-        )
+        used_authorities.insert(*authority);
+        // Update weight.
+        let voting_rights = committee.weight(authority);
+        fp_ensure!(voting_rights > 0, FastPayError::UnknownSigner);
+        weight += voting_rights;
+    }
+    fp_ensure!(
+        weight >= committee.quorum_threshold(),
+        FastPayError::CertificateRequiresQuorum
+    );
+    Ok(())
+}
+
+impl CommitteeDescription {
+    pub fn new(committee: &Committee) -> Self {
+        Self {
+            epoch: CURRENT_EPOCH,
+            voting_rights: committee
+                .voting_rights
+                .iter()
+                .map(|(name, weight)| (*name, *weight))
+                .collect(),
+        }
+    }
+
+    /// Check the voting rights and build the corresponding committee.
+    pub fn committee(&self) -> Result<Committee, FastPayError> {
+        self.voting_rights
+            .iter()
+            .fold(CommitteeBuilder::new(), |builder, (name, weight)| {
+                builder.add_authority(*name, *weight)
+            })
+            .build()
+    }
+}
+
+impl EquivocationProof {
+    /// Verify that both orders are signed by the sender and conflict with each other.
+    pub fn check(&self) -> Result<(), FastPayError> {
+        self.check_conflict()?;
+        self.first.check_signature()?;
+        self.second.check_signature()
+    }
+
+    fn check_conflict(&self) -> Result<(), FastPayError> {
+        let first = &self.first.transfer;
+        let second = &self.second.transfer;
+        fp_ensure!(
+            first.sender == second.sender
+                && first.sequence_number == second.sequence_number
+                && first != second,
+            FastPayError::InvalidEquivocationProof
+        );
+        Ok(())
+    }
+}
+
+impl Receipt {
+    pub fn new(transfer: &Transfer) -> Self {
+        Self {
+            sender: transfer.sender,
+            sequence_number: transfer.sequence_number,
+            recipient: transfer.recipient,
+            amount: transfer.amount,
+        }
     }
 }
 
@@ -291,4 +749,320 @@ impl ConfirmationOrder {
     }
 }
 
+impl CompactConfirmationOrder {
+    pub fn new(certificate: &CertifiedTransferOrder) -> Self {
+        Self {
+            sender: certificate.value.transfer.sender,
+            sequence_number: certificate.value.transfer.sequence_number,
+            order_signature: certificate.value.signature,
+            signatures: certificate.signatures.clone(),
+        }
+    }
+
+    /// Whether this compact order refers to the given transfer order.
+    pub fn matches(&self, order: &TransferOrder) -> bool {
+        order.transfer.sender == self.sender
+            && order.transfer.sequence_number == self.sequence_number
+            && order.signature == self.order_signature
+    }
+
+    /// Rebuild the full confirmation order using the transfer order it refers to.
+    /// The resulting certificate still needs to be checked.
+    pub fn expand(self, order: TransferOrder) -> Result<ConfirmationOrder, FastPayError> {
+        fp_ensure!(self.matches(&order), FastPayError::UnknownTransferOrder);
+        Ok(ConfirmationOrder::new(CertifiedTransferOrder {
+            value: order,
+            signatures: self.signatures,
+        }))
+    }
+}
+
 impl BcsSignable for Transfer {}
+impl BcsSignable for AccountSnapshot {}
+impl BcsSignable for Freeze {}
+impl BcsSignable for CommitteeDescription {}
+impl BcsSignable for EquivocationProof {}
+impl BcsSignable for Receipt {}
+impl BcsSignable for GenesisState {}
+
+fn deserialize_signatures<'de, D>(
+    deserializer: D,
+) -> Result<Vec<(AuthorityName, Signature)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(BoundedVecVisitor::new(MAX_SIGNATURES_PER_CERTIFICATE))
+}
+
+fn deserialize_received_transfers<'de, D>(
+    deserializer: D,
+) -> Result<Vec<CertifiedTransferOrder>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(BoundedVecVisitor::new(
+        MAX_RECEIVED_CERTIFICATES_PER_RESPONSE,
+    ))
+}
+
+fn deserialize_voting_rights<'de, D>(
+    deserializer: D,
+) -> Result<Vec<(AuthorityName, usize)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // A larger committee could not produce valid certificates.
+    deserializer.deserialize_seq(BoundedVecVisitor::new(MAX_SIGNATURES_PER_CERTIFICATE))
+}
+
+fn deserialize_confirmation_orders<'de, D>(
+    deserializer: D,
+) -> Result<Vec<ConfirmationOrder>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(BoundedVecVisitor::new(MAX_CONFIRMATION_ORDERS_PER_BATCH))
+}
+
+fn deserialize_batch_senders<'de, D>(deserializer: D) -> Result<Vec<FastPayAddress>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(BoundedVecVisitor::new(MAX_ACCOUNTS_PER_INFO_BATCH))
+}
+
+#[allow(clippy::type_complexity)]
+fn deserialize_batch_responses<'de, D>(
+    deserializer: D,
+) -> Result<Vec<(FastPayAddress, Result<AccountInfoResponse, FastPayError>)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(BoundedVecVisitor::new(MAX_ACCOUNTS_PER_INFO_BATCH))
+}
+
+/// Deserialize a sequence of at most `max_len` elements. Announced lengths are checked
+/// before allocating anything so that malicious peers cannot exhaust our memory.
+struct BoundedVecVisitor<T> {
+    max_len: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T> BoundedVecVisitor<T> {
+    fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'de, T> de::Visitor<'de> for BoundedVecVisitor<T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = Vec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of at most {} elements", self.max_len)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let len = seq.size_hint().unwrap_or(0);
+        if len > self.max_len {
+            return Err(de::Error::invalid_length(len, &self));
+        }
+        let mut values = Vec::with_capacity(len);
+        while let Some(value) = seq.next_element()? {
+            if values.len() == self.max_len {
+                return Err(de::Error::invalid_length(values.len() + 1, &self));
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+// `Signed<T>` and `Certified<T>` are (de)serialized by hand so that each instance keeps
+// its own name in the serialization format, e.g. `SignedTransferOrder`.
+
+const SIGNED_FIELDS: &[&str] = &["value", "authority", "signature"];
+const CERTIFIED_FIELDS: &[&str] = &["value", "signatures"];
+
+impl<T> Serialize for Signed<T>
+where
+    T: Certifiable + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct(T::SIGNED_NAME, SIGNED_FIELDS.len())?;
+        state.serialize_field("value", &self.value)?;
+        state.serialize_field("authority", &self.authority)?;
+        state.serialize_field("signature", &self.signature)?;
+        state.end()
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Signed<T>
+where
+    T: Certifiable + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_struct(T::SIGNED_NAME, SIGNED_FIELDS, SignedVisitor(PhantomData))
+    }
+}
+
+struct SignedVisitor<T>(PhantomData<T>);
+
+impl<'de, T> de::Visitor<'de> for SignedVisitor<T>
+where
+    T: Certifiable + Deserialize<'de>,
+{
+    type Value = Signed<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "struct {}", T::SIGNED_NAME)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let value = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let authority = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let signature = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        Ok(Signed {
+            value,
+            authority,
+            signature,
+        })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let (mut value, mut authority, mut signature) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "value" => value = Some(map.next_value()?),
+                "authority" => authority = Some(map.next_value()?),
+                "signature" => signature = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Signed {
+            value: value.ok_or_else(|| de::Error::missing_field("value"))?,
+            authority: authority.ok_or_else(|| de::Error::missing_field("authority"))?,
+            signature: signature.ok_or_else(|| de::Error::missing_field("signature"))?,
+        })
+    }
+}
+
+impl<T> Serialize for Certified<T>
+where
+    T: Certifiable + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct(T::CERTIFIED_NAME, CERTIFIED_FIELDS.len())?;
+        state.serialize_field("value", &self.value)?;
+        state.serialize_field("signatures", &self.signatures)?;
+        state.end()
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Certified<T>
+where
+    T: Certifiable + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_struct(
+            T::CERTIFIED_NAME,
+            CERTIFIED_FIELDS,
+            CertifiedVisitor(PhantomData),
+        )
+    }
+}
+
+/// The signatures of a certificate, with a bounded length.
+struct CertificateSignatures(Vec<(AuthorityName, Signature)>);
+
+impl<'de> Deserialize<'de> for CertificateSignatures {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_signatures(deserializer).map(CertificateSignatures)
+    }
+}
+
+struct CertifiedVisitor<T>(PhantomData<T>);
+
+impl<'de, T> de::Visitor<'de> for CertifiedVisitor<T>
+where
+    T: Certifiable + Deserialize<'de>,
+{
+    type Value = Certified<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "struct {}", T::CERTIFIED_NAME)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let value = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let CertificateSignatures(signatures) = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(Certified { value, signatures })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let (mut value, mut signatures) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "value" => value = Some(map.next_value()?),
+                "signatures" => {
+                    let CertificateSignatures(values) = map.next_value()?;
+                    signatures = Some(values);
+                }
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Certified {
+            value: value.ok_or_else(|| de::Error::missing_field("value"))?,
+            signatures: signatures.ok_or_else(|| de::Error::missing_field("signatures"))?,
+        })
+    }
+}
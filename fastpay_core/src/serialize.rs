@@ -6,20 +6,67 @@ use crate::error::*;
 
 use failure::format_err;
 use serde::{Deserialize, Serialize};
+use std::{
+    convert::{TryFrom, TryInto},
+    io::Read,
+};
 
 #[cfg(test)]
 #[path = "unit_tests/serialize_tests.rs"]
 mod serialize_tests;
 
+/// First byte of every message. This value is never the first byte of a legacy
+/// (headerless) message.
+const MESSAGE_MAGIC: u8 = 0xfa;
+
+/// Version of the wire protocol. Messages with a different version are rejected.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Encoding of the payload following the message header.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum SerializationFormat {
+    Bincode = 0,
+    /// BCS payloads are not self-delimiting: they must extend to the end of the input.
+    Bcs = 1,
+}
+
+impl SerializationFormat {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Bincode),
+            1 => Some(Self::Bcs),
+            _ => None,
+        }
+    }
+}
+
+fn message_header(format: SerializationFormat) -> [u8; 3] {
+    [MESSAGE_MAGIC, PROTOCOL_VERSION, format as u8]
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum SerializedMessage {
     Order(Box<TransferOrder>),
     Vote(Box<SignedTransferOrder>),
     Cert(Box<CertifiedTransferOrder>),
-    CrossShard(Box<CertifiedTransferOrder>),
+    CrossShard(Box<CrossShardRequest>),
     Error(Box<FastPayError>),
     InfoReq(Box<AccountInfoRequest>),
     InfoResp(Box<AccountInfoResponse>),
+    CrossShardAck(Box<CrossShardAck>),
+    SnapshotReq(Box<AccountSnapshotRequest>),
+    SnapshotVote(Box<SignedAccountSnapshot>),
+    Confirmations(Box<ConfirmationOrderBatch>),
+    Freeze(Box<FreezeOrder>),
+    CompactCert(Box<CompactConfirmationOrder>),
+    CommitteeReq(Box<CommitteeRequest>),
+    CommitteeVote(Box<SignedCommittee>),
+    Ping,
+    Pong(Box<HealthResponse>),
+    BatchInfoReq(Box<BatchAccountInfoRequest>),
+    BatchInfoResp(Box<BatchAccountInfoResponse>),
+    EquivocationProofReq(Box<EquivocationProofRequest>),
+    EquivocationProofVote(Box<SignedEquivocationProof>),
 }
 
 // This helper structure is only here to avoid cloning while serializing commands.
@@ -30,17 +77,45 @@ enum ShallowSerializedMessage<'a> {
     Order(&'a TransferOrder),
     Vote(&'a SignedTransferOrder),
     Cert(&'a CertifiedTransferOrder),
-    CrossShard(&'a CertifiedTransferOrder),
+    CrossShard(ShallowCrossShardRequest<'a>),
     Error(&'a FastPayError),
     InfoReq(&'a AccountInfoRequest),
     InfoResp(&'a AccountInfoResponse),
+    CrossShardAck(&'a CrossShardAck),
+    SnapshotReq(&'a AccountSnapshotRequest),
+    SnapshotVote(&'a SignedAccountSnapshot),
+    Confirmations(ShallowConfirmationOrderBatch<'a>),
+    Freeze(&'a FreezeOrder),
+    CompactCert(&'a CompactConfirmationOrder),
+    CommitteeReq(&'a CommitteeRequest),
+    CommitteeVote(&'a SignedCommittee),
+    Ping,
+    Pong(&'a HealthResponse),
+    BatchInfoReq(&'a BatchAccountInfoRequest),
+    BatchInfoResp(&'a BatchAccountInfoResponse),
+    EquivocationProofReq(&'a EquivocationProofRequest),
+    EquivocationProofVote(&'a SignedEquivocationProof),
+}
+
+// Must match the definition of CrossShardRequest.
+#[derive(Serialize)]
+struct ShallowCrossShardRequest<'a> {
+    transfer_certificate: &'a CertifiedTransferOrder,
+    timestamp_us: u64,
 }
 
-fn serialize_into<T, W>(writer: W, msg: &T) -> Result<(), failure::Error>
+// Must match the definition of ConfirmationOrderBatch.
+#[derive(Serialize)]
+struct ShallowConfirmationOrderBatch<'a> {
+    orders: &'a [ConfirmationOrder],
+}
+
+fn serialize_into<T, W>(mut writer: W, msg: &T) -> Result<(), failure::Error>
 where
     W: std::io::Write,
     T: Serialize,
 {
+    writer.write_all(&message_header(SerializationFormat::Bincode))?;
     bincode::serialize_into(writer, msg).map_err(|err| format_err!("{}", err))
 }
 
@@ -49,8 +124,7 @@ where
     T: Serialize,
 {
     let mut buf = Vec::new();
-    bincode::serialize_into(&mut buf, msg)
-        .expect("Serializing to a resizable buffer should not fail.");
+    serialize_into(&mut buf, msg).expect("Serializing to a resizable buffer should not fail.");
     buf
 }
 
@@ -58,6 +132,18 @@ pub fn serialize_message(msg: &SerializedMessage) -> Vec<u8> {
     serialize(msg)
 }
 
+pub fn serialize_message_as(msg: &SerializedMessage, format: SerializationFormat) -> Vec<u8> {
+    match format {
+        SerializationFormat::Bincode => serialize(msg),
+        SerializationFormat::Bcs => {
+            let mut buf = message_header(format).to_vec();
+            bcs::serialize_into(&mut buf, msg)
+                .expect("Serializing to a resizable buffer should not fail.");
+            buf
+        }
+    }
+}
+
 pub fn serialize_transfer_order(value: &TransferOrder) -> Vec<u8> {
     serialize(&ShallowSerializedMessage::Order(value))
 }
@@ -90,6 +176,12 @@ where
     serialize_into(writer, &ShallowSerializedMessage::Cert(value))
 }
 
+pub fn serialize_confirmation_orders(orders: &[ConfirmationOrder]) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::Confirmations(
+        ShallowConfirmationOrderBatch { orders },
+    ))
+}
+
 pub fn serialize_info_request(value: &AccountInfoRequest) -> Vec<u8> {
     serialize(&ShallowSerializedMessage::InfoReq(value))
 }
@@ -98,8 +190,65 @@ pub fn serialize_info_response(value: &AccountInfoResponse) -> Vec<u8> {
     serialize(&ShallowSerializedMessage::InfoResp(value))
 }
 
-pub fn serialize_cross_shard(value: &CertifiedTransferOrder) -> Vec<u8> {
-    serialize(&ShallowSerializedMessage::CrossShard(value))
+pub fn serialize_batch_info_request(value: &BatchAccountInfoRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::BatchInfoReq(value))
+}
+
+pub fn serialize_batch_info_response(value: &BatchAccountInfoResponse) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::BatchInfoResp(value))
+}
+
+pub fn serialize_cross_shard(value: &CertifiedTransferOrder, timestamp_us: u64) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CrossShard(
+        ShallowCrossShardRequest {
+            transfer_certificate: value,
+            timestamp_us,
+        },
+    ))
+}
+
+pub fn serialize_cross_shard_ack(value: &CrossShardAck) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CrossShardAck(value))
+}
+
+pub fn serialize_snapshot_request(value: &AccountSnapshotRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::SnapshotReq(value))
+}
+
+pub fn serialize_snapshot_vote(value: &SignedAccountSnapshot) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::SnapshotVote(value))
+}
+
+pub fn serialize_freeze_order(value: &FreezeOrder) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::Freeze(value))
+}
+
+pub fn serialize_compact_cert(value: &CompactConfirmationOrder) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CompactCert(value))
+}
+
+pub fn serialize_committee_request(value: &CommitteeRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CommitteeReq(value))
+}
+
+pub fn serialize_committee_vote(value: &SignedCommittee) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CommitteeVote(value))
+}
+
+pub fn serialize_equivocation_proof_request(value: &EquivocationProofRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::EquivocationProofReq(value))
+}
+
+pub fn serialize_equivocation_proof_vote(value: &SignedEquivocationProof) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::EquivocationProofVote(value))
+}
+
+pub fn serialize_ping() -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::Ping)
+}
+
+pub fn serialize_health_response(value: &HealthResponse) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::Pong(value))
 }
 
 pub fn serialize_vote(value: &SignedTransferOrder) -> Vec<u8> {
@@ -113,9 +262,75 @@ where
     serialize_into(writer, &ShallowSerializedMessage::Vote(value))
 }
 
-pub fn deserialize_message<R>(reader: R) -> Result<SerializedMessage, failure::Error>
+/// Decode a message received from the network. Malformed messages, including messages
+/// that exceed the size limits of their sequences, are rejected with `InvalidDecoding`.
+pub fn deserialize_message<R>(mut reader: R) -> Result<SerializedMessage, failure::Error>
 where
     R: std::io::Read,
 {
-    bincode::deserialize_from(reader).map_err(|err| format_err!("{}", err))
+    let mut magic = [0u8; 1];
+    reader.read_exact(&mut magic)?;
+    if magic[0] != MESSAGE_MAGIC {
+        // Legacy message without header.
+        return bincode::deserialize_from(magic.chain(reader))
+            .map_err(|_| FastPayError::InvalidDecoding.into());
+    }
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header)?;
+    let [version, format] = header;
+    if version != PROTOCOL_VERSION {
+        return Err(FastPayError::InvalidDecoding.into());
+    }
+    match SerializationFormat::from_byte(format) {
+        Some(SerializationFormat::Bincode) => {
+            bincode::deserialize_from(reader).map_err(|_| FastPayError::InvalidDecoding.into())
+        }
+        Some(SerializationFormat::Bcs) => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            bcs::from_bytes(&bytes).map_err(|_| FastPayError::InvalidDecoding.into())
+        }
+        None => Err(FastPayError::InvalidDecoding.into()),
+    }
+}
+
+/// Wire indices of some variants of `SerializedMessage`.
+const ORDER_VARIANT: u32 = 0;
+const CERT_VARIANT: u32 = 2;
+
+/// Read the variant index of a serialized message without decoding its content.
+fn message_variant(buffer: &[u8]) -> Option<u32> {
+    let (format, payload) = match buffer {
+        [MESSAGE_MAGIC, PROTOCOL_VERSION, format, payload @ ..] => {
+            (SerializationFormat::from_byte(*format)?, payload)
+        }
+        [MESSAGE_MAGIC, ..] => return None,
+        // Legacy message without header.
+        payload => (SerializationFormat::Bincode, payload),
+    };
+    match format {
+        SerializationFormat::Bincode => {
+            Some(u32::from_le_bytes(payload.get(..4)?.try_into().ok()?))
+        }
+        SerializationFormat::Bcs => {
+            // ULEB128-encoded index.
+            let mut value = 0u64;
+            for (i, byte) in payload.iter().take(5).enumerate() {
+                value |= u64::from(byte & 0x7f) << (7 * i);
+                if byte & 0x80 == 0 {
+                    return u32::try_from(value).ok();
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Whether a serialized message is a transfer order or a certificate, judging only from
+/// its header, e.g. to decide cheaply whether to check its signatures ahead of time.
+pub fn is_order_or_certificate(buffer: &[u8]) -> bool {
+    matches!(
+        message_variant(buffer),
+        Some(ORDER_VARIANT) | Some(CERT_VARIANT)
+    )
 }
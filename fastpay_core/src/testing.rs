@@ -0,0 +1,289 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-memory authorities, to test FastPay clients without networking.
+
+use crate::{
+    authority::{AccountOffchainState, Authority, AuthorityState},
+    base_types::*,
+    client::{AsyncResult, AuthorityClient, ClientState},
+    committee::Committee,
+    error::FastPayError,
+    messages::*,
+};
+use futures::lock::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Call the shards of an authority directly. Cross-shard updates are delivered (and
+/// acknowledged) before confirmation orders return.
+#[derive(Clone)]
+pub struct LocalAuthorityClient {
+    shards: Arc<Vec<Mutex<AuthorityState>>>,
+    /// Simulated network delay before handling each request.
+    latency: Duration,
+}
+
+impl LocalAuthorityClient {
+    pub fn new(state: AuthorityState) -> Self {
+        Self::new_sharded(vec![state])
+    }
+
+    /// Use all the shards of an authority, ordered by shard id.
+    pub fn new_sharded(shards: Vec<AuthorityState>) -> Self {
+        assert!(
+            shards
+                .iter()
+                .enumerate()
+                .all(|(i, state)| state.shard_id as usize == i
+                    && state.number_of_shards as usize == shards.len()),
+            "Shards must be complete and ordered by shard id"
+        );
+        Self {
+            shards: Arc::new(shards.into_iter().map(Mutex::new).collect()),
+            latency: Duration::from_secs(0),
+        }
+    }
+
+    /// Access the same authority, waiting for `latency` before handling each request.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    async fn simulate_latency(&self) {
+        if self.latency > Duration::from_secs(0) {
+            tokio::time::delay_for(self.latency).await;
+        }
+    }
+
+    /// The shard handling the given account.
+    pub fn shard_of(&self, address: &FastPayAddress) -> &Mutex<AuthorityState> {
+        let shard_id = AuthorityState::get_shard(self.shards.len() as u32, address);
+        &self.shards[shard_id as usize]
+    }
+
+    async fn deliver_cross_shard_updates(
+        &self,
+        updates: Vec<CrossShardUpdate>,
+    ) -> Result<(), FastPayError> {
+        for update in updates {
+            let ack = self.shards[update.shard_id as usize]
+                .lock()
+                .await
+                .handle_cross_shard_recipient_commit(update.transfer_certificate)?;
+            self.shards[ack.shard_id as usize]
+                .lock()
+                .await
+                .handle_cross_shard_ack(ack)?;
+        }
+        Ok(())
+    }
+}
+
+impl AuthorityClient for LocalAuthorityClient {
+    fn handle_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            client
+                .shard_of(&order.transfer.sender)
+                .lock()
+                .await
+                .handle_transfer_order(order)
+        })
+    }
+
+    fn handle_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            let sender = order.transfer_certificate.value.transfer.sender;
+            let (info, updates) = client
+                .shard_of(&sender)
+                .lock()
+                .await
+                .handle_confirmation_order(order)?;
+            client.deliver_cross_shard_updates(updates).await?;
+            Ok(info)
+        })
+    }
+
+    fn handle_account_info_request(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            client
+                .shard_of(&request.sender)
+                .lock()
+                .await
+                .handle_account_info_request(request)
+        })
+    }
+
+    fn handle_compact_confirmation_order(
+        &mut self,
+        certificate: CertifiedTransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            let mut state = client
+                .shard_of(&certificate.value.transfer.sender)
+                .lock()
+                .await;
+            let order = CompactConfirmationOrder::new(&certificate);
+            let (info, updates) = match state.handle_compact_confirmation_order(order) {
+                Err(FastPayError::UnknownTransferOrder) => {
+                    state.handle_confirmation_order(ConfirmationOrder::new(certificate))?
+                }
+                result => result?,
+            };
+            drop(state);
+            client.deliver_cross_shard_updates(updates).await?;
+            Ok(info)
+        })
+    }
+
+    fn handle_account_snapshot_request(
+        &mut self,
+        request: AccountSnapshotRequest,
+    ) -> AsyncResult<SignedAccountSnapshot, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            client
+                .shard_of(&request.account)
+                .lock()
+                .await
+                .handle_account_snapshot_request(request)
+        })
+    }
+
+    fn handle_committee_request(
+        &mut self,
+        request: CommitteeRequest,
+    ) -> AsyncResult<SignedCommittee, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            client.shards[0]
+                .lock()
+                .await
+                .handle_committee_request(request)
+        })
+    }
+
+    fn handle_equivocation_proof_request(
+        &mut self,
+        request: EquivocationProofRequest,
+    ) -> AsyncResult<SignedEquivocationProof, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            client
+                .shard_of(&request.sender)
+                .lock()
+                .await
+                .handle_equivocation_proof_request(request)
+        })
+    }
+
+    fn handle_freeze_order(
+        &mut self,
+        order: FreezeOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let client = self.clone();
+        Box::pin(async move {
+            client.simulate_latency().await;
+            client
+                .shard_of(&order.freeze.account)
+                .lock()
+                .await
+                .handle_freeze_order(order)
+        })
+    }
+}
+
+/// Create a committee of `count` authorities with equal voting rights, each made of
+/// `num_shards` shards.
+pub fn init_local_authorities(
+    count: usize,
+    num_shards: u32,
+) -> (HashMap<AuthorityName, LocalAuthorityClient>, Committee) {
+    let mut key_pairs = Vec::new();
+    let mut voting_rights = BTreeMap::new();
+    for _ in 0..count {
+        let key_pair = get_authority_key_pair();
+        voting_rights.insert(key_pair.0, 1);
+        key_pairs.push(key_pair);
+    }
+    let committee = Committee::new(voting_rights);
+
+    let mut clients = HashMap::new();
+    for (address, secret) in key_pairs {
+        let shards = (0..num_shards)
+            .map(|shard_id| {
+                AuthorityState::new_shard(
+                    committee.clone(),
+                    address,
+                    secret.copy(),
+                    shard_id,
+                    num_shards,
+                )
+            })
+            .collect();
+        clients.insert(address, LocalAuthorityClient::new_sharded(shards));
+    }
+    (clients, committee)
+}
+
+/// Create a client with a fresh account.
+pub fn make_client(
+    authority_clients: HashMap<AuthorityName, LocalAuthorityClient>,
+    committee: Committee,
+) -> ClientState<LocalAuthorityClient> {
+    let (address, secret) = get_key_pair();
+    ClientState::new(
+        address,
+        secret,
+        committee,
+        authority_clients,
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        Balance::from(0),
+    )
+}
+
+/// Set the balance of an account on each authority (missing values default to zero).
+/// This bypasses the synchronization with the Primary blockchain.
+pub fn fund_account<I: IntoIterator<Item = i128>>(
+    clients: &mut HashMap<AuthorityName, LocalAuthorityClient>,
+    address: FastPayAddress,
+    balances: I,
+) {
+    let mut balances = balances.into_iter().map(Balance::from);
+    for (_, client) in clients.iter_mut() {
+        let mut account = AccountOffchainState::new();
+        account.balance = balances.next().unwrap_or_else(Balance::zero);
+        client
+            .shard_of(&address)
+            .try_lock()
+            .expect("Authority should not be in use")
+            .accounts
+            .insert(address, account);
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::*;
+use crate::clock::MockClock;
 
 #[test]
 fn test_handle_transfer_order_bad_signature() {
@@ -45,6 +46,29 @@ fn test_handle_transfer_order_zero_amount() {
         .is_none());
 }
 
+#[test]
+fn test_handle_transfer_order_min_amount() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(50));
+    authority_state.min_transfer_amount = Amount::from(10);
+
+    let below = init_transfer_order(sender, &sender_key, recipient.clone(), Amount::from(9));
+    assert_eq!(
+        authority_state.handle_transfer_order(below),
+        Err(FastPayError::AmountBelowMinimum {
+            minimum: Amount::from(10)
+        })
+    );
+    let at = init_transfer_order(sender, &sender_key, recipient.clone(), Amount::from(10));
+    assert!(authority_state.handle_transfer_order(at).is_ok());
+
+    let mut authority_state = init_state_with_account(sender, Balance::from(50));
+    authority_state.min_transfer_amount = Amount::from(10);
+    let above = init_transfer_order(sender, &sender_key, recipient, Amount::from(11));
+    assert!(authority_state.handle_transfer_order(above).is_ok());
+}
+
 #[test]
 fn test_handle_transfer_order_unknown_sender() {
     let (sender, sender_key) = get_key_pair();
@@ -82,9 +106,14 @@ fn test_handle_transfer_order_bad_sequence_number() {
             .next_sequence_number
             .increment()
             .unwrap();
-    assert!(sequence_number_state
-        .handle_transfer_order(transfer_order)
-        .is_err());
+    assert_eq!(
+        sequence_number_state.handle_transfer_order(transfer_order),
+        Err(FastPayError::UnexpectedSequenceNumber {
+            sender,
+            expected_sequence_number: SequenceNumber::from(1),
+            received_sequence_number: SequenceNumber::from(0),
+        })
+    );
     assert!(sequence_number_state
         .accounts
         .get(&sender)
@@ -110,6 +139,193 @@ fn test_handle_transfer_order_exceed_balance() {
         .is_none());
 }
 
+#[cfg(not(feature = "benchmark"))]
+#[test]
+fn test_handle_transfer_order_rate_limited() {
+    let (sender, sender_key) = get_key_pair();
+    let (other, other_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state =
+        init_state_with_accounts(vec![(sender, Balance::from(5)), (other, Balance::from(5))]);
+    authority_state.rate_limit = Some(RateLimit {
+        burst: 2,
+        period: Duration::from_secs(3600),
+    });
+    // Rejected orders count against the limit.
+    for amount in 6..8 {
+        let large_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(amount));
+        assert!(matches!(
+            authority_state.handle_transfer_order(large_order),
+            Err(FastPayError::InsufficientFunding { .. })
+        ));
+    }
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order),
+        Err(FastPayError::RateLimited { address: sender })
+    );
+    // Other accounts are not affected.
+    let other_order = init_transfer_order(other, &other_key, recipient, Amount::from(5));
+    assert!(authority_state
+        .handle_transfer_order(other_order.clone())
+        .is_ok());
+    // Repeating a signed transfer order is free.
+    for _ in 0..3 {
+        assert!(authority_state
+            .handle_transfer_order(other_order.clone())
+            .is_ok());
+    }
+    assert_eq!(authority_state.token_buckets[&other].tokens, 1);
+}
+
+#[cfg(not(feature = "benchmark"))]
+#[test]
+fn test_rate_limit_ignores_forged_orders() {
+    let (sender, sender_key) = get_key_pair();
+    let (unknown, unknown_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.rate_limit = Some(RateLimit {
+        burst: 1,
+        period: Duration::from_secs(3600),
+    });
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    let mut forged_order = transfer_order.clone();
+    forged_order.signature = Signature::new(&transfer_order.transfer, &unknown_key);
+    for _ in 0..3 {
+        assert!(authority_state
+            .handle_transfer_order(forged_order.clone())
+            .is_err());
+    }
+    let unknown_order = init_transfer_order(unknown, &unknown_key, recipient, Amount::from(5));
+    assert_eq!(
+        authority_state.handle_transfer_order(unknown_order),
+        Err(FastPayError::UnknownSenderAccount)
+    );
+    assert!(authority_state.token_buckets.is_empty());
+    // The owner of the account is not locked out.
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
+        .is_ok());
+}
+
+#[cfg(not(feature = "benchmark"))]
+#[test]
+fn test_rate_limit_refills_over_time() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.rate_limit = Some(RateLimit {
+        burst: 1,
+        period: Duration::from_millis(50),
+    });
+    let large_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(6));
+    assert!(authority_state.handle_transfer_order(large_order).is_err());
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::RateLimited { address: sender })
+    );
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
+        .is_ok());
+}
+
+#[cfg(not(feature = "benchmark"))]
+#[test]
+fn test_rate_limit_with_mock_clock() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let clock = MockClock::new();
+    authority_state.clock = Arc::new(clock.clone());
+    authority_state.rate_limit = Some(RateLimit {
+        burst: 1,
+        period: Duration::from_secs(3600),
+    });
+    let large_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(6));
+    assert!(authority_state.handle_transfer_order(large_order).is_err());
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    clock.advance(Duration::from_secs(3599));
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::RateLimited { address: sender })
+    );
+    clock.advance(Duration::from_secs(1));
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
+        .is_ok());
+}
+
+#[test]
+fn test_handle_transfer_order_expiry() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let mut transfer =
+        init_transfer_order(sender, &sender_key, recipient, Amount::from(5)).transfer;
+    transfer.valid_until = Some(VersionNumber::from(1));
+    let transfer_order = TransferOrder::new(transfer, &sender_key);
+
+    authority_state.last_transaction_index = VersionNumber::from(2);
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::ExpiredTransferOrder {
+            valid_until: VersionNumber::from(1)
+        })
+    );
+    assert!(authority_state
+        .accounts
+        .get(&sender)
+        .unwrap()
+        .pending_confirmation
+        .is_none());
+
+    authority_state.last_transaction_index = VersionNumber::from(1);
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
+        .is_ok());
+    assert!(authority_state
+        .accounts
+        .get(&sender)
+        .unwrap()
+        .pending_confirmation
+        .is_some());
+}
+
+#[test]
+fn test_handle_transfer_order_expiry_with_mock_clock() {
+    let (sender1, sender1_key) = get_key_pair();
+    let (sender2, sender2_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_accounts(vec![
+        (sender1, Balance::from(5)),
+        (sender2, Balance::from(5)),
+    ]);
+    let clock = MockClock::new();
+    authority_state.clock = Arc::new(clock.clone());
+    let make_order = |sender, key| {
+        let mut transfer = init_transfer_order(sender, key, recipient, Amount::from(5)).transfer;
+        transfer.valid_until = Some(VersionNumber::from(1));
+        TransferOrder::new(transfer, key)
+    };
+
+    clock.advance_transaction_index(1);
+    assert!(authority_state
+        .handle_transfer_order(make_order(sender1, &sender1_key))
+        .is_ok());
+    clock.advance_transaction_index(1);
+    assert_eq!(
+        authority_state.handle_transfer_order(make_order(sender2, &sender2_key)),
+        Err(FastPayError::ExpiredTransferOrder {
+            valid_until: VersionNumber::from(1)
+        })
+    );
+    // The primary synchronization is unchanged.
+    assert_eq!(authority_state.last_transaction_index, VersionNumber::new());
+}
+
 #[test]
 fn test_handle_transfer_order_ok() {
     let (sender, sender_key) = get_key_pair();
@@ -147,6 +363,169 @@ fn test_handle_transfer_order_double_spend() {
         .handle_transfer_order(transfer_order)
         .unwrap();
     assert_eq!(signed_order, double_spend_signed_order);
+    assert_eq!(
+        authority_state.handle_equivocation_proof_request(EquivocationProofRequest { sender }),
+        Err(FastPayError::UnknownEquivocationProof)
+    );
+}
+
+#[test]
+fn test_handle_transfer_order_equivocation() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let first_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(2)),
+        Amount::from(5),
+    );
+    let second_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(3)),
+        Amount::from(5),
+    );
+
+    authority_state
+        .handle_transfer_order(first_order.clone())
+        .unwrap();
+    assert!(matches!(
+        authority_state.handle_transfer_order(second_order.clone()),
+        Err(FastPayError::PreviousTransferMustBeConfirmedFirst { .. })
+    ));
+    let proof = authority_state
+        .handle_equivocation_proof_request(EquivocationProofRequest { sender })
+        .unwrap();
+    assert_eq!(proof.authority, authority_state.name);
+    assert_eq!(proof.value.first, first_order);
+    assert_eq!(proof.value.second, second_order);
+    assert!(proof.check(&authority_state.committee).is_ok());
+
+    // Orders that do not conflict are not evidence of equivocation.
+    let not_a_proof = EquivocationProof {
+        first: first_order.clone(),
+        second: first_order,
+    };
+    assert_eq!(
+        not_a_proof.check(),
+        Err(FastPayError::InvalidEquivocationProof)
+    );
+}
+
+#[test]
+fn test_handle_freeze_order() {
+    let (sender, sender_key) = get_key_pair();
+    let (other, other_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state =
+        init_state_with_accounts(vec![(sender, Balance::from(5)), (other, Balance::from(5))]);
+    let freeze = |frozen, version| Freeze {
+        account: sender,
+        frozen,
+        version: SequenceNumber::from(version),
+    };
+
+    // Only the owner can freeze the account.
+    assert!(authority_state
+        .handle_freeze_order(FreezeOrder::new(freeze(true, 0), &other_key))
+        .is_err());
+    let freeze_order = FreezeOrder::new(freeze(true, 0), &sender_key);
+    authority_state
+        .handle_freeze_order(freeze_order.clone())
+        .unwrap();
+    assert!(authority_state.accounts.get(&sender).unwrap().frozen);
+
+    // A frozen account cannot send.
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(1));
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::AccountFrozen)
+    );
+
+    // A frozen account can receive.
+    let certificate = init_certified_transfer_order(
+        other,
+        &other_key,
+        Address::FastPay(sender),
+        Amount::from(3),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().balance,
+        Balance::from(8)
+    );
+
+    // Unfreezing restores sending.
+    authority_state
+        .handle_freeze_order(FreezeOrder::new(freeze(false, 1), &sender_key))
+        .unwrap();
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
+        .is_ok());
+
+    // Older orders cannot be replayed.
+    assert_eq!(
+        authority_state.handle_freeze_order(freeze_order),
+        Err(FastPayError::StaleFreezeOrder {
+            next_freeze_version: SequenceNumber::from(2)
+        })
+    );
+    // Repeating the last order is accepted.
+    authority_state
+        .handle_freeze_order(FreezeOrder::new(freeze(false, 1), &sender_key))
+        .unwrap();
+    assert!(!authority_state.accounts.get(&sender).unwrap().frozen);
+}
+
+#[test]
+fn test_handle_compact_confirmation_order() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let transfer_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+    );
+    let certificate = certify_transfer_order(transfer_order.clone(), &authority_state);
+    let compact = CompactConfirmationOrder::new(&certificate);
+
+    // The authority has not seen the transfer order yet.
+    assert_eq!(
+        authority_state.handle_compact_confirmation_order(compact.clone()),
+        Err(FastPayError::UnknownTransferOrder)
+    );
+
+    authority_state
+        .handle_transfer_order(transfer_order)
+        .unwrap();
+    let (info, _) = authority_state
+        .handle_compact_confirmation_order(compact.clone())
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(0));
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(1));
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().confirmed_log,
+        vec![certificate]
+    );
+    assert_eq!(
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(5)
+    );
+
+    // Confirming again is harmless.
+    let (info, _) = authority_state
+        .handle_compact_confirmation_order(compact)
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(1));
+    assert_eq!(
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(5)
+    );
 }
 
 #[test]
@@ -275,6 +654,64 @@ fn test_handle_confirmation_order_receiver_equal_sender() {
     assert_eq!(Balance::from(1), account.balance);
     assert_eq!(SequenceNumber::from(1), account.next_sequence_number);
     assert_eq!(account.confirmed_log.len(), 1);
+    // The amount is neither debited nor credited.
+    assert!(account.received_log.is_empty());
+}
+
+#[test]
+fn test_handle_confirmation_order_self_transfer_with_fee() {
+    let (address, key) = get_key_pair();
+    let fee_recipient = dbg_addr(3);
+    let mut authority_state = init_state_with_account(address, Balance::from(10));
+    let mut transfer =
+        init_transfer_order(address, &key, Address::FastPay(address), Amount::from(5)).transfer;
+    transfer.fee = Amount::from(2);
+    let certificate = certify_transfer_order(TransferOrder::new(transfer, &key), &authority_state);
+
+    // Only the fee is paid.
+    let mut paying_state = init_state_with_account(address, Balance::from(10));
+    paying_state.name = authority_state.name;
+    paying_state.committee = authority_state.committee.clone();
+    paying_state.fee_recipient = Some(fee_recipient);
+    paying_state
+        .accounts
+        .get_mut(&address)
+        .unwrap()
+        .initial_balance = Balance::from(10);
+    let (info, updates) = paying_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(8));
+    assert!(updates.is_empty());
+    let accounts = &paying_state.accounts;
+    assert_eq!(
+        accounts.get(&fee_recipient).unwrap().balance,
+        Balance::from(2)
+    );
+    let account = accounts.get(&address).unwrap();
+    assert!(account.received_log.is_empty());
+    account
+        .verify_invariants(address, &paying_state.committee)
+        .unwrap();
+
+    // Paying the fee to oneself changes nothing.
+    authority_state.fee_recipient = Some(address);
+    authority_state
+        .accounts
+        .get_mut(&address)
+        .unwrap()
+        .initial_balance = Balance::from(10);
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    let account = authority_state.accounts.get(&address).unwrap();
+    assert_eq!(account.balance, Balance::from(10));
+    assert!(account.received_log.is_empty());
+    assert_eq!(account.received_fees, Balance::from(2));
+    assert!(account.received_keys.contains(&certificate.key()));
+    account
+        .verify_invariants(address, &authority_state.committee)
+        .unwrap();
 }
 
 #[test]
@@ -290,13 +727,156 @@ fn test_handle_cross_shard_recipient_commit() {
         Amount::from(10),
         &authority_state,
     );
-    assert!(authority_state
-        .handle_cross_shard_recipient_commit(certified_transfer_order)
-        .is_ok());
+    let ack = authority_state
+        .handle_cross_shard_recipient_commit(certified_transfer_order.clone())
+        .unwrap();
+    assert_eq!(
+        ack,
+        CrossShardAck {
+            shard_id: 0,
+            sender,
+            sequence_number: SequenceNumber::from(0),
+            updated_shard_id: 0,
+        }
+    );
     let account = authority_state.accounts.get(&recipient).unwrap();
     assert_eq!(Balance::from(11), account.balance);
     assert_eq!(SequenceNumber::from(0), account.next_sequence_number);
     assert_eq!(account.confirmed_log.len(), 0);
+
+    // Replaying the update is acknowledged again but does not credit the recipient twice.
+    assert_eq!(
+        authority_state.handle_cross_shard_recipient_commit(certified_transfer_order),
+        Ok(ack)
+    );
+    let account = authority_state.accounts.get(&recipient).unwrap();
+    assert_eq!(Balance::from(11), account.balance);
+    assert_eq!(account.received_log.len(), 1);
+}
+
+#[test]
+fn test_received_transfers_are_sorted() {
+    let (recipient, _) = get_key_pair();
+    let mut state_a = init_state_with_account(recipient, Balance::from(0));
+    let mut state_b = init_state_with_account(recipient, Balance::from(0));
+    let mut certificates = Vec::new();
+    for _ in 0..3 {
+        let (sender, sender_key) = get_key_pair();
+        for i in 0..2 {
+            let mut transfer_order = init_transfer_order(
+                sender,
+                &sender_key,
+                Address::FastPay(recipient),
+                Amount::from(1),
+            );
+            transfer_order.transfer.sequence_number = SequenceNumber::from(i);
+            let transfer_order = TransferOrder::new(transfer_order.transfer, &sender_key);
+            certificates.push(certify_transfer_order(transfer_order, &state_a));
+        }
+    }
+    for certificate in &certificates {
+        state_a
+            .handle_cross_shard_recipient_commit(certificate.clone())
+            .unwrap();
+    }
+    for certificate in certificates.iter().rev() {
+        state_b
+            .handle_cross_shard_recipient_commit(certificate.clone())
+            .unwrap();
+    }
+
+    let request = AccountInfoRequest {
+        sender: recipient,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: Some(0),
+    };
+    let response_a = state_a
+        .handle_account_info_request(request.clone())
+        .unwrap();
+    let response_b = state_b.handle_account_info_request(request).unwrap();
+    assert_eq!(response_a, response_b);
+    certificates.sort_by_key(CertifiedTransferOrder::key);
+    assert_eq!(response_a.requested_received_transfers, certificates);
+}
+
+#[test]
+fn test_cross_shard_update_is_retried_until_acknowledged() {
+    let (sender, sender_key) = get_key_pair();
+    let (authority_address, authority_key) = get_authority_key_pair();
+    let mut authorities = BTreeMap::new();
+    authorities.insert(authority_address, 1);
+    let committee = Committee::new(authorities);
+    let mut shards: Vec<_> = (0..2)
+        .map(|shard_id| {
+            AuthorityState::new_shard(
+                committee.clone(),
+                authority_address,
+                authority_key.copy(),
+                shard_id,
+                2,
+            )
+        })
+        .collect();
+    let sender_shard = shards[0].which_shard(&sender) as usize;
+    let recipient_shard = 1 - sender_shard;
+    let recipient = loop {
+        let (address, _) = get_key_pair();
+        if shards[0].which_shard(&address) as usize == recipient_shard {
+            break address;
+        }
+    };
+    shards[sender_shard]
+        .accounts
+        .entry(sender)
+        .or_insert_with(AccountOffchainState::new)
+        .balance = Balance::from(5);
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &shards[sender_shard],
+    );
+    let (_, mut updates) = shards[sender_shard]
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    assert_eq!(updates.len(), 1);
+    let update = updates.pop().unwrap();
+    assert_eq!(update.shard_id as usize, recipient_shard);
+    // The first cross-shard message is dropped: the update remains pending.
+    assert_eq!(
+        shards[sender_shard]
+            .pending_cross_shard_updates
+            .values()
+            .next(),
+        Some(&update)
+    );
+    assert!(shards[recipient_shard].accounts.get(&recipient).is_none());
+
+    // Retry pending updates.
+    let pending: Vec<_> = shards[sender_shard]
+        .pending_cross_shard_updates
+        .values()
+        .cloned()
+        .collect();
+    for update in pending {
+        let ack = shards[update.shard_id as usize]
+            .handle_cross_shard_recipient_commit(update.transfer_certificate)
+            .unwrap();
+        assert_eq!(ack.shard_id as usize, sender_shard);
+        shards[ack.shard_id as usize]
+            .handle_cross_shard_ack(ack)
+            .unwrap();
+    }
+    assert!(shards[sender_shard].pending_cross_shard_updates.is_empty());
+    assert_eq!(
+        shards[recipient_shard]
+            .accounts
+            .get(&recipient)
+            .unwrap()
+            .balance,
+        Balance::from(5)
+    );
 }
 
 #[test]
@@ -357,13 +937,830 @@ fn test_handle_confirmation_order_ok() {
 }
 
 #[test]
-fn test_handle_primary_synchronization_order_update() {
-    let mut state = init_state();
-    let mut updated_transaction_index = state.last_transaction_index;
-    let address = dbg_addr(1);
-    let order = init_primary_synchronization_order(address);
-
-    assert!(state
+fn test_handle_transfer_order_fee_exceeds_balance() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let mut transfer = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(2)),
+        Amount::from(4),
+    )
+    .transfer;
+    transfer.fee = Amount::from(2);
+    assert_eq!(
+        authority_state.handle_transfer_order(TransferOrder::new(transfer, &sender_key)),
+        Err(FastPayError::InsufficientFunding {
+            current_balance: Balance::from(5)
+        })
+    );
+}
+
+#[test]
+fn test_authority_events() {
+    let (sender, sender_key) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let (event_sender, mut events) = tokio::sync::broadcast::channel(16);
+    authority_state.event_sender = Some(event_sender);
+
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert_eq!(
+        events.try_recv(),
+        Ok(AuthorityEvent::TransferConfirmed {
+            sender,
+            sequence_number: SequenceNumber::new()
+        })
+    );
+    assert_eq!(
+        events.try_recv(),
+        Ok(AuthorityEvent::AccountCreated { address: recipient })
+    );
+    assert_eq!(
+        events.try_recv(),
+        Ok(AuthorityEvent::AccountCredited {
+            address: recipient,
+            amount: Amount::from(5)
+        })
+    );
+    assert!(events.try_recv().is_err());
+
+    // Nothing happens when confirming again, or on a failure.
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    let mut transfer_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(1),
+    );
+    transfer_order.transfer.sequence_number = SequenceNumber::from(5);
+    let transfer_order = TransferOrder::new(transfer_order.transfer, &sender_key);
+    let certificate = certify_transfer_order(transfer_order, &authority_state);
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .is_err());
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn test_handle_confirmation_order_out_of_order() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+    let certificates: Vec<_> = (0..4)
+        .map(|i| {
+            let mut transfer_order = init_transfer_order(
+                sender,
+                &sender_key,
+                Address::FastPay(recipient),
+                Amount::from(1),
+            );
+            transfer_order.transfer.sequence_number = SequenceNumber::from(i);
+            let transfer_order = TransferOrder::new(transfer_order.transfer, &sender_key);
+            certify_transfer_order(transfer_order, &authority_state)
+        })
+        .collect();
+
+    // Early certificates are rejected but kept.
+    for certificate in certificates[1..].iter().rev() {
+        assert!(matches!(
+            authority_state.handle_confirmation_order(ConfirmationOrder::new(certificate.clone())),
+            Err(FastPayError::MissingEalierConfirmations { .. })
+        ));
+    }
+    assert_eq!(
+        authority_state
+            .accounts
+            .get(&sender)
+            .unwrap()
+            .next_sequence_number,
+        SequenceNumber::new()
+    );
+
+    // The first certificate fills the gap.
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[0].clone()))
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(4));
+    assert_eq!(info.balance, Balance::from(6));
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().confirmed_log,
+        certificates
+    );
+    assert_eq!(
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(4)
+    );
+}
+
+#[test]
+fn test_failed_buffered_confirmation_is_kept() {
+    let mut authority_state = init_state();
+    authority_state.number_of_shards = 2;
+    let key_pair_in_shard = |in_shard: bool| loop {
+        let (address, key) = get_key_pair();
+        if authority_state.in_shard(&address) == in_shard {
+            break (address, key);
+        }
+    };
+    let (sender, sender_key) = key_pair_in_shard(true);
+    let (recipient, _) = key_pair_in_shard(false);
+    authority_state.accounts.insert(
+        sender,
+        AccountOffchainState::new_with_balance(Balance::from(std::i128::MIN + 5), Vec::new()),
+    );
+    let certificates: Vec<_> = [5, 3]
+        .iter()
+        .enumerate()
+        .map(|(i, amount)| {
+            let mut transfer_order = init_transfer_order(
+                sender,
+                &sender_key,
+                Address::FastPay(recipient),
+                Amount::from(*amount),
+            );
+            transfer_order.transfer.sequence_number = SequenceNumber::from(i as u64);
+            let transfer_order = TransferOrder::new(transfer_order.transfer, &sender_key);
+            certify_transfer_order(transfer_order, &authority_state)
+        })
+        .collect();
+    assert!(matches!(
+        authority_state.handle_confirmation_order(ConfirmationOrder::new(certificates[1].clone())),
+        Err(FastPayError::MissingEalierConfirmations { .. })
+    ));
+
+    // The buffered certificate would underflow the balance, but the first one is confirmed
+    // and its cross-shard update is returned.
+    let (info, updates) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[0].clone()))
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(1));
+    assert_eq!(info.balance, Balance::from(std::i128::MIN));
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].transfer_certificate, certificates[0]);
+    assert_eq!(
+        authority_state.buffered_confirmations.get(&sender).unwrap()[0],
+        certificates[1]
+    );
+
+    // Once the sender is credited, the certificate is accepted.
+    authority_state.accounts.get_mut(&sender).unwrap().balance = Balance::from(3);
+    let (info, updates) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[1].clone()))
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(updates.len(), 1);
+    assert!(authority_state.buffered_confirmations.is_empty());
+}
+
+#[test]
+fn test_buffered_confirmations_are_bounded() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+    authority_state.max_buffered_confirmations = 2;
+    let certificates: Vec<_> = (0..4)
+        .map(|i| {
+            let mut transfer_order = init_transfer_order(
+                sender,
+                &sender_key,
+                Address::FastPay(recipient),
+                Amount::from(1),
+            );
+            transfer_order.transfer.sequence_number = SequenceNumber::from(i);
+            let transfer_order = TransferOrder::new(transfer_order.transfer, &sender_key);
+            certify_transfer_order(transfer_order, &authority_state)
+        })
+        .collect();
+
+    // The certificate received first is evicted.
+    for certificate in certificates[1..].iter().rev() {
+        assert!(authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .is_err());
+    }
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[0].clone()))
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(3));
+
+    // The missing certificate can still be delivered later.
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[3].clone()))
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(4));
+}
+
+#[test]
+fn test_confirmation_receipts() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let keys: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+    let mut states: Vec<_> = keys
+        .iter()
+        .map(|(name, key)| AuthorityState::new(committee.clone(), *name, key.copy()))
+        .collect();
+
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    let mut builder = SignatureAggregator::try_new(transfer_order.clone(), &committee).unwrap();
+    let mut certificate = None;
+    for state in &states {
+        let vote = SignedTransferOrder::new(transfer_order.clone(), state.name, &state.secret);
+        certificate = builder.append(vote.authority, vote.signature).unwrap();
+        if certificate.is_some() {
+            break;
+        }
+    }
+    let certificate = certificate.unwrap();
+
+    // Receipts are only signed on demand.
+    let (info, _) = states[0]
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert!(info.receipt.is_none());
+
+    let mut signatures = Vec::new();
+    for state in &mut states {
+        state.sign_receipts = true;
+        let (info, _) = state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .unwrap();
+        let receipt = info.receipt.unwrap();
+        assert_eq!(receipt.value, Receipt::new(&transfer_order.transfer));
+        assert_eq!(receipt.check(&committee), Ok(1));
+        signatures.push((receipt.authority, receipt.signature));
+    }
+    // Other responses carry no receipt.
+    let info = states[0]
+        .handle_account_info_request(AccountInfoRequest {
+            sender,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+        })
+        .unwrap();
+    assert!(info.receipt.is_none());
+
+    let mut receipt_certificate = CertifiedReceipt {
+        value: Receipt::new(&transfer_order.transfer),
+        signatures: signatures[..2].to_vec(),
+    };
+    assert!(receipt_certificate.check(&committee).is_err());
+    receipt_certificate.signatures = signatures[..3].to_vec();
+    assert!(receipt_certificate.check(&committee).is_ok());
+    receipt_certificate.value.amount = Amount::from(6);
+    assert!(receipt_certificate.check(&committee).is_err());
+}
+
+#[test]
+fn test_handle_confirmation_order_with_certificate_cache() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.certificate_cache = Some(CertificateCache::new(100));
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(5),
+        &authority_state,
+    );
+    for _ in 0..2 {
+        authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .unwrap();
+    }
+    assert_eq!(authority_state.certificate_cache.unwrap().hits(), 1);
+}
+
+#[test]
+fn test_buffered_confirmations_are_not_checked_again() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.certificate_cache = Some(CertificateCache::new(100));
+    let certificates: Vec<_> = (0..2)
+        .map(|i| {
+            let mut order = init_transfer_order(sender, &sender_key, recipient, Amount::from(1));
+            order.transfer.sequence_number = SequenceNumber::from(i);
+            certify_transfer_order(
+                TransferOrder::new(order.transfer, &sender_key),
+                &authority_state,
+            )
+        })
+        .collect();
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[1].clone()))
+        .is_err());
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificates[0].clone()))
+        .unwrap();
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(2));
+    // Each certificate was checked once.
+    let cache = authority_state.certificate_cache.unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.hits(), 0);
+}
+
+#[test]
+fn test_handle_confirmation_order_with_fee() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let fee_recipient = dbg_addr(3);
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+    let mut transfer = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+    )
+    .transfer;
+    transfer.fee = Amount::from(2);
+    let certificate =
+        certify_transfer_order(TransferOrder::new(transfer, &sender_key), &authority_state);
+
+    // Without a fee recipient, fees are burnt.
+    let mut burning_state = init_state_with_account(sender, Balance::from(10));
+    burning_state.name = authority_state.name;
+    burning_state.committee = authority_state.committee.clone();
+    let (info, updates) = burning_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(3));
+    assert!(updates.is_empty());
+    assert_eq!(burning_state.accounts.len(), 2);
+
+    authority_state.fee_recipient = Some(fee_recipient);
+    let (info, updates) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(3));
+    assert!(updates.is_empty());
+    let accounts = &authority_state.accounts;
+    assert_eq!(accounts.get(&recipient).unwrap().balance, Balance::from(5));
+    let fee_account = accounts.get(&fee_recipient).unwrap();
+    assert_eq!(fee_account.balance, Balance::from(2));
+    // Certificates that only pay a fee are not logged.
+    assert!(fee_account.received_log.is_empty());
+    assert_eq!(fee_account.received_fees, Balance::from(2));
+    assert!(fee_account.received_keys.contains(&certificate.key()));
+    fee_account
+        .verify_invariants(fee_recipient, &authority_state.committee)
+        .unwrap();
+}
+
+#[test]
+fn test_cross_shard_fee() {
+    let (sender, sender_key) = get_key_pair();
+    let (authority_address, authority_key) = get_authority_key_pair();
+    let mut authorities = BTreeMap::new();
+    authorities.insert(authority_address, 1);
+    let committee = Committee::new(authorities);
+    let sender_shard = AuthorityState::get_shard(2, &sender);
+    let fee_recipient = loop {
+        let (address, _) = get_key_pair();
+        if AuthorityState::get_shard(2, &address) != sender_shard {
+            break address;
+        }
+    };
+    let mut shards: Vec<_> = (0..2)
+        .map(|shard_id| {
+            let mut state = AuthorityState::new_shard(
+                committee.clone(),
+                authority_address,
+                authority_key.copy(),
+                shard_id,
+                2,
+            );
+            state.fee_recipient = Some(fee_recipient);
+            state
+        })
+        .collect();
+    shards[sender_shard as usize]
+        .accounts
+        .entry(sender)
+        .or_insert_with(AccountOffchainState::new)
+        .balance = Balance::from(5);
+    // Recipient is the sender itself: only the fee crosses shards.
+    let mut transfer = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(sender),
+        Amount::from(3),
+    )
+    .transfer;
+    transfer.fee = Amount::from(1);
+    let certificate = certify_transfer_order(
+        TransferOrder::new(transfer, &sender_key),
+        &shards[sender_shard as usize],
+    );
+    let (info, updates) = shards[sender_shard as usize]
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(4));
+    assert_eq!(
+        shards[sender_shard as usize]
+            .accounts
+            .get(&sender)
+            .unwrap()
+            .balance,
+        Balance::from(4)
+    );
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].shard_id, 1 - sender_shard);
+
+    let ack = shards[1 - sender_shard as usize]
+        .handle_cross_shard_recipient_commit(updates[0].transfer_certificate.clone())
+        .unwrap();
+    assert_eq!(ack.updated_shard_id, 1 - sender_shard);
+    // Repeated updates are only credited once.
+    assert_eq!(
+        shards[1 - sender_shard as usize]
+            .handle_cross_shard_recipient_commit(updates[0].transfer_certificate.clone()),
+        Ok(ack.clone())
+    );
+    assert_eq!(
+        shards[1 - sender_shard as usize]
+            .accounts
+            .get(&fee_recipient)
+            .unwrap()
+            .balance,
+        Balance::from(1)
+    );
+    shards[sender_shard as usize]
+        .handle_cross_shard_ack(ack)
+        .unwrap();
+    assert!(shards[sender_shard as usize]
+        .pending_cross_shard_updates
+        .is_empty());
+}
+
+#[test]
+fn test_account_invariants_and_repair() {
+    let (sender, sender_key) = get_key_pair();
+    let (recipient, recipient_key) = get_key_pair();
+    let mut authority_state = init_state();
+    authority_state.accounts.insert(
+        sender,
+        AccountOffchainState::new_with_balance(Balance::from(10), Vec::new()),
+    );
+    authority_state.accounts.insert(
+        recipient,
+        AccountOffchainState::new_with_balance(Balance::from(1), Vec::new()),
+    );
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(4),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    let certificate = init_certified_transfer_order(
+        recipient,
+        &recipient_key,
+        Address::FastPay(sender),
+        Amount::from(2),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    let committee = authority_state.committee.clone();
+    for (address, balance) in &[(sender, 8), (recipient, 3)] {
+        let account = authority_state.accounts.get(address).unwrap();
+        assert_eq!(account.balance, Balance::from(*balance));
+        assert!(account.verify_invariants(*address, &committee).is_ok());
+    }
+
+    // Simulate a partial write.
+    let account = authority_state.accounts.get_mut(&sender).unwrap();
+    account.balance = Balance::from(100);
+    account.next_sequence_number = SequenceNumber::from(0);
+    match account.verify_invariants(sender, &committee) {
+        Err(FastPayError::InvalidAccountState { address, .. }) => assert_eq!(address, sender),
+        result => panic!("unexpected result {:?}", result),
+    }
+    account.next_sequence_number = SequenceNumber::from(1);
+    assert!(account.verify_invariants(sender, &committee).is_err());
+
+    account.repair(sender).unwrap();
+    assert_eq!(account.balance, Balance::from(8));
+    assert_eq!(account.next_sequence_number, SequenceNumber::from(1));
+    assert!(account.verify_invariants(sender, &committee).is_ok());
+
+    // Logged certificates must be valid.
+    let account = authority_state.accounts.get(&recipient).unwrap();
+    let other_committee = init_state().committee;
+    assert!(account
+        .verify_invariants(recipient, &other_committee)
+        .is_err());
+}
+
+#[test]
+fn test_prune_confirmed_log() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state();
+    authority_state.accounts.insert(
+        sender,
+        AccountOffchainState::new_with_balance(Balance::from(10), Vec::new()),
+    );
+    let committee = authority_state.committee.clone();
+    let confirm_transfer = |authority_state: &mut AuthorityState, sequence_number: u64| {
+        let transfer = Transfer {
+            sender,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(1),
+            sequence_number: SequenceNumber::from(sequence_number),
+            user_data: UserData::default(),
+            valid_until: None,
+            fee: Amount::zero(),
+        };
+        let certificate =
+            certify_transfer_order(TransferOrder::new(transfer, &sender_key), authority_state);
+        authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .unwrap();
+    };
+    let make_checkpoint = |authority_state: &AuthorityState| {
+        let vote = authority_state
+            .handle_account_snapshot_request(AccountSnapshotRequest { account: sender })
+            .unwrap();
+        let mut builder = SignatureAggregator::try_new(vote.value.clone(), &committee).unwrap();
+        builder
+            .append(vote.authority, vote.signature)
+            .unwrap()
+            .unwrap()
+    };
+    for sequence_number in 0..4 {
+        confirm_transfer(&mut authority_state, sequence_number);
+    }
+    let checkpoint = make_checkpoint(&authority_state);
+
+    // Pruning is disabled by default.
+    assert_eq!(
+        authority_state.prune_confirmed_log(checkpoint.clone()),
+        Ok(0)
+    );
+    authority_state.pruning_depth = Some(1);
+    assert_eq!(
+        authority_state.prune_confirmed_log(checkpoint.clone()),
+        Ok(3)
+    );
+    assert_eq!(authority_state.prune_confirmed_log(checkpoint), Ok(0));
+
+    let account = authority_state.accounts.get(&sender).unwrap();
+    assert_eq!(account.balance, Balance::from(6));
+    assert_eq!(account.next_sequence_number, SequenceNumber::from(4));
+    assert_eq!(account.confirmed_log.len(), 1);
+    assert!(account.verify_invariants(sender, &committee).is_ok());
+
+    // Pruned certificates point to the checkpoint.
+    let request = |sequence_number| AccountInfoRequest {
+        sender,
+        request_sequence_number: Some(SequenceNumber::from(sequence_number)),
+        request_received_transfers_excluding_first_nth: None,
+    };
+    match authority_state.handle_account_info_request(request(2)) {
+        Err(FastPayError::CertificatePruned { snapshot }) => {
+            assert_eq!(snapshot.balance, Balance::from(6));
+            assert_eq!(snapshot.next_sequence_number, SequenceNumber::from(4));
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+    let response = authority_state
+        .handle_account_info_request(request(3))
+        .unwrap();
+    assert_eq!(
+        response
+            .requested_certificate
+            .unwrap()
+            .value
+            .transfer
+            .sequence_number,
+        SequenceNumber::from(3)
+    );
+    assert_eq!(
+        authority_state.handle_account_info_request(request(4)),
+        Err(FastPayError::CertificateNotfound)
+    );
+
+    // Transfers and repairs still work after pruning.
+    confirm_transfer(&mut authority_state, 4);
+    let account = authority_state.accounts.get_mut(&sender).unwrap();
+    account.balance = Balance::from(100);
+    account.next_sequence_number = SequenceNumber::from(0);
+    account.repair(sender).unwrap();
+    assert_eq!(account.balance, Balance::from(5));
+    assert_eq!(account.next_sequence_number, SequenceNumber::from(5));
+    assert!(account.verify_invariants(sender, &committee).is_ok());
+}
+
+#[test]
+fn test_export_import_snapshot() {
+    let (sender, sender_key) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(3),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    authority_state
+        .handle_primary_synchronization_order(init_primary_synchronization_order(recipient))
+        .unwrap();
+
+    let snapshot = authority_state.export_snapshot();
+    let bytes = bincode::serialize(&snapshot).unwrap();
+    let snapshot: AuthoritySnapshot = bincode::deserialize(&bytes).unwrap();
+    let mut imported_state = AuthorityState::new(
+        authority_state.committee.clone(),
+        authority_state.name,
+        authority_state.secret.copy(),
+    );
+    imported_state.import_snapshot(snapshot).unwrap();
+
+    assert_eq!(
+        imported_state.last_transaction_index,
+        authority_state.last_transaction_index
+    );
+    let requests = vec![
+        AccountInfoRequest {
+            sender,
+            request_sequence_number: Some(SequenceNumber::from(0)),
+            request_received_transfers_excluding_first_nth: None,
+        },
+        AccountInfoRequest {
+            sender: recipient,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: Some(0),
+        },
+    ];
+    for request in requests {
+        assert_eq!(
+            imported_state.handle_account_info_request(request.clone()),
+            authority_state.handle_account_info_request(request)
+        );
+    }
+}
+
+#[test]
+fn test_import_snapshot_resets_derived_state() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.rate_limit = Some(RateLimit {
+        burst: 5,
+        period: Duration::from_secs(1),
+    });
+    authority_state.certificate_cache = Some(CertificateCache::new(10));
+    let recipient = Address::FastPay(dbg_addr(2));
+    let first_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(1));
+    let second_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(2));
+    authority_state.handle_transfer_order(first_order).unwrap();
+    assert!(authority_state.handle_transfer_order(second_order).is_err());
+    // A certificate received before its predecessor is buffered.
+    let mut transfer =
+        init_transfer_order(sender, &sender_key, recipient, Amount::from(1)).transfer;
+    transfer.sequence_number = SequenceNumber::from(1);
+    let early_certificate =
+        certify_transfer_order(TransferOrder::new(transfer, &sender_key), &authority_state);
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(early_certificate))
+        .is_err());
+    assert!(!authority_state.token_buckets.is_empty());
+    assert!(!authority_state.buffered_confirmations.is_empty());
+    assert!(!authority_state.equivocation_proofs.is_empty());
+    assert!(authority_state.certificate_cache.as_ref().unwrap().len() > 0);
+
+    let snapshot = authority_state.export_snapshot();
+    authority_state.import_snapshot(snapshot).unwrap();
+    assert!(authority_state.token_buckets.is_empty());
+    assert!(authority_state.buffered_confirmations.is_empty());
+    assert!(authority_state.equivocation_proofs.is_empty());
+    assert_eq!(authority_state.certificate_cache.as_ref().unwrap().len(), 0);
+}
+
+#[test]
+fn test_import_snapshot_wrong_shard() {
+    let (authority_name, authority_key) = get_authority_key_pair();
+    let mut authorities = BTreeMap::new();
+    authorities.insert(authority_name, 1);
+    let committee = Committee::new(authorities);
+    let mut state =
+        AuthorityState::new_shard(committee, authority_name, authority_key.copy(), 0, 2);
+    let address = loop {
+        let (address, _) = get_key_pair();
+        if AuthorityState::get_shard(2, &address) == 1 {
+            break address;
+        }
+    };
+    let mut snapshot = AuthoritySnapshot {
+        shard_id: 0,
+        number_of_shards: 2,
+        last_transaction_index: VersionNumber::from(3),
+        accounts: vec![(address, AccountOffchainState::new())],
+        pending_cross_shard_updates: Vec::new(),
+    };
+    assert!(matches!(
+        state.import_snapshot(snapshot.clone()),
+        Err(FastPayError::WrongShard { .. })
+    ));
+    // Snapshots of other shards are rejected as a whole.
+    snapshot.shard_id = 1;
+    snapshot.accounts.clear();
+    assert_eq!(
+        state.import_snapshot(snapshot),
+        Err(FastPayError::WrongSnapshotShard {
+            shard_id: 1,
+            number_of_shards: 2
+        })
+    );
+    assert!(state.accounts.is_empty());
+    assert_eq!(state.last_transaction_index, VersionNumber::new());
+}
+
+#[test]
+fn test_load_genesis() {
+    let keys: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+    let (sender, _) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let genesis = GenesisState {
+        accounts: vec![(sender, Balance::from(5)), (recipient, Balance::from(3))],
+        fee_recipient: Some(recipient),
+    };
+    let signatures = keys
+        .iter()
+        .take(3)
+        .map(|(name, secret)| {
+            let vote = SignedGenesisState::new(genesis.clone(), *name, secret);
+            assert_eq!(vote.check(&committee), Ok(1));
+            (vote.authority, vote.signature)
+        })
+        .collect();
+    let certificate = CertifiedGenesisState {
+        value: genesis,
+        signatures,
+    };
+    for (name, secret) in &keys {
+        let mut state = AuthorityState::new(committee.clone(), *name, secret.copy());
+        state.load_genesis(&certificate).unwrap();
+        assert_eq!(state.fee_recipient, Some(recipient));
+        assert_eq!(
+            state.accounts.get(&sender).unwrap().balance,
+            Balance::from(5)
+        );
+        assert_eq!(
+            state.accounts.get(&recipient).unwrap().balance,
+            Balance::from(3)
+        );
+        assert_eq!(
+            state.accounts.get(&sender).unwrap().initial_balance,
+            Balance::from(5)
+        );
+        // The genesis state cannot be loaded twice, nor on top of existing accounts.
+        assert_eq!(
+            state.load_genesis(&certificate),
+            Err(FastPayError::GenesisAlreadyLoaded)
+        );
+    }
+
+    // Changing the balances invalidates the certificate.
+    let mut tampered = certificate;
+    tampered.value.accounts[0].1 = Balance::from(500);
+    let (name, secret) = &keys[0];
+    let mut state = AuthorityState::new(committee, *name, secret.copy());
+    assert!(state.load_genesis(&tampered).is_err());
+    assert!(state.accounts.is_empty());
+}
+
+#[test]
+fn test_handle_primary_synchronization_order_update() {
+    let mut state = init_state();
+    let mut updated_transaction_index = state.last_transaction_index;
+    let address = dbg_addr(1);
+    let order = init_primary_synchronization_order(address);
+
+    assert!(state
         .handle_primary_synchronization_order(order.clone())
         .is_ok());
     updated_transaction_index = updated_transaction_index.increment().unwrap();
@@ -412,6 +1809,77 @@ fn test_account_state_unknown_account() {
     assert!(authority_state.account_state(&unknown_address).is_err());
 }
 
+#[test]
+fn test_handle_account_info_request_wrong_shard() {
+    let (sender, _) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.number_of_shards = 16;
+    let shard_id = authority_state.which_shard(&sender);
+    authority_state.shard_id = (shard_id + 1) % 16;
+    let request = AccountInfoRequest {
+        sender,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: None,
+    };
+    let error = authority_state
+        .handle_account_info_request(request)
+        .unwrap_err();
+    assert_eq!(
+        error,
+        FastPayError::WrongShard {
+            address: sender,
+            shard_id
+        }
+    );
+    assert!(error.to_string().contains(&format!("shard {}", shard_id)));
+}
+
+#[test]
+fn test_handle_batch_account_info_request() {
+    let mut authority_state = init_state();
+    authority_state.number_of_shards = 2;
+    let address_in_shard = |in_shard: bool| loop {
+        let (address, _) = get_key_pair();
+        if authority_state.in_shard(&address) == in_shard {
+            break address;
+        }
+    };
+    let on_shard = address_in_shard(true);
+    let off_shard = address_in_shard(false);
+    let unknown = address_in_shard(true);
+    authority_state.accounts.insert(
+        on_shard,
+        AccountOffchainState::new_with_balance(Balance::from(5), Vec::new()),
+    );
+
+    let request = BatchAccountInfoRequest {
+        senders: vec![off_shard, on_shard, unknown],
+    };
+    let response = authority_state
+        .handle_batch_account_info_request(request)
+        .unwrap();
+    let addresses: Vec<_> = response
+        .responses
+        .iter()
+        .map(|(address, _)| *address)
+        .collect();
+    assert_eq!(addresses, vec![off_shard, on_shard, unknown]);
+    assert_eq!(
+        response.responses[0].1,
+        Err(FastPayError::WrongShard {
+            address: off_shard,
+            shard_id: authority_state.which_shard(&off_shard),
+        })
+    );
+    let info = response.responses[1].1.clone().unwrap();
+    assert_eq!(info.balance, Balance::from(5));
+    assert_eq!(info.next_sequence_number, SequenceNumber::new());
+    assert_eq!(
+        response.responses[2].1,
+        Err(FastPayError::UnknownSenderAccount)
+    );
+}
+
 #[test]
 fn test_get_shards() {
     let num_shards = 16u32;
@@ -435,7 +1903,7 @@ fn test_get_shards() {
 
 #[cfg(test)]
 fn init_state() -> AuthorityState {
-    let (authority_address, authority_key) = get_key_pair();
+    let (authority_address, authority_key) = get_authority_key_pair();
     let mut authorities = BTreeMap::new();
     authorities.insert(
         /* address */ authority_address,
@@ -478,6 +1946,8 @@ fn init_transfer_order(
         amount,
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     TransferOrder::new(transfer, secret)
 }
@@ -491,6 +1961,14 @@ fn init_certified_transfer_order(
     authority_state: &AuthorityState,
 ) -> CertifiedTransferOrder {
     let transfer_order = init_transfer_order(sender, secret, recipient, amount);
+    certify_transfer_order(transfer_order, authority_state)
+}
+
+#[cfg(test)]
+fn certify_transfer_order(
+    transfer_order: TransferOrder,
+    authority_state: &AuthorityState,
+) -> CertifiedTransferOrder {
     let vote = SignedTransferOrder::new(
         transfer_order.clone(),
         authority_state.name,
@@ -36,3 +36,121 @@ fn test_max_sequence_number() {
     let max = SequenceNumber::max();
     assert_eq!(max.0 * 2 + 1, std::u64::MAX);
 }
+
+#[test]
+fn test_sequence_number_arithmetic() {
+    let zero = SequenceNumber::new();
+    let one = SequenceNumber::from(1);
+    let max = SequenceNumber::max();
+    let before_max = SequenceNumber::from(max.0 - 1);
+
+    assert_eq!(zero.checked_add(0), Ok(zero));
+    assert_eq!(zero.checked_add(1), Ok(one));
+    assert_eq!(zero.checked_add(max.0), Ok(max));
+    assert_eq!(
+        zero.checked_add(max.0 + 1),
+        Err(FastPayError::SequenceOverflow)
+    );
+    assert_eq!(zero.checked_sub(0), Ok(zero));
+    assert_eq!(zero.checked_sub(1), Err(FastPayError::SequenceUnderflow));
+    assert_eq!(zero.increment(), Ok(one));
+    assert_eq!(zero.decrement(), Err(FastPayError::SequenceUnderflow));
+    assert_eq!(one.decrement(), Ok(zero));
+
+    assert_eq!(max.checked_add(0), Ok(max));
+    assert_eq!(max.checked_add(1), Err(FastPayError::SequenceOverflow));
+    assert_eq!(
+        max.checked_add(std::u64::MAX),
+        Err(FastPayError::SequenceOverflow)
+    );
+    assert_eq!(max.checked_sub(max.0), Ok(zero));
+    assert_eq!(
+        max.checked_sub(max.0 + 1),
+        Err(FastPayError::SequenceUnderflow)
+    );
+    assert_eq!(max.increment(), Err(FastPayError::SequenceOverflow));
+    assert_eq!(max.decrement(), Ok(before_max));
+    assert_eq!(before_max.increment(), Ok(max));
+
+    let mut number = before_max;
+    assert_eq!(number.checked_add_assign(1), Ok(()));
+    assert_eq!(number, max);
+    assert_eq!(
+        number.checked_add_assign(1),
+        Err(FastPayError::SequenceOverflow)
+    );
+    assert_eq!(number, max);
+}
+
+#[test]
+fn test_public_key_equality() {
+    let (addr1, _) = get_key_pair();
+    let (addr2, _) = get_key_pair();
+    assert_eq!(addr1, addr1);
+    assert_eq!(addr1, PublicKeyBytes(addr1.0));
+    assert_ne!(addr1, addr2);
+    assert!(bool::from(addr1.ct_eq(&addr1)));
+    assert!(!bool::from(addr1.ct_eq(&addr2)));
+}
+
+#[test]
+fn test_signature_equality() {
+    let (_, sec1) = get_key_pair();
+    let (_, sec2) = get_key_pair();
+    let foo = Foo("hello".into());
+    let foox = Foo("hellox".into());
+
+    let s1 = Signature::new(&foo, &sec1);
+    assert_eq!(s1, Signature::new(&foo, &sec1));
+    assert_ne!(s1, Signature::new(&foox, &sec1));
+    assert_ne!(s1, Signature::new(&foo, &sec2));
+    assert!(bool::from(s1.ct_eq(&s1)));
+}
+
+#[test]
+fn test_key_pair_debug_is_redacted() {
+    let (_, key_pair) = get_key_pair();
+    let debug = format!("{:?}", key_pair);
+    assert_eq!(debug, "KeyPair(<redacted>)");
+    let secret = key_pair.0.secret.as_bytes();
+    let hex: String = secret.iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert!(!debug.to_lowercase().contains(&hex));
+    assert!(!debug.contains(&base64::encode(secret)));
+}
+
+#[test]
+fn test_key_derivation() {
+    // Frozen vectors: changing the derivation scheme would lose access to existing accounts.
+    let vectors: &[([u8; 32], &[u32], &str)] = &[
+        (
+            [0u8; 32],
+            &[],
+            "fyiEygajrETO5IY4UNPX76X67AXL0EWVYf+Frb5008Q=",
+        ),
+        (
+            [0u8; 32],
+            &[0],
+            "GtUc7tms1ETZJLKMVv1emGT1itvIgd4KC2Mxj52KSNE=",
+        ),
+        (
+            [0u8; 32],
+            &[1],
+            "50hy5XuC/i7YXA1NZWZq7uz+SWSUxoDcJA2moCn+cm4=",
+        ),
+        (
+            [7u8; 32],
+            &[1, 2],
+            "YggLN/Qh9qxScM6hdFdGZb5RockCJoLpbm0YGmeomNc=",
+        ),
+    ];
+    for (master, path, expected) in vectors {
+        let key = KeyPair::derive_from_seed(master, path);
+        assert_eq!(encode_address(&key.public()), *expected);
+        let again = KeyPair::derive_from_seed(master, path);
+        assert_eq!(key.public(), again.public());
+    }
+    assert_ne!(
+        KeyPair::derive_from_seed(&[0u8; 32], &[1, 2]).public(),
+        KeyPair::derive_from_seed(&[0u8; 32], &[2, 1]).public()
+    );
+}
@@ -0,0 +1,89 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{base_types::*, messages::*};
+
+fn make_certificate(
+    sequence_number: u64,
+    authorities: &[(AuthorityName, KeyPair)],
+    committee: &Committee,
+) -> CertifiedTransferOrder {
+    let (sender, sender_key) = get_key_pair();
+    let transfer = Transfer {
+        sender,
+        recipient: Address::FastPay(dbg_addr(2)),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::from(sequence_number),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sender_key);
+    let mut builder = SignatureAggregator::try_new(order.clone(), committee).unwrap();
+    for (name, key) in authorities {
+        let vote = SignedTransferOrder::new(order.clone(), *name, key);
+        if let Some(certificate) = builder.append(vote.authority, vote.signature).unwrap() {
+            return certificate;
+        }
+    }
+    panic!("not enough votes");
+}
+
+fn make_committee(authorities: &[(AuthorityName, KeyPair)]) -> Committee {
+    Committee::new(authorities.iter().map(|(name, _)| (*name, 1)).collect())
+}
+
+#[test]
+fn test_certificate_cache() {
+    let authorities: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let committee = make_committee(&authorities);
+    let certificate = make_certificate(0, &authorities, &committee);
+    let mut cache = CertificateCache::new(10);
+
+    cache.check(&certificate, &committee).unwrap();
+    assert_eq!(cache.hits(), 0);
+    cache.check(&certificate, &committee).unwrap();
+    assert_eq!(cache.hits(), 1);
+
+    // Invalid certificates are rejected every time.
+    let mut invalid = certificate.clone();
+    invalid.value.transfer.amount = Amount::from(2);
+    assert!(cache.check(&invalid, &committee).is_err());
+    assert!(cache.check(&invalid, &committee).is_err());
+    assert_eq!(cache.hits(), 1);
+    assert_eq!(cache.len(), 1);
+
+    // A change of committee invalidates the cache.
+    let other_committee = make_committee(&authorities[..3]);
+    cache.check(&certificate, &other_committee).unwrap();
+    assert_eq!(cache.hits(), 1);
+    assert_eq!(cache.len(), 1);
+    cache.check(&certificate, &other_committee).unwrap();
+    assert_eq!(cache.hits(), 2);
+    let new_authorities: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let new_committee = make_committee(&new_authorities);
+    assert!(cache.check(&certificate, &new_committee).is_err());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_certificate_cache_evicts_least_recently_used() {
+    let authorities: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let committee = make_committee(&authorities);
+    let certificates: Vec<_> = (0..3)
+        .map(|i| make_certificate(i, &authorities, &committee))
+        .collect();
+    let mut cache = CertificateCache::new(2);
+    cache.check(&certificates[0], &committee).unwrap();
+    cache.check(&certificates[1], &committee).unwrap();
+    // Use the first certificate again so that the second one is evicted.
+    cache.check(&certificates[0], &committee).unwrap();
+    cache.check(&certificates[2], &committee).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.hits(), 1);
+    cache.check(&certificates[0], &committee).unwrap();
+    assert_eq!(cache.hits(), 2);
+    cache.check(&certificates[1], &committee).unwrap();
+    assert_eq!(cache.hits(), 2);
+}
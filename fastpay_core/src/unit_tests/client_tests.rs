@@ -3,79 +3,9 @@
 #![allow(clippy::same_item_push)] // get_key_pair returns random elements
 
 use super::*;
-use crate::{
-    authority::{AccountOffchainState, Authority, AuthorityState},
-    base_types::Amount,
-};
-use futures::lock::Mutex;
-use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
-};
+use crate::{authority::AuthorityState, base_types::Amount, testing::*};
 use tokio::runtime::Runtime;
 
-#[derive(Clone)]
-struct LocalAuthorityClient(Arc<Mutex<AuthorityState>>);
-
-impl AuthorityClient for LocalAuthorityClient {
-    fn handle_transfer_order(
-        &mut self,
-        order: TransferOrder,
-    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
-        let state = self.0.clone();
-        Box::pin(async move { state.lock().await.handle_transfer_order(order) })
-    }
-
-    fn handle_confirmation_order(
-        &mut self,
-        order: ConfirmationOrder,
-    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
-        let state = self.0.clone();
-        Box::pin(async move {
-            state
-                .lock()
-                .await
-                .handle_confirmation_order(order)
-                .map(|(info, _)| info)
-        })
-    }
-
-    fn handle_account_info_request(
-        &mut self,
-        request: AccountInfoRequest,
-    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
-        let state = self.0.clone();
-        Box::pin(async move { state.lock().await.handle_account_info_request(request) })
-    }
-}
-
-impl LocalAuthorityClient {
-    fn new(state: AuthorityState) -> Self {
-        Self(Arc::new(Mutex::new(state)))
-    }
-}
-
-#[cfg(test)]
-fn init_local_authorities(
-    count: usize,
-) -> (HashMap<AuthorityName, LocalAuthorityClient>, Committee) {
-    let mut key_pairs = Vec::new();
-    let mut voting_rights = BTreeMap::new();
-    for _ in 0..count {
-        let key_pair = get_key_pair();
-        voting_rights.insert(key_pair.0, 1);
-        key_pairs.push(key_pair);
-    }
-    let committee = Committee::new(voting_rights);
-
-    let mut clients = HashMap::new();
-    for (address, secret) in key_pairs {
-        let state = AuthorityState::new(committee.clone(), address, secret);
-        clients.insert(address, LocalAuthorityClient::new(state));
-    }
-    (clients, committee)
-}
-
 #[cfg(test)]
 fn init_local_authorities_bad_1(
     count: usize,
@@ -83,11 +13,11 @@ fn init_local_authorities_bad_1(
     let mut key_pairs = Vec::new();
     let mut voting_rights = BTreeMap::new();
     for i in 0..count {
-        let key_pair = get_key_pair();
+        let key_pair = get_authority_key_pair();
         voting_rights.insert(key_pair.0, 1);
         if i + 1 < (count + 2) / 3 {
             // init 1 authority with a bad keypair
-            key_pairs.push(get_key_pair());
+            key_pairs.push(get_authority_key_pair());
         } else {
             key_pairs.push(key_pair);
         }
@@ -102,45 +32,9 @@ fn init_local_authorities_bad_1(
     (clients, committee)
 }
 
-#[cfg(test)]
-fn make_client(
-    authority_clients: HashMap<AuthorityName, LocalAuthorityClient>,
-    committee: Committee,
-) -> ClientState<LocalAuthorityClient> {
-    let (address, secret) = get_key_pair();
-    ClientState::new(
-        address,
-        secret,
-        committee,
-        authority_clients,
-        SequenceNumber::new(),
-        Vec::new(),
-        Vec::new(),
-        Balance::from(0),
-    )
-}
-
-#[cfg(test)]
-fn fund_account<I: IntoIterator<Item = i128>>(
-    clients: &mut HashMap<AuthorityName, LocalAuthorityClient>,
-    address: FastPayAddress,
-    balances: I,
-) {
-    let mut balances = balances.into_iter().map(Balance::from);
-    for (_, client) in clients.iter_mut() {
-        client.0.as_ref().try_lock().unwrap().accounts_mut().insert(
-            address,
-            AccountOffchainState::new_with_balance(
-                balances.next().unwrap_or_else(Balance::zero),
-                /* no receive log to justify the balances */ Vec::new(),
-            ),
-        );
-    }
-}
-
 #[cfg(test)]
 fn init_local_client_state(balances: Vec<i128>) -> ClientState<LocalAuthorityClient> {
-    let (mut authority_clients, committee) = init_local_authorities(balances.len());
+    let (mut authority_clients, committee) = init_local_authorities(balances.len(), 1);
     let client = make_client(authority_clients.clone(), committee);
     fund_account(&mut authority_clients, client.address, balances);
     client
@@ -198,6 +92,45 @@ fn test_initiating_valid_transfer() {
     );
 }
 
+#[test]
+fn test_resume_interrupted_transfer() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let mut sender = init_local_client_state(vec![4, 4, 4, 4]);
+    sender.balance = Balance::from(4);
+
+    // Block two authorities so that no quorum is reached, then drop the transfer.
+    let authority_clients: Vec<_> = sender.authority_clients.values().cloned().collect();
+    rt.block_on(async {
+        let _guard_0 = authority_clients[0].shard_of(&sender.address).lock().await;
+        let _guard_1 = authority_clients[1].shard_of(&sender.address).lock().await;
+        let transfer = sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default());
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), transfer)
+                .await
+                .is_err()
+        );
+    });
+    assert!(sender.pending_transfer.is_some());
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(0));
+
+    // The interrupted transfer is completed first.
+    assert_eq!(
+        rt.block_on(sender.get_spendable_amount()).unwrap(),
+        Amount::from(1)
+    );
+    assert_eq!(sender.pending_transfer, None);
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(1));
+    rt.block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(sender.balance, Balance::from(0));
+    assert_eq!(
+        rt.block_on(sender.get_strong_majority_balance()),
+        Balance::from(0)
+    );
+}
+
 #[test]
 fn test_initiating_valid_transfer_despite_bad_authority() {
     let mut rt = Runtime::new().unwrap();
@@ -225,6 +158,44 @@ fn test_initiating_valid_transfer_despite_bad_authority() {
     );
 }
 
+#[test]
+fn test_initiating_transfer_with_expiry() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+
+    let mut sender = init_local_client_state(vec![4, 4, 4, 4]);
+    sender.balance = Balance::from(4);
+    sender.set_valid_until(Some(VersionNumber::from(0)));
+    let certificate = rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(
+        certificate.value.transfer.valid_until,
+        Some(VersionNumber::from(0))
+    );
+
+    // Authorities have now seen a later transaction of the Primary blockchain.
+    for client in sender.authority_clients.values() {
+        rt.block_on(client.shard_of(&sender.address).lock())
+            .last_transaction_index = VersionNumber::from(1);
+    }
+    assert!(rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+        .is_err());
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(1));
+    // The expired transfer does not block the account.
+    assert_eq!(sender.pending_transfer, None);
+    sender.set_valid_until(None);
+    let certificate = rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(
+        certificate.value.transfer.sequence_number,
+        SequenceNumber::from(1)
+    );
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(2));
+}
+
 #[test]
 fn test_initiating_transfer_low_funds() {
     let mut rt = Runtime::new().unwrap();
@@ -244,10 +215,600 @@ fn test_initiating_transfer_low_funds() {
     );
 }
 
+#[test]
+fn test_simulate_transfer() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+
+    let mut sender = init_local_client_state(vec![4, 4, 4, 4]);
+    sender.balance = Balance::from(4);
+    let report = rt
+        .block_on(sender.simulate_transfer(Amount::from(3), Address::FastPay(recipient)))
+        .unwrap();
+    assert_eq!(
+        report,
+        SimulationReport {
+            sequence_number: SequenceNumber::from(0),
+            new_balance: Balance::from(1),
+            new_next_sequence_number: SequenceNumber::from(1),
+        }
+    );
+    // Nothing was sent.
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(0));
+    assert_eq!(sender.pending_transfer, None);
+    assert_eq!(sender.balance, Balance::from(4));
+    assert_eq!(
+        rt.block_on(sender.get_strong_majority_balance()),
+        Balance::from(4)
+    );
+
+    // A transfer to oneself only costs its fee.
+    sender.set_fee(Amount::from(1));
+    let report = rt
+        .block_on(sender.simulate_transfer(Amount::from(3), Address::FastPay(sender.address)))
+        .unwrap();
+    assert_eq!(report.new_balance, Balance::from(3));
+}
+
+#[test]
+fn test_transfer_with_fee() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let mut sender = init_local_client_state(vec![4, 4, 4, 4]);
+    sender.balance = Balance::from(4);
+    sender.set_fee(Amount::from(1));
+    // The fee is part of the balance check.
+    assert!(rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(4), recipient, UserData::default()))
+        .is_err());
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(0));
+
+    let certificate = rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(certificate.value.transfer.fee, Amount::from(1));
+    assert_eq!(sender.balance, Balance::from(0));
+    assert_eq!(
+        rt.block_on(sender.get_strong_majority_balance()),
+        Balance::from(0)
+    );
+}
+
+#[test]
+fn test_transfer_below_min_amount() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let mut sender = init_local_client_state(vec![4, 4, 4, 4]);
+    sender.balance = Balance::from(4);
+    assert_eq!(sender.min_transfer_amount(), Amount::from(1));
+    sender.set_min_transfer_amount(Amount::from(2));
+    assert_eq!(
+        sender.check_transfer_amount(Amount::from(1)),
+        Err(FastPayError::AmountBelowMinimum {
+            minimum: Amount::from(2)
+        })
+    );
+    assert!(sender.check_transfer_amount(Amount::from(2)).is_ok());
+    // Nothing is sent for amounts below the minimum.
+    assert!(rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+        .is_err());
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(0));
+    assert_eq!(sender.pending_transfer, None);
+}
+
+#[test]
+fn test_simulate_transfer_low_funds() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+
+    let mut sender = init_local_client_state(vec![2, 2, 4, 4]);
+    sender.balance = Balance::from(2);
+    assert!(rt
+        .block_on(sender.simulate_transfer(Amount::from(3), Address::Primary(recipient)))
+        .is_err());
+    assert!(rt
+        .block_on(sender.simulate_transfer(Amount::zero(), Address::Primary(recipient)))
+        .is_err());
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(0));
+    assert_eq!(sender.pending_transfer, None);
+}
+
+#[test]
+fn test_pending_transfer_votes() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+
+    let mut sender = init_local_client_state(vec![4, 4, 4, 4]);
+    assert!(rt.block_on(sender.pending_transfer_votes()).is_err());
+
+    let transfer = Transfer {
+        sender: sender.address,
+        recipient: Address::FastPay(recipient),
+        amount: Amount::from(3),
+        sequence_number: sender.next_sequence_number,
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sender.secret);
+    sender.pending_transfer = Some(order.clone());
+    assert_eq!(
+        rt.block_on(sender.pending_transfer_votes()).unwrap(),
+        (0, false)
+    );
+
+    // Only some authorities have voted so far.
+    let mut clients: Vec<_> = sender.authority_clients.values().cloned().collect();
+    for client in clients.iter_mut().take(2) {
+        rt.block_on(client.handle_transfer_order(order.clone()))
+            .unwrap();
+    }
+    assert_eq!(
+        rt.block_on(sender.pending_transfer_votes()).unwrap(),
+        (2, false)
+    );
+
+    rt.block_on(clients[2].handle_transfer_order(order))
+        .unwrap();
+    assert_eq!(
+        rt.block_on(sender.pending_transfer_votes()).unwrap(),
+        (3, true)
+    );
+}
+
+#[test]
+fn test_fast_forward() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let mut sender = init_local_client_state(vec![100, 100, 100, 100]);
+    sender.balance = Balance::from(100);
+    for _ in 0..30 {
+        rt.block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+            .unwrap();
+    }
+
+    // Restore the account from its key only, over a slow network.
+    let latency = std::time::Duration::from_millis(10);
+    let slow_clients: HashMap<_, _> = sender
+        .authority_clients
+        .iter()
+        .map(|(name, client)| (*name, client.clone().with_latency(latency)))
+        .collect();
+    let restore = |next_sequence_number| {
+        ClientState::new(
+            sender.address,
+            sender.secret.copy(),
+            sender.committee.clone(),
+            slow_clients.clone(),
+            next_sequence_number,
+            Vec::new(),
+            Vec::new(),
+            Balance::from(100),
+        )
+    };
+
+    // Recovering the missing certificates one by one.
+    let mut naive = restore(SequenceNumber::from(30));
+    let start = std::time::Instant::now();
+    assert_eq!(
+        rt.block_on(naive.get_spendable_amount()).unwrap(),
+        Amount::from(70)
+    );
+    let naive_duration = start.elapsed();
+    assert!(naive_duration >= latency * 30);
+
+    // Fast-forwarding from scratch.
+    let mut client = restore(SequenceNumber::new());
+    let start = std::time::Instant::now();
+    assert_eq!(
+        rt.block_on(client.fast_forward()).unwrap(),
+        SequenceNumber::from(30)
+    );
+    let duration = start.elapsed();
+    assert_eq!(client.balance, Balance::from(70));
+    assert_eq!(client.sent_certificates, sender.sent_certificates);
+    assert!(
+        duration * 4 < naive_duration,
+        "{:?} vs {:?}",
+        duration,
+        naive_duration
+    );
+
+    // Nothing left to download.
+    assert_eq!(
+        rt.block_on(client.fast_forward()).unwrap(),
+        SequenceNumber::from(30)
+    );
+    assert_eq!(client.balance, Balance::from(70));
+}
+
+#[test]
+fn test_fast_forward_after_pruning() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let mut sender = init_local_client_state(vec![100, 100, 100, 100]);
+    sender.balance = Balance::from(100);
+    for _ in 0..3 {
+        rt.block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+            .unwrap();
+    }
+    let checkpoint = rt
+        .block_on(sender.read_only().certify_account_snapshot())
+        .unwrap();
+    for client in sender.authority_clients.values() {
+        let mut state = rt.block_on(client.shard_of(&sender.address).lock());
+        state.pruning_depth = Some(1);
+        // Authorities left behind by the quorum have nothing to prune.
+        assert!(state.prune_confirmed_log(checkpoint.clone()).is_ok());
+    }
+
+    // Pruned certificates cannot be recovered from authorities.
+    let mut client = ClientState::new(
+        sender.address,
+        sender.secret.copy(),
+        sender.committee.clone(),
+        sender.authority_clients.clone(),
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        Balance::from(100),
+    );
+    let error = rt.block_on(client.fast_forward()).unwrap_err();
+    assert_eq!(
+        error.downcast::<FastPayError>().unwrap(),
+        FastPayError::CertificatePruned {
+            snapshot: checkpoint.value.clone()
+        }
+    );
+    assert_eq!(client.next_sequence_number, SequenceNumber::new());
+    let error = rt
+        .block_on(sender.read_only().download_sent_certificates())
+        .unwrap_err();
+    assert_eq!(
+        error.downcast::<FastPayError>().unwrap(),
+        FastPayError::CertificatePruned {
+            snapshot: checkpoint.value
+        }
+    );
+
+    // Clients that kept their certificates are not affected.
+    assert_eq!(
+        rt.block_on(sender.fast_forward()).unwrap(),
+        SequenceNumber::from(3)
+    );
+    rt.block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(sender.balance, Balance::from(96));
+}
+
+#[test]
+fn test_verify_certificate_chain() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let mut sender = init_local_client_state(vec![3, 3, 3, 3]);
+    sender.balance = Balance::from(3);
+    for _ in 0..3 {
+        rt.block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+            .unwrap();
+    }
+    let chain = sender.sent_certificates.clone();
+    assert_eq!(verify_certificate_chain(&chain, &sender.committee), Ok(()));
+    assert_eq!(verify_certificate_chain(&[], &sender.committee), Ok(()));
+
+    // A missing certificate breaks the chain.
+    let gap = vec![chain[0].clone(), chain[2].clone()];
+    assert!(matches!(
+        verify_certificate_chain(&gap, &sender.committee),
+        Err(FastPayError::InvalidAccountState { .. })
+    ));
+    // So does a chain that does not start at 0.
+    assert!(matches!(
+        verify_certificate_chain(&chain[1..], &sender.committee),
+        Err(FastPayError::InvalidAccountState { .. })
+    ));
+
+    // Certificates of another committee are rejected.
+    let (_, other_committee) = init_local_authorities(4, 1);
+    assert!(verify_certificate_chain(&chain, &other_committee).is_err());
+}
+
+#[test]
+fn test_receive_certificate_of_another_recipient() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
+    let mut client1 = make_client(authority_clients.clone(), committee.clone());
+    let mut client2 = make_client(authority_clients.clone(), committee.clone());
+    let mut client3 = make_client(authority_clients.clone(), committee);
+    fund_account(&mut authority_clients, client1.address, vec![3, 3, 3, 3]);
+    client1.balance = Balance::from(3);
+    let certificate = rt
+        .block_on(client1.transfer_to_fastpay(
+            Amount::from(2),
+            client2.address,
+            UserData::default(),
+        ))
+        .unwrap();
+
+    // The transfer was sent to client 2: client 3 must not be credited.
+    assert!(rt
+        .block_on(client3.receive_from_fastpay(certificate.clone()))
+        .is_err());
+    assert_eq!(client3.balance, Balance::from(0));
+    assert_eq!(client3.received_certificates().count(), 0);
+
+    // The credit path performs the same check.
+    assert!(client3
+        .credit_received_certificate(certificate.clone())
+        .is_err());
+    assert_eq!(client3.balance, Balance::from(0));
+    assert_eq!(client3.received_certificates().count(), 0);
+    assert!(client2
+        .credit_received_certificate(certificate.clone())
+        .unwrap());
+    assert!(!client2.credit_received_certificate(certificate).unwrap());
+    assert_eq!(client2.balance, Balance::from(2));
+}
+
+#[test]
+fn test_read_only_client() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
+    let mut client1 = make_client(authority_clients.clone(), committee.clone());
+    let client2 = make_client(authority_clients.clone(), committee);
+    fund_account(&mut authority_clients, client1.address, vec![3, 3, 3, 3]);
+    client1.balance = Balance::from(3);
+    let certificate = rt
+        .block_on(client1.transfer_to_fastpay(
+            Amount::from(2),
+            client2.address,
+            UserData::default(),
+        ))
+        .unwrap();
+
+    // Observers can synchronize without the keys of the accounts.
+    // (`ReadOnlyClientState` does not implement `Client`, hence no `transfer_to_fastpay`.)
+    let mut observer1 = client1.read_only();
+    assert_eq!(observer1.address(), client1.address);
+    assert_eq!(
+        rt.block_on(observer1.get_strong_majority_balance())
+            .unwrap(),
+        Balance::from(1)
+    );
+    assert_eq!(
+        rt.block_on(observer1.download_sent_certificates()).unwrap(),
+        vec![certificate.clone()]
+    );
+    assert!(rt
+        .block_on(observer1.download_received_certificates())
+        .unwrap()
+        .is_empty());
+
+    let mut observer2 = client2.read_only();
+    assert_eq!(
+        rt.block_on(observer2.get_strong_majority_balance())
+            .unwrap(),
+        Balance::from(2)
+    );
+    assert!(rt
+        .block_on(observer2.download_sent_certificates())
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        rt.block_on(observer2.download_received_certificates())
+            .unwrap(),
+        vec![certificate]
+    );
+}
+
+#[test]
+fn test_receive_all() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
+    let mut client1 = make_client(authority_clients.clone(), committee.clone());
+    let mut client2 = make_client(authority_clients.clone(), committee.clone());
+    let mut client3 = make_client(authority_clients.clone(), committee);
+    fund_account(&mut authority_clients, client1.address, vec![5, 5, 5, 5]);
+    fund_account(&mut authority_clients, client2.address, vec![5, 5, 5, 5]);
+    client1.balance = Balance::from(5);
+    client2.balance = Balance::from(5);
+
+    // First batch: two transfers from two senders.
+    rt.block_on(client1.transfer_to_fastpay(Amount::from(1), client3.address, UserData::default()))
+        .unwrap();
+    rt.block_on(client2.transfer_to_fastpay(Amount::from(2), client3.address, UserData::default()))
+        .unwrap();
+    assert_eq!(rt.block_on(client3.receive_all()).unwrap(), 2);
+    assert_eq!(client3.balance(), Balance::from(3));
+    assert_eq!(client3.received_certificates().count(), 2);
+
+    // Second batch.
+    rt.block_on(client1.transfer_to_fastpay(Amount::from(3), client3.address, UserData::default()))
+        .unwrap();
+    assert_eq!(rt.block_on(client3.receive_all()).unwrap(), 1);
+    assert_eq!(client3.balance(), Balance::from(6));
+    assert_eq!(rt.block_on(client3.receive_all()).unwrap(), 0);
+    assert_eq!(client3.balance(), Balance::from(6));
+    assert_eq!(client3.next_sequence_number(), SequenceNumber::from(0));
+
+    // Certificates that failed to be received are downloaded again.
+    rt.block_on(client2.transfer_to_fastpay(Amount::from(1), client3.address, UserData::default()))
+        .unwrap();
+    client3.balance = Balance::max();
+    assert!(rt.block_on(client3.receive_all()).is_err());
+    client3.balance = Balance::from(6);
+    assert_eq!(rt.block_on(client3.receive_all()).unwrap(), 1);
+    assert_eq!(client3.balance(), Balance::from(7));
+}
+
+#[test]
+fn test_certify_account_snapshot() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
+    let mut client1 = make_client(authority_clients.clone(), committee.clone());
+    let client2 = make_client(authority_clients.clone(), committee.clone());
+    fund_account(&mut authority_clients, client1.address, vec![3, 3, 3, 3]);
+    client1.balance = Balance::from(3);
+    rt.block_on(client1.transfer_to_fastpay(Amount::from(2), client2.address, UserData::default()))
+        .unwrap();
+
+    let mut observer = client1.read_only();
+    let certificate = rt.block_on(observer.certify_account_snapshot()).unwrap();
+    assert!(certificate.check(&committee).is_ok());
+    assert_eq!(
+        certificate.value,
+        AccountSnapshot {
+            account: client1.address,
+            balance: Balance::from(1),
+            next_sequence_number: SequenceNumber::from(1),
+        }
+    );
+
+    // Unknown accounts cannot be certified.
+    let mut observer = ReadOnlyClientState::new(dbg_addr(0), committee, authority_clients);
+    assert!(rt.block_on(observer.certify_account_snapshot()).is_err());
+}
+
+#[test]
+fn test_discover_committee() {
+    let mut rt = Runtime::new().unwrap();
+    let (authority_clients, committee) = init_local_authorities(4, 2);
+    let mut observer = ReadOnlyClientState::new(dbg_addr(0), committee.clone(), authority_clients);
+    let certificate = rt.block_on(observer.discover_committee()).unwrap();
+    assert_eq!(certificate.value, CommitteeDescription::new(&committee));
+    assert_eq!(certificate.value.epoch, CURRENT_EPOCH);
+    assert_eq!(certificate.check(&committee), Ok(()));
+    assert_eq!(certificate.value.committee(), Ok(committee));
+}
+
+#[test]
+fn test_get_strong_majority_balances() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 2);
+    let (account1, _) = get_key_pair();
+    let (account2, _) = get_key_pair();
+    let (unknown, _) = get_key_pair();
+    fund_account(&mut authority_clients, account1, vec![3, 3, 3, 3]);
+    // Only one authority vouches for the higher balance.
+    fund_account(&mut authority_clients, account2, vec![7, 5, 5, 5]);
+
+    let accounts = vec![account1, account2, unknown].into_iter().collect();
+    let balances = rt.block_on(get_strong_majority_balances(
+        &mut authority_clients,
+        &committee,
+        accounts,
+    ));
+    assert_eq!(balances.len(), 3);
+    assert_eq!(balances[&account1], Balance::from(3));
+    assert_eq!(balances[&account2], Balance::from(5));
+    assert_eq!(balances[&unknown], Balance::zero());
+}
+
+#[test]
+fn test_watch_balance() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
+    let client = make_client(authority_clients.clone(), committee);
+    fund_account(&mut authority_clients, client.address, vec![3, 3, 3, 3]);
+
+    let mut observer = client.read_only();
+    rt.block_on(async {
+        let mut balances = Box::pin(observer.watch_balance(Duration::from_millis(10)));
+        assert_eq!(balances.next().await, Some(Balance::from(3)));
+        fund_account(&mut authority_clients, client.address, vec![5, 5, 5, 5]);
+        assert_eq!(balances.next().await, Some(Balance::from(5)));
+        // Unchanged balances are not reported again.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), balances.next())
+                .await
+                .is_err()
+        );
+    });
+}
+
+#[test]
+fn test_transfer_across_shards() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 4);
+    let mut client1 = make_client(authority_clients.clone(), committee.clone());
+    let mut client2 = loop {
+        let client = make_client(authority_clients.clone(), committee.clone());
+        if AuthorityState::get_shard(4, &client.address)
+            != AuthorityState::get_shard(4, &client1.address)
+        {
+            break client;
+        }
+    };
+    fund_account(&mut authority_clients, client1.address, vec![3, 3, 3, 3]);
+    client1.balance = Balance::from(3);
+
+    let certificate = rt
+        .block_on(client1.transfer_to_fastpay(
+            Amount::from(2),
+            client2.address,
+            UserData::default(),
+        ))
+        .unwrap();
+    // The cross-shard update was delivered and acknowledged.
+    assert_eq!(
+        rt.block_on(client2.get_strong_majority_balance()),
+        Balance::from(2)
+    );
+    for client in authority_clients.values() {
+        assert!(rt
+            .block_on(client.shard_of(&client1.address).lock())
+            .pending_cross_shard_updates
+            .is_empty());
+    }
+    rt.block_on(client2.receive_from_fastpay(certificate))
+        .unwrap();
+    assert_eq!(client2.balance, Balance::from(2));
+}
+
+#[test]
+fn test_set_frozen() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
+    let mut client1 = make_client(authority_clients.clone(), committee.clone());
+    let mut client2 = make_client(authority_clients.clone(), committee);
+    fund_account(&mut authority_clients, client1.address, vec![4, 4, 4, 4]);
+    fund_account(&mut authority_clients, client2.address, vec![4, 4, 4, 4]);
+    client1.balance = Balance::from(4);
+    client2.balance = Balance::from(4);
+
+    rt.block_on(client1.set_frozen(true)).unwrap();
+    assert!(rt
+        .block_on(client1.transfer_to_fastpay(
+            Amount::from(1),
+            client2.address,
+            UserData::default()
+        ))
+        .is_err());
+    assert_eq!(client1.next_sequence_number, SequenceNumber::from(0));
+    assert!(client1.pending_transfer.is_some());
+
+    // Frozen accounts still receive transfers.
+    rt.block_on(client2.transfer_to_fastpay(Amount::from(2), client1.address, UserData::default()))
+        .unwrap();
+    assert_eq!(
+        rt.block_on(client1.get_strong_majority_balance()),
+        Balance::from(6)
+    );
+
+    // Once unfrozen, the pending transfer goes through. The freeze version is
+    // recovered from the authorities, e.g. after a restart.
+    client1.next_freeze_version = SequenceNumber::new();
+    rt.block_on(client1.set_frozen(false)).unwrap();
+    assert_eq!(client1.next_freeze_version, SequenceNumber::from(2));
+    rt.block_on(client1.get_spendable_amount()).unwrap();
+    assert_eq!(client1.next_sequence_number, SequenceNumber::from(1));
+    assert_eq!(client1.pending_transfer, None);
+}
+
 #[test]
 fn test_bidirectional_transfer() {
     let mut rt = Runtime::new().unwrap();
-    let (mut authority_clients, committee) = init_local_authorities(4);
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
     let mut client1 = make_client(authority_clients.clone(), committee.clone());
     let mut client2 = make_client(authority_clients.clone(), committee);
     fund_account(&mut authority_clients, client1.address, vec![2, 3, 4, 4]);
@@ -318,7 +879,7 @@ fn test_bidirectional_transfer() {
 #[test]
 fn test_receiving_unconfirmed_transfer() {
     let mut rt = Runtime::new().unwrap();
-    let (mut authority_clients, committee) = init_local_authorities(4);
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
     let mut client1 = make_client(authority_clients.clone(), committee.clone());
     let mut client2 = make_client(authority_clients.clone(), committee);
     fund_account(&mut authority_clients, client1.address, vec![2, 3, 4, 4]);
@@ -356,7 +917,7 @@ fn test_receiving_unconfirmed_transfer() {
 #[test]
 fn test_receiving_unconfirmed_transfer_with_lagging_sender_balances() {
     let mut rt = Runtime::new().unwrap();
-    let (mut authority_clients, committee) = init_local_authorities(4);
+    let (mut authority_clients, committee) = init_local_authorities(4, 1);
     let mut client0 = make_client(authority_clients.clone(), committee.clone());
     let mut client1 = make_client(authority_clients.clone(), committee.clone());
     let mut client2 = make_client(authority_clients.clone(), committee);
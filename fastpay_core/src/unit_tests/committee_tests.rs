@@ -0,0 +1,223 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+#[test]
+fn test_is_quorum_uses_voting_rights() {
+    let (a1, _) = get_authority_key_pair();
+    let (a2, _) = get_authority_key_pair();
+    let (a3, _) = get_authority_key_pair();
+    let (a4, _) = get_authority_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(a1, 5);
+    voting_rights.insert(a2, 1);
+    voting_rights.insert(a3, 1);
+    voting_rights.insert(a4, 1);
+    let committee = Committee::new(voting_rights);
+    // Total votes: 8, quorum threshold: 6.
+    assert_eq!(committee.quorum_threshold(), 6);
+
+    // Three authorities out of four are not enough without the heavy one.
+    let light: BTreeSet<_> = vec![a2, a3, a4].into_iter().collect();
+    assert!(!committee.is_quorum(&light));
+    // Two authorities out of four are enough with the heavy one.
+    let heavy: BTreeSet<_> = vec![a1, a2].into_iter().collect();
+    assert!(committee.is_quorum(&heavy));
+    // Unknown authorities do not count.
+    let (unknown, _) = get_authority_key_pair();
+    let with_unknown: BTreeSet<_> = vec![a2, a3, a4, unknown].into_iter().collect();
+    assert!(!committee.is_quorum(&with_unknown));
+}
+
+#[test]
+fn test_lower_bounds() {
+    let names: Vec<_> = (0..4).map(|_| get_authority_key_pair().0).collect();
+    let committee = Committee::new(names.iter().map(|name| (*name, 1)).collect());
+    let values: Vec<_> = names.iter().cloned().zip(vec![1, 5, 3, 9]).collect();
+    // A quorum (3 votes) supports 3 and one honest authority (2 votes) supports 5.
+    assert_eq!(committee.get_strong_majority_lower_bound(values.clone()), 3);
+    assert_eq!(committee.get_validity_lower_bound(values.clone()), 5);
+    // Missing votes fall back to the default value.
+    assert_eq!(committee.get_validity_lower_bound(values[..1].to_vec()), 0);
+}
+
+#[test]
+fn test_quorums_intersect_uses_voting_rights() {
+    let (a1, _) = get_authority_key_pair();
+    let (a2, _) = get_authority_key_pair();
+    let (a3, _) = get_authority_key_pair();
+    let (a4, _) = get_authority_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(a1, 1);
+    voting_rights.insert(a2, 1);
+    voting_rights.insert(a3, 1);
+    voting_rights.insert(a4, 4);
+    let committee = Committee::new(voting_rights);
+    // Total votes: 7, validity threshold: 3.
+    assert_eq!(committee.validity_threshold(), 3);
+
+    // The intersection {a1, a2} has two members out of four (a majority by count)
+    // but only carries two votes out of seven.
+    let set_a: BTreeSet<_> = vec![a1, a2, a3].into_iter().collect();
+    let set_b: BTreeSet<_> = vec![a1, a2].into_iter().collect();
+    assert!(!committee.quorums_intersect(&set_a, &set_b));
+
+    // A single heavy authority in the intersection is enough.
+    let set_c: BTreeSet<_> = vec![a1, a4].into_iter().collect();
+    let set_d: BTreeSet<_> = vec![a2, a4].into_iter().collect();
+    assert!(committee.quorums_intersect(&set_c, &set_d));
+
+    // Disjoint sets never intersect.
+    let set_e: BTreeSet<_> = vec![a4].into_iter().collect();
+    assert!(!committee.quorums_intersect(&set_a, &set_e));
+}
+
+#[test]
+fn test_quorums_always_intersect() {
+    let mut keys = Vec::new();
+    let mut voting_rights = BTreeMap::new();
+    for weight in &[1, 2, 3, 5] {
+        let (name, _) = get_authority_key_pair();
+        voting_rights.insert(name, *weight);
+        keys.push(name);
+    }
+    let committee = Committee::new(voting_rights);
+    // Enumerate all pairs of subsets: any two quorums must intersect in an honest authority.
+    let subsets: Vec<BTreeSet<_>> = (0..(1 << keys.len()))
+        .map(|mask: usize| {
+            keys.iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, name)| *name)
+                .collect()
+        })
+        .collect();
+    for set_a in subsets.iter().filter(|set| committee.is_quorum(set)) {
+        for set_b in subsets.iter().filter(|set| committee.is_quorum(set)) {
+            assert!(committee.quorums_intersect(set_a, set_b));
+        }
+    }
+}
+
+#[test]
+fn test_committee_builder() {
+    let (a1, _) = get_authority_key_pair();
+    let (a2, _) = get_authority_key_pair();
+    let committee = CommitteeBuilder::new()
+        .add_authority(a1, 2)
+        .add_authority(a2, 1)
+        .build()
+        .unwrap();
+    assert_eq!(committee.total_votes, 3);
+    assert_eq!(committee.weight(&a1), 2);
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(a1, 2);
+    voting_rights.insert(a2, 1);
+    assert_eq!(committee, Committee::new(voting_rights));
+
+    assert_eq!(
+        CommitteeBuilder::new().build(),
+        Err(FastPayError::EmptyCommittee)
+    );
+    assert_eq!(
+        CommitteeBuilder::new()
+            .add_authority(a1, 1)
+            .add_authority(a2, 0)
+            .build(),
+        Err(FastPayError::InvalidVotingRights { authority: a2 })
+    );
+    assert_eq!(
+        CommitteeBuilder::new()
+            .add_authority(a1, 1)
+            .add_authority(a1, 1)
+            .build(),
+        Err(FastPayError::DuplicateAuthority { authority: a1 })
+    );
+    assert_eq!(
+        CommitteeBuilder::new()
+            .add_authority(a1, usize::MAX)
+            .add_authority(a2, 1)
+            .build(),
+        Err(FastPayError::VotingRightsOverflow)
+    );
+}
+
+#[test]
+fn test_committee_from_stakes() {
+    let mut names: Vec<_> = (0..4).map(|_| get_authority_key_pair().0).collect();
+    names.sort();
+    let stakes: BTreeMap<_, _> = names
+        .iter()
+        .cloned()
+        .zip(vec![500u128, 300, 150, 50])
+        .collect();
+    let committee = Committee::from_stakes(stakes.clone(), 100).unwrap();
+    assert_eq!(committee.total_votes, 100);
+    assert_eq!(
+        names
+            .iter()
+            .map(|name| committee.weight(name))
+            .collect::<Vec<_>>(),
+        vec![50, 30, 15, 5]
+    );
+    assert_eq!(
+        Committee::from_stakes(stakes.clone(), 100).unwrap(),
+        committee
+    );
+
+    // Shares 3.5, 2.1, 1.05, 0.35: the units left go to the largest remainders.
+    let committee = Committee::from_stakes(stakes, 7).unwrap();
+    assert_eq!(committee.total_votes, 7);
+    assert_eq!(
+        names
+            .iter()
+            .map(|name| committee.weight(name))
+            .collect::<Vec<_>>(),
+        vec![4, 2, 1, 0]
+    );
+    assert_eq!(committee.voting_rights.len(), 3);
+}
+
+#[test]
+fn test_committee_from_stakes_ties() {
+    let mut names: Vec<_> = (0..3).map(|_| get_authority_key_pair().0).collect();
+    names.sort();
+    let stakes: BTreeMap<_, _> = names.iter().map(|name| (*name, 7u128)).collect();
+    // Equal remainders are served in the order of names.
+    let committee = Committee::from_stakes(stakes.clone(), 10).unwrap();
+    assert_eq!(
+        names
+            .iter()
+            .map(|name| committee.weight(name))
+            .collect::<Vec<_>>(),
+        vec![4, 3, 3]
+    );
+    let committee = Committee::from_stakes(stakes, 11).unwrap();
+    assert_eq!(
+        names
+            .iter()
+            .map(|name| committee.weight(name))
+            .collect::<Vec<_>>(),
+        vec![4, 4, 3]
+    );
+}
+
+#[test]
+fn test_committee_from_stakes_errors() {
+    let (name, _) = get_authority_key_pair();
+    assert_eq!(
+        Committee::from_stakes(BTreeMap::new(), 10),
+        Err(FastPayError::EmptyCommittee)
+    );
+    let stakes: BTreeMap<_, _> = vec![(name, 0u128)].into_iter().collect();
+    assert_eq!(
+        Committee::from_stakes(stakes, 10),
+        Err(FastPayError::EmptyCommittee)
+    );
+    let stakes: BTreeMap<_, _> = vec![(name, u128::MAX)].into_iter().collect();
+    assert_eq!(
+        Committee::from_stakes(stakes, 10),
+        Err(FastPayError::VotingRightsOverflow)
+    );
+}
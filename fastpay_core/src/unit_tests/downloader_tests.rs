@@ -44,3 +44,42 @@ fn test_local_downloader() {
         assert_eq!(values, vec![10, 0, 1, 11]);
     });
 }
+
+/// A requester that takes some time to answer and counts its queries.
+#[derive(Clone)]
+struct SlowRequester(Arc<AtomicU32>);
+
+impl Requester for SlowRequester {
+    type Key = u32;
+    type Value = u32;
+
+    fn query(&mut self, key: Self::Key) -> future::BoxFuture<Self::Value> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Box::pin(async move {
+            tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+            key * 2
+        })
+    }
+}
+
+#[test]
+fn test_concurrent_queries_are_coalesced() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async move {
+        let counter = Arc::new(AtomicU32::new(0));
+        let (task, handle) = Downloader::start(SlowRequester(counter.clone()), Vec::new());
+        let queries = (0..100).map(|i| {
+            let mut handle = handle.clone();
+            async move { handle.query(i % 2).await.unwrap() }
+        });
+        let values = future::join_all(queries).await;
+        for (i, value) in values.into_iter().enumerate() {
+            assert_eq!(value, (i as u32 % 2) * 2);
+        }
+        // One request per distinct key, although all queries were in flight together.
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+        handle.clone().stop().await.unwrap();
+        let values: Vec<_> = task.await.unwrap().collect();
+        assert_eq!(values, vec![0, 2]);
+    });
+}
@@ -132,7 +132,7 @@ fn test_handle_redeem_transaction_double_spend() {
 // helpers
 #[cfg(test)]
 fn init_contract() -> (FastPaySmartContractState, AuthorityName, KeyPair) {
-    let (authority_address, authority_key) = get_key_pair();
+    let (authority_address, authority_key) = get_authority_key_pair();
     let mut authorities = BTreeMap::new();
     authorities.insert(
         /* address */ authority_address,
@@ -166,6 +166,8 @@ fn init_redeem_transaction(
         amount: Amount::from(3),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(primary_transfer, &sender_key);
     let vote = SignedTransferOrder::new(order.clone(), name, &secret);
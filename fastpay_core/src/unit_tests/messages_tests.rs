@@ -7,20 +7,22 @@ use std::collections::BTreeMap;
 #[test]
 fn test_signed_values() {
     let mut authorities = BTreeMap::new();
-    let (a1, sec1) = get_key_pair();
-    let (a2, sec2) = get_key_pair();
-    let (a3, sec3) = get_key_pair();
+    let (a1, sec1) = get_authority_key_pair();
+    let (a2, sec2) = get_authority_key_pair();
+    let (a3, sec3) = get_authority_key_pair();
 
     authorities.insert(/* address */ a1, /* voting right */ 1);
     authorities.insert(/* address */ a2, /* voting right */ 0);
     let committee = Committee::new(authorities);
 
     let transfer = Transfer {
-        sender: a1,
-        recipient: Address::FastPay(a2),
+        sender: a1.0,
+        recipient: Address::FastPay(a2.0),
         amount: Amount::from(1),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer.clone(), &sec1);
     let bad_order = TransferOrder::new(transfer, &sec2);
@@ -40,9 +42,9 @@ fn test_signed_values() {
 
 #[test]
 fn test_certificates() {
-    let (a1, sec1) = get_key_pair();
-    let (a2, sec2) = get_key_pair();
-    let (a3, sec3) = get_key_pair();
+    let (a1, sec1) = get_authority_key_pair();
+    let (a2, sec2) = get_authority_key_pair();
+    let (a3, sec3) = get_authority_key_pair();
 
     let mut authorities = BTreeMap::new();
     authorities.insert(/* address */ a1, /* voting right */ 1);
@@ -50,11 +52,13 @@ fn test_certificates() {
     let committee = Committee::new(authorities);
 
     let transfer = Transfer {
-        sender: a1,
-        recipient: Address::FastPay(a2),
+        sender: a1.0,
+        recipient: Address::FastPay(a2.0),
         amount: Amount::from(1),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer.clone(), &sec1);
     let bad_order = TransferOrder::new(transfer, &sec2);
@@ -80,5 +84,329 @@ fn test_certificates() {
         .is_none());
     assert!(builder.append(v3.authority, v3.signature).is_err());
 
-    assert!(SignatureAggregator::try_new(bad_order, &committee).is_err());
+    assert!(SignatureAggregator::try_new(bad_order.clone(), &committee).is_err());
+
+    // The signature of the sender is verified with the votes.
+    let transfer = bad_order.transfer.clone();
+    let mut builder = SignatureAggregator::new_unsafe(bad_order, &committee);
+    builder
+        .append(a1, Signature::new(&transfer, &sec1))
+        .unwrap();
+    let c = builder
+        .append(a2, Signature::new(&transfer, &sec2))
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        c.check(&committee),
+        Err(FastPayError::InvalidSignature { .. })
+    ));
+}
+
+#[test]
+fn test_certificates_from_votes() {
+    let keys: Vec<_> = (0..4).map(|_| get_authority_key_pair()).collect();
+    let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+    let orders: Vec<_> = (0..3)
+        .map(|_| {
+            let (sender, sender_secret) = get_key_pair();
+            let transfer = Transfer {
+                sender,
+                recipient: Address::FastPay(dbg_addr(2)),
+                amount: Amount::from(1),
+                sequence_number: SequenceNumber::new(),
+                user_data: UserData::default(),
+                valid_until: None,
+                fee: Amount::zero(),
+            };
+            TransferOrder::new(transfer, &sender_secret)
+        })
+        .collect();
+
+    // Interleave the votes of all authorities on the first two orders. The last order only
+    // receives two votes, which is below the quorum.
+    let mut votes = Vec::new();
+    for (name, secret) in &keys {
+        for order in &orders[..2] {
+            votes.push(SignedTransferOrder::new(order.clone(), *name, secret));
+        }
+    }
+    for (name, secret) in &keys[..2] {
+        votes.push(SignedTransferOrder::new(orders[2].clone(), *name, secret));
+    }
+    let (certificates, errors) = CertifiedTransferOrder::from_votes(votes.clone(), &committee);
+    assert!(errors.is_empty());
+    assert_eq!(certificates.len(), 2);
+    for (certificate, order) in certificates.iter().zip(&orders) {
+        assert_eq!(certificate.value, *order);
+        assert_eq!(certificate.signatures.len(), committee.quorum_threshold());
+        assert!(certificate.check(&committee).is_ok());
+    }
+
+    // A vote whose signature covers another transfer is skipped.
+    let mut mismatched_vote = SignedTransferOrder::new(orders[2].clone(), keys[2].0, &keys[2].1);
+    mismatched_vote.value = orders[0].clone();
+    votes.insert(0, mismatched_vote);
+    let (certificates, errors) = CertifiedTransferOrder::from_votes(votes, &committee);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(certificates.len(), 2);
+    for (certificate, order) in certificates.iter().zip(&orders) {
+        assert_eq!(certificate.value, *order);
+        assert!(certificate.check(&committee).is_ok());
+    }
+}
+
+#[test]
+fn test_large_certificate() {
+    let keys: Vec<_> = (0..64).map(|_| get_authority_key_pair()).collect();
+    let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+
+    let (sender, sender_secret) = get_key_pair();
+    let transfer = Transfer {
+        sender,
+        recipient: Address::FastPay(dbg_addr(2)),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sender_secret);
+    let mut certificate = CertifiedTransferOrder {
+        value: order.clone(),
+        signatures: keys
+            .iter()
+            .map(|(name, secret)| (*name, Signature::new(&order.transfer, secret)))
+            .collect(),
+    };
+    assert!(check_large_certificate(&certificate, &committee).is_ok());
+
+    // A single bad signature anywhere in the batch invalidates the certificate.
+    for index in &[0, 31, 32, 63] {
+        let saved = certificate.signatures[*index].1;
+        certificate.signatures[*index].1 = Signature::new(&order.transfer, &sender_secret);
+        assert!(check_large_certificate(&certificate, &committee).is_err());
+        certificate.signatures[*index].1 = saved;
+    }
+    assert!(check_large_certificate(&certificate, &committee).is_ok());
+}
+
+/// Check a certificate, verifying that a single thread (sequential batch) and several
+/// threads (parallel sub-batches) give the same result.
+fn check_large_certificate(
+    certificate: &CertifiedTransferOrder,
+    committee: &Committee,
+) -> Result<(), FastPayError> {
+    let result = certificate.check(committee);
+    #[cfg(feature = "parallel")]
+    for threads in &[1, 4] {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(*threads)
+            .build()
+            .unwrap();
+        assert_eq!(pool.install(|| certificate.check(committee)), result);
+    }
+    result
+}
+
+#[test]
+fn test_duplicate_signatures() {
+    let (a1, sec1) = get_authority_key_pair();
+    let (a2, sec2) = get_authority_key_pair();
+    let (a3, sec3) = get_authority_key_pair();
+
+    let mut authorities = BTreeMap::new();
+    authorities.insert(/* address */ a1, /* voting right */ 2);
+    authorities.insert(/* address */ a2, /* voting right */ 1);
+    authorities.insert(/* address */ a3, /* voting right */ 1);
+    let committee = Committee::new(authorities);
+
+    let transfer = Transfer {
+        sender: a1.0,
+        recipient: Address::FastPay(a2.0),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sec1);
+
+    let v1 = SignedTransferOrder::new(order.clone(), a1, &sec1);
+    let v2 = SignedTransferOrder::new(order.clone(), a2, &sec2);
+    let v3 = SignedTransferOrder::new(order.clone(), a3, &sec3);
+    // A valid signature of the order, but not by a1.
+    let forged = SignedTransferOrder::new(order.clone(), a1, &sec2);
+
+    let mut builder = SignatureAggregator::try_new(order, &committee).unwrap();
+    assert_eq!(builder.append(v1.authority, v1.signature), Ok(None));
+    // Benign duplicates do not count twice.
+    assert_eq!(builder.append(v1.authority, v1.signature), Ok(None));
+    // Conflicting signatures from the same authority are still rejected.
+    assert_eq!(
+        builder.append(forged.authority, forged.signature),
+        Err(FastPayError::CertificateAuthorityReuse)
+    );
+    let c = builder.append(v2.authority, v2.signature).unwrap().unwrap();
+    assert_eq!(c.signatures.len(), 2);
+    assert!(c.check(&committee).is_ok());
+    // Duplicates after the quorum return the certificate again.
+    assert_eq!(builder.append(v2.authority, v2.signature), Ok(Some(c)));
+    let c = builder.append(v3.authority, v3.signature).unwrap().unwrap();
+    assert_eq!(c.signatures.len(), 3);
+    assert_eq!(
+        builder.append(v3.authority, v1.signature),
+        Err(FastPayError::CertificateAuthorityReuse)
+    );
+}
+
+#[test]
+fn test_compact_confirmation_order() {
+    let (a1, sec1) = get_authority_key_pair();
+    let (a2, sec2) = get_authority_key_pair();
+
+    let mut authorities = BTreeMap::new();
+    authorities.insert(/* address */ a1, /* voting right */ 1);
+    authorities.insert(/* address */ a2, /* voting right */ 1);
+    let committee = Committee::new(authorities);
+
+    let transfer = Transfer {
+        sender: a1.0,
+        recipient: Address::FastPay(a2.0),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer.clone(), &sec1);
+    let bad_order = TransferOrder::new(transfer, &sec2);
+    let certificate = CertifiedTransferOrder {
+        value: order.clone(),
+        signatures: vec![
+            (a1, Signature::new(&order.transfer, &sec1)),
+            (a2, Signature::new(&order.transfer, &sec2)),
+        ],
+    };
+    assert!(certificate.check(&committee).is_ok());
+
+    let compact = CompactConfirmationOrder::new(&certificate);
+    assert!(compact.matches(&order));
+    assert!(!compact.matches(&bad_order));
+    let expanded = compact.clone().expand(order).unwrap();
+    assert_eq!(expanded.transfer_certificate, certificate);
+    assert!(expanded.transfer_certificate.check(&committee).is_ok());
+    assert_eq!(
+        compact.expand(bad_order),
+        Err(FastPayError::UnknownTransferOrder)
+    );
+}
+
+#[test]
+fn test_committee_certificates() {
+    let (a1, sec1) = get_authority_key_pair();
+    let (a2, sec2) = get_authority_key_pair();
+    let (a3, sec3) = get_authority_key_pair();
+
+    let mut authorities = BTreeMap::new();
+    authorities.insert(/* address */ a1, /* voting right */ 1);
+    authorities.insert(/* address */ a2, /* voting right */ 1);
+    let committee = Committee::new(authorities);
+    let description = CommitteeDescription::new(&committee);
+    assert_eq!(description.committee(), Ok(committee.clone()));
+
+    let v1 = SignedCommittee::new(description.clone(), a1, &sec1);
+    let v2 = SignedCommittee::new(description.clone(), a2, &sec2);
+    let v3 = SignedCommittee::new(description.clone(), a3, &sec3);
+    assert_eq!(v1.check(&committee), Ok(1));
+    assert!(v3.check(&committee).is_err());
+
+    let mut c = CertifiedCommittee {
+        value: description.clone(),
+        signatures: vec![(a1, v1.signature), (a2, v2.signature)],
+    };
+    assert_eq!(c.check(&committee), Ok(()));
+    assert_eq!(c.value.committee(), Ok(committee.clone()));
+
+    // Signatures do not carry over to a different committee.
+    c.value.voting_rights.push((a3, 1));
+    assert!(c.check(&committee).is_err());
+    c.value = description;
+    c.signatures.pop();
+    assert!(c.check(&committee).is_err());
+    c.signatures.push((a3, v3.signature));
+    assert!(c.check(&committee).is_err());
+}
+
+#[test]
+fn test_forged_committee_certificate() {
+    let (a1, _) = get_authority_key_pair();
+    let (a2, _) = get_authority_key_pair();
+    let mut authorities = BTreeMap::new();
+    authorities.insert(/* address */ a1, /* voting right */ 1);
+    authorities.insert(/* address */ a2, /* voting right */ 1);
+    let committee = Committee::new(authorities);
+
+    // An attacker certifies a committee made of their own keys.
+    let (f1, fsec1) = get_authority_key_pair();
+    let (f2, fsec2) = get_authority_key_pair();
+    let mut forged_authorities = BTreeMap::new();
+    forged_authorities.insert(f1, 1);
+    forged_authorities.insert(f2, 1);
+    let forged_committee = Committee::new(forged_authorities);
+    let description = CommitteeDescription::new(&forged_committee);
+    let v1 = SignedCommittee::new(description.clone(), f1, &fsec1);
+    let v2 = SignedCommittee::new(description.clone(), f2, &fsec2);
+    let forged = CertifiedCommittee {
+        value: description,
+        signatures: vec![(f1, v1.signature), (f2, v2.signature)],
+    };
+    // The certificate is only accepted by the committee that it describes.
+    assert_eq!(forged.check(&forged_committee), Ok(()));
+    assert_eq!(v1.check(&committee), Err(FastPayError::UnknownSigner));
+    assert_eq!(forged.check(&committee), Err(FastPayError::UnknownSigner));
+}
+
+#[test]
+fn test_account_snapshot_certificates() {
+    let (a1, sec1) = get_authority_key_pair();
+    let (a2, sec2) = get_authority_key_pair();
+    let (a3, sec3) = get_authority_key_pair();
+
+    let mut authorities = BTreeMap::new();
+    authorities.insert(/* address */ a1, /* voting right */ 1);
+    authorities.insert(/* address */ a2, /* voting right */ 1);
+    let committee = Committee::new(authorities);
+
+    let snapshot = AccountSnapshot {
+        account: dbg_addr(1),
+        balance: Balance::from(5),
+        next_sequence_number: SequenceNumber::from(2),
+    };
+    let v1 = SignedAccountSnapshot::new(snapshot.clone(), a1, &sec1);
+    let v2 = SignedAccountSnapshot::new(snapshot.clone(), a2, &sec2);
+    let v3 = SignedAccountSnapshot::new(snapshot.clone(), a3, &sec3);
+    assert_eq!(v1.check(&committee), Ok(1));
+    assert!(v3.check(&committee).is_err());
+
+    let mut builder = SignatureAggregator::try_new(snapshot.clone(), &committee).unwrap();
+    assert!(builder
+        .append(v1.authority, v1.signature)
+        .unwrap()
+        .is_none());
+    // Repeating a vote is a no-op but an authority cannot sign twice.
+    assert!(builder
+        .append(v1.authority, v1.signature)
+        .unwrap()
+        .is_none());
+    assert!(builder.append(v1.authority, v2.signature).is_err());
+    assert!(builder.append(v3.authority, v3.signature).is_err());
+    let mut c = builder.append(v2.authority, v2.signature).unwrap().unwrap();
+    assert!(c.check(&committee).is_ok());
+
+    // Signatures do not carry over to a different snapshot.
+    c.value.balance = Balance::from(6);
+    assert!(c.check(&committee).is_err());
+    c.value = snapshot;
+    c.signatures.pop();
+    assert!(c.check(&committee).is_err());
 }
@@ -19,6 +19,64 @@ fn test_error() {
     }
 }
 
+#[test]
+fn test_message_header() {
+    let err = FastPayError::UnknownSigner;
+    let buf = serialize_error(&err);
+    assert_eq!(
+        buf[..3],
+        [
+            MESSAGE_MAGIC,
+            PROTOCOL_VERSION,
+            SerializationFormat::Bincode as u8
+        ]
+    );
+
+    // Messages from other protocol versions are rejected.
+    let mut bad_version = buf.clone();
+    bad_version[1] = PROTOCOL_VERSION + 1;
+    match deserialize_message(bad_version.as_slice()) {
+        Err(error) => assert_eq!(
+            error.downcast::<FastPayError>().unwrap(),
+            FastPayError::InvalidDecoding
+        ),
+        Ok(_) => panic!(),
+    }
+    let mut bad_format = buf;
+    bad_format[2] = 0xff;
+    assert!(deserialize_message(bad_format.as_slice()).is_err());
+}
+
+#[test]
+fn test_legacy_message() {
+    let err = FastPayError::UnknownSigner;
+    let buf = bincode::serialize(&ShallowSerializedMessage::Error(&err)).unwrap();
+    if let SerializedMessage::Error(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert!(*o == err);
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn test_bcs_message() {
+    let req = AccountInfoRequest {
+        sender: dbg_addr(0x20),
+        request_sequence_number: Some(SequenceNumber::from(129)),
+        request_received_transfers_excluding_first_nth: Some(3),
+    };
+    let buf = serialize_message_as(
+        &SerializedMessage::InfoReq(Box::new(req.clone())),
+        SerializationFormat::Bcs,
+    );
+    assert_eq!(buf[2], SerializationFormat::Bcs as u8);
+    if let SerializedMessage::InfoReq(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert!(*o == req);
+    } else {
+        panic!()
+    }
+}
+
 #[test]
 fn test_info_request() {
     let req1 = AccountInfoRequest {
@@ -52,6 +110,74 @@ fn test_info_request() {
     }
 }
 
+#[test]
+fn test_cross_shard_request() {
+    let (sender_name, sender_key) = get_key_pair();
+    let transfer = Transfer {
+        sender: sender_name,
+        recipient: Address::FastPay(dbg_addr(0x20)),
+        amount: Amount::from(5),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let certificate = CertifiedTransferOrder {
+        value: TransferOrder::new(transfer, &sender_key),
+        signatures: Vec::new(),
+    };
+    let buf = serialize_cross_shard(&certificate, 123_456);
+    if let SerializedMessage::CrossShard(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(o.transfer_certificate, certificate);
+        assert_eq!(o.timestamp_us, 123_456);
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn test_cross_shard_ack() {
+    let ack = CrossShardAck {
+        shard_id: 3,
+        sender: dbg_addr(0x20),
+        sequence_number: SequenceNumber::from(129),
+        updated_shard_id: 1,
+    };
+    let buf = serialize_cross_shard_ack(&ack);
+    let result = deserialize_message(buf.as_slice());
+    assert!(result.is_ok());
+    if let SerializedMessage::CrossShardAck(o) = result.unwrap() {
+        assert!(*o == ack);
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn test_health() {
+    let buf = serialize_ping();
+    assert!(matches!(
+        deserialize_message(buf.as_slice()).unwrap(),
+        SerializedMessage::Ping
+    ));
+
+    let health = HealthResponse {
+        shard_id: 2,
+        number_of_accounts: 10,
+        last_transaction_index: VersionNumber::from(7),
+        uptime_us: 1000,
+        cross_shard_updates: 3,
+        cross_shard_delay_p50_us: 127,
+        cross_shard_delay_p99_us: 511,
+    };
+    let buf = serialize_health_response(&health);
+    if let SerializedMessage::Pong(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(*o, health);
+    } else {
+        panic!()
+    }
+}
+
 #[test]
 fn test_order() {
     let (sender_name, sender_key) = get_key_pair();
@@ -62,6 +188,8 @@ fn test_order() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let transfer_order = TransferOrder::new(transfer, &sender_key);
 
@@ -81,6 +209,8 @@ fn test_order() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let transfer_order2 = TransferOrder::new(transfer2, &sender_key);
 
@@ -103,10 +233,12 @@ fn test_vote() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
 
-    let (authority_name, authority_key) = get_key_pair();
+    let (authority_name, authority_key) = get_authority_key_pair();
     let vote = SignedTransferOrder::new(order, authority_name, &authority_key);
 
     let buf = serialize_vote(&vote);
@@ -128,6 +260,8 @@ fn test_cert() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
     let mut cert = CertifiedTransferOrder {
@@ -136,7 +270,7 @@ fn test_cert() {
     };
 
     for _ in 0..3 {
-        let (authority_name, authority_key) = get_key_pair();
+        let (authority_name, authority_key) = get_authority_key_pair();
         let sig = Signature::new(&cert.value.transfer, &authority_key);
 
         cert.signatures.push((authority_name, sig));
@@ -152,6 +286,160 @@ fn test_cert() {
     }
 }
 
+#[test]
+fn test_is_order_or_certificate() {
+    let (sender_name, sender_key) = get_key_pair();
+    let transfer = Transfer {
+        sender: sender_name,
+        recipient: Address::FastPay(dbg_addr(0x20)),
+        amount: Amount::from(5),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sender_key);
+    let cert = CertifiedTransferOrder {
+        value: order.clone(),
+        signatures: Vec::new(),
+    };
+    let messages = vec![
+        SerializedMessage::Order(Box::new(order)),
+        SerializedMessage::Cert(Box::new(cert.clone())),
+    ];
+    for message in &messages {
+        assert!(is_order_or_certificate(&serialize_message(message)));
+        assert!(is_order_or_certificate(&serialize_message_as(
+            message,
+            SerializationFormat::Bcs
+        )));
+        assert!(is_order_or_certificate(
+            &bincode::serialize(message).unwrap()
+        ));
+    }
+
+    let request = AccountInfoRequest {
+        sender: sender_name,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: None,
+    };
+    assert!(!is_order_or_certificate(&serialize_info_request(&request)));
+    assert!(!is_order_or_certificate(&serialize_ping()));
+    assert!(!is_order_or_certificate(&serialize_cross_shard(&cert, 0)));
+    assert!(!is_order_or_certificate(&serialize_message_as(
+        &SerializedMessage::Ping,
+        SerializationFormat::Bcs
+    )));
+    let mut bad_format = serialize_cert(&cert);
+    bad_format[2] = 0xff;
+    assert!(!is_order_or_certificate(&bad_format));
+    assert!(!is_order_or_certificate(&[]));
+    assert!(!is_order_or_certificate(&[MESSAGE_MAGIC]));
+}
+
+#[test]
+fn test_compact_cert() {
+    let (sender_name, sender_key) = get_key_pair();
+    let transfer = Transfer {
+        sender: sender_name,
+        recipient: Address::Primary(dbg_addr(0x20)),
+        amount: Amount::from(5),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sender_key);
+    let mut cert = CertifiedTransferOrder {
+        value: order,
+        signatures: Vec::new(),
+    };
+    for _ in 0..3 {
+        let (authority_name, authority_key) = get_authority_key_pair();
+        let sig = Signature::new(&cert.value.transfer, &authority_key);
+        cert.signatures.push((authority_name, sig));
+    }
+    let compact = CompactConfirmationOrder::new(&cert);
+
+    let buf = serialize_compact_cert(&compact);
+    assert!(buf.len() < serialize_cert(&cert).len());
+    if let SerializedMessage::CompactCert(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(*o, compact);
+        let expanded = o.expand(cert.value.clone()).unwrap();
+        assert_eq!(expanded.transfer_certificate, cert);
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn test_oversized_sequences() {
+    let (sender_name, sender_key) = get_key_pair();
+    let transfer = Transfer {
+        sender: sender_name,
+        recipient: Address::Primary(dbg_addr(0x20)),
+        amount: Amount::from(5),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
+    };
+    let order = TransferOrder::new(transfer, &sender_key);
+    let (authority_name, authority_key) = get_authority_key_pair();
+    let vote = (
+        authority_name,
+        Signature::new(&order.transfer, &authority_key),
+    );
+    let mut cert = CertifiedTransferOrder {
+        value: order,
+        signatures: vec![vote],
+    };
+    let assert_invalid = |buf: &[u8]| match deserialize_message(buf) {
+        Err(error) => assert_eq!(
+            error.downcast::<FastPayError>().unwrap(),
+            FastPayError::InvalidDecoding
+        ),
+        Ok(_) => panic!("oversized message was accepted"),
+    };
+
+    // Forge the announced number of signatures, which is the last field of the message.
+    let buf1 = serialize_cert(&cert);
+    cert.signatures.push(vote);
+    let buf2 = serialize_cert(&cert);
+    let offset = 2 * buf1.len() - buf2.len() - 8;
+    assert_eq!(buf1[offset..offset + 8], 1u64.to_le_bytes());
+    let mut forged = buf1;
+    forged[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    let start = Instant::now();
+    assert_invalid(&forged);
+    assert!(start.elapsed().as_secs() < 1);
+
+    // Actual sequences are bounded too, in every format.
+    cert.signatures = vec![vote; MAX_SIGNATURES_PER_CERTIFICATE];
+    assert!(deserialize_message(serialize_cert(&cert).as_slice()).is_ok());
+    cert.signatures.push(vote);
+    let message = SerializedMessage::Cert(Box::new(cert.clone()));
+    assert_invalid(&serialize_message_as(
+        &message,
+        SerializationFormat::Bincode,
+    ));
+    assert_invalid(&serialize_message_as(&message, SerializationFormat::Bcs));
+
+    cert.signatures = vec![vote];
+    let info = AccountInfoResponse {
+        sender: dbg_addr(0x20),
+        balance: Balance::from(0),
+        next_sequence_number: SequenceNumber::new(),
+        pending_confirmation: None,
+        requested_certificate: None,
+        requested_received_transfers: vec![cert; MAX_RECEIVED_CERTIFICATES_PER_RESPONSE + 1],
+        receipt: None,
+        frozen: false,
+        next_freeze_version: SequenceNumber::new(),
+    };
+    assert_invalid(&serialize_info_response(&info));
+}
+
 #[test]
 fn test_info_response() {
     let (sender_name, sender_key) = get_key_pair();
@@ -161,10 +449,12 @@ fn test_info_response() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
 
-    let (auth_name, auth_key) = get_key_pair();
+    let (auth_name, auth_key) = get_authority_key_pair();
     let vote = SignedTransferOrder::new(order.clone(), auth_name, &auth_key);
 
     let mut cert = CertifiedTransferOrder {
@@ -173,7 +463,7 @@ fn test_info_response() {
     };
 
     for _ in 0..3 {
-        let (authority_name, authority_key) = get_key_pair();
+        let (authority_name, authority_key) = get_authority_key_pair();
         let sig = Signature::new(&cert.value.transfer, &authority_key);
 
         cert.signatures.push((authority_name, sig));
@@ -186,6 +476,9 @@ fn test_info_response() {
         pending_confirmation: None,
         requested_certificate: None,
         requested_received_transfers: Vec::new(),
+        receipt: None,
+        frozen: false,
+        next_freeze_version: SequenceNumber::new(),
     };
     let resp2 = AccountInfoResponse {
         sender: dbg_addr(0x20),
@@ -194,6 +487,9 @@ fn test_info_response() {
         pending_confirmation: Some(vote.clone()),
         requested_certificate: None,
         requested_received_transfers: Vec::new(),
+        receipt: None,
+        frozen: false,
+        next_freeze_version: SequenceNumber::new(),
     };
     let resp3 = AccountInfoResponse {
         sender: dbg_addr(0x20),
@@ -202,6 +498,9 @@ fn test_info_response() {
         pending_confirmation: None,
         requested_certificate: Some(cert.clone()),
         requested_received_transfers: Vec::new(),
+        receipt: None,
+        frozen: false,
+        next_freeze_version: SequenceNumber::new(),
     };
     let resp4 = AccountInfoResponse {
         sender: dbg_addr(0x20),
@@ -210,6 +509,9 @@ fn test_info_response() {
         pending_confirmation: Some(vote),
         requested_certificate: Some(cert),
         requested_received_transfers: Vec::new(),
+        receipt: None,
+        frozen: false,
+        next_freeze_version: SequenceNumber::new(),
     };
 
     for resp in [resp1, resp2, resp3, resp4].iter() {
@@ -233,6 +535,8 @@ fn test_time_order() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
 
     let mut buf = Vec::new();
@@ -266,10 +570,12 @@ fn test_time_vote() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
 
-    let (authority_name, authority_key) = get_key_pair();
+    let (authority_name, authority_key) = get_authority_key_pair();
 
     let mut buf = Vec::new();
     let now = Instant::now();
@@ -305,6 +611,8 @@ fn test_time_cert() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        valid_until: None,
+        fee: Amount::zero(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
     let mut cert = CertifiedTransferOrder {
@@ -313,7 +621,7 @@ fn test_time_cert() {
     };
 
     for _ in 0..7 {
-        let (authority_name, authority_key) = get_key_pair();
+        let (authority_name, authority_key) = get_authority_key_pair();
         let sig = Signature::new(&cert.value.transfer, &authority_key);
         cert.signatures.push((authority_name, sig));
     }
@@ -50,12 +50,19 @@ done
 # Create clients' accounts.
 ./client --committee committee.json --accounts accounts.json create_accounts $num_accounts >> initial_accounts.json
 
+# Sign the initial balances.
+for (( i=1; i<=$committee_size; i++ ))
+do
+	./server --server server-"$i".json sign_genesis \
+		--initial-accounts initial_accounts.json \
+		--genesis genesis.json
+done
+
 # Run a single authority (with multiple shards).
 for (( i=0; i<$num_shards; i++ ))
 do
 	./server --server server-1.json run \
-		--initial_accounts initial_accounts.json \
-		--initial_balance 100 \
+		--genesis genesis.json \
 		--committee committee.json \
 		--shard $i &
 done